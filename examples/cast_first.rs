@@ -0,0 +1,67 @@
+//! Cast a file to the first renderer found on the network.
+//!
+//! ```sh
+//! cargo run --example cast_first -- movie.mp4
+//! ```
+
+use std::path::PathBuf;
+
+use localcast::{CastSession, DeviceRef, PlaybackState, SessionCommand};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let file: PathBuf = std::env::args_os()
+        .nth(1)
+        .ok_or("usage: cast_first <video file>")?
+        .into();
+
+    let session = CastSession::builder().start().await?;
+    session.request(SessionCommand::SetFiles(vec![file])).await?;
+
+    println!("Searching for renderers...");
+    let state = session.request(SessionCommand::Discover).await?;
+    let Some(device) = state.devices.first() else {
+        return Err("no renderers found".into());
+    };
+    println!("Casting to {}", device.friendly_name);
+    let device = Some(DeviceRef::Udn(device.udn.clone()));
+    session.request(SessionCommand::Cast { device }).await?;
+
+    // Print progress until the item ends or Ctrl-C
+    let mut state = session.watch();
+    let mut started = false;
+    loop {
+        tokio::select! {
+            changed = state.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let s = state.borrow_and_update().clone();
+                println!(
+                    "{} {} / {}",
+                    s.playback_state.label(),
+                    s.position.elapsed_display(),
+                    s.position.duration_display()
+                );
+                // The session stays casting once the item ends; stop when the
+                // renderer does, unless it is being brought back
+                match s.playback_state {
+                    PlaybackState::Playing => started = true,
+                    PlaybackState::Stopped | PlaybackState::NoMediaPresent
+                        if started && s.recovering.is_none() =>
+                    {
+                        break;
+                    }
+                    _ => {}
+                }
+                if !s.casting {
+                    break;
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    session.request(SessionCommand::Quit).await?;
+    Ok(())
+}
@@ -0,0 +1,310 @@
+//! A stand-in for an Apple TV, to try the AirPlay backend without one.
+//!
+//! It answers the legacy AirPlay video endpoints localcast uses:
+//! `/server-info`, `/play`, `/rate`, `/scrub`, `/stop` and `/playback-info`,
+//! playing `--secs` seconds of media on a clock. `/play` fetches the start of
+//! the media URL before reporting the video ready, so the media server is
+//! exercised too. With `--mdns`, it is also announced like a real device.
+//!
+//! ```sh
+//! cargo run --example fake_appletv -- --port 7000
+//! localcast cast --device-url airplay://127.0.0.1:7000 movie.mp4
+//! ```
+//!
+//! The integration tests include this file and start the stand-in with
+//! [`run`] on a listener of their own.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use localcast::airplay::SERVICE_TYPE;
+use plist::{Dictionary, Value};
+use tokio::net::TcpListener;
+
+/// Video, photos, screen mirroring and then some, as an Apple TV 4 says.
+const FEATURES: (u32, u32) = (0x5A7F_FFF7, 0x1E);
+
+pub struct Options {
+    pub port: u16,
+    pub name: String,
+    /// Length of the pretend media.
+    pub secs: f64,
+    pub mdns: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            port: 7000,
+            name: "Fake Apple TV".into(),
+            secs: 600.0,
+            mdns: false,
+        }
+    }
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse().map_err(|_| "bad --port")?,
+            "--name" => options.name = value()?,
+            "--secs" => options.secs = value()?.parse().map_err(|_| "bad --secs")?,
+            "--mdns" => options.mdns = true,
+            other => return Err(format!("unknown argument {other}")),
+        }
+    }
+    Ok(options)
+}
+
+/// What the device is doing, shared by every request.
+struct Player {
+    secs: f64,
+    device_id: String,
+    video: Option<Video>,
+}
+
+struct Video {
+    url: String,
+    /// False until the start of the URL has been fetched.
+    ready: bool,
+    rate: f64,
+    /// Position when `since` was taken.
+    position: f64,
+    since: Instant,
+}
+
+impl Video {
+    fn position(&self, secs: f64) -> f64 {
+        if !self.ready {
+            return self.position;
+        }
+        (self.position + self.since.elapsed().as_secs_f64() * self.rate).min(secs)
+    }
+
+    /// Fix the position where it is now, before changing the rate.
+    fn settle(&mut self, secs: f64) {
+        self.position = self.position(secs);
+        self.since = Instant::now();
+    }
+}
+
+impl Player {
+    /// The video, unless it has played to the end, which unloads it.
+    fn video(&mut self) -> Option<&mut Video> {
+        let secs = self.secs;
+        if self.video.as_ref().is_some_and(|v| v.ready && v.position(secs) >= secs) {
+            println!("Finished {}", self.video.take().map(|v| v.url).unwrap_or_default());
+        }
+        self.video.as_mut()
+    }
+}
+
+type Shared = Arc<Mutex<Player>>;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = options()?;
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), options.port)).await?;
+    let port = listener.local_addr()?.port();
+    println!("{} ({}) listening on port {port}", options.name, device_id(port));
+
+    // Kept alive for as long as the device should stay announced
+    let _mdns = if options.mdns { Some(announce(&options.name, &device_id(port), port)?) } else { None };
+    run(listener, &options).await
+}
+
+/// A MAC-like device ID, told apart by the port.
+fn device_id(port: u16) -> String {
+    format!("02:00:00:00:{:02X}:{:02X}", port >> 8, port & 0xff)
+}
+
+/// Play the device for whoever connects to `listener`, until it fails.
+pub async fn run(listener: TcpListener, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let player = Arc::new(Mutex::new(Player {
+        secs: options.secs,
+        device_id: device_id(listener.local_addr()?.port()),
+        video: None,
+    }));
+    let app = Router::new()
+        .route("/server-info", get(server_info))
+        .route("/play", post(play))
+        .route("/rate", post(rate))
+        .route("/scrub", get(scrub_position).post(scrub))
+        .route("/stop", post(stop))
+        .route("/playback-info", get(playback_info))
+        .with_state(player);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Announce the device over mDNS the way real Apple TVs do.
+fn announce(name: &str, device_id: &str, port: u16) -> Result<mdns_sd::ServiceDaemon, Box<dyn std::error::Error>> {
+    let ip = local_ip_address::local_ip()?;
+    let features = format!("0x{:X},0x{:X}", FEATURES.0, FEATURES.1);
+    let properties = [
+        ("deviceid", device_id),
+        ("features", features.as_str()),
+        ("model", "AppleTV5,3"),
+        ("srcvers", "220.68"),
+    ];
+    let host = format!("Fake-Apple-TV-{port}.local.");
+    let info = mdns_sd::ServiceInfo::new(SERVICE_TYPE, name, &host, ip, port, &properties[..])?;
+    let daemon = mdns_sd::ServiceDaemon::new()?;
+    daemon.register(info)?;
+    println!("Announced as {name} on {ip}");
+    Ok(daemon)
+}
+
+fn plist_response(dict: Dictionary) -> impl IntoResponse {
+    let mut body = Vec::new();
+    match Value::Dictionary(dict).to_writer_xml(&mut body) {
+        Ok(()) => (StatusCode::OK, [("Content-Type", "text/x-apple-plist+xml")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn server_info(State(player): State<Shared>) -> impl IntoResponse {
+    let player = player.lock().unwrap();
+    let mut dict = Dictionary::new();
+    dict.insert("deviceid".into(), player.device_id.clone().into());
+    dict.insert("features".into(), (u64::from(FEATURES.1) << 32 | u64::from(FEATURES.0)).into());
+    dict.insert("model".into(), "AppleTV5,3".into());
+    dict.insert("protovers".into(), "1.0".into());
+    dict.insert("srcvers".into(), "220.68".into());
+    plist_response(dict)
+}
+
+async fn play(State(player): State<Shared>, headers: HeaderMap, body: String) -> StatusCode {
+    if !headers.contains_key("X-Apple-Session-ID") {
+        println!("play without a session ID");
+    }
+    let parameters: HashMap<&str, &str> = body
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let Some(url) = parameters.get("Content-Location").map(|url| url.to_string()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let start = parameters
+        .get("Start-Position")
+        .and_then(|p| p.parse::<f64>().ok())
+        .unwrap_or_default();
+    println!("play {url} from {:.0}%", start * 100.0);
+    {
+        let mut player = player.lock().unwrap();
+        let position = start * player.secs;
+        player.video = Some(Video {
+            url: url.clone(),
+            ready: false,
+            rate: 1.0,
+            position,
+            since: Instant::now(),
+        });
+    }
+
+    // Load in the background, as the real thing does
+    tokio::spawn(async move {
+        let fetched = fetch(&url).await;
+        let mut player = player.lock().unwrap();
+        match (fetched, player.video.as_mut().filter(|v| v.url == url)) {
+            (Ok(()), Some(video)) => {
+                println!("Loaded {url}");
+                video.ready = true;
+                video.since = Instant::now();
+            }
+            (Err(e), Some(_)) => {
+                println!("Cannot fetch {url}: {e}");
+                player.video = None;
+            }
+            (_, None) => {}
+        }
+    });
+    StatusCode::OK
+}
+
+async fn rate(State(player): State<Shared>, Query(query): Query<HashMap<String, String>>) -> StatusCode {
+    let Some(rate) = query.get("value").and_then(|v| v.parse::<f64>().ok()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    println!("rate {rate}");
+    let mut player = player.lock().unwrap();
+    let secs = player.secs;
+    if let Some(video) = player.video() {
+        video.settle(secs);
+        video.rate = rate.clamp(0.0, 1.0);
+    }
+    StatusCode::OK
+}
+
+async fn scrub(State(player): State<Shared>, Query(query): Query<HashMap<String, String>>) -> StatusCode {
+    let Some(position) = query.get("position").and_then(|p| p.parse::<f64>().ok()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    println!("scrub {position}");
+    let mut player = player.lock().unwrap();
+    let secs = player.secs;
+    if let Some(video) = player.video() {
+        video.settle(secs);
+        video.position = position.clamp(0.0, secs);
+    }
+    StatusCode::OK
+}
+
+async fn scrub_position(State(player): State<Shared>) -> String {
+    let mut player = player.lock().unwrap();
+    let secs = player.secs;
+    match player.video() {
+        Some(video) if video.ready => format!("duration: {secs:.6}\nposition: {:.6}\n", video.position(secs)),
+        _ => "duration: 0.000000\nposition: 0.000000\n".into(),
+    }
+}
+
+async fn stop(State(player): State<Shared>) -> StatusCode {
+    println!("stop");
+    player.lock().unwrap().video = None;
+    StatusCode::OK
+}
+
+async fn playback_info(State(player): State<Shared>) -> impl IntoResponse {
+    let mut player = player.lock().unwrap();
+    let secs = player.secs;
+    let mut dict = Dictionary::new();
+    match player.video() {
+        Some(video) if video.ready => {
+            dict.insert("duration".into(), secs.into());
+            dict.insert("position".into(), video.position(secs).into());
+            dict.insert("rate".into(), video.rate.into());
+            dict.insert("readyToPlay".into(), true.into());
+            dict.insert("playbackBufferEmpty".into(), false.into());
+            dict.insert("playbackLikelyToKeepUp".into(), true.into());
+        }
+        _ => {
+            dict.insert("readyToPlay".into(), false.into());
+        }
+    }
+    plist_response(dict)
+}
+
+/// Read the first bytes of `url`, as a real receiver would to start playing.
+async fn fetch(url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let request = http02::Request::get(url)
+        .header("Range", "bytes=0-1023")
+        .body(hyper014::Body::empty())?;
+    let response = hyper014::Client::new().request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()).into());
+    }
+    hyper014::body::to_bytes(response.into_body()).await?;
+    Ok(())
+}
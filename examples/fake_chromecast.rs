@@ -0,0 +1,328 @@
+//! A stand-in for a Chromecast, to try the Google Cast backend without one.
+//!
+//! It speaks enough of the Cast v2 protocol for localcast: TLS with a
+//! self-signed certificate, heartbeats, launching the Default Media
+//! Receiver, volume, and LOAD/PLAY/PAUSE/SEEK/STOP/GET_STATUS on a clock
+//! that plays `--secs` seconds of media. LOAD fetches the start of the
+//! media URL, so the media server is exercised too. With `--mdns`, it is
+//! also announced like a real device; with `--ignore-load`, LOAD is never
+//! answered, as by a receiver that hangs.
+//!
+//! ```sh
+//! cargo run --example fake_chromecast -- --port 8009
+//! localcast cast --device-url cast://127.0.0.1:8009 movie.mp4
+//! ```
+//!
+//! The integration tests include this file and start the stand-in with
+//! [`run`] on a listener of their own.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use localcast::googlecast::proto::{self, CastMessage};
+use localcast::googlecast::{DEFAULT_MEDIA_RECEIVER, NS_HEARTBEAT, NS_MEDIA, NS_RECEIVER, SERVICE_TYPE};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// The transport of our pretend Default Media Receiver.
+const TRANSPORT_ID: &str = "web-1";
+
+pub struct Options {
+    pub port: u16,
+    pub name: String,
+    /// Length of the pretend media.
+    pub secs: f64,
+    pub mdns: bool,
+    pub ignore_load: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            port: 8009,
+            name: "Fake Chromecast".into(),
+            secs: 600.0,
+            mdns: false,
+            ignore_load: false,
+        }
+    }
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse().map_err(|_| "bad --port")?,
+            "--name" => options.name = value()?,
+            "--secs" => options.secs = value()?.parse().map_err(|_| "bad --secs")?,
+            "--mdns" => options.mdns = true,
+            "--ignore-load" => options.ignore_load = true,
+            other => return Err(format!("unknown argument {other}")),
+        }
+    }
+    Ok(options)
+}
+
+/// What the device is doing, shared by every connection.
+struct Player {
+    secs: f64,
+    volume: f64,
+    launched: bool,
+    media: Option<Loaded>,
+    next_session: u64,
+    ignore_load: bool,
+}
+
+struct Loaded {
+    session_id: u64,
+    content_id: String,
+    content_type: String,
+    title: Value,
+    /// PLAYING, PAUSED or IDLE.
+    state: &'static str,
+    idle_reason: Option<&'static str>,
+    /// Position when `since` was taken.
+    position: f64,
+    since: Instant,
+}
+
+impl Loaded {
+    fn current_time(&self, secs: f64) -> f64 {
+        match self.state {
+            "PLAYING" => (self.position + self.since.elapsed().as_secs_f64()).min(secs),
+            _ => self.position,
+        }
+    }
+
+    /// Fix the position where it is now, before changing state.
+    fn settle(&mut self, secs: f64) {
+        self.position = self.current_time(secs);
+        self.since = Instant::now();
+    }
+}
+
+impl Player {
+    fn receiver_status(&self) -> Value {
+        let applications: Vec<Value> = if self.launched {
+            vec![json!({
+                "appId": DEFAULT_MEDIA_RECEIVER,
+                "displayName": "Default Media Receiver",
+                "sessionId": "fake-session",
+                "transportId": TRANSPORT_ID,
+                "isIdleScreen": false,
+            })]
+        } else {
+            Vec::new()
+        };
+        json!({
+            "type": "RECEIVER_STATUS",
+            "status": {
+                "applications": applications,
+                "volume": { "level": self.volume, "muted": false },
+            },
+        })
+    }
+
+    fn media_status(&mut self) -> Value {
+        let secs = self.secs;
+        let Some(media) = &mut self.media else {
+            return json!({ "type": "MEDIA_STATUS", "status": [] });
+        };
+        if media.state == "PLAYING" && media.current_time(secs) >= secs {
+            media.settle(secs);
+            media.state = "IDLE";
+            media.idle_reason = Some("FINISHED");
+        }
+        json!({
+            "type": "MEDIA_STATUS",
+            "status": [{
+                "mediaSessionId": media.session_id,
+                "playerState": media.state,
+                "idleReason": media.idle_reason,
+                "currentTime": media.current_time(secs),
+                "supportedMediaCommands": 15,
+                "media": {
+                    "contentId": media.content_id,
+                    "contentType": media.content_type,
+                    "streamType": "BUFFERED",
+                    "duration": secs,
+                    "metadata": { "metadataType": 0, "title": media.title },
+                },
+            }],
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = options()?;
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), options.port)).await?;
+    let port = listener.local_addr()?.port();
+    println!("{} listening on port {port}", options.name);
+
+    // Kept alive for as long as the device should stay announced
+    let _mdns = if options.mdns { Some(announce(&options.name, port)?) } else { None };
+    run(listener, &options).await
+}
+
+/// Play the device for whoever connects to `listener`, until it fails.
+pub async fn run(listener: TcpListener, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let key = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let private_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.key_pair.serialize_der()));
+    let tls = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(vec![key.cert.der().clone()], private_key)?;
+    let acceptor = TlsAcceptor::from(Arc::new(tls));
+
+    let player = Arc::new(Mutex::new(Player {
+        secs: options.secs,
+        volume: 0.5,
+        launched: false,
+        media: None,
+        next_session: 1,
+        ignore_load: options.ignore_load,
+    }));
+    loop {
+        let (tcp, from) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let player = player.clone();
+        tokio::spawn(async move {
+            println!("{from} connected");
+            if let Err(e) = serve(acceptor, tcp, player).await {
+                println!("{from}: {e}");
+            }
+            println!("{from} disconnected");
+        });
+    }
+}
+
+/// Announce the device over mDNS the way real Cast devices do.
+fn announce(name: &str, port: u16) -> Result<mdns_sd::ServiceDaemon, Box<dyn std::error::Error>> {
+    let ip = local_ip_address::local_ip()?;
+    let id = format!("{:032x}", u128::from(port) << 64 | u128::from(std::process::id()));
+    let properties = [("id", id.as_str()), ("fn", name), ("md", "localcast stand-in")];
+    let instance = format!("Fake-Chromecast-{id}");
+    let info = mdns_sd::ServiceInfo::new(SERVICE_TYPE, &instance, &format!("{instance}.local."), ip, port, &properties[..])?;
+    let daemon = mdns_sd::ServiceDaemon::new()?;
+    daemon.register(info)?;
+    println!("Announced as {name} ({id}) on {ip}");
+    Ok(daemon)
+}
+
+async fn serve(acceptor: TlsAcceptor, tcp: TcpStream, player: Arc<Mutex<Player>>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stream = acceptor.accept(tcp).await?;
+    loop {
+        // Senders usually just hang up
+        let Ok(message) = proto::read_message(&mut stream).await else {
+            return Ok(());
+        };
+        let Some(request) = message.payload() else { continue };
+        let kind = request["type"].as_str().unwrap_or_default();
+        let reply = match message.namespace.as_str() {
+            NS_HEARTBEAT if kind == "PING" => Some(json!({ "type": "PONG" })),
+            NS_RECEIVER => receiver_request(kind, &request, &player),
+            NS_MEDIA => media_request(kind, &request, &player).await,
+            _ => None,
+        };
+        if message.namespace != NS_HEARTBEAT {
+            println!("{} {kind}", message.namespace.rsplit('.').next().unwrap_or_default());
+        }
+        if let Some(mut reply) = reply {
+            if !request["requestId"].is_null() {
+                reply["requestId"] = request["requestId"].clone();
+            }
+            let out = CastMessage::json(&message.destination_id, &message.source_id, &message.namespace, &reply);
+            proto::write_message(&mut stream, &out).await?;
+        }
+    }
+}
+
+fn receiver_request(kind: &str, request: &Value, player: &Mutex<Player>) -> Option<Value> {
+    let mut player = player.lock().unwrap();
+    match kind {
+        "GET_STATUS" => {}
+        "LAUNCH" if request["appId"] == DEFAULT_MEDIA_RECEIVER => player.launched = true,
+        "LAUNCH" => return Some(json!({ "type": "LAUNCH_ERROR", "reason": "NOT_FOUND" })),
+        "STOP" => {
+            player.launched = false;
+            player.media = None;
+        }
+        "SET_VOLUME" => {
+            if let Some(level) = request["volume"]["level"].as_f64() {
+                player.volume = level.clamp(0.0, 1.0);
+            }
+        }
+        _ => return Some(json!({ "type": "INVALID_REQUEST", "reason": "INVALID_COMMAND" })),
+    }
+    Some(player.receiver_status())
+}
+
+async fn media_request(kind: &str, request: &Value, player: &Mutex<Player>) -> Option<Value> {
+    if kind == "LOAD" {
+        if player.lock().unwrap().ignore_load {
+            return None;
+        }
+        let content_id = request["media"]["contentId"].as_str().unwrap_or_default().to_string();
+        if let Err(e) = fetch(&content_id).await {
+            println!("Cannot fetch {content_id}: {e}");
+            return Some(json!({ "type": "LOAD_FAILED" }));
+        }
+        println!("Loaded {content_id}");
+        let mut player = player.lock().unwrap();
+        let session_id = player.next_session;
+        player.next_session += 1;
+        let autoplay = request["autoplay"].as_bool().unwrap_or(true);
+        player.media = Some(Loaded {
+            session_id,
+            content_id,
+            content_type: request["media"]["contentType"].as_str().unwrap_or_default().to_string(),
+            title: request["media"]["metadata"]["title"].clone(),
+            state: if autoplay { "PLAYING" } else { "PAUSED" },
+            idle_reason: None,
+            position: request["currentTime"].as_f64().unwrap_or_default(),
+            since: Instant::now(),
+        });
+        return Some(player.media_status());
+    }
+
+    let mut player = player.lock().unwrap();
+    let secs = player.secs;
+    if kind != "GET_STATUS" {
+        let Some(media) = player.media.as_mut().filter(|m| request["mediaSessionId"] == m.session_id) else {
+            return Some(json!({ "type": "INVALID_REQUEST", "reason": "INVALID_MEDIA_SESSION_ID" }));
+        };
+        media.settle(secs);
+        match kind {
+            "PLAY" => media.state = "PLAYING",
+            "PAUSE" => media.state = "PAUSED",
+            "STOP" => {
+                media.state = "IDLE";
+                media.idle_reason = Some("CANCELLED");
+            }
+            "SEEK" => media.position = request["currentTime"].as_f64().unwrap_or_default().clamp(0.0, secs),
+            _ => return Some(json!({ "type": "INVALID_REQUEST", "reason": "INVALID_COMMAND" })),
+        }
+    }
+    Some(player.media_status())
+}
+
+/// Read the first bytes of `url`, as a real receiver would to start playing.
+async fn fetch(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let request = http02::Request::get(url)
+        .header("Range", "bytes=0-1023")
+        .body(hyper014::Body::empty())?;
+    let response = hyper014::Client::new().request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()).into());
+    }
+    hyper014::body::to_bytes(response.into_body()).await?;
+    Ok(())
+}
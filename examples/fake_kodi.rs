@@ -0,0 +1,404 @@
+//! A stand-in for Kodi, to try the Kodi backend without one.
+//!
+//! It answers the JSON-RPC methods localcast uses at `/jsonrpc`, playing
+//! `--secs` seconds of media on a clock, and over a WebSocket on
+//! `--websocket-port` (9090), where it also sends player notifications.
+//! `Player.Open` fetches the start of the media URL before a player shows
+//! up, so the media server is exercised too. `--auth user:password` makes
+//! the web server ask for credentials; with `--mdns`, it is also announced
+//! like a real instance.
+//!
+//! ```sh
+//! cargo run --example fake_kodi -- --port 8080
+//! localcast cast --device-url kodi://127.0.0.1:8080 movie.mp4
+//! ```
+//!
+//! The integration tests include this file and start the stand-in with
+//! [`run`] on a listener of their own.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures::{SinkExt, StreamExt};
+use localcast::kodi::{SERVICE_TYPE, WEBSOCKET_PORT};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+/// The video player's ID, as in Kodi.
+const VIDEO_PLAYER: i64 = 1;
+
+pub struct Options {
+    pub port: u16,
+    pub name: String,
+    /// Length of the pretend media.
+    pub secs: f64,
+    /// `user:password` the web server asks for.
+    pub auth: Option<String>,
+    pub websocket_port: u16,
+    pub mdns: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            name: "Fake Kodi".into(),
+            secs: 600.0,
+            auth: None,
+            websocket_port: WEBSOCKET_PORT,
+            mdns: false,
+        }
+    }
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse().map_err(|_| "bad --port")?,
+            "--name" => options.name = value()?,
+            "--secs" => options.secs = value()?.parse().map_err(|_| "bad --secs")?,
+            "--auth" => options.auth = Some(value()?),
+            "--websocket-port" => options.websocket_port = value()?.parse().map_err(|_| "bad --websocket-port")?,
+            "--mdns" => options.mdns = true,
+            other => return Err(format!("unknown argument {other}")),
+        }
+    }
+    Ok(options)
+}
+
+/// What Kodi is doing, shared by every request.
+struct Player {
+    name: String,
+    secs: f64,
+    /// `Basic ...` header the web server wants, if any.
+    authorization: Option<String>,
+    volume: u32,
+    video: Option<Video>,
+    /// Notifications for WebSocket clients.
+    notifications: broadcast::Sender<Value>,
+}
+
+struct Video {
+    url: String,
+    /// False until the start of the URL has been fetched; no player until then.
+    ready: bool,
+    speed: f64,
+    /// Position when `since` was taken.
+    position: f64,
+    since: Instant,
+}
+
+impl Video {
+    fn position(&self, secs: f64) -> f64 {
+        if !self.ready {
+            return self.position;
+        }
+        (self.position + self.since.elapsed().as_secs_f64() * self.speed).min(secs)
+    }
+
+    /// Fix the position where it is now, before changing the speed.
+    fn settle(&mut self, secs: f64) {
+        self.position = self.position(secs);
+        self.since = Instant::now();
+    }
+}
+
+impl Player {
+    /// The video while it plays, unless it has played to the end, which
+    /// closes the player.
+    fn video(&mut self) -> Option<&mut Video> {
+        let secs = self.secs;
+        if self.video.as_ref().is_some_and(|v| v.ready && v.position(secs) >= secs) {
+            println!("Finished {}", self.video.take().map(|v| v.url).unwrap_or_default());
+        }
+        self.video.as_mut().filter(|v| v.ready)
+    }
+
+    /// Tell WebSocket clients about a player change.
+    fn notify(&self, method: &str) {
+        let data = json!({ "player": { "playerid": VIDEO_PLAYER } });
+        let notification = json!({ "jsonrpc": "2.0", "method": method, "params": { "sender": "xbmc", "data": data } });
+        let _ = self.notifications.send(notification);
+    }
+}
+
+type Shared = Arc<Mutex<Player>>;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = options()?;
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), options.port)).await?;
+    let port = listener.local_addr()?.port();
+    println!("{} listening on port {port}", options.name);
+
+    // Kept alive for as long as the instance should stay announced
+    let _mdns = if options.mdns { Some(announce(&options.name, port)?) } else { None };
+
+    let websocket = match TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), options.websocket_port)).await {
+        Ok(listener) => {
+            println!("WebSocket listening on port {}", options.websocket_port);
+            Some(listener)
+        }
+        Err(e) => {
+            println!("No WebSocket on port {}: {e}", options.websocket_port);
+            None
+        }
+    };
+    run(listener, websocket, &options).await
+}
+
+/// Answer JSON-RPC requests that come to `listener`, and to `websocket` if
+/// given, until it fails.
+pub async fn run(
+    listener: TcpListener,
+    websocket: Option<TcpListener>,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let player = Arc::new(Mutex::new(Player {
+        name: options.name.clone(),
+        secs: options.secs,
+        authorization: options.auth.as_ref().map(|auth| format!("Basic {}", base64(auth.as_bytes()))),
+        volume: 100,
+        video: None,
+        notifications: broadcast::channel(16).0,
+    }));
+    if let Some(websocket) = websocket {
+        tokio::spawn(serve_websockets(websocket, player.clone()));
+    }
+    let app = Router::new().route("/jsonrpc", post(jsonrpc)).with_state(player);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Announce the instance over mDNS the way Kodi does.
+fn announce(name: &str, port: u16) -> Result<mdns_sd::ServiceDaemon, Box<dyn std::error::Error>> {
+    let ip = local_ip_address::local_ip()?;
+    let uuid = format!("00000000-0000-4000-8000-0000000{port:05}");
+    let properties = [("txtvers", "1"), ("uuid", uuid.as_str())];
+    let host = format!("Fake-Kodi-{port}.local.");
+    let info = mdns_sd::ServiceInfo::new(SERVICE_TYPE, name, &host, ip, port, &properties[..])?;
+    let daemon = mdns_sd::ServiceDaemon::new()?;
+    daemon.register(info)?;
+    println!("Announced as {name} on {ip}");
+    Ok(daemon)
+}
+
+async fn jsonrpc(State(player): State<Shared>, headers: HeaderMap, Json(request): Json<Value>) -> Response {
+    let wanted = player.lock().unwrap().authorization.clone();
+    if let Some(wanted) = wanted {
+        let given = headers.get("Authorization").and_then(|v| v.to_str().ok());
+        if given != Some(wanted.as_str()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    Json(reply(&player, &request)).into_response()
+}
+
+/// The JSON-RPC reply to `request`.
+fn reply(player: &Shared, request: &Value) -> Value {
+    let method = request["method"].as_str().unwrap_or_default().to_string();
+    let params = &request["params"];
+    match call(player, &method, params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+        Err((code, message)) => {
+            println!("{method} failed: {message}");
+            json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": code, "message": message } })
+        }
+    }
+}
+
+/// Answer JSON-RPC over each WebSocket opened on `listener`. Kodi asks for
+/// no credentials there.
+async fn serve_websockets(listener: TcpListener, player: Shared) {
+    while let Ok((stream, from)) = listener.accept().await {
+        let player = player.clone();
+        tokio::spawn(async move {
+            println!("{from} opened a WebSocket");
+            if let Err(e) = serve_websocket(stream, player).await {
+                println!("{from}: {e}");
+            }
+            println!("{from} closed its WebSocket");
+        });
+    }
+}
+
+async fn serve_websocket(stream: TcpStream, player: Shared) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut socket = tokio_tungstenite::accept_async(stream).await?;
+    let mut notifications = player.lock().unwrap().notifications.subscribe();
+    loop {
+        let reply = tokio::select! {
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let request: Value = serde_json::from_str(&text)?;
+                    reply(&player, &request)
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            },
+            Ok(notification) = notifications.recv() => notification,
+        };
+        socket.send(Message::text(reply.to_string())).await?;
+    }
+}
+
+fn call(player: &Shared, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+    let invalid = || (-32602, "Invalid params.".to_string());
+    let mut guard = player.lock().unwrap();
+    let secs = guard.secs;
+    match method {
+        "JSONRPC.Ping" => Ok(json!("pong")),
+        "XBMC.GetInfoLabels" => Ok(json!({ "System.FriendlyName": guard.name })),
+        "Application.GetProperties" => Ok(json!({ "volume": guard.volume, "muted": false })),
+        "Application.SetVolume" => {
+            let volume = params["volume"].as_u64().ok_or_else(invalid)?;
+            println!("volume {volume}");
+            guard.volume = volume.min(100) as u32;
+            Ok(json!(guard.volume))
+        }
+        "Player.Open" => {
+            let url = params["item"]["file"].as_str().ok_or_else(invalid)?.to_string();
+            println!("open {url}");
+            guard.video = Some(Video {
+                url: url.clone(),
+                ready: false,
+                speed: 1.0,
+                position: 0.0,
+                since: Instant::now(),
+            });
+            drop(guard);
+            tokio::spawn(load(player.clone(), url));
+            Ok(json!("OK"))
+        }
+        "Player.GetActivePlayers" => match guard.video() {
+            Some(_) => Ok(json!([{ "playerid": VIDEO_PLAYER, "playertype": "internal", "type": "video" }])),
+            None => Ok(json!([])),
+        },
+        _ if method.starts_with("Player.") => {
+            if params["playerid"].as_i64() != Some(VIDEO_PLAYER) {
+                return Err(invalid());
+            }
+            let Some(video) = guard.video() else {
+                return Err((-32100, "Failed to execute method.".into()));
+            };
+            match method {
+                "Player.GetProperties" => Ok(json!({
+                    "time": time(video.position(secs)),
+                    "totaltime": time(secs),
+                    "speed": video.speed,
+                })),
+                "Player.GetItem" => Ok(json!({
+                    "item": { "type": "unknown", "label": label(&video.url), "file": video.url, "title": "" },
+                })),
+                "Player.PlayPause" => {
+                    let play = match &params["play"] {
+                        Value::Bool(play) => *play,
+                        _ => video.speed == 0.0,
+                    };
+                    println!("{}", if play { "play" } else { "pause" });
+                    video.settle(secs);
+                    video.speed = if play { 1.0 } else { 0.0 };
+                    let speed = video.speed;
+                    guard.notify(if play { "Player.OnResume" } else { "Player.OnPause" });
+                    Ok(json!({ "speed": speed }))
+                }
+                "Player.Seek" => {
+                    let target = &params["value"]["time"];
+                    let field = |name: &str| target[name].as_f64().unwrap_or_default();
+                    let position = field("hours") * 3600.0 + field("minutes") * 60.0 + field("seconds");
+                    println!("seek {position}");
+                    video.settle(secs);
+                    video.position = position.clamp(0.0, secs);
+                    let reply = json!({ "time": time(video.position), "totaltime": time(secs) });
+                    guard.notify("Player.OnSeek");
+                    Ok(reply)
+                }
+                "Player.Stop" => {
+                    println!("stop");
+                    guard.video = None;
+                    guard.notify("Player.OnStop");
+                    Ok(json!("OK"))
+                }
+                _ => Err((-32601, "Method not found.".into())),
+            }
+        }
+        _ => Err((-32601, "Method not found.".into())),
+    }
+}
+
+/// Fetch the start of `url` in the background, as Kodi does before its
+/// player appears.
+async fn load(player: Shared, url: String) {
+    let fetched = fetch(&url).await;
+    let mut player = player.lock().unwrap();
+    match (fetched, player.video.as_mut().filter(|v| v.url == url)) {
+        (Ok(()), Some(video)) => {
+            println!("Loaded {url}");
+            video.ready = true;
+            video.since = Instant::now();
+            player.notify("Player.OnPlay");
+        }
+        (Err(e), Some(_)) => {
+            println!("Cannot fetch {url}: {e}");
+            player.video = None;
+        }
+        (_, None) => {}
+    }
+}
+
+fn time(secs: f64) -> Value {
+    let whole = secs as u64;
+    json!({
+        "hours": whole / 3600,
+        "minutes": whole / 60 % 60,
+        "seconds": whole % 60,
+        "milliseconds": ((secs - whole as f64) * 1000.0) as u64,
+    })
+}
+
+/// The file name, as Kodi labels items it has no title for.
+fn label(url: &str) -> String {
+    let name = url.rsplit('/').next().unwrap_or(url);
+    urlencoding::decode(name).map(|s| s.into_owned()).unwrap_or_else(|_| name.to_string())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Read the first bytes of `url`, as a real player would to start playing.
+async fn fetch(url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let request = http02::Request::get(url)
+        .header("Range", "bytes=0-1023")
+        .body(hyper014::Body::empty())?;
+    let response = hyper014::Client::new().request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()).into());
+    }
+    hyper014::body::to_bytes(response.into_body()).await?;
+    Ok(())
+}
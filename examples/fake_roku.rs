@@ -0,0 +1,396 @@
+//! A stand-in for a Roku, to try the Roku backend without one.
+//!
+//! It answers the ECP endpoints localcast uses: `/query/device-info`,
+//! `/query/media-player`, `/query/active-app`, `/launch`, `/input` and
+//! `/keypress`, with Play on Roku playing `--secs` seconds of media on a
+//! clock. Launching fetches the start of the media URL before the player
+//! reports it playing, so the media server is exercised too. With `--ssdp`,
+//! it also answers `roku:ecp` searches like a real device. `--stall launch`,
+//! `--stall fetch` or `--stall startup` make it hang at that point of a
+//! cast, to see how one fails.
+//!
+//! ```sh
+//! cargo run --example fake_roku -- --port 8060
+//! localcast cast --device-url roku://127.0.0.1:8060 movie.mp4
+//! ```
+//!
+//! The integration tests include this file and start the stand-in with
+//! [`run`] on a listener of their own.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use localcast::roku::{PLAY_ON_ROKU, SEARCH_TARGET};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+
+const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+pub struct Options {
+    pub port: u16,
+    pub name: String,
+    /// Length of the pretend media.
+    pub secs: f64,
+    pub ssdp: bool,
+    pub stall: Option<Stall>,
+}
+
+/// Where a cast gets stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stall {
+    /// `/launch` never answers.
+    Launch,
+    /// The media URL is never fetched.
+    Fetch,
+    /// The media URL is fetched, but the video never starts.
+    Startup,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            port: 8060,
+            name: "Fake Roku".into(),
+            secs: 600.0,
+            ssdp: false,
+            stall: None,
+        }
+    }
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse().map_err(|_| "bad --port")?,
+            "--name" => options.name = value()?,
+            "--secs" => options.secs = value()?.parse().map_err(|_| "bad --secs")?,
+            "--ssdp" => options.ssdp = true,
+            "--stall" => {
+                options.stall = Some(match value()?.as_str() {
+                    "launch" => Stall::Launch,
+                    "fetch" => Stall::Fetch,
+                    "startup" => Stall::Startup,
+                    other => return Err(format!("cannot stall at {other}")),
+                })
+            }
+            other => return Err(format!("unknown argument {other}")),
+        }
+    }
+    Ok(options)
+}
+
+/// What the device is doing, shared by every request.
+struct Roku {
+    name: String,
+    serial_number: String,
+    secs: f64,
+    stall: Option<Stall>,
+    /// The channel in front; the home screen if `None`.
+    app: Option<String>,
+    video: Option<Video>,
+}
+
+struct Video {
+    url: String,
+    /// False until the start of the URL has been fetched.
+    ready: bool,
+    paused: bool,
+    /// Position when `since` was taken.
+    position: f64,
+    since: Instant,
+}
+
+impl Video {
+    fn position(&self, secs: f64) -> f64 {
+        if !self.ready || self.paused {
+            return self.position;
+        }
+        (self.position + self.since.elapsed().as_secs_f64()).min(secs)
+    }
+
+    /// Fix the position where it is now, before pausing or resuming.
+    fn settle(&mut self, secs: f64) {
+        self.position = self.position(secs);
+        self.since = Instant::now();
+    }
+}
+
+impl Roku {
+    /// The video, unless it has played to the end, which closes the player.
+    fn video(&mut self) -> Option<&mut Video> {
+        let secs = self.secs;
+        if self.video.as_ref().is_some_and(|v| v.ready && v.position(secs) >= secs) {
+            println!("Finished {}", self.video.take().map(|v| v.url).unwrap_or_default());
+        }
+        self.video.as_mut()
+    }
+}
+
+type Shared = Arc<Mutex<Roku>>;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = options()?;
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), options.port)).await?;
+    let port = listener.local_addr()?.port();
+    println!("{} ({}) listening on port {port}", options.name, serial_number(port));
+
+    if options.ssdp {
+        let socket = ssdp_socket()?;
+        tokio::spawn(answer_searches(socket, serial_number(port), port));
+    }
+    run(listener, &options).await
+}
+
+/// A serial number told apart by the port.
+fn serial_number(port: u16) -> String {
+    format!("FAKE0000{port:05}")
+}
+
+/// Answer ECP requests that come to `listener`, until it fails.
+pub async fn run(listener: TcpListener, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let roku = Arc::new(Mutex::new(Roku {
+        name: options.name.clone(),
+        serial_number: serial_number(listener.local_addr()?.port()),
+        secs: options.secs,
+        stall: options.stall,
+        app: None,
+        video: None,
+    }));
+    let app = Router::new()
+        .route("/query/device-info", get(device_info))
+        .route("/query/media-player", get(media_player))
+        .route("/query/active-app", get(active_app))
+        .route("/launch/{channel}", post(launch))
+        .route("/input/{channel}", post(input))
+        .route("/keypress/{key}", post(keypress))
+        .with_state(roku);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// The SSDP port, shared with whatever else listens on it here.
+fn ssdp_socket() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 1900)).into())?;
+    socket.join_multicast_v4(&SSDP_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Answer M-SEARCH requests for `roku:ecp` (or everything), as Rokus do.
+async fn answer_searches(socket: UdpSocket, serial_number: String, port: u16) {
+    let ip = local_ip_address::local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    println!("Answering SSDP searches as {ip}");
+    let mut buf = [0u8; 2048];
+    loop {
+        let Ok((len, from)) = socket.recv_from(&mut buf).await else { continue };
+        let request = String::from_utf8_lossy(&buf[..len]);
+        if !request.starts_with("M-SEARCH") {
+            continue;
+        }
+        let target = request
+            .lines()
+            .find_map(|line| line.split_once(':').filter(|(key, _)| key.eq_ignore_ascii_case("ST")))
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default();
+        if target != SEARCH_TARGET && target != "ssdp:all" {
+            continue;
+        }
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Cache-Control: max-age=3600\r\n\
+             ST: {SEARCH_TARGET}\r\n\
+             USN: uuid:roku:ecp:{serial_number}\r\n\
+             Ext: \r\n\
+             Server: Roku/12.0.0 UPnP/1.0 Roku/12.0.0\r\n\
+             LOCATION: http://{ip}:{port}/\r\n\r\n"
+        );
+        if let Err(e) = socket.send_to(response.as_bytes(), from).await {
+            println!("Cannot answer {from}: {e}");
+        }
+    }
+}
+
+fn xml(body: String) -> impl IntoResponse {
+    (
+        [("Content-Type", "text/xml; charset=utf-8")],
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n{body}\n"),
+    )
+}
+
+async fn device_info(State(roku): State<Shared>) -> impl IntoResponse {
+    let roku = roku.lock().unwrap();
+    xml(format!(
+        "<device-info>\n\
+         \t<udn>29380000-0000-1000-8000-{serial}</udn>\n\
+         \t<serial-number>{serial}</serial-number>\n\
+         \t<vendor-name>Roku</vendor-name>\n\
+         \t<model-name>Roku Ultra</model-name>\n\
+         \t<model-number>4800X</model-number>\n\
+         \t<wifi-mac>02:00:00:00:00:01</wifi-mac>\n\
+         \t<ethernet-mac>02:00:00:00:00:02</ethernet-mac>\n\
+         \t<network-type>ethernet</network-type>\n\
+         \t<user-device-name>{name}</user-device-name>\n\
+         \t<default-device-name>Roku Ultra - {serial}</default-device-name>\n\
+         \t<power-mode>PowerOn</power-mode>\n\
+         </device-info>",
+        serial = roku.serial_number,
+        name = roku.name,
+    ))
+}
+
+async fn media_player(State(roku): State<Shared>) -> impl IntoResponse {
+    let mut roku = roku.lock().unwrap();
+    let secs = roku.secs;
+    let body = match roku.video() {
+        Some(video) => {
+            let state = match (video.ready, video.paused) {
+                (false, _) => "startup",
+                (true, false) => "play",
+                (true, true) => "pause",
+            };
+            format!(
+                "<player error=\"false\" state=\"{state}\">\n\
+                 \t<plugin bandwidth=\"10000000 bps\" id=\"{PLAY_ON_ROKU}\" name=\"Play on Roku\"/>\n\
+                 \t<format audio=\"aac\" captions=\"none\" container=\"mp4\" drm=\"none\" video=\"avc\"/>\n\
+                 \t<position>{} ms</position>\n\
+                 \t<duration>{} ms</duration>\n\
+                 \t<is_live>false</is_live>\n\
+                 </player>",
+                (video.position(secs) * 1000.0) as u64,
+                (secs * 1000.0) as u64,
+            )
+        }
+        None => "<player error=\"false\" state=\"close\"/>".into(),
+    };
+    xml(body)
+}
+
+async fn active_app(State(roku): State<Shared>) -> impl IntoResponse {
+    let roku = roku.lock().unwrap();
+    xml(match &roku.app {
+        Some(id) => format!("<active-app>\n\t<app id=\"{id}\" type=\"appl\" version=\"1.0.0\">Play on Roku</app>\n</active-app>"),
+        None => "<active-app>\n\t<app>Roku</app>\n</active-app>".into(),
+    })
+}
+
+async fn launch(
+    State(roku): State<Shared>,
+    Path(channel): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> StatusCode {
+    println!("launch {channel}");
+    if roku.lock().unwrap().stall == Some(Stall::Launch) {
+        return std::future::pending().await;
+    }
+    roku.lock().unwrap().app = Some(channel.clone());
+    play_url(roku, &channel, params)
+}
+
+async fn input(
+    State(roku): State<Shared>,
+    Path(channel): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> StatusCode {
+    println!("input {channel}");
+    if roku.lock().unwrap().app.as_deref() != Some(channel.as_str()) {
+        println!("input to {channel}, which is not running");
+        return StatusCode::OK;
+    }
+    play_url(roku, &channel, params)
+}
+
+/// Start the video Play on Roku was handed, loading it in the background as
+/// the real thing does.
+fn play_url(roku: Shared, channel: &str, params: HashMap<String, String>) -> StatusCode {
+    if channel != PLAY_ON_ROKU {
+        return StatusCode::OK;
+    }
+    let Some(url) = params.get("u").cloned() else {
+        return StatusCode::OK;
+    };
+    println!(
+        "play {url} ({}, {})",
+        params.get("videoName").map_or("untitled", String::as_str),
+        params.get("videoFormat").map_or("no format", String::as_str),
+    );
+    let stall = {
+        let mut roku = roku.lock().unwrap();
+        roku.video = Some(Video {
+            url: url.clone(),
+            ready: false,
+            paused: false,
+            position: 0.0,
+            since: Instant::now(),
+        });
+        roku.stall
+    };
+    if stall == Some(Stall::Fetch) {
+        return StatusCode::OK;
+    }
+    tokio::spawn(async move {
+        let fetched = fetch(&url).await;
+        let mut roku = roku.lock().unwrap();
+        match (fetched, roku.video.as_mut().filter(|v| v.url == url)) {
+            (Ok(()), Some(_)) if stall == Some(Stall::Startup) => println!("Fetched {url}, staying in startup"),
+            (Ok(()), Some(video)) => {
+                println!("Loaded {url}");
+                video.ready = true;
+                video.since = Instant::now();
+            }
+            (Err(e), Some(_)) => {
+                println!("Cannot fetch {url}: {e}");
+                roku.video = None;
+            }
+            (_, None) => {}
+        }
+    });
+    StatusCode::OK
+}
+
+async fn keypress(State(roku): State<Shared>, Path(key): Path<String>) -> StatusCode {
+    println!("keypress {key}");
+    let mut roku = roku.lock().unwrap();
+    let secs = roku.secs;
+    match key.as_str() {
+        "Play" => {
+            if let Some(video) = roku.video().filter(|v| v.ready) {
+                video.settle(secs);
+                video.paused = !video.paused;
+            }
+        }
+        "Back" => roku.video = None,
+        "Home" => {
+            roku.video = None;
+            roku.app = None;
+        }
+        _ => {}
+    }
+    StatusCode::OK
+}
+
+/// Read the first bytes of `url`, as a real player would to start playing.
+async fn fetch(url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let request = http02::Request::get(url)
+        .header("Range", "bytes=0-1023")
+        .body(hyper014::Body::empty())?;
+    let response = hyper014::Client::new().request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()).into());
+    }
+    hyper014::body::to_bytes(response.into_body()).await?;
+    Ok(())
+}
@@ -50,6 +50,12 @@ class S {
   String get noFile => _isZh ? '无文件' : 'No file';
   String castingTo(String device) => _isZh ? '投屏到 $device' : 'Casting to $device';
   String get noDevice => _isZh ? '无设备' : 'No device';
+  String takenOver(String media) => _isZh
+      ? '其他应用已接管电视：$media'
+      : 'Another app took over the TV: $media';
+  String recovering(int attempt) => _isZh
+      ? '电视中断了播放，正在恢复（第 $attempt 次）...'
+      : 'TV dropped playback, recovering (attempt $attempt)...';
 
   // ---- Playback State Labels ----
   String playbackStateLabel(String state) {
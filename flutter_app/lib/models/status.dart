@@ -8,6 +8,28 @@ class PlaybackStatus {
   final String fileName;
   final String deviceName;
 
+  /// Controls the renderer allows right now (play, pause, stop, seek, ...).
+  /// Empty until the backend has heard from the renderer.
+  final List<String> allowedActions;
+
+  /// True when another app has taken over the TV.
+  final bool takenOver;
+
+  /// Title (or URI) of what the renderer reports as loaded.
+  final String rendererMedia;
+
+  /// Step of the cast being confirmed (e.g. "waiting_for_request"), if any.
+  final String? castStep;
+
+  /// Why the last cast failed, if it did.
+  final String? castError;
+
+  /// Attempt number while resuming after the TV dropped playback.
+  final int? recovering;
+
+  /// Times playback was resumed after a drop.
+  final int recoveries;
+
   PlaybackStatus({
     required this.playbackState,
     required this.elapsedSecs,
@@ -17,8 +39,23 @@ class PlaybackStatus {
     required this.progress,
     required this.fileName,
     required this.deviceName,
+    this.allowedActions = const [],
+    this.takenOver = false,
+    this.rendererMedia = '',
+    this.castStep,
+    this.castError,
+    this.recovering,
+    this.recoveries = 0,
   });
 
+  bool allows(String action) =>
+      allowedActions.isEmpty || allowedActions.contains(action);
+
+  bool get canPlay => allows('play');
+  bool get canPause => allows('pause');
+  bool get canStop => allows('stop');
+  bool get canSeek => allows('seek');
+
   factory PlaybackStatus.fromJson(Map<String, dynamic> json) {
     return PlaybackStatus(
       playbackState: json['playback_state'] as String? ?? 'Stopped',
@@ -29,9 +66,23 @@ class PlaybackStatus {
       progress: (json['progress'] as num?)?.toDouble() ?? 0.0,
       fileName: json['file_name'] as String? ?? '',
       deviceName: json['device_name'] as String? ?? '',
+      allowedActions: (json['allowed_actions'] as List?)?.cast<String>() ?? const [],
+      takenOver: json['taken_over'] as bool? ?? false,
+      rendererMedia: _rendererMedia(json['media_info'] as Map<String, dynamic>?),
+      castStep: json['cast_step'] as String?,
+      castError: json['cast_error'] as String?,
+      recovering: json['recovering'] as int?,
+      recoveries: json['recoveries'] as int? ?? 0,
     );
   }
 
+  static String _rendererMedia(Map<String, dynamic>? info) {
+    if (info == null) return '';
+    final title = info['title'] as String?;
+    if (title != null && title.isNotEmpty) return title;
+    return info['current_uri'] as String? ?? '';
+  }
+
   static PlaybackStatus empty() {
     return PlaybackStatus(
       playbackState: 'Stopped',
@@ -65,12 +65,27 @@ class _PlaybackScreenState extends State<PlaybackScreen> {
             ),
             const SizedBox(height: 8),
             _buildStateChip(context, status.playbackState, s),
+            if (status.takenOver) ...[
+              const SizedBox(height: 8),
+              Text(
+                s.takenOver(status.rendererMedia),
+                style: TextStyle(color: Theme.of(context).colorScheme.error),
+                textAlign: TextAlign.center,
+              ),
+            ],
+            if (status.recovering != null) ...[
+              const SizedBox(height: 8),
+              Text(
+                s.recovering(status.recovering!),
+                textAlign: TextAlign.center,
+              ),
+            ],
             const Spacer(flex: 1),
             // Progress bar (click to seek, hover to preview time)
             Column(
               children: [
                 MouseRegion(
-                  cursor: status.durationSecs > 0
+                  cursor: status.durationSecs > 0 && status.canSeek
                       ? SystemMouseCursors.click
                       : SystemMouseCursors.basic,
                   onEnter: (_) => setState(() => _hovering = true),
@@ -88,7 +103,7 @@ class _PlaybackScreenState extends State<PlaybackScreen> {
                   },
                   child: GestureDetector(
                     behavior: HitTestBehavior.opaque,
-                    onTapDown: status.durationSecs > 0
+                    onTapDown: status.durationSecs > 0 && status.canSeek
                         ? (details) => _seekFromDetails(details, playback)
                         : null,
                     child: SizedBox(
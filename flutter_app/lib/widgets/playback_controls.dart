@@ -10,6 +10,8 @@ class PlaybackControls extends StatelessWidget {
   @override
   Widget build(BuildContext context) {
     final s = S.of(context);
+    final status = playback.status;
+    final canToggle = playback.isPlaying ? status.canPause : status.canPlay;
 
     return Column(
       children: [
@@ -22,7 +24,7 @@ class PlaybackControls extends StatelessWidget {
               icon: const Icon(Icons.fast_rewind),
               iconSize: 32,
               tooltip: s.seekBackward5Min,
-              onPressed: () => playback.seekRelative(-300),
+              onPressed: status.canSeek ? () => playback.seekRelative(-300) : null,
             ),
             const SizedBox(width: 8),
             // Seek backward 30s
@@ -30,7 +32,7 @@ class PlaybackControls extends StatelessWidget {
               icon: const Icon(Icons.replay_30),
               iconSize: 36,
               tooltip: s.seekBackward30s,
-              onPressed: () => playback.seekRelative(-30),
+              onPressed: status.canSeek ? () => playback.seekRelative(-30) : null,
             ),
             const SizedBox(width: 16),
             // Play / Pause
@@ -39,7 +41,7 @@ class PlaybackControls extends StatelessWidget {
                 shape: const CircleBorder(),
                 padding: const EdgeInsets.all(16),
               ),
-              onPressed: () => playback.togglePlayPause(),
+              onPressed: canToggle ? () => playback.togglePlayPause() : null,
               child: Icon(
                 playback.isPlaying ? Icons.pause : Icons.play_arrow,
                 size: 36,
@@ -51,7 +53,7 @@ class PlaybackControls extends StatelessWidget {
               icon: const Icon(Icons.forward_30),
               iconSize: 36,
               tooltip: s.seekForward30s,
-              onPressed: () => playback.seekRelative(30),
+              onPressed: status.canSeek ? () => playback.seekRelative(30) : null,
             ),
             const SizedBox(width: 8),
             // Seek forward 5 min
@@ -59,14 +61,14 @@ class PlaybackControls extends StatelessWidget {
               icon: const Icon(Icons.fast_forward),
               iconSize: 32,
               tooltip: s.seekForward5Min,
-              onPressed: () => playback.seekRelative(300),
+              onPressed: status.canSeek ? () => playback.seekRelative(300) : null,
             ),
           ],
         ),
         const SizedBox(height: 16),
         // Stop button
         OutlinedButton.icon(
-          onPressed: () => playback.stop(),
+          onPressed: status.canStop ? () => playback.stop() : null,
           icon: const Icon(Icons.stop),
           label: Text(s.stop),
         ),
@@ -0,0 +1,140 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use hyper014::body::Bytes;
+use hyper014::client::HttpConnector;
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
+
+use super::types::{PlaybackInfo, ServerInfo};
+use crate::error::AppError;
+
+/// What we say we are; receivers expect an Apple sender.
+const USER_AGENT: &str = "MediaControl/1.0";
+
+/// HTTP requests to one AirPlay device.
+///
+/// An Apple TV stops the video when the connection that sent `/play`
+/// closes, so requests take turns on a single kept-alive connection, and
+/// all carry the same session ID.
+pub struct AirPlayClient {
+    http: hyper014::Client<HttpConnector>,
+    base_url: String,
+    session_id: String,
+    request_timeout: Duration,
+    /// Held for the length of a request, so no second connection opens.
+    turn: Mutex<()>,
+}
+
+impl AirPlayClient {
+    /// A client for the device at `base_url`, e.g. `http://10.0.0.7:7000`.
+    pub fn new(base_url: String, connect_timeout: Duration, request_timeout: Duration) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(connect_timeout));
+        connector.set_keepalive(Some(Duration::from_secs(60)));
+        connector.set_nodelay(true);
+        let http = hyper014::Client::builder()
+            .pool_idle_timeout(None)
+            .pool_max_idle_per_host(1)
+            .build(connector);
+        Self {
+            http,
+            base_url,
+            session_id: session_id(),
+            request_timeout,
+            turn: Mutex::new(()),
+        }
+    }
+
+    pub async fn server_info(&self) -> Result<ServerInfo, AppError> {
+        let body = self.request("server-info", "GET", "/server-info", None).await?;
+        parse_plist("server-info", &body)
+    }
+
+    /// Start playing `url` at `start_position`, a fraction of its duration.
+    pub async fn play(&self, url: &str, start_position: f64) -> Result<(), AppError> {
+        let body = format!("Content-Location: {url}\nStart-Position: {start_position:.6}\n");
+        self.request("play", "POST", "/play", Some(body)).await?;
+        Ok(())
+    }
+
+    /// Set the playback rate: 1 plays, 0 pauses.
+    pub async fn rate(&self, rate: f64) -> Result<(), AppError> {
+        self.request("rate", "POST", &format!("/rate?value={rate:.6}"), None).await?;
+        Ok(())
+    }
+
+    /// Jump to `position` seconds.
+    pub async fn scrub(&self, position: f64) -> Result<(), AppError> {
+        self.request("scrub", "POST", &format!("/scrub?position={position:.6}"), None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<(), AppError> {
+        self.request("stop", "POST", "/stop", None).await?;
+        Ok(())
+    }
+
+    pub async fn playback_info(&self) -> Result<PlaybackInfo, AppError> {
+        let body = self.request("playback-info", "GET", "/playback-info", None).await?;
+        if body.is_empty() {
+            return Ok(PlaybackInfo::default());
+        }
+        parse_plist("playback-info", &body)
+    }
+
+    /// Send a request, with `body` as `text/parameters` if given, and return
+    /// the reply body.
+    async fn request(&self, what: &str, method: &str, path: &str, body: Option<String>) -> Result<Bytes, AppError> {
+        let mut request = http02::Request::builder()
+            .method(method)
+            .uri(format!("{}{path}", self.base_url))
+            .header("User-Agent", USER_AGENT)
+            .header("X-Apple-Session-ID", &self.session_id);
+        if body.is_some() {
+            request = request.header("Content-Type", "text/parameters");
+        }
+        let request = request
+            .body(body.map(hyper014::Body::from).unwrap_or_else(hyper014::Body::empty))
+            .map_err(|e| AppError::AirPlayAction(format!("Failed to build {what} request: {e}")))?;
+
+        let _turn = self.turn.lock().await;
+        let exchange = async {
+            let response = self
+                .http
+                .request(request)
+                .await
+                .map_err(|e| AppError::NetworkError(format!("AirPlay {what}: {e}")))?;
+            let status = response.status();
+            let body = hyper014::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| AppError::NetworkError(format!("AirPlay {what}: {e}")))?;
+            Ok::<_, AppError>((status, body))
+        };
+        let (status, body) = tokio::time::timeout(self.request_timeout, exchange)
+            .await
+            .map_err(|_| AppError::Timeout(format!("AirPlay {what} after {}s", self.request_timeout.as_secs_f32())))??;
+        match status.as_u16() {
+            200..=299 => Ok(body),
+            401 | 403 | 470 => Err(AppError::AirPlayAction(format!(
+                "{what} refused with HTTP {status}: the device wants a PIN or password; allow everyone on the network to AirPlay to it"
+            ))),
+            _ => Err(AppError::AirPlayAction(format!("{what} refused with HTTP {status}"))),
+        }
+    }
+}
+
+/// Read a property list reply, XML or binary.
+fn parse_plist<T: DeserializeOwned>(what: &str, body: &[u8]) -> Result<T, AppError> {
+    plist::from_bytes(body).map_err(|e| AppError::AirPlayAction(format!("Unreadable {what} reply: {e}")))
+}
+
+/// A random UUID naming our session with the device.
+fn session_id() -> String {
+    let random = || RandomState::new().hash_one(std::time::SystemTime::now());
+    let bits = u128::from(random()) << 64 | u128::from(random());
+    let hex = format!("{bits:032X}");
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
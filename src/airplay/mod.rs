@@ -0,0 +1,54 @@
+//! AirPlay video (Apple TV): the legacy HTTP protocol, where the device is
+//! handed a media URL with `/play` and driven with `/rate`, `/scrub` and
+//! `/stop`.
+
+#[doc(hidden)]
+pub mod client;
+#[doc(hidden)]
+pub mod types;
+
+
+use crate::error::AppError;
+use crate::net::Host;
+use crate::renderer::DeviceUrl;
+
+/// Port the AirPlay server listens on.
+pub const DEFAULT_PORT: u16 = 7000;
+
+/// mDNS service type AirPlay receivers advertise.
+pub const SERVICE_TYPE: &str = "_airplay._tcp.local.";
+
+/// `features` bit of receivers that play video. Speakers advertise
+/// AirPlay too, without it.
+pub const FEATURE_VIDEO: u64 = 1;
+
+/// URL an AirPlay device is listed with, e.g. `airplay://192.168.1.30:7000`.
+pub fn device_url(host: &Host, port: u16) -> String {
+    format!("airplay://{host}:{port}")
+}
+
+/// Where to send HTTP requests for a device listed with `device_url`,
+/// e.g. `http://192.168.1.30:7000`.
+pub fn base_url(device_url: &DeviceUrl) -> Result<String, AppError> {
+    let host: Host = device_url
+        .host()
+        .ok_or_else(|| AppError::NetworkError(format!("{device_url} has no host")))?
+        .parse()
+        .map_err(AppError::NetworkError)?;
+    Ok(format!("http://{host}:{}", device_url.port().unwrap_or(DEFAULT_PORT)))
+}
+
+/// A device's unique name from its `deviceid`, a MAC address.
+pub fn udn(device_id: &str) -> String {
+    format!("airplay-{}", device_id.replace([':', '-'], "").to_lowercase())
+}
+
+/// The `features` a device advertises: one hex number, or two 32-bit
+/// halves separated by a comma (low half first).
+pub fn parse_features(features: &str) -> Option<u64> {
+    let hex = |s: &str| u64::from_str_radix(s.trim().trim_start_matches("0x").trim_start_matches("0X"), 16).ok();
+    match features.split_once(',') {
+        Some((low, high)) => Some(hex(low)? | hex(high)? << 32),
+        None => hex(features),
+    }
+}
@@ -0,0 +1,58 @@
+use serde::Deserialize;
+
+use crate::dlna::types::PlaybackState;
+
+/// The reply to `GET /server-info`: what the device is.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerInfo {
+    /// A MAC address of the device, e.g. `58:55:CA:1A:E2:88`.
+    #[serde(rename = "deviceid")]
+    pub device_id: Option<String>,
+    /// Hardware model, e.g. `AppleTV5,3`.
+    pub model: Option<String>,
+    /// A bit field; see [`FEATURE_VIDEO`](super::FEATURE_VIDEO).
+    pub features: Option<u64>,
+    /// The name set on the device; older versions leave it out.
+    pub name: Option<String>,
+}
+
+/// The reply to `GET /playback-info`. While nothing is playing, fields are
+/// missing or zero, and `readyToPlay` is false.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackInfo {
+    /// In seconds; 0 until the device knows it.
+    pub duration: Option<f64>,
+    pub position: Option<f64>,
+    /// 1 while playing, 0 while paused.
+    pub rate: Option<f64>,
+    pub ready_to_play: Option<bool>,
+    pub playback_buffer_empty: Option<bool>,
+}
+
+impl PlaybackInfo {
+    /// Whether a video is loaded and ready. A video still loading looks
+    /// just like no video at all.
+    pub fn is_loaded(&self) -> bool {
+        self.duration.is_some_and(|d| d > 0.0) || self.ready_to_play == Some(true)
+    }
+
+    /// The state of a loaded video.
+    pub fn playback_state(&self) -> PlaybackState {
+        let rate = self.rate.unwrap_or_default();
+        match self.playback_buffer_empty {
+            Some(true) if rate > 0.0 => PlaybackState::Transitioning,
+            _ if rate > 0.0 => PlaybackState::Playing,
+            _ => PlaybackState::Paused,
+        }
+    }
+
+    pub fn elapsed_secs(&self) -> u64 {
+        self.position.unwrap_or_default().max(0.0) as u64
+    }
+
+    pub fn duration_secs(&self) -> u64 {
+        self.duration.unwrap_or_default().max(0.0) as u64
+    }
+}
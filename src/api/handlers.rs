@@ -1,26 +1,20 @@
-use std::net::{SocketAddr, UdpSocket};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
 
-use axum::extract::State;
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use tokio::sync::Mutex;
 
-use crate::api::state::ApiState;
+use crate::api::state::{self, ApiState};
 use crate::api::types::*;
-use crate::discovery;
-use crate::dlna::transport;
-use crate::dlna::types::PlaybackState;
-use crate::server;
+use localcast::dlna::types::PlayMode;
+use localcast::net::{self, Host};
+use localcast::session::{DeviceRef, SessionCommand, SessionError, SessionState};
 
-const SUPPORTED_EXTENSIONS: &[&str] = &["mp4", "mkv", "avi", "webm"];
+type SharedState = Arc<ApiState>;
 
-type SharedState = Arc<Mutex<ApiState>>;
-
-fn err(status: StatusCode, msg: impl Into<String>) -> (StatusCode, Json<ErrorResponse>) {
+pub(super) fn err(status: StatusCode, msg: impl Into<String>) -> (StatusCode, Json<ErrorResponse>) {
     (
         status,
         Json(ErrorResponse {
@@ -29,274 +23,162 @@ fn err(status: StatusCode, msg: impl Into<String>) -> (StatusCode, Json<ErrorRes
     )
 }
 
+/// Map a failed session request to an HTTP response.
+fn error_response(e: SessionError) -> Response {
+    match e {
+        SessionError::Invalid(message) => err(StatusCode::BAD_REQUEST, message).into_response(),
+        SessionError::NotAllowed(message) => err(StatusCode::CONFLICT, message).into_response(),
+        SessionError::Cast(failure) => (
+            StatusCode::BAD_GATEWAY,
+            Json(CastErrorResponse {
+                error: failure.to_string(),
+                failed_step: failure.step.id().to_string(),
+            }),
+        )
+            .into_response(),
+        SessionError::Cancelled { step } => (
+            StatusCode::CONFLICT,
+            Json(CastErrorResponse {
+                error: "Cast cancelled".into(),
+                failed_step: step.map(|s| s.id()).unwrap_or_default().to_string(),
+            }),
+        )
+            .into_response(),
+        SessionError::Timeout(message) => err(StatusCode::GATEWAY_TIMEOUT, message).into_response(),
+        e @ SessionError::Closed => err(StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+        e => err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Send `command` to the session and render the resulting state with `respond`.
+async fn request<T: serde::Serialize>(
+    state: &SharedState,
+    command: SessionCommand,
+    respond: impl FnOnce(&SessionState) -> T,
+) -> Response {
+    match state.session.request(command).await {
+        Ok(s) => (StatusCode::OK, Json(respond(&s))).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
 /// POST /api/select-file
-/// Validates file, starts media HTTP server, stores file info.
+/// Validates file, registers it with the media server and makes it the only queue item.
 pub async fn select_file(
     State(state): State<SharedState>,
     Json(req): Json<SelectFileRequest>,
 ) -> impl IntoResponse {
-    let path = PathBuf::from(&req.file_path);
-
-    // Validate file exists
-    let path = match path.canonicalize() {
-        Ok(p) => p,
-        Err(_) => return err(StatusCode::BAD_REQUEST, "File not found").into_response(),
-    };
-
-    if !path.is_file() {
-        return err(StatusCode::BAD_REQUEST, "Not a file").into_response();
-    }
-
-    // Validate extension
-    let ext = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .map(|e| e.to_lowercase())
-        .unwrap_or_default();
-
-    if !SUPPORTED_EXTENSIONS.contains(&ext.as_str()) {
-        return err(
-            StatusCode::BAD_REQUEST,
-            format!(
-                "Unsupported file type: .{}. Supported: {}",
-                ext,
-                SUPPORTED_EXTENSIONS.join(", ")
-            ),
-        )
-        .into_response();
-    }
-
-    let file_name = path
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-
-    let file_size = match std::fs::metadata(&path) {
-        Ok(m) => m.len(),
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Cannot read file: {e}")).into_response(),
-    };
-
-    let mime_type = mime_guess::from_path(&path)
-        .first_or_octet_stream()
-        .to_string();
-
-    // Stop previous media server if running
-    {
-        let mut s = state.lock().await;
-        if let Some(handle) = s.media_server_handle.take() {
-            handle.abort();
+    let command = SessionCommand::SetFiles(vec![PathBuf::from(req.file_path)]);
+    request(&state, command, |s| {
+        let item = s.queue().current();
+        FileInfoResponse {
+            file_name: item.map(|i| i.file_name.clone()).unwrap_or_default(),
+            file_size: item.map(|i| i.file_size).unwrap_or_default(),
+            mime_type: item.map(|i| i.mime_type.clone()).unwrap_or_default(),
         }
-    }
-
-    // Start media server
-    let (addr, serve_path, server_handle) = match server::start_server(path.clone(), 0).await {
-        Ok(v) => v,
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start media server: {e}")).into_response(),
-    };
-
-    let mut s = state.lock().await;
-    s.file_path = Some(path.to_string_lossy().to_string());
-    s.file_name = Some(file_name.clone());
-    s.file_size = file_size;
-    s.mime_type = Some(mime_type.clone());
-    s.media_server_handle = Some(server_handle);
-    s.serve_path = Some(serve_path);
-    s.server_port = addr.port();
-
-    (
-        StatusCode::OK,
-        Json(FileInfoResponse {
-            file_name,
-            file_size,
-            mime_type,
-        }),
-    )
-        .into_response()
+    })
+    .await
 }
 
-/// GET /api/discover
-/// Runs SSDP discovery and returns device list.
-pub async fn discover(State(state): State<SharedState>) -> impl IntoResponse {
-    let devices = match discovery::discover_devices(Duration::from_secs(5)).await {
-        Ok(d) => d,
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Discovery failed: {e}")).into_response(),
+/// GET /api/discover?timeout=<secs>
+/// Runs discovery and returns device list.
+pub async fn discover(
+    State(state): State<SharedState>,
+    Query(query): Query<DiscoverQuery>,
+) -> Response {
+    let command = match discovery_timeout(query.timeout) {
+        Ok(Some(timeout)) => SessionCommand::DiscoverFor(timeout),
+        Ok(None) => SessionCommand::Discover,
+        Err(message) => return err(StatusCode::BAD_REQUEST, message).into_response(),
     };
-
-    let response_devices: Vec<DeviceResponse> = devices
-        .iter()
-        .enumerate()
-        .map(|(i, d)| DeviceResponse {
-            index: i,
-            friendly_name: d.friendly_name.clone(),
-            device_url: d.device_url.to_string(),
-        })
-        .collect();
-
-    let mut s = state.lock().await;
-    s.devices = devices;
-    s.selected_device = None;
-    s.control_url = None;
-
-    (StatusCode::OK, Json(DeviceListResponse { devices: response_devices })).into_response()
+    request(&state, command, state::device_list_response).await
 }
 
 /// POST /api/select-device
-/// Stores selected device and resolves control URL.
+/// Resolves the device's control URL and reads its capabilities.
 pub async fn select_device(
     State(state): State<SharedState>,
     Json(req): Json<SelectDeviceRequest>,
 ) -> impl IntoResponse {
-    let device = {
-        let s = state.lock().await;
-        if req.device_index >= s.devices.len() {
-            return err(StatusCode::BAD_REQUEST, "Invalid device index").into_response();
-        }
-        s.devices[req.device_index].clone()
-    };
+    // Navigating to another device abandons a cast still being confirmed
+    state.session.cancel();
+    request(&state, SessionCommand::SelectDevice(req.device_index), |_| OkResponse::new()).await
+}
 
-    // Resolve control URL
-    let control_url = match transport::resolve_control_url(&device).await {
-        Ok(url) => url,
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resolve control URL: {e}")).into_response(),
+/// POST /api/devices/manual
+/// Adds a renderer by description URL, `cast://`, `airplay://`, `kodi://` or
+/// `roku://` URL or IP address and returns it as listed, for networks where discovery finds
+/// nothing. A device that is added but can't be matched in the list is a 500.
+pub async fn add_device(
+    State(state): State<SharedState>,
+    Json(req): Json<AddDeviceRequest>,
+) -> Response {
+    let address = match (req.url, req.ip) {
+        (Some(address), None) | (None, Some(address)) => address,
+        _ => return err(StatusCode::BAD_REQUEST, "give either url or ip").into_response(),
     };
-
-    let mut s = state.lock().await;
-    s.selected_device = Some(req.device_index);
-    s.control_url = Some(control_url);
-
-    (StatusCode::OK, Json(OkResponse::new())).into_response()
+    let wanted_ip = net::parse_host_port(&address).ok().map(|(host, _)| host.ip);
+    let s = match state.session.request(SessionCommand::AddDevice(address.clone())).await {
+        Ok(s) => s,
+        Err(e) => return error_response(e),
+    };
+    let added = s.devices.iter().position(|d| {
+        let host_ip = d.device_url.host().and_then(|h| h.parse::<Host>().ok()).map(|h| h.ip);
+        let url = d.device_url.to_string();
+        url.trim_end_matches('/') == address.trim_end_matches('/') || (wanted_ip.is_some() && host_ip == wanted_ip)
+    });
+    match added {
+        Some(index) => (StatusCode::OK, Json(state::device_list_response(&s).devices.swap_remove(index))).into_response(),
+        None => err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("the device at {address} was added but is not in the device list"),
+        )
+        .into_response(),
+    }
 }
 
 /// POST /api/cast
-/// Sets AV transport URI + Play, starts status poller.
+/// Casts the current queue item, confirming each step until the renderer
+/// reports PLAYING, then starts the status poller. Progress is broadcast over
+/// SSE as `cast_step`; a failure returns 502 with the step that failed.
 pub async fn cast(State(state): State<SharedState>) -> impl IntoResponse {
-    let (device, control_url, serve_path, server_port, file_name, mime_type, file_size) = {
-        let s = state.lock().await;
-        let device = match s.current_device().cloned() {
-            Some(d) => d,
-            None => return err(StatusCode::BAD_REQUEST, "No device selected").into_response(),
-        };
-        let control_url = match &s.control_url {
-            Some(u) => u.clone(),
-            None => return err(StatusCode::BAD_REQUEST, "No control URL resolved").into_response(),
-        };
-        let serve_path = match &s.serve_path {
-            Some(p) => p.clone(),
-            None => return err(StatusCode::BAD_REQUEST, "No file selected").into_response(),
-        };
-        let file_name = s.file_name.clone().unwrap_or_default();
-        let mime_type = s.mime_type.clone().unwrap_or_default();
-        (device, control_url, serve_path, s.server_port, file_name, mime_type, s.file_size)
-    };
+    request(&state, SessionCommand::Cast { device: None }, |_| OkResponse::new()).await
+}
 
-    // Build media URL based on device IP
-    let media_url = match media_url_for_device(&device, server_port, &serve_path) {
-        Ok(u) => u,
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Cannot determine media URL: {e}")).into_response(),
+/// POST /api/cast/wake
+/// Wakes the device from standby with Wake-on-LAN, waits until it answers,
+/// then casts as `/api/cast` does. The wait shows over SSE as the `waking`
+/// cast step.
+pub async fn wake_and_cast(
+    State(state): State<SharedState>,
+    Json(req): Json<WakeAndCastRequest>,
+) -> impl IntoResponse {
+    let command = SessionCommand::WakeAndCast {
+        device: req.udn.map(DeviceRef::Udn).or(req.device_index.map(DeviceRef::Index)),
     };
+    request(&state, command, |_| OkResponse::new()).await
+}
 
-    // Set URI
-    if let Err(e) = transport::set_av_transport_uri(
-        &device,
-        &control_url,
-        &media_url,
-        &file_name,
-        &mime_type,
-        file_size,
-    )
-    .await
-    {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, format!("SetAVTransportURI failed: {e}")).into_response();
-    }
-
-    // Play
-    if let Err(e) = transport::play(&device, &control_url).await {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Play failed: {e}")).into_response();
-    }
-
-    // Update state and start poller
-    {
-        let mut s = state.lock().await;
-        s.playback_state = PlaybackState::Playing;
-
-        // Stop previous poller
-        if let Some(handle) = s.poller_handle.take() {
-            handle.abort();
-        }
-
-        // Start poller
-        let poller_device = device.clone();
-        let poller_control_url = control_url.clone();
-        let poller_state = state.clone();
-        let handle = tokio::spawn(async move {
-            playback_poller(poller_device, poller_control_url, poller_state).await;
-        });
-        s.poller_handle = Some(handle);
-    }
-
-    (StatusCode::OK, Json(OkResponse::new())).into_response()
+/// POST /api/cast/cancel
+/// Abandons a cast that is still being confirmed.
+pub async fn cancel_cast(State(state): State<SharedState>) -> impl IntoResponse {
+    state.session.cancel();
+    (StatusCode::OK, Json(OkResponse::new()))
 }
 
 /// POST /api/play
 pub async fn play(State(state): State<SharedState>) -> impl IntoResponse {
-    let (device, control_url) = {
-        let s = state.lock().await;
-        match (s.current_device().cloned(), s.control_url.clone()) {
-            (Some(d), Some(u)) => (d, u),
-            _ => return err(StatusCode::BAD_REQUEST, "No device selected").into_response(),
-        }
-    };
-
-    if let Err(e) = transport::play(&device, &control_url).await {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Play failed: {e}")).into_response();
-    }
-
-    state.lock().await.playback_state = PlaybackState::Playing;
-    (StatusCode::OK, Json(OkResponse::new())).into_response()
+    request(&state, SessionCommand::Play, |_| OkResponse::new()).await
 }
 
 /// POST /api/pause
 pub async fn pause(State(state): State<SharedState>) -> impl IntoResponse {
-    let (device, control_url) = {
-        let s = state.lock().await;
-        match (s.current_device().cloned(), s.control_url.clone()) {
-            (Some(d), Some(u)) => (d, u),
-            _ => return err(StatusCode::BAD_REQUEST, "No device selected").into_response(),
-        }
-    };
-
-    if let Err(e) = transport::pause(&device, &control_url).await {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Pause failed: {e}")).into_response();
-    }
-
-    state.lock().await.playback_state = PlaybackState::Paused;
-    (StatusCode::OK, Json(OkResponse::new())).into_response()
+    request(&state, SessionCommand::Pause, |_| OkResponse::new()).await
 }
 
 /// POST /api/stop
 pub async fn stop(State(state): State<SharedState>) -> impl IntoResponse {
-    let (device, control_url) = {
-        let s = state.lock().await;
-        match (s.current_device().cloned(), s.control_url.clone()) {
-            (Some(d), Some(u)) => (d, u),
-            _ => return err(StatusCode::BAD_REQUEST, "No device selected").into_response(),
-        }
-    };
-
-    if let Err(e) = transport::stop(&device, &control_url).await {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Stop failed: {e}")).into_response();
-    }
-
-    let mut s = state.lock().await;
-    s.playback_state = PlaybackState::Stopped;
-
-    // Stop poller
-    if let Some(handle) = s.poller_handle.take() {
-        handle.abort();
-    }
-
-    (StatusCode::OK, Json(OkResponse::new())).into_response()
+    request(&state, SessionCommand::Stop, |_| OkResponse::new()).await
 }
 
 /// POST /api/seek
@@ -304,81 +186,94 @@ pub async fn seek(
     State(state): State<SharedState>,
     Json(req): Json<SeekRequest>,
 ) -> impl IntoResponse {
-    let (device, control_url) = {
-        let s = state.lock().await;
-        match (s.current_device().cloned(), s.control_url.clone()) {
-            (Some(d), Some(u)) => (d, u),
-            _ => return err(StatusCode::BAD_REQUEST, "No device selected").into_response(),
-        }
-    };
-
-    if let Err(e) = transport::seek(&device, &control_url, req.position_secs).await {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Seek failed: {e}")).into_response();
-    }
-
-    (StatusCode::OK, Json(OkResponse::new())).into_response()
+    request(&state, SessionCommand::Seek(req.position_secs), |_| OkResponse::new()).await
 }
 
 /// GET /api/status
 pub async fn status(State(state): State<SharedState>) -> impl IntoResponse {
-    let s = state.lock().await;
-    (StatusCode::OK, Json(s.status_response()))
+    (StatusCode::OK, Json(state::status_response(&state.session.state())))
 }
 
-// --- Helpers ---
+/// GET /api/transport-settings
+pub async fn transport_settings(State(state): State<SharedState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(state::transport_settings_response(&state.session.state())))
+}
 
-fn local_ip_for(target: &str) -> anyhow::Result<std::net::IpAddr> {
-    let target_addr: SocketAddr = if target.contains(':') {
-        target.parse()?
-    } else {
-        format!("{target}:80").parse()?
+/// POST /api/play-mode
+/// Sets repeat/shuffle. Modes the renderer can't apply itself are emulated by the queue.
+pub async fn set_play_mode(
+    State(state): State<SharedState>,
+    Json(req): Json<PlayModeRequest>,
+) -> impl IntoResponse {
+    let Some(mode) = PlayMode::from_upnp(&req.play_mode) else {
+        return err(StatusCode::BAD_REQUEST, format!("Unknown play mode: {}", req.play_mode)).into_response();
     };
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    socket.connect(target_addr)?;
-    Ok(socket.local_addr()?.ip())
+    request(&state, SessionCommand::SetPlayMode(mode), state::transport_settings_response).await
 }
 
-fn media_url_for_device(
-    device: &crate::dlna::types::DlnaDevice,
-    server_port: u16,
-    serve_path: &str,
-) -> anyhow::Result<String> {
-    let device_host = device
-        .device_url
-        .host()
-        .ok_or_else(|| anyhow::anyhow!("Device URL has no host"))?;
-    let local_ip = local_ip_for(device_host)?;
-    Ok(format!("http://{}:{}{}", local_ip, server_port, serve_path))
+/// POST /api/speed
+pub async fn set_speed(
+    State(state): State<SharedState>,
+    Json(req): Json<SpeedRequest>,
+) -> impl IntoResponse {
+    request(&state, SessionCommand::SetSpeed(req.speed), state::transport_settings_response).await
 }
 
-/// Background poller that queries the device for position/state and updates ApiState + broadcasts via SSE.
-async fn playback_poller(
-    device: crate::dlna::types::DlnaDevice,
-    control_url: String,
-    state: SharedState,
-) {
-    let mut interval = tokio::time::interval(Duration::from_secs(1));
-    loop {
-        interval.tick().await;
+/// GET /api/queue
+pub async fn get_queue(State(state): State<SharedState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(state::queue_response(&state.session.state())))
+}
 
-        // Get position info
-        if let Ok(pos) = transport::get_position_info(&device, &control_url).await {
-            let mut s = state.lock().await;
-            s.position = pos;
-        }
+/// POST /api/queue
+/// Appends a file to the queue.
+pub async fn add_to_queue(
+    State(state): State<SharedState>,
+    Json(req): Json<AddToQueueRequest>,
+) -> impl IntoResponse {
+    let command = SessionCommand::Enqueue(PathBuf::from(req.file_path));
+    request(&state, command, state::queue_response).await
+}
 
-        // Get transport state
-        if let Ok(new_state) = transport::get_transport_info(&device, &control_url).await {
-            let mut s = state.lock().await;
-            s.playback_state = new_state;
-        }
+/// DELETE /api/queue
+/// Removes every item except the one being cast.
+pub async fn clear_queue(State(state): State<SharedState>) -> impl IntoResponse {
+    request(&state, SessionCommand::ClearQueue, state::queue_response).await
+}
 
-        // Broadcast status update
-        let status = {
-            let s = state.lock().await;
-            s.status_response()
-        };
-        let s = state.lock().await;
-        let _ = s.status_tx.send(status);
-    }
+/// DELETE /api/queue/{index}
+pub async fn remove_from_queue(
+    State(state): State<SharedState>,
+    Path(index): Path<usize>,
+) -> impl IntoResponse {
+    request(&state, SessionCommand::RemoveItem(index), state::queue_response).await
+}
+
+/// POST /api/queue/move
+pub async fn move_queue_item(
+    State(state): State<SharedState>,
+    Json(req): Json<MoveQueueItemRequest>,
+) -> impl IntoResponse {
+    let command = SessionCommand::MoveItem {
+        from: req.from,
+        to: req.to,
+    };
+    request(&state, command, state::queue_response).await
+}
+
+/// POST /api/queue/jump
+pub async fn jump_to_item(
+    State(state): State<SharedState>,
+    Json(req): Json<JumpRequest>,
+) -> impl IntoResponse {
+    request(&state, SessionCommand::Jump(req.index), state::queue_response).await
+}
+
+/// POST /api/queue/next
+pub async fn next_item(State(state): State<SharedState>) -> impl IntoResponse {
+    request(&state, SessionCommand::Next, state::queue_response).await
+}
+
+/// POST /api/queue/previous
+pub async fn previous_item(State(state): State<SharedState>) -> impl IntoResponse {
+    request(&state, SessionCommand::Previous, state::queue_response).await
 }
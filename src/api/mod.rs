@@ -4,15 +4,14 @@ pub mod state;
 pub mod types;
 
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use axum::Router;
 use tower_http::cors::{Any, CorsLayer};
 
 use crate::api::state::ApiState;
 
-pub fn api_router(state: Arc<Mutex<ApiState>>) -> Router {
+pub fn api_router(state: Arc<ApiState>) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -21,14 +20,33 @@ pub fn api_router(state: Arc<Mutex<ApiState>>) -> Router {
     Router::new()
         .route("/api/select-file", post(handlers::select_file))
         .route("/api/discover", get(handlers::discover))
+        .route("/api/discover/stream", get(sse::discover_stream))
+        .route("/api/devices/manual", post(handlers::add_device))
         .route("/api/select-device", post(handlers::select_device))
         .route("/api/cast", post(handlers::cast))
+        .route("/api/cast/wake", post(handlers::wake_and_cast))
+        .route("/api/cast/cancel", post(handlers::cancel_cast))
         .route("/api/play", post(handlers::play))
         .route("/api/pause", post(handlers::pause))
         .route("/api/stop", post(handlers::stop))
         .route("/api/seek", post(handlers::seek))
         .route("/api/status", get(handlers::status))
+        .route("/api/transport-settings", get(handlers::transport_settings))
+        .route("/api/play-mode", post(handlers::set_play_mode))
+        .route("/api/speed", post(handlers::set_speed))
+        .route(
+            "/api/queue",
+            get(handlers::get_queue)
+                .post(handlers::add_to_queue)
+                .delete(handlers::clear_queue),
+        )
+        .route("/api/queue/{index}", delete(handlers::remove_from_queue))
+        .route("/api/queue/move", post(handlers::move_queue_item))
+        .route("/api/queue/jump", post(handlers::jump_to_item))
+        .route("/api/queue/next", post(handlers::next_item))
+        .route("/api/queue/previous", post(handlers::previous_item))
         .route("/api/status/stream", get(sse::status_stream))
+        .route("/api/devices/stream", get(sse::devices_stream))
         .layer(cors)
         .with_state(state)
 }
@@ -2,33 +2,36 @@ use std::convert::Infallible;
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum::extract::State;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
 use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use futures::stream::Stream;
-use tokio::sync::Mutex;
-use tokio_stream::wrappers::BroadcastStream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{BroadcastStream, WatchStream};
 use tokio_stream::StreamExt;
 
-use crate::api::state::ApiState;
+use crate::api::handlers::err;
+use crate::api::state::{self, ApiState};
+use crate::api::types::{
+    discovery_timeout, DeviceChangeResponse, DeviceResponse, DiscoverDoneResponse, DiscoverStreamQuery,
+};
+use localcast::{CastSession, Device, SessionEvent};
 
-type SharedState = Arc<Mutex<ApiState>>;
+type SharedState = Arc<ApiState>;
+
+/// How long a device found by a search may take to show up in the session's list.
+const LIST_WAIT: Duration = Duration::from_secs(1);
 
 /// GET /api/status/stream
-/// SSE endpoint that streams status updates at ~1/sec from the poller.
+/// SSE endpoint that streams the session status whenever it changes
+/// (~1/sec from the poller while casting).
 pub async fn status_stream(
     State(state): State<SharedState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let rx = {
-        let s = state.lock().await;
-        s.status_tx.subscribe()
-    };
-
-    let stream = BroadcastStream::new(rx).filter_map(|result| match result {
-        Ok(status) => {
-            let json = serde_json::to_string(&status).unwrap_or_default();
-            Some(Ok(Event::default().data(json)))
-        }
-        Err(_) => None,
+    let stream = WatchStream::new(state.session.watch()).map(|session| {
+        let json = serde_json::to_string(&state::status_response(&session)).unwrap_or_default();
+        Ok(Event::default().data(json))
     });
 
     Sse::new(stream).keep_alive(
@@ -37,3 +40,132 @@ pub async fn status_stream(
             .text("keep-alive"),
     )
 }
+
+/// GET /api/devices/stream
+/// SSE endpoint that sends the device list as a `devices` event, then an
+/// `added`, `updated` or `removed` event whenever a renderer comes or goes.
+pub async fn devices_stream(
+    State(state): State<SharedState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let session = state.session.clone();
+    let initial = state::device_list_response(&session.state());
+    let initial = futures::stream::once(async move {
+        let json = serde_json::to_string(&initial).unwrap_or_default();
+        Ok(Event::default().event("devices").data(json))
+    });
+
+    let changes = BroadcastStream::new(session.subscribe()).filter_map(move |event| {
+        let (change, device) = match event {
+            Ok(SessionEvent::DeviceAdded(d)) => ("added", d),
+            Ok(SessionEvent::DeviceUpdated(d)) => ("updated", d),
+            Ok(SessionEvent::DeviceRemoved(d)) => ("removed", d),
+            _ => return None,
+        };
+        let response = DeviceChangeResponse {
+            change: change.to_string(),
+            udn: device.udn,
+            friendly_name: device.friendly_name,
+            devices: state::device_list_response(&session.state()).devices,
+        };
+        let json = serde_json::to_string(&response).unwrap_or_default();
+        Some(Ok(Event::default().event(change).data(json)))
+    });
+
+    Sse::new(initial.chain(changes)).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("keep-alive"),
+    )
+}
+
+/// GET /api/discover/stream?timeout=<secs>&first=1&name=<text>
+/// SSE endpoint that searches for renderers and sends a `device` event for
+/// each one as it answers, then a `done` event. `first` and `name` end the
+/// search at the first (matching) device instead of waiting out the timeout.
+pub async fn discover_stream(
+    State(state): State<SharedState>,
+    Query(query): Query<DiscoverStreamQuery>,
+) -> Response {
+    let discovery = state.session.discovery();
+    let timeout = match discovery_timeout(query.timeout) {
+        Ok(timeout) => timeout.unwrap_or_else(|| discovery.search_timeout()),
+        Err(message) => return err(StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let found = match discovery.search_stream(timeout) {
+        Ok(found) => found,
+        Err(e) => return err(StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+    };
+
+    let search = Search {
+        session: state.session.clone(),
+        found,
+        stop_early: query.first || query.name.is_some(),
+        wanted: query.name.map(|name| name.to_lowercase()),
+        count: 0,
+        matched: false,
+        finished: false,
+    };
+    Sse::new(futures::stream::unfold(search, Search::next_event))
+        .keep_alive(
+            KeepAlive::new()
+                .interval(Duration::from_secs(15))
+                .text("keep-alive"),
+        )
+        .into_response()
+}
+
+/// Progress of a `/api/discover/stream` request.
+struct Search {
+    session: CastSession,
+    found: mpsc::UnboundedReceiver<Device>,
+    /// Lowercased `name` filter.
+    wanted: Option<String>,
+    stop_early: bool,
+    count: usize,
+    matched: bool,
+    finished: bool,
+}
+
+impl Search {
+    async fn next_event(mut self) -> Option<(Result<Event, Infallible>, Self)> {
+        if self.finished {
+            return None;
+        }
+        if !self.matched {
+            while let Some(device) = self.found.recv().await {
+                let wanted = self.wanted.as_deref();
+                if wanted.is_some_and(|w| !device.friendly_name.to_lowercase().contains(w)) {
+                    continue;
+                }
+                let Some(response) = listed(&self.session, &device).await else {
+                    continue;
+                };
+                self.count += 1;
+                self.matched = self.stop_early;
+                let json = serde_json::to_string(&response).unwrap_or_default();
+                return Some((Ok(Event::default().event("device").data(json)), self));
+            }
+        }
+
+        // With `first` or `name`, dropping the stream (and the receiver)
+        // ends the search early
+        self.finished = true;
+        let done = DiscoverDoneResponse {
+            found: self.count,
+            matched: self.matched,
+        };
+        let json = serde_json::to_string(&done).unwrap_or_default();
+        Some((Ok(Event::default().event("done").data(json)), self))
+    }
+}
+
+/// `device` as listed by the session, so its index can be used to select it.
+async fn listed(session: &CastSession, device: &Device) -> Option<DeviceResponse> {
+    let mut watch = session.watch();
+    let listed = watch.wait_for(|s| s.devices.iter().any(|d| d.udn == device.udn));
+    let s = tokio::time::timeout(LIST_WAIT, listed).await.ok()?.ok()?;
+    state::device_list_response(&s)
+        .devices
+        .into_iter()
+        .find(|d| d.udn == device.udn)
+}
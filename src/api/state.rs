@@ -1,79 +1,109 @@
-use tokio::sync::broadcast;
-use tokio::task::JoinHandle;
-
-use crate::api::types::StatusResponse;
-use crate::dlna::types::{DlnaDevice, PlaybackState, PositionInfo};
+use crate::api::types::{
+    DeviceListResponse, DeviceResponse, MediaInfoResponse, QueueItemResponse, QueueResponse,
+    StatusResponse, TransportSettingsResponse,
+};
+use localcast::dlna::types::{PlayMode, PLAY_SPEEDS};
+use localcast::session::{CastSession, SessionState};
 
+/// State shared by the API handlers. Device control lives in the session;
+/// handlers only send it requests and render its state.
 pub struct ApiState {
-    // Discovered devices
-    pub devices: Vec<DlnaDevice>,
-    pub selected_device: Option<usize>,
-    pub control_url: Option<String>,
-
-    // File info
-    pub file_path: Option<String>,
-    pub file_name: Option<String>,
-    pub file_size: u64,
-    pub mime_type: Option<String>,
-
-    // Media server
-    pub media_server_handle: Option<JoinHandle<()>>,
-    pub serve_path: Option<String>,
-    pub server_port: u16,
-
-    // Playback
-    pub playback_state: PlaybackState,
-    pub position: PositionInfo,
-
-    // Poller
-    pub poller_handle: Option<JoinHandle<()>>,
-
-    // SSE broadcast
-    pub status_tx: broadcast::Sender<StatusResponse>,
+    pub session: CastSession,
 }
 
 impl ApiState {
-    pub fn new() -> Self {
-        let (status_tx, _) = broadcast::channel(64);
-        Self {
-            devices: Vec::new(),
-            selected_device: None,
-            control_url: None,
-            file_path: None,
-            file_name: None,
-            file_size: 0,
-            mime_type: None,
-            media_server_handle: None,
-            serve_path: None,
-            server_port: 0,
-            playback_state: PlaybackState::Stopped,
-            position: PositionInfo::default(),
-            poller_handle: None,
-            status_tx,
-        }
+    pub fn new(session: CastSession) -> Self {
+        Self { session }
     }
+}
+
+pub fn status_response(s: &SessionState) -> StatusResponse {
+    StatusResponse {
+        playback_state: s.playback_state.label().to_string(),
+        elapsed_secs: s.position.elapsed_secs,
+        duration_secs: s.position.duration_secs,
+        elapsed_display: s.position.elapsed_display(),
+        duration_display: s.position.duration_display(),
+        progress: s.position.progress_ratio(),
+        file_name: s.current_file_name(),
+        device_name: s.device_name.clone(),
+        queue_index: s.queue().current_index(),
+        queue_length: s.queue().len(),
+        play_mode: s.queue().play_mode().as_upnp().to_string(),
+        play_mode_emulated: s.queue().play_mode() != s.renderer_play_mode,
+        speed: s.speed.clone(),
+        allowed_actions: s.transport_actions.allowed(),
+        taken_over: s.taken_over,
+        media_info: MediaInfoResponse {
+            current_uri: s.media_info.current_uri.clone(),
+            title: s.media_info.title.clone(),
+            nr_tracks: s.media_info.nr_tracks,
+            media_duration_secs: s.media_info.media_duration_secs,
+        },
+        cast_step: s.cast_step.map(|step| step.id().to_string()),
+        cast_error: s.cast_error.clone(),
+        recovering: s.recovering,
+        recoveries: s.recoveries,
+    }
+}
 
-    pub fn current_device(&self) -> Option<&DlnaDevice> {
-        self.selected_device
-            .and_then(|i| self.devices.get(i))
+pub fn device_list_response(s: &SessionState) -> DeviceListResponse {
+    DeviceListResponse {
+        devices: s
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(i, d)| DeviceResponse {
+                index: i,
+                udn: d.udn.clone(),
+                friendly_name: d.friendly_name.clone(),
+                protocol: d.protocol.id(),
+                device_url: d.device_url.to_string(),
+                interface: d.interface.clone(),
+                stale: d.stale,
+                mac: d.mac.map(|mac| mac.to_string()),
+            })
+            .collect(),
     }
+}
 
-    pub fn device_name(&self) -> String {
-        self.current_device()
-            .map(|d| d.friendly_name.clone())
-            .unwrap_or_default()
+pub fn transport_settings_response(s: &SessionState) -> TransportSettingsResponse {
+    TransportSettingsResponse {
+        play_mode: s.queue().play_mode().as_upnp().to_string(),
+        renderer_play_mode: s.renderer_play_mode.as_upnp().to_string(),
+        speed: s.speed.clone(),
+        available_play_modes: PlayMode::ALL
+            .iter()
+            .map(|m| m.as_upnp().to_string())
+            .collect(),
+        native_play_modes: PlayMode::ALL
+            .iter()
+            .filter(|m| s.capabilities.supports_play_mode(**m))
+            .map(|m| m.as_upnp().to_string())
+            .collect(),
+        available_speeds: PLAY_SPEEDS
+            .iter()
+            .filter(|speed| s.capabilities.supports_speed(speed, &s.transport_actions))
+            .map(|speed| speed.to_string())
+            .collect(),
     }
+}
 
-    pub fn status_response(&self) -> StatusResponse {
-        StatusResponse {
-            playback_state: self.playback_state.label().to_string(),
-            elapsed_secs: self.position.elapsed_secs,
-            duration_secs: self.position.duration_secs,
-            elapsed_display: self.position.elapsed_display(),
-            duration_display: self.position.duration_display(),
-            progress: self.position.progress_ratio(),
-            file_name: self.file_name.clone().unwrap_or_default(),
-            device_name: self.device_name(),
-        }
+pub fn queue_response(s: &SessionState) -> QueueResponse {
+    QueueResponse {
+        items: s
+            .queue()
+            .items()
+            .iter()
+            .enumerate()
+            .map(|(i, item)| QueueItemResponse {
+                index: i,
+                file_path: item.path.to_string_lossy().to_string(),
+                file_name: item.file_name.clone(),
+                file_size: item.file_size,
+                mime_type: item.mime_type.clone(),
+            })
+            .collect(),
+        current_index: s.queue().current_index(),
     }
 }
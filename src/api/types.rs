@@ -1,7 +1,49 @@
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Longest search a discovery request may ask for.
+const MAX_DISCOVERY_TIMEOUT_SECS: f64 = 60.0;
 
 // --- Requests ---
 
+/// Query of `GET /api/discover`.
+#[derive(Debug, Deserialize)]
+pub struct DiscoverQuery {
+    /// Seconds to search; the configured discovery timeout if absent.
+    pub timeout: Option<f64>,
+}
+
+/// Query of `GET /api/discover/stream`.
+#[derive(Debug, Deserialize)]
+pub struct DiscoverStreamQuery {
+    pub timeout: Option<f64>,
+    /// Stop at the first device found: `?first=1`.
+    #[serde(default, deserialize_with = "flag")]
+    pub first: bool,
+    /// Stop at the first device whose name contains this, ignoring case.
+    pub name: Option<String>,
+}
+
+/// Validate a `timeout` query parameter.
+pub fn discovery_timeout(timeout: Option<f64>) -> Result<Option<Duration>, String> {
+    match timeout {
+        None => Ok(None),
+        Some(secs) if secs > 0.0 && secs <= MAX_DISCOVERY_TIMEOUT_SECS => {
+            Ok(Some(Duration::from_secs_f64(secs)))
+        }
+        Some(_) => Err(format!(
+            "timeout must be between 0 and {MAX_DISCOVERY_TIMEOUT_SECS} seconds"
+        )),
+    }
+}
+
+/// A query flag: `1`, `true` or `yes` (or empty, as in `?first`) turn it on.
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(matches!(value.as_str(), "" | "1" | "true" | "yes"))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SelectFileRequest {
     pub file_path: String,
@@ -12,11 +54,60 @@ pub struct SelectDeviceRequest {
     pub device_index: usize,
 }
 
+/// The device to wake and cast to, by UDN or by index; the selected one if
+/// both are omitted.
+#[derive(Debug, Deserialize)]
+pub struct WakeAndCastRequest {
+    #[serde(default)]
+    pub udn: Option<String>,
+    #[serde(default)]
+    pub device_index: Option<usize>,
+}
+
+/// A renderer discovery cannot find: give `url` (its description URL,
+/// `cast://host` for a Chromecast, `airplay://host` for an Apple TV,
+/// `kodi://host` or `roku://host`) or `ip` (optionally with `:port`; common
+/// description locations and the Cast, AirPlay, Kodi and Roku ports are
+/// tried).
+#[derive(Debug, Deserialize)]
+pub struct AddDeviceRequest {
+    pub url: Option<String>,
+    pub ip: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SeekRequest {
     pub position_secs: u64,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AddToQueueRequest {
+    pub file_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveQueueItemRequest {
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JumpRequest {
+    pub index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayModeRequest {
+    /// NORMAL, REPEAT_ONE, REPEAT_ALL or SHUFFLE
+    pub play_mode: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpeedRequest {
+    /// A TransportPlaySpeed value: "1/2", "1" or "2"
+    pub speed: String,
+}
+
 // --- Responses ---
 
 #[derive(Debug, Serialize)]
@@ -29,8 +120,17 @@ pub struct FileInfoResponse {
 #[derive(Debug, Serialize, Clone)]
 pub struct DeviceResponse {
     pub index: usize,
+    pub udn: String,
     pub friendly_name: String,
+    /// Protocol the device is controlled with, e.g. "dlna".
+    pub protocol: &'static str,
     pub device_url: String,
+    /// Local network interface the device was found on.
+    pub interface: Option<String>,
+    /// Remembered from an earlier run and not seen on the network yet.
+    pub stale: bool,
+    /// Hardware address; the device can be woken when it is known.
+    pub mac: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -38,6 +138,26 @@ pub struct DeviceListResponse {
     pub devices: Vec<DeviceResponse>,
 }
 
+/// A device change pushed over `/api/devices/stream`, with the list after it.
+#[derive(Debug, Serialize)]
+pub struct DeviceChangeResponse {
+    /// "added", "updated" or "removed"
+    pub change: String,
+    pub udn: String,
+    pub friendly_name: String,
+    pub devices: Vec<DeviceResponse>,
+}
+
+/// Last event of `/api/discover/stream`.
+#[derive(Debug, Serialize)]
+pub struct DiscoverDoneResponse {
+    /// Devices sent by this search.
+    pub found: usize,
+    /// True if the search stopped at a device matching `first` or `name`,
+    /// false if it ran for the whole timeout.
+    pub matched: bool,
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct StatusResponse {
     pub playback_state: String,
@@ -48,6 +168,57 @@ pub struct StatusResponse {
     pub progress: f64,
     pub file_name: String,
     pub device_name: String,
+    pub queue_index: Option<usize>,
+    pub queue_length: usize,
+    pub play_mode: String,
+    pub play_mode_emulated: bool,
+    pub speed: String,
+    /// Controls the renderer allows right now: play, pause, stop, seek, next, previous
+    pub allowed_actions: Vec<String>,
+    /// True when the TV is playing something localcast didn't send
+    pub taken_over: bool,
+    pub media_info: MediaInfoResponse,
+    /// Step of the cast being confirmed, e.g. "waiting_for_request"
+    pub cast_step: Option<String>,
+    /// Why the last cast failed
+    pub cast_error: Option<String>,
+    /// Attempt number while resuming after the TV dropped playback
+    pub recovering: Option<u32>,
+    /// Times playback was resumed after a drop since the cast started
+    pub recoveries: u32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MediaInfoResponse {
+    pub current_uri: String,
+    pub title: Option<String>,
+    pub nr_tracks: u32,
+    pub media_duration_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransportSettingsResponse {
+    pub play_mode: String,
+    pub renderer_play_mode: String,
+    pub speed: String,
+    pub available_play_modes: Vec<String>,
+    pub native_play_modes: Vec<String>,
+    pub available_speeds: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueItemResponse {
+    pub index: usize,
+    pub file_path: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub mime_type: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueResponse {
+    pub items: Vec<QueueItemResponse>,
+    pub current_index: Option<usize>,
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +226,14 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+/// Returned when a cast did not reach PLAYING.
+#[derive(Debug, Serialize)]
+pub struct CastErrorResponse {
+    pub error: String,
+    /// The step that failed, e.g. "waiting_for_request"
+    pub failed_step: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct OkResponse {
     pub ok: bool,
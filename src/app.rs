@@ -1,4 +1,13 @@
-use crate::dlna::types::{DlnaDevice, PlaybackState, PositionInfo};
+use std::time::{Duration, Instant};
+
+use localcast::Device;
+use localcast::session::{SessionEvent, SessionState};
+
+/// How long a toast stays on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+
+/// Frames of the busy spinner, advanced once per event loop tick.
+const SPINNER: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
 /// Which screen the TUI is displaying.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -7,83 +16,127 @@ pub enum AppScreen {
     Playback,
 }
 
-/// Message sent from the playback poller task to the TUI.
+/// A short-lived message shown over the current screen.
 #[derive(Debug, Clone)]
-pub enum PollerMessage {
-    PositionUpdate(PositionInfo),
-    StateUpdate(PlaybackState),
+pub struct Toast {
+    pub message: String,
+    pub is_error: bool,
+    expires: Instant,
 }
 
-/// Application state shared across the TUI.
+/// Application state shared across the TUI. Device control lives in the
+/// session actor; this is the view of it plus local navigation state.
 pub struct App {
     pub screen: AppScreen,
-    pub devices: Vec<DlnaDevice>,
+    /// Cursor in the session's device list.
     pub selected_device: usize,
-    pub scanning: bool,
+    /// Text typed so far while adding a TV by address.
+    pub device_prompt: Option<String>,
 
-    pub file_name: String,
-    pub media_url: String,
-    pub control_url: String,
-    pub mime_type: String,
-    pub file_size: u64,
-
-    pub playback_state: PlaybackState,
-    pub position: PositionInfo,
+    /// Latest copy of the session's state.
+    pub session: SessionState,
+    pub toasts: Vec<Toast>,
+    /// Event loop iterations, drives the spinner.
+    pub tick: usize,
+    /// Seconds skipped by ←/→ unless the device config overrides it.
+    pub default_seek_step: u64,
+    /// Seconds skipped by Shift+←/→.
+    pub seek_step_long: u64,
 
     pub should_quit: bool,
 }
 
 impl App {
-    pub fn new(file_name: String, media_url: String, mime_type: String, file_size: u64) -> Self {
+    pub fn new(session: SessionState, seek_step: u64, seek_step_long: u64) -> Self {
         Self {
             screen: AppScreen::DeviceBrowser,
-            devices: Vec::new(),
             selected_device: 0,
-            scanning: true,
-
-            file_name,
-            media_url,
-            control_url: String::new(),
-            mime_type,
-            file_size,
+            device_prompt: None,
 
-            playback_state: PlaybackState::Stopped,
-            position: PositionInfo::default(),
+            session,
+            toasts: Vec::new(),
+            tick: 0,
+            default_seek_step: seek_step,
+            seek_step_long,
 
             should_quit: false,
         }
     }
 
-    pub fn current_device(&self) -> Option<&DlnaDevice> {
-        self.devices.get(self.selected_device)
+    /// Seconds skipped by ←/→ on the selected device.
+    pub fn seek_step(&self) -> u64 {
+        self.session.device_config.seek_step.unwrap_or(self.default_seek_step)
+    }
+
+    pub fn current_device(&self) -> Option<&Device> {
+        self.session.devices.get(self.selected_device)
     }
 
     pub fn current_device_name(&self) -> String {
-        self.current_device()
-            .map(|d| d.friendly_name.clone())
-            .unwrap_or_else(|| "Unknown".into())
+        if self.session.device_name.is_empty() {
+            "Unknown".into()
+        } else {
+            self.session.device_name.clone()
+        }
+    }
+
+    pub fn current_file_name(&self) -> String {
+        self.session.current_file_name()
     }
 
     pub fn select_next(&mut self) {
-        if !self.devices.is_empty() {
-            self.selected_device = (self.selected_device + 1) % self.devices.len();
+        let count = self.session.devices.len();
+        if count > 0 {
+            self.selected_device = (self.selected_device + 1) % count;
         }
     }
 
     pub fn select_prev(&mut self) {
-        if !self.devices.is_empty() {
+        let count = self.session.devices.len();
+        if count > 0 {
             if self.selected_device == 0 {
-                self.selected_device = self.devices.len() - 1;
+                self.selected_device = count - 1;
             } else {
                 self.selected_device -= 1;
             }
         }
     }
 
-    pub fn apply_poller_message(&mut self, msg: PollerMessage) {
-        match msg {
-            PollerMessage::PositionUpdate(pos) => self.position = pos,
-            PollerMessage::StateUpdate(state) => self.playback_state = state,
+    pub fn spinner(&self) -> &'static str {
+        SPINNER[self.tick % SPINNER.len()]
+    }
+
+    pub fn push_toast(&mut self, message: impl Into<String>, is_error: bool) {
+        self.toasts.push(Toast {
+            message: message.into(),
+            is_error,
+            expires: Instant::now() + TOAST_DURATION,
+        });
+    }
+
+    /// Advance the spinner and drop expired toasts.
+    pub fn on_tick(&mut self) {
+        self.tick = self.tick.wrapping_add(1);
+        let now = Instant::now();
+        self.toasts.retain(|t| t.expires > now);
+    }
+
+    /// Take a new copy of the session state, keeping the cursor in range.
+    pub fn set_session_state(&mut self, session: SessionState) {
+        if self.selected_device >= session.devices.len() {
+            self.selected_device = 0;
+        }
+        self.session = session;
+    }
+
+    pub fn apply_session_event(&mut self, event: SessionEvent) {
+        match event {
+            SessionEvent::CastStarted => self.screen = AppScreen::Playback,
+            SessionEvent::Disconnected => self.screen = AppScreen::DeviceBrowser,
+            SessionEvent::Notice(message) => self.push_toast(message, false),
+            SessionEvent::Error(message) | SessionEvent::RecoveryFailed(message) => self.push_toast(message, true),
+            SessionEvent::Closed => self.should_quit = true,
+            _ => {}
         }
     }
 }
@@ -0,0 +1,362 @@
+//! The device cache: renderers seen before, kept in a JSON file so they can
+//! be listed before discovery finishes and cast to again without it.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::renderer::{Device, DlnaDevice, Protocol};
+use crate::wol::MacAddress;
+
+/// Contents of the cache file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct DeviceCache {
+    /// UDN of the device last cast to.
+    #[serde(default)]
+    pub last_used: Option<String>,
+    #[serde(default)]
+    pub devices: Vec<CachedDevice>,
+}
+
+/// What is remembered about one renderer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CachedDevice {
+    pub udn: String,
+    pub friendly_name: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// Where the device is reached; for DLNA, its device description.
+    pub device_url: String,
+    /// Type of the AVTransport service, with its version. DLNA only.
+    #[serde(default)]
+    pub service_type: String,
+    /// Where commands are sent, once resolved.
+    #[serde(default)]
+    pub control_url: Option<String>,
+    /// `Sink` entries from the ConnectionManager, once read.
+    #[serde(default)]
+    pub protocol_info: Vec<String>,
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// Hardware address, for waking the renderer from standby.
+    #[serde(default)]
+    pub mac: Option<MacAddress>,
+    /// When the device was last seen on the network, in seconds since the
+    /// Unix epoch.
+    pub last_seen: u64,
+}
+
+impl CachedDevice {
+    /// The renderer as discovery would list it, marked stale. `None` if the
+    /// stored URL no longer parses.
+    pub fn to_device(&self) -> Option<Device> {
+        Some(Device {
+            friendly_name: self.friendly_name.clone(),
+            udn: self.udn.clone(),
+            protocol: self.protocol,
+            device_url: self.device_url.parse().ok()?,
+            interface: self.interface.clone(),
+            stale: true,
+            mac: self.mac,
+            dlna: (self.protocol == Protocol::Dlna).then(|| DlnaDevice {
+                service_type: self.service_type.clone(),
+            }),
+        })
+    }
+}
+
+impl DeviceCache {
+    /// `$XDG_CACHE_HOME/localcast/devices.json`, or `~/.cache/localcast/devices.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(base.join("localcast").join("devices.json"))
+    }
+
+    /// Read the cache at `path`. A missing or unreadable file is an empty
+    /// cache: it only saves time, so it never stops a launch.
+    pub fn load(path: &Path) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!("Cannot read device cache {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring corrupt device cache {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    /// Write the cache to `path`, creating its directory.
+    pub fn save(&self, path: &Path) -> Result<(), AppError> {
+        let error = |e: std::io::Error| AppError::Cache(format!("cannot write {}: {e}", path.display()));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(error)?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| AppError::Cache(e.to_string()))?;
+        // Write then rename, so a reader never sees half a file; the name
+        // is ours alone, so writers don't clobber each other's
+        let partial = beside(path, &format!(".{}.tmp", std::process::id()));
+        std::fs::write(&partial, json).map_err(error)?;
+        std::fs::rename(&partial, path).map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            error(e)
+        })
+    }
+
+    /// Apply `change` to the file's current contents and save the result, so
+    /// several localcast processes don't drop each other's devices. Updates
+    /// take turns through an advisory lock on `<path>.lock`.
+    pub fn update(path: &Path, change: impl FnOnce(&mut Self)) -> Result<(), AppError> {
+        let error = |e: std::io::Error| AppError::Cache(format!("cannot lock {}: {e}", path.display()));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(error)?;
+        }
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(beside(path, ".lock"))
+            .map_err(error)?;
+        lock.lock().map_err(error)?;
+        let mut cache = Self::load(path);
+        change(&mut cache);
+        // Dropping `lock` releases it
+        cache.save(path)
+    }
+
+    pub fn get(&self, udn: &str) -> Option<&CachedDevice> {
+        self.devices.iter().find(|d| d.udn == udn)
+    }
+
+    /// The device last cast to.
+    pub fn last_used(&self) -> Option<&CachedDevice> {
+        self.get(self.last_used.as_deref()?)
+    }
+
+    /// Every cached renderer, most recently seen first, marked stale.
+    pub fn stale_devices(&self) -> Vec<Device> {
+        let mut cached: Vec<&CachedDevice> = self.devices.iter().collect();
+        cached.sort_by_key(|d| std::cmp::Reverse(d.last_seen));
+        cached.into_iter().filter_map(CachedDevice::to_device).collect()
+    }
+
+    /// Record `device` as seen now. What was resolved for it is kept unless
+    /// it moved to another description URL or service; its MAC address is
+    /// kept unless a new one was read.
+    pub fn remember(&mut self, device: &Device) {
+        let entry = CachedDevice {
+            udn: device.udn.clone(),
+            friendly_name: device.friendly_name.clone(),
+            protocol: device.protocol,
+            device_url: device.device_url.to_string(),
+            service_type: device.dlna.as_ref().map(|dlna| dlna.service_type.clone()).unwrap_or_default(),
+            control_url: None,
+            protocol_info: Vec::new(),
+            interface: device.interface.clone(),
+            mac: device.mac,
+            last_seen: now(),
+        };
+        match self.devices.iter_mut().find(|d| d.udn == device.udn) {
+            Some(cached) => {
+                let entry = CachedDevice {
+                    mac: entry.mac.or(cached.mac),
+                    ..entry
+                };
+                if cached.protocol == entry.protocol
+                    && cached.device_url == entry.device_url
+                    && cached.service_type == entry.service_type
+                {
+                    let CachedDevice {
+                        control_url,
+                        protocol_info,
+                        ..
+                    } = std::mem::replace(cached, entry);
+                    cached.control_url = control_url;
+                    cached.protocol_info = protocol_info;
+                } else {
+                    *cached = entry;
+                }
+            }
+            None => self.devices.push(entry),
+        }
+    }
+
+    /// Record the control URL and protocol info resolved for `device`.
+    pub fn resolved(&mut self, device: &Device, control_url: &str, protocol_info: Vec<String>) {
+        self.remember(device);
+        if let Some(cached) = self.devices.iter_mut().find(|d| d.udn == device.udn) {
+            cached.control_url = Some(control_url.to_string());
+            if !protocol_info.is_empty() {
+                cached.protocol_info = protocol_info;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// `path` with `suffix` added to its file name.
+fn beside(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(url: &str, service_type: &str) -> Device {
+        Device {
+            friendly_name: "Living Room".into(),
+            udn: "uuid:tv".into(),
+            protocol: Protocol::Dlna,
+            device_url: url.parse().unwrap(),
+            interface: None,
+            stale: false,
+            mac: None,
+            dlna: Some(DlnaDevice {
+                service_type: service_type.into(),
+            }),
+        }
+    }
+
+    const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+    const DESCRIPTION: &str = "http://192.168.1.5:49152/description.xml";
+
+    /// A cache that has resolved the control URL of `device()`.
+    fn resolved() -> DeviceCache {
+        let mut cache = DeviceCache::default();
+        let protocol_info = vec!["http-get:*:video/mp4:*".to_string()];
+        cache.resolved(&device(DESCRIPTION, AV_TRANSPORT), "/control", protocol_info);
+        cache
+    }
+
+    #[test]
+    fn resolved_details_survive_an_unchanged_device() {
+        let mut cache = resolved();
+        cache.remember(&Device {
+            friendly_name: "Renamed".into(),
+            ..device(DESCRIPTION, AV_TRANSPORT)
+        });
+        let cached = cache.get("uuid:tv").unwrap();
+        assert_eq!(cached.friendly_name, "Renamed");
+        assert_eq!(cached.control_url.as_deref(), Some("/control"));
+        assert_eq!(cached.protocol_info, ["http-get:*:video/mp4:*"]);
+        assert_eq!(cache.devices.len(), 1);
+    }
+
+    #[test]
+    fn resolved_details_are_reset_when_the_device_moves() {
+        let mut cache = resolved();
+        cache.remember(&device("http://192.168.1.5:49153/description.xml", AV_TRANSPORT));
+        assert_eq!(cache.get("uuid:tv").unwrap().control_url, None);
+
+        let mut cache = resolved();
+        cache.remember(&device(DESCRIPTION, "urn:schemas-upnp-org:service:AVTransport:2"));
+        let cached = cache.get("uuid:tv").unwrap();
+        assert_eq!(cached.control_url, None);
+        assert!(cached.protocol_info.is_empty());
+
+        let mut cache = resolved();
+        cache.remember(&Device {
+            protocol: Protocol::Kodi,
+            dlna: None,
+            ..device(DESCRIPTION, "")
+        });
+        assert_eq!(cache.get("uuid:tv").unwrap().control_url, None);
+    }
+
+    #[test]
+    fn mac_is_kept_until_a_new_one_is_read() {
+        let mac: MacAddress = "aa:bb:cc:dd:ee:ff".parse().unwrap();
+        let mut cache = DeviceCache::default();
+        cache.remember(&Device {
+            mac: Some(mac),
+            ..device(DESCRIPTION, AV_TRANSPORT)
+        });
+        // Moving doesn't lose it either
+        cache.remember(&device("http://192.168.1.9:49152/description.xml", AV_TRANSPORT));
+        assert_eq!(cache.get("uuid:tv").unwrap().mac, Some(mac));
+
+        let new: MacAddress = "11:22:33:44:55:66".parse().unwrap();
+        cache.remember(&Device {
+            mac: Some(new),
+            ..device(DESCRIPTION, AV_TRANSPORT)
+        });
+        assert_eq!(cache.get("uuid:tv").unwrap().mac, Some(new));
+    }
+
+    #[test]
+    fn resolving_without_protocol_info_keeps_the_old() {
+        let mut cache = resolved();
+        cache.resolved(&device(DESCRIPTION, AV_TRANSPORT), "/control2", Vec::new());
+        let cached = cache.get("uuid:tv").unwrap();
+        assert_eq!(cached.control_url.as_deref(), Some("/control2"));
+        assert_eq!(cached.protocol_info, ["http-get:*:video/mp4:*"]);
+    }
+
+    #[test]
+    fn cached_devices_come_back_stale() {
+        let cache = resolved();
+        let devices = cache.stale_devices();
+        assert_eq!(devices.len(), 1);
+        assert!(devices[0].stale);
+        assert_eq!(devices[0].device_url.to_string(), DESCRIPTION);
+        assert_eq!(devices[0].dlna.as_ref().unwrap().service_type, AV_TRANSPORT);
+    }
+
+    #[test]
+    fn updates_merge_with_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join("devices.json");
+        assert!(DeviceCache::load(&path).devices.is_empty());
+
+        DeviceCache::update(&path, |cache| cache.remember(&device(DESCRIPTION, AV_TRANSPORT))).unwrap();
+        // As another process would: its own change on top of what is there
+        DeviceCache::update(&path, |cache| {
+            cache.remember(&Device {
+                udn: "uuid:other".into(),
+                ..device("http://192.168.1.6:49152/", AV_TRANSPORT)
+            });
+            cache.last_used = Some("uuid:other".into());
+        })
+        .unwrap();
+
+        let cache = DeviceCache::load(&path);
+        assert_eq!(cache.devices.len(), 2);
+        assert_eq!(cache.last_used().map(|d| d.udn.as_str()), Some("uuid:other"));
+        // Only the cache and its lock are left behind
+        let mut names: Vec<String> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["devices.json", "devices.json.lock"]);
+    }
+
+    #[test]
+    fn corrupt_files_load_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("devices.json");
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(DeviceCache::load(&path).devices.is_empty());
+        DeviceCache::update(&path, |cache| cache.remember(&device(DESCRIPTION, AV_TRANSPORT))).unwrap();
+        assert_eq!(DeviceCache::load(&path).devices.len(), 1);
+    }
+}
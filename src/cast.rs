@@ -0,0 +1,218 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tokio::time::{timeout_at, Instant};
+
+use crate::dlna::types::PlaybackState;
+use crate::queue::QueueItem;
+use crate::renderer::Renderer;
+use crate::server::{MediaRequest, MediaServer};
+
+/// Steps of a confirmed cast, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CastStep {
+    /// Waking the TV with Wake-on-LAN and waiting for it to answer.
+    Waking,
+    /// Stopping whatever the renderer was playing.
+    Stopping,
+    /// Loading the media URL on the renderer.
+    SettingUri,
+    /// Sending Play.
+    Starting,
+    /// Waiting for the TV to request the media URL from our server.
+    WaitingForRequest,
+    /// The TV fetched the file; waiting for it to start decoding.
+    Transitioning,
+    /// The renderer reports PLAYING.
+    Playing,
+}
+
+impl CastStep {
+    /// Stable identifier for APIs.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Waking => "waking",
+            Self::Stopping => "stopping",
+            Self::SettingUri => "setting_uri",
+            Self::Starting => "starting",
+            Self::WaitingForRequest => "waiting_for_request",
+            Self::Transitioning => "transitioning",
+            Self::Playing => "playing",
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Waking => "Waking the TV",
+            Self::Stopping => "Stopping current playback",
+            Self::SettingUri => "Sending media URL",
+            Self::Starting => "Starting playback",
+            Self::WaitingForRequest => "Waiting for TV to fetch media",
+            Self::Transitioning => "TV is loading media",
+            Self::Playing => "Playing",
+        }
+    }
+}
+
+/// Why a cast didn't reach PLAYING, and at which step.
+#[derive(Error, Debug, Clone)]
+#[error("{} failed: {message}", step.label())]
+#[non_exhaustive]
+pub struct CastFailure {
+    pub step: CastStep,
+    pub message: String,
+}
+
+impl CastFailure {
+    fn new(step: CastStep, message: impl Into<String>) -> Self {
+        Self {
+            step,
+            message: message.into(),
+        }
+    }
+}
+
+/// How long a STOPPED state right after the media request is not treated as failure.
+const STOPPED_GRACE: Duration = Duration::from_secs(2);
+
+/// Time allowed for each waiting step of a confirmed cast.
+#[derive(Debug, Clone, Copy)]
+pub struct CastTimeouts {
+    /// From Play until the TV requests the media URL.
+    pub request: Duration,
+    /// From the first media request until the renderer reports PLAYING.
+    pub playing: Duration,
+    /// How often to poll the transport state while waiting.
+    pub poll_interval: Duration,
+}
+
+impl Default for CastTimeouts {
+    fn default() -> Self {
+        Self {
+            request: Duration::from_secs(10),
+            playing: Duration::from_secs(20),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Cast `item` and confirm each step: stop if needed, load, play, wait for
+/// the TV to fetch the media URL, then wait for TRANSITIONING and PLAYING.
+/// `progress` is called as each step starts.
+pub async fn confirm_cast(
+    renderer: &dyn Renderer,
+    item: &QueueItem,
+    media_base: &str,
+    media_server: &MediaServer,
+    timeouts: CastTimeouts,
+    mut progress: impl FnMut(CastStep),
+) -> Result<(), CastFailure> {
+    // Subscribe before loading: some TVs probe the URL right away.
+    let mut requests = media_server.subscribe_requests();
+
+    // Stop if the renderer is busy; many reject a new URI while playing.
+    if let Ok(state) = renderer.status().await {
+        if matches!(
+            state,
+            PlaybackState::Playing | PlaybackState::Paused | PlaybackState::Transitioning
+        ) {
+            progress(CastStep::Stopping);
+            if let Err(e) = renderer.stop().await {
+                tracing::debug!("Stop before cast failed: {e}");
+            }
+        }
+    }
+
+    progress(CastStep::SettingUri);
+    let media = item.media(media_base);
+    let media_url = &media.url;
+    renderer
+        .load(&media)
+        .await
+        .map_err(|e| CastFailure::new(CastStep::SettingUri, format!("TV rejected the media URL: {e}")))?;
+
+    progress(CastStep::Starting);
+    renderer
+        .play("1")
+        .await
+        .map_err(|e| CastFailure::new(CastStep::Starting, format!("TV refused to play: {e}")))?;
+
+    progress(CastStep::WaitingForRequest);
+    let deadline = Instant::now() + timeouts.request;
+    let request = wait_for_request(&mut requests, &item.serve_path, deadline)
+        .await
+        .ok_or_else(|| {
+            CastFailure::new(
+                CastStep::WaitingForRequest,
+                format!(
+                    "TV never requested the media URL {media_url} — check the firewall on port {}",
+                    media_server.port()
+                ),
+            )
+        })?;
+    tracing::info!(
+        "TV fetched media from {} (range: {})",
+        request.remote_addr,
+        request.range.as_deref().unwrap_or("none")
+    );
+
+    progress(CastStep::Transitioning);
+    let deadline = Instant::now() + timeouts.playing;
+    // Some TVs still report STOPPED for a moment after fetching
+    let grace = Instant::now() + STOPPED_GRACE;
+    let mut last_state = PlaybackState::Transitioning;
+    loop {
+        if Instant::now() >= deadline {
+            return Err(CastFailure::new(
+                CastStep::Transitioning,
+                format!(
+                    "TV fetched the file but did not start playing within {}s (state: {})",
+                    timeouts.playing.as_secs(),
+                    last_state.label()
+                ),
+            ));
+        }
+        tokio::time::sleep(timeouts.poll_interval).await;
+
+        let state = match renderer.status().await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::debug!("Reading transport state while confirming cast: {e}");
+                continue;
+            }
+        };
+        match state {
+            PlaybackState::Playing => break,
+            PlaybackState::Stopped | PlaybackState::NoMediaPresent if Instant::now() >= grace => {
+                return Err(CastFailure::new(
+                    CastStep::Transitioning,
+                    format!(
+                        "TV fetched the file but stopped: unsupported codec or container ({})",
+                        item.mime_type
+                    ),
+                ));
+            }
+            other => last_state = other,
+        }
+    }
+
+    progress(CastStep::Playing);
+    Ok(())
+}
+
+/// Wait until the media server sees a request for `serve_path`.
+async fn wait_for_request(
+    requests: &mut broadcast::Receiver<MediaRequest>,
+    serve_path: &str,
+    deadline: Instant,
+) -> Option<MediaRequest> {
+    loop {
+        match timeout_at(deadline, requests.recv()).await {
+            Ok(Ok(request)) if request.serve_path == serve_path => return Some(request),
+            Ok(Ok(_)) | Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => return None,
+        }
+    }
+}
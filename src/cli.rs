@@ -1,18 +1,408 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
-/// Cast local video files to DLNA-compatible TVs
+use localcast::dlna::client::SoapConfig;
+use localcast::{net, wol, AppError, Config, DeviceCache, InterfaceFilter, SessionBuilder};
+
+/// Cast local video files to DLNA-compatible TVs, Chromecasts, Apple TVs, Kodi and Roku
+///
+/// Options can also be set with LOCALCAST_* environment variables or in
+/// ~/.config/localcast/config.toml; flags win over the environment, which
+/// wins over the file.
 #[derive(Parser, Debug)]
-#[command(name = "localcast", version, about)]
+#[command(name = "localcast", version, about, after_help = EXIT_CODES_HELP)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
-    /// Path to the video file to cast
-    pub file: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    /// Port for the HTTP media server (0 = auto-assign)
-    #[arg(short, long, default_value_t = 0)]
-    pub port: u16,
+    /// Video files to cast, played in order as a queue
+    pub files: Vec<PathBuf>,
+
+    /// Port for the HTTP media server (0 = auto-assign) [default: 0]
+    #[arg(short, long, env = "LOCALCAST_PORT")]
+    pub port: Option<u16>,
 
     /// Run as HTTP API server for the Flutter GUI
     #[arg(long)]
     pub api: bool,
+
+    /// Cast to the TV used last time right away, without waiting for discovery
+    #[arg(long)]
+    pub last: bool,
+
+    /// Wake the TV from standby with Wake-on-LAN before casting (with --last)
+    #[arg(long, requires = "last")]
+    pub wake: bool,
+
+    /// Address the HTTP API listens on; Kodi's web server defaults to the same port [default: 127.0.0.1:8080]
+    #[arg(long, env = "LOCALCAST_API_BIND")]
+    pub api_bind: Option<SocketAddr>,
+
+    /// Times to try resuming when the TV drops playback mid-item (0 = never) [default: 3]
+    #[arg(long, env = "LOCALCAST_RECOVERY_ATTEMPTS")]
+    pub recovery_attempts: Option<u32>,
+
+    /// Seconds to wait before the first resume attempt; doubles per retry [default: 2]
+    #[arg(long, env = "LOCALCAST_RECOVERY_BACKOFF")]
+    pub recovery_backoff: Option<u64>,
+
+    /// Seconds skipped by ←/→ in the TUI [default: 30]
+    #[arg(long, env = "LOCALCAST_SEEK_STEP", value_parser = clap::value_parser!(u64).range(1..))]
+    pub seek_step: Option<u64>,
+
+    /// Seconds skipped by Shift+←/→ in the TUI [default: 300]
+    #[arg(long, env = "LOCALCAST_SEEK_STEP_LONG", value_parser = clap::value_parser!(u64).range(1..))]
+    pub seek_step_long: Option<u64>,
+
+    /// Config file [default: ~/.config/localcast/config.toml]
+    #[arg(long, global = true, env = "LOCALCAST_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Log file [default: localcast.log in $TMPDIR]
+    #[arg(long, global = true, env = "LOCALCAST_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// File remembering TVs between runs [default: ~/.cache/localcast/devices.json]
+    #[arg(long, global = true, env = "LOCALCAST_CACHE_FILE")]
+    pub cache_file: Option<PathBuf>,
+
+    /// Seconds allowed to connect to the TV [default: 3]
+    #[arg(long, global = true, env = "LOCALCAST_CONNECT_TIMEOUT", value_parser = parse_seconds)]
+    pub connect_timeout: Option<f64>,
+
+    /// Seconds allowed for each request to the TV [default: 5]
+    #[arg(long, global = true, env = "LOCALCAST_REQUEST_TIMEOUT", value_parser = parse_seconds)]
+    pub request_timeout: Option<f64>,
+
+    /// Extra attempts for status queries that fail (commands are never resent) [default: 2]
+    #[arg(long, global = true, env = "LOCALCAST_REQUEST_RETRIES")]
+    pub request_retries: Option<u32>,
+
+    /// Seconds the TV may take to fetch the video after Play [default: 10]
+    #[arg(long, global = true, env = "LOCALCAST_FETCH_TIMEOUT", value_parser = parse_seconds)]
+    pub fetch_timeout: Option<f64>,
+
+    /// Seconds the TV may take to start playing once it has fetched the video [default: 20]
+    #[arg(long, global = true, env = "LOCALCAST_START_TIMEOUT", value_parser = parse_seconds)]
+    pub start_timeout: Option<f64>,
+
+    /// Seconds to search for TVs before giving up [default: 5]
+    #[arg(long, global = true, env = "LOCALCAST_DISCOVERY_TIMEOUT", value_parser = parse_seconds)]
+    pub discovery_timeout: Option<f64>,
+
+    /// Seconds between background searches for TVs [default: 60]
+    #[arg(long, global = true, env = "LOCALCAST_SEARCH_INTERVAL", value_parser = parse_seconds)]
+    pub search_interval: Option<f64>,
+
+    /// Seconds between status polls while casting [default: 1]
+    #[arg(long, global = true, env = "LOCALCAST_POLL_INTERVAL", value_parser = parse_seconds)]
+    pub poll_interval: Option<f64>,
+
+    /// Description URL of a TV that discovery cannot find, e.g.
+    /// http://10.0.0.5:49152/description.xml, cast://10.0.0.6 for a
+    /// Chromecast, airplay://10.0.0.7 for an Apple TV, kodi://10.0.0.8 (with
+    /// user:password@ if its web server asks) or roku://10.0.0.9; may be
+    /// repeated
+    #[arg(long, global = true, env = "LOCALCAST_DEVICE_URL", value_delimiter = ',', value_parser = parse_device_url)]
+    pub device_url: Vec<String>,
+
+    /// IP address (optionally with :port) of a TV that discovery cannot find;
+    /// common description locations and the Cast, AirPlay, Kodi and Roku
+    /// ports are tried;
+    /// may be repeated
+    #[arg(long, global = true, env = "LOCALCAST_DEVICE_IP", value_delimiter = ',', value_parser = parse_device_ip)]
+    pub device_ip: Vec<String>,
+
+    /// Network interface to find TVs and serve media on: a name (en0), an
+    /// address, or a subnet (192.168.1.0/24) [default: all]
+    #[arg(long, global = true, env = "LOCALCAST_INTERFACE")]
+    pub interface: Option<InterfaceFilter>,
+
+    /// UDP port Wake-on-LAN packets are sent to [default: 9]
+    #[arg(long, global = true, env = "LOCALCAST_WAKE_PORT")]
+    pub wake_port: Option<u16>,
+
+    /// Seconds a TV woken with Wake-on-LAN may take to answer [default: 60]
+    #[arg(long, global = true, env = "LOCALCAST_WAKE_TIMEOUT", value_parser = parse_seconds)]
+    pub wake_timeout: Option<f64>,
+}
+
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs > 0.0 => Ok(secs),
+        _ => Err("expected a positive number of seconds".into()),
+    }
+}
+
+fn parse_device_url(s: &str) -> Result<String, String> {
+    match s.parse::<http02::Uri>() {
+        Ok(uri) if matches!(uri.scheme_str(), Some("http" | "https" | "cast" | "airplay" | "kodi" | "roku"))
+            && uri.host().is_some() =>
+        {
+            Ok(s.to_string())
+        }
+        _ => Err("expected an http://, cast://, airplay://, kodi:// or roku:// URL".into()),
+    }
+}
+
+fn parse_device_ip(s: &str) -> Result<String, String> {
+    match net::parse_host_port(s) {
+        Ok(_) => Ok(s.to_string()),
+        Err(_) => Err("expected an IP address, optionally with a port ([fe80::1%eth0]:8000)".into()),
+    }
+}
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0    success
+  1    other error
+  2    invalid arguments or file
+  3    no matching TV found
+  4    the TV rejected the command
+  5    the TV did not respond
+  6    the cast did not start, or playback was lost and could not be resumed
+  130  interrupted";
+
+/// Headless commands for scripts. Commands other than `cast` talk to the TV
+/// directly, so they also control a cast started by another localcast.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// List the TVs found on the network
+    List {
+        /// Print a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the network interfaces TVs can be found on
+    Interfaces {
+        /// Print a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Cast files and keep serving them until playback ends
+    Cast {
+        /// Video files to cast, played in order
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[command(flatten)]
+        target: DeviceTarget,
+        /// Port for the HTTP media server (0 = auto-assign)
+        #[arg(short, long)]
+        port: Option<u16>,
+        /// Wake the TV from standby with Wake-on-LAN first; it must have
+        /// been seen on the network before
+        #[arg(long)]
+        wake: bool,
+    },
+    /// Resume playback
+    Play {
+        #[command(flatten)]
+        target: DeviceTarget,
+    },
+    /// Pause playback
+    Pause {
+        #[command(flatten)]
+        target: DeviceTarget,
+    },
+    /// Stop playback
+    Stop {
+        #[command(flatten)]
+        target: DeviceTarget,
+    },
+    /// Seek to a position: SECONDS, MM:SS or HH:MM:SS; prefix + or - to seek relative
+    Seek {
+        #[arg(allow_hyphen_values = true)]
+        time: String,
+        #[command(flatten)]
+        target: DeviceTarget,
+    },
+    /// Show what the TV is playing
+    Status {
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        target: DeviceTarget,
+    },
+    /// Show the volume, or set it: LEVEL (0-100) or +N / -N
+    Volume {
+        #[arg(allow_hyphen_values = true)]
+        level: Option<String>,
+        #[command(flatten)]
+        target: DeviceTarget,
+    },
+    /// Share folders with TVs as a UPnP media server, to browse from the TV
+    ///
+    /// The server shows up in the TV's media browser (as a source, or under
+    /// media servers) until localcast is stopped with Ctrl-C.
+    Serve {
+        /// Folders to share [default: media_folders from the config file]
+        folders: Vec<PathBuf>,
+        /// Name TVs list the server under [default: localcast on <host name>]
+        #[arg(long)]
+        name: Option<String>,
+        /// Port for the server (0 = auto-assign)
+        #[arg(short, long)]
+        port: Option<u16>,
+    },
+    /// Keep a cast session running and accept JSON commands on a Unix socket
+    ///
+    /// Each line sent to the socket is a request such as
+    /// {"command": ["seek", 90], "request_id": 1}; each reply is a line with the
+    /// same request_id and "error": "success" or a message. Send ["subscribe"]
+    /// to receive status and events as they happen. Example:
+    ///
+    ///   echo '{"command": ["toggle_pause"]}' | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/localcast.sock
+    #[command(verbatim_doc_comment)]
+    Daemon {
+        /// Socket path [default: $XDG_RUNTIME_DIR/localcast.sock; required without it]
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+}
+
+/// Which TV a headless command talks to.
+#[derive(clap::Args, Debug, Clone)]
+pub struct DeviceTarget {
+    /// TV name (or a unique part of it), UDN or IP address; may be omitted
+    /// when only one TV is on the network
+    #[arg(short, long)]
+    pub device: Option<String>,
+
+    /// The TV used last time, without discovery
+    #[arg(long, conflicts_with = "device")]
+    pub last: bool,
+}
+
+/// Options after layering flags (and their environment variables, which
+/// clap reads) over the config file over the built-in defaults.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub port: u16,
+    pub api_bind: SocketAddr,
+    pub discovery_timeout: Duration,
+    pub search_interval: Duration,
+    pub poll_interval: Duration,
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    pub request_retries: u32,
+    pub fetch_timeout: Duration,
+    pub start_timeout: Duration,
+    pub recovery_attempts: u32,
+    pub recovery_backoff: Duration,
+    pub seek_step: u64,
+    pub seek_step_long: u64,
+    pub log_file: PathBuf,
+    /// Where to remember TVs between runs; `None` if there is no home directory.
+    pub cache_file: Option<PathBuf>,
+    /// TVs to add without discovery: description URLs, cast://, airplay://,
+    /// kodi:// and roku:// URLs and IP addresses.
+    pub devices: Vec<String>,
+    /// Interfaces to search and serve media on; all if `None`.
+    pub interface: Option<InterfaceFilter>,
+    pub wake_port: u16,
+    pub wake_timeout: Duration,
+    /// Folders `serve` shares when none are named.
+    pub media_folders: Vec<PathBuf>,
+    pub server_name: Option<String>,
+    /// The config file, for its per-device sections.
+    pub config: Config,
+}
+
+impl Args {
+    /// Load the config file and resolve every option.
+    pub fn settings(&self) -> Result<Settings, AppError> {
+        let config = Config::load_or_default(self.config.as_deref())?;
+        let secs = |flag: Option<f64>, file: Option<f64>, default: f64| {
+            Duration::from_secs_f64(flag.or(file).unwrap_or(default))
+        };
+
+        Ok(Settings {
+            port: self.port.or(config.port).unwrap_or(0),
+            api_bind: self
+                .api_bind
+                .or(config.api_bind)
+                .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 8080))),
+            discovery_timeout: secs(self.discovery_timeout, config.discovery_timeout, 5.0),
+            search_interval: secs(self.search_interval, config.search_interval, 60.0),
+            poll_interval: secs(self.poll_interval, config.poll_interval, 1.0),
+            connect_timeout: secs(self.connect_timeout, config.connect_timeout, 3.0),
+            request_timeout: secs(self.request_timeout, config.request_timeout, 5.0),
+            request_retries: self.request_retries.or(config.request_retries).unwrap_or(2),
+            fetch_timeout: secs(self.fetch_timeout, config.fetch_timeout, 10.0),
+            start_timeout: secs(self.start_timeout, config.start_timeout, 20.0),
+            recovery_attempts: self.recovery_attempts.or(config.recovery_attempts).unwrap_or(3),
+            recovery_backoff: Duration::from_secs(
+                self.recovery_backoff.or(config.recovery_backoff).unwrap_or(2),
+            ),
+            seek_step: self.seek_step.or(config.seek_step).unwrap_or(30),
+            seek_step_long: self.seek_step_long.or(config.seek_step_long).unwrap_or(300),
+            // Use $TMPDIR so this works when launched from inside a .app bundle (cwd = /).
+            log_file: self.log_file.clone().or(config.log_file.clone()).unwrap_or_else(|| {
+                std::env::var("TMPDIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| std::env::temp_dir())
+                    .join("localcast.log")
+            }),
+            cache_file: self
+                .cache_file
+                .clone()
+                .or(config.cache_file.clone())
+                .or_else(DeviceCache::default_path),
+            devices: self.device_url.iter().chain(&self.device_ip).cloned().collect(),
+            interface: match &self.interface {
+                Some(interface) => Some(interface.clone()),
+                // Validated when the config was loaded
+                None => config.interface.as_deref().and_then(|s| s.parse().ok()),
+            },
+            wake_port: self.wake_port.or(config.wake_port).unwrap_or(wol::DEFAULT_PORT),
+            wake_timeout: secs(self.wake_timeout, config.wake_timeout, 60.0),
+            media_folders: config.media_folders.clone().unwrap_or_default(),
+            server_name: config.server_name.clone(),
+            config,
+        })
+    }
+}
+
+impl Settings {
+    /// Timeouts and retries for talking to a TV outside a session.
+    pub fn soap_config(&self) -> SoapConfig {
+        let mut config = SoapConfig::default();
+        config.connect_timeout = self.connect_timeout;
+        config.request_timeout = self.request_timeout;
+        config.retries = self.request_retries;
+        config
+    }
+
+    pub fn session_builder(&self) -> SessionBuilder {
+        let builder = SessionBuilder::default()
+            .media_port(self.port)
+            .discovery_timeout(self.discovery_timeout)
+            .search_interval(self.search_interval)
+            .poll_interval(self.poll_interval)
+            .recovery_attempts(self.recovery_attempts)
+            .recovery_backoff(self.recovery_backoff)
+            .connect_timeout(self.connect_timeout)
+            .request_timeout(self.request_timeout)
+            .request_retries(self.request_retries)
+            .fetch_timeout(self.fetch_timeout)
+            .start_timeout(self.start_timeout)
+            .wake_port(self.wake_port)
+            .wake_timeout(self.wake_timeout)
+            .config(self.config.clone());
+        let builder = match &self.interface {
+            Some(interface) => builder.interface(interface.clone()),
+            None => builder,
+        };
+        let builder = match &self.cache_file {
+            Some(path) => builder.device_cache(path),
+            None => builder,
+        };
+        self.devices
+            .iter()
+            .fold(builder, |builder, address| builder.device(address.clone()))
+    }
 }
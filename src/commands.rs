@@ -0,0 +1,486 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use localcast::dlna::client::SoapClient;
+use localcast::dlna::types::{format_duration, parse_duration, PlaybackState};
+use localcast::dms::ContentServer;
+use localcast::{
+    discovery, net, renderer, AppError, CachedDevice, Device, DeviceCache, DeviceRef, Renderer,
+    SessionCommand, SessionError, SessionEvent,
+};
+
+use crate::cli::{Command, DeviceTarget, Settings};
+
+/// Exit codes of the headless commands, listed in `--help`.
+mod exit {
+    pub const FAILURE: u8 = 1;
+    pub const USAGE: u8 = 2;
+    pub const NO_DEVICE: u8 = 3;
+    pub const REJECTED: u8 = 4;
+    pub const UNREACHABLE: u8 = 5;
+    pub const CAST_FAILED: u8 = 6;
+    pub const INTERRUPTED: u8 = 130;
+}
+
+/// A failed command: the message for stderr and the exit code.
+struct Failure {
+    code: u8,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: u8, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<AppError> for Failure {
+    fn from(e: AppError) -> Self {
+        let code = match e {
+            AppError::FileNotFound(_) | AppError::UnsupportedFormat(_) | AppError::Config(_) => exit::USAGE,
+            AppError::NoDevicesFound | AppError::DeviceNotFound(_) => exit::NO_DEVICE,
+            AppError::DlnaAction(_)
+            | AppError::CastAction(_)
+            | AppError::AirPlayAction(_)
+            | AppError::KodiAction(_)
+            | AppError::RokuAction(_)
+            | AppError::Unsupported(_) => exit::REJECTED,
+            AppError::Timeout(_) | AppError::NetworkError(_) => exit::UNREACHABLE,
+            _ => exit::FAILURE,
+        };
+        let message = match e {
+            AppError::NoDevicesFound => format!(
+                "{e}; if multicast is blocked, name the TV with --device-ip or --device-url"
+            ),
+            e => e.to_string(),
+        };
+        Self::new(code, message)
+    }
+}
+
+impl From<SessionError> for Failure {
+    fn from(e: SessionError) -> Self {
+        let code = match e {
+            SessionError::Invalid(_) => exit::USAGE,
+            SessionError::NotAllowed(_) => exit::REJECTED,
+            SessionError::Cast(_) | SessionError::Cancelled { .. } => exit::CAST_FAILED,
+            SessionError::Timeout(_) => exit::UNREACHABLE,
+            _ => exit::FAILURE,
+        };
+        Self::new(code, e.to_string())
+    }
+}
+
+/// Run a headless command and return the process exit code.
+pub async fn run(command: Command, settings: &Settings) -> ExitCode {
+    let result = match command {
+        Command::List { json } => list(settings, json).await,
+        Command::Interfaces { json } => interfaces(settings, json),
+        Command::Cast {
+            files,
+            target,
+            port,
+            wake,
+        } => cast(settings, files, &target, port, wake).await,
+        Command::Play { target } => play(settings, &target).await,
+        Command::Pause { target } => pause(settings, &target).await,
+        Command::Stop { target } => stop(settings, &target).await,
+        Command::Seek { time, target } => seek(settings, &time, &target).await,
+        Command::Status { json, target } => status(settings, json, &target).await,
+        Command::Volume { level, target } => volume(settings, level.as_deref(), &target).await,
+        Command::Serve { folders, name, port } => serve(settings, folders, name, port).await,
+        Command::Daemon { .. } => {
+            Err(Failure::new(exit::USAGE, "daemon mode needs Unix domain sockets"))
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("localcast: {}", failure.message);
+            ExitCode::from(failure.code)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DeviceJson<'a> {
+    name: &'a str,
+    udn: &'a str,
+    protocol: &'a str,
+    ip: &'a str,
+    url: String,
+    interface: Option<&'a str>,
+    mac: Option<String>,
+}
+
+/// The TVs given with --device-url/--device-ip, or else the ones discovery finds.
+/// Either way they are remembered in the device cache.
+async fn find_devices(settings: &Settings) -> Result<Vec<Device>, Failure> {
+    let devices = if settings.devices.is_empty() {
+        discovery::discover_devices_on(settings.discovery_timeout, settings.interface.as_ref()).await?
+    } else {
+        let mut devices = Vec::new();
+        for address in &settings.devices {
+            devices.push(discovery::device_at(address).await?);
+        }
+        devices
+    };
+    update_cache(settings, |cache| devices.iter().for_each(|d| cache.remember(d)));
+    Ok(devices)
+}
+
+/// Change the device cache, warning if it can't be written.
+fn update_cache(settings: &Settings, change: impl FnOnce(&mut DeviceCache)) {
+    if let Some(path) = &settings.cache_file {
+        if let Err(e) = DeviceCache::update(path, change) {
+            tracing::warn!("{e}");
+        }
+    }
+}
+
+/// The TV last cast to, from the device cache.
+fn last_device(settings: &Settings) -> Result<CachedDevice, Failure> {
+    settings
+        .cache_file
+        .as_deref()
+        .and_then(|path| DeviceCache::load(path).last_used().cloned())
+        .ok_or_else(|| Failure::new(exit::NO_DEVICE, "no TV was cast to before; pick one with --device"))
+}
+
+async fn list(settings: &Settings, json: bool) -> Result<(), Failure> {
+    let devices = find_devices(settings).await?;
+    if json {
+        let entries: Vec<DeviceJson> = devices
+            .iter()
+            .map(|d| DeviceJson {
+                name: &d.friendly_name,
+                udn: &d.udn,
+                protocol: d.protocol.id(),
+                ip: d.device_url.host().unwrap_or_default().trim_matches(['[', ']']),
+                url: d.device_url.to_string(),
+                interface: d.interface.as_deref(),
+                mac: d.mac.map(|mac| mac.to_string()),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap_or_default());
+    } else if devices.is_empty() {
+        return Err(AppError::NoDevicesFound.into());
+    } else {
+        for d in &devices {
+            let ip = d.device_url.host().unwrap_or_default().trim_matches(['[', ']']);
+            let interface = d.interface.as_deref().unwrap_or("-");
+            println!("{}\t{ip}\t{interface}\t{}", d.friendly_name, d.udn);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct InterfaceJson<'a> {
+    name: &'a str,
+    address: String,
+    /// Whether discovery searches on it with the current settings.
+    selected: bool,
+}
+
+fn interfaces(settings: &Settings, json: bool) -> Result<(), Failure> {
+    let all = net::interfaces()?;
+    let selected = net::eligible_interfaces(settings.interface.as_ref())?;
+    let entries: Vec<InterfaceJson> = all
+        .iter()
+        .map(|i| InterfaceJson {
+            name: &i.name,
+            address: i.addr.to_string(),
+            selected: selected.contains(i),
+        })
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap_or_default());
+    } else {
+        for i in &entries {
+            let mark = if i.selected { "*" } else { " " };
+            println!("{mark} {}\t{}", i.name, i.address);
+        }
+    }
+    Ok(())
+}
+
+/// Find the target TV, or the only TV on the network if none was named.
+async fn find_target(settings: &Settings, target: &DeviceTarget) -> Result<Device, Failure> {
+    if target.last {
+        let cached = last_device(settings)?;
+        return cached
+            .to_device()
+            .ok_or_else(|| Failure::new(exit::NO_DEVICE, "the cached TV has an invalid URL"));
+    }
+    let devices = find_devices(settings).await?;
+    let device = match (&target.device, devices.as_slice()) {
+        (_, []) => return Err(AppError::NoDevicesFound.into()),
+        (Some(query), devices) => discovery::find_device(devices, query)?,
+        (None, [device]) => device,
+        (None, _) => {
+            return Err(Failure::new(
+                exit::USAGE,
+                "several TVs found; pick one with --device (see `localcast list`)",
+            ))
+        }
+    };
+    Ok(device.clone())
+}
+
+/// Find the target TV and connect to it, at its cached control URL if
+/// it is the last one cast to.
+async fn connect(settings: &Settings, target: &DeviceTarget) -> Result<Arc<dyn Renderer>, Failure> {
+    let control_url = if target.last { last_device(settings)?.control_url } else { None };
+    let device = find_target(settings, target).await?;
+    let client = SoapClient::new(settings.soap_config());
+    Ok(renderer::connect(&device, control_url.as_deref(), &client).await?)
+}
+
+/// The target TV in a session's device list.
+fn target_device<'a>(settings: &Settings, target: &DeviceTarget, devices: &'a [Device]) -> Result<&'a Device, Failure> {
+    let device = match (&target.device, devices) {
+        _ if target.last => {
+            let last = last_device(settings)?;
+            devices
+                .iter()
+                .find(|d| d.udn == last.udn)
+                .ok_or_else(|| Failure::new(exit::NO_DEVICE, format!("{} is not listed", last.friendly_name)))?
+        }
+        (_, []) => return Err(AppError::NoDevicesFound.into()),
+        (Some(query), devices) => discovery::find_device(devices, query)?,
+        (None, [device]) => device,
+        (None, _) => {
+            return Err(Failure::new(
+                exit::USAGE,
+                "several TVs found; pick one with --device (see `localcast list`)",
+            ))
+        }
+    };
+    Ok(device)
+}
+
+/// Cast through a session and keep serving until playback ends.
+async fn cast(
+    settings: &Settings,
+    files: Vec<PathBuf>,
+    target: &DeviceTarget,
+    port: Option<u16>,
+    wake: bool,
+) -> Result<(), Failure> {
+    let mut builder = settings.session_builder();
+    if let Some(port) = port {
+        builder = builder.media_port(port);
+    }
+    let session = builder.start().await?;
+    session.request(SessionCommand::SetFiles(files)).await?;
+
+    // Devices given by address, and cached ones, were added as the session
+    // started, ahead of the request above. A TV in standby won't answer a
+    // search, so one to be woken is taken from the cache if it is there.
+    let listed = session.state();
+    let skip_search = !settings.devices.is_empty()
+        || target.last
+        || (wake && target_device(settings, target, &listed.devices).is_ok());
+    let state = if skip_search {
+        listed
+    } else {
+        session.request(SessionCommand::Discover).await?
+    };
+    // By UDN, as discovery may reorder the list before the session gets
+    // to the command
+    let device = target_device(settings, target, &state.devices)?;
+    let udn = Some(DeviceRef::Udn(device.udn.clone()));
+    let command = if wake {
+        eprintln!("Waking {}", device.friendly_name);
+        SessionCommand::WakeAndCast { device: udn }
+    } else {
+        SessionCommand::Cast { device: udn }
+    };
+    session.request(command).await?;
+    eprintln!("Casting to {}", session.state().device_name);
+
+    // The TV streams from our media server, so stay up until it is done
+    let mut watch = session.watch();
+    let mut events = session.subscribe();
+    let mut started = false;
+    let outcome = loop {
+        tokio::select! {
+            // A failed recovery leaves playback stopped, which mustn't pass
+            // for the end of the item; the event comes before the state
+            biased;
+            event = events.recv() => {
+                if let Ok(SessionEvent::RecoveryFailed(message)) = event {
+                    break Err(Failure::new(exit::CAST_FAILED, message));
+                }
+            }
+            changed = watch.changed() => {
+                if changed.is_err() {
+                    break Ok(());
+                }
+                let s = watch.borrow_and_update().clone();
+                match s.playback_state {
+                    PlaybackState::Playing => started = true,
+                    PlaybackState::Stopped | PlaybackState::NoMediaPresent
+                        if started && s.recovering.is_none() =>
+                    {
+                        break Ok(());
+                    }
+                    _ => {}
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break Err(Failure::new(exit::INTERRUPTED, "interrupted")),
+        }
+    };
+
+    let _ = session.request(SessionCommand::Quit).await;
+    outcome
+}
+
+async fn play(settings: &Settings, target: &DeviceTarget) -> Result<(), Failure> {
+    connect(settings, target).await?.play("1").await?;
+    Ok(())
+}
+
+async fn pause(settings: &Settings, target: &DeviceTarget) -> Result<(), Failure> {
+    connect(settings, target).await?.pause().await?;
+    Ok(())
+}
+
+async fn stop(settings: &Settings, target: &DeviceTarget) -> Result<(), Failure> {
+    connect(settings, target).await?.stop().await?;
+    Ok(())
+}
+
+async fn seek(settings: &Settings, time: &str, target: &DeviceTarget) -> Result<(), Failure> {
+    let (relative, time) = match time.as_bytes().first() {
+        Some(b'+') => (Some(1), &time[1..]),
+        Some(b'-') => (Some(-1), &time[1..]),
+        _ => (None, time),
+    };
+    let secs = parse_time(time)
+        .ok_or_else(|| Failure::new(exit::USAGE, format!("invalid time '{time}'")))?;
+
+    let renderer = connect(settings, target).await?;
+    let target_secs = match relative {
+        Some(sign) => {
+            let position = renderer.position().await?;
+            (position.elapsed_secs as i64 + sign * secs as i64).max(0) as u64
+        }
+        None => secs,
+    };
+    renderer.seek(target_secs).await?;
+    Ok(())
+}
+
+/// Parse SECONDS, MM:SS or HH:MM:SS.
+fn parse_time(time: &str) -> Option<u64> {
+    if time.is_empty() || !time.chars().all(|c| c.is_ascii_digit() || c == ':') {
+        return None;
+    }
+    match time.matches(':').count() {
+        0 => time.parse().ok(),
+        1 => Some(parse_duration(&format!("0:{time}"))),
+        2 => Some(parse_duration(time)),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+struct StatusJson<'a> {
+    device: &'a str,
+    state: &'a str,
+    elapsed_secs: u64,
+    duration_secs: u64,
+    title: Option<&'a str>,
+    uri: &'a str,
+}
+
+async fn status(settings: &Settings, json: bool, target: &DeviceTarget) -> Result<(), Failure> {
+    let renderer = connect(settings, target).await?;
+    let device = renderer.device();
+    let state = renderer.status().await?;
+    let position = renderer.position().await?;
+    let media = renderer.media_info().await.unwrap_or_default();
+
+    if json {
+        let status = StatusJson {
+            device: &device.friendly_name,
+            state: state.label(),
+            elapsed_secs: position.elapsed_secs,
+            duration_secs: position.duration_secs,
+            title: media.title.as_deref(),
+            uri: &media.current_uri,
+        };
+        println!("{}", serde_json::to_string_pretty(&status).unwrap_or_default());
+    } else {
+        println!(
+            "{}: {} {} / {}",
+            device.friendly_name,
+            state.label(),
+            format_duration(position.elapsed_secs),
+            format_duration(position.duration_secs)
+        );
+        if !media.current_uri.is_empty() {
+            println!("{}", media.display_name());
+        }
+    }
+    Ok(())
+}
+
+async fn volume(settings: &Settings, level: Option<&str>, target: &DeviceTarget) -> Result<(), Failure> {
+    let renderer = connect(settings, target).await?;
+
+    if let Some(level) = level {
+        let invalid = || Failure::new(exit::USAGE, format!("invalid volume '{level}'"));
+        let volume = match level.as_bytes().first() {
+            Some(b'+' | b'-') => {
+                let delta: i64 = level.parse().map_err(|_| invalid())?;
+                let current = renderer.volume().await?;
+                (current as i64 + delta).clamp(0, 100) as u32
+            }
+            _ => level.parse().ok().filter(|v| *v <= 100).ok_or_else(invalid)?,
+        };
+        renderer.set_volume(volume).await?;
+    }
+    println!("{}", renderer.volume().await?);
+    Ok(())
+}
+
+/// Share folders as a UPnP media server until interrupted.
+async fn serve(
+    settings: &Settings,
+    folders: Vec<PathBuf>,
+    name: Option<String>,
+    port: Option<u16>,
+) -> Result<(), Failure> {
+    let folders = if folders.is_empty() { settings.media_folders.clone() } else { folders };
+    if folders.is_empty() {
+        return Err(Failure::new(
+            exit::USAGE,
+            "no folders to share; name some or set media_folders in the config file",
+        ));
+    }
+    let mut builder = ContentServer::builder().port(port.unwrap_or(settings.port));
+    for folder in folders {
+        builder = builder.folder(folder);
+    }
+    if let Some(name) = name.or_else(|| settings.server_name.clone()) {
+        builder = builder.name(name);
+    }
+    if let Some(interface) = &settings.interface {
+        builder = builder.interface(interface.clone());
+    }
+    let server = builder.start().await?;
+    eprintln!("Sharing as \"{}\" on port {}; press Ctrl-C to stop", server.name(), server.port());
+
+    let _ = tokio::signal::ctrl_c().await;
+    server.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! The `config.toml` file: global defaults and per-device settings.
+//!
+//! ```toml
+//! discovery_timeout = 3
+//! interface = "192.168.1.0/24"
+//! api_bind = "0.0.0.0:8080"
+//!
+//! [device."Living Room TV"]
+//! volume = 20
+//! subtitle_language = "en"
+//! quirks = ["no-gapless"]
+//!
+//! [device."192.168.1.40"]
+//! seek_step = 10
+//! ```
+//!
+//! Every key is optional. Front-ends layer their command-line flags and
+//! environment variables over the values read here.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::AppError;
+use crate::net::{Host, InterfaceFilter};
+use crate::renderer::Device;
+
+/// Global defaults from the config file; `None` means not set there.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Config {
+    /// Port for the HTTP media server (0 = auto-assign).
+    pub port: Option<u16>,
+    /// Address the HTTP API listens on.
+    pub api_bind: Option<SocketAddr>,
+    /// Seconds to search for renderers.
+    pub discovery_timeout: Option<f64>,
+    /// Seconds between background searches for renderers.
+    pub search_interval: Option<f64>,
+    /// Network interface to search and serve media on: a name such as `en0`,
+    /// an address, or a subnet such as `192.168.1.0/24`.
+    pub interface: Option<String>,
+    /// Seconds between playback status polls while casting.
+    pub poll_interval: Option<f64>,
+    /// Seconds allowed to connect to a renderer.
+    pub connect_timeout: Option<f64>,
+    /// Seconds allowed for each request to a renderer.
+    pub request_timeout: Option<f64>,
+    /// Extra attempts for status queries that fail.
+    pub request_retries: Option<u32>,
+    /// Seconds a renderer may take to fetch the media after Play.
+    pub fetch_timeout: Option<f64>,
+    /// Seconds a renderer may take to start playing once it has fetched the media.
+    pub start_timeout: Option<f64>,
+    /// Times to try resuming when the renderer drops playback.
+    pub recovery_attempts: Option<u32>,
+    /// Seconds before the first resume attempt.
+    pub recovery_backoff: Option<u64>,
+    /// Seconds skipped by a short seek.
+    pub seek_step: Option<u64>,
+    /// Seconds skipped by a long seek.
+    pub seek_step_long: Option<u64>,
+    /// Log file path.
+    pub log_file: Option<PathBuf>,
+    /// Where to remember renderers between runs.
+    pub cache_file: Option<PathBuf>,
+    /// UDP port Wake-on-LAN packets are sent to.
+    pub wake_port: Option<u16>,
+    /// Seconds a renderer woken with Wake-on-LAN may take to answer.
+    pub wake_timeout: Option<f64>,
+    /// Folders `localcast serve` shares when none are named.
+    pub media_folders: Option<Vec<PathBuf>>,
+    /// Name TVs list `localcast serve` under.
+    pub server_name: Option<String>,
+    /// Per-device settings keyed by UDN, friendly name or IP address:
+    /// `[device."<udn, name or address>"]`.
+    #[serde(default, rename = "device")]
+    pub devices: BTreeMap<String, DeviceConfig>,
+}
+
+/// Settings for one renderer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct DeviceConfig {
+    /// Language tag of the sidecar subtitles to send, e.g. "en" for `movie.en.srt`.
+    pub subtitle_language: Option<String>,
+    /// Volume (0-100) to set once a cast has started.
+    pub volume: Option<u32>,
+    /// Known renderer bugs to work around.
+    #[serde(default)]
+    pub quirks: Vec<Quirk>,
+    /// Seconds skipped by a short seek on this device.
+    pub seek_step: Option<u64>,
+}
+
+impl DeviceConfig {
+    pub fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+}
+
+/// A renderer bug that capability detection does not catch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Quirk {
+    /// Accepts `SetNextAVTransportURI` but never switches; use stop-then-set.
+    NoGapless,
+    /// Misbehaves after `SetPlayMode`; emulate repeat and shuffle locally.
+    NoPlayMode,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/localcast/config.toml`, or `~/.config/localcast/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("localcast").join("config.toml"))
+    }
+
+    /// Read and validate a config file.
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| AppError::Config(format!("cannot read {}: {e}", path.display())))?;
+        Self::parse(&text).map_err(|e| AppError::Config(format!("{}: {e}", path.display())))
+    }
+
+    /// Read `path` if given, otherwise the default file if it exists.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, AppError> {
+        match path {
+            Some(path) => Self::load(path),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => Self::load(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Parse and validate config file contents.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string().trim_end().to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check value ranges, naming the offending key.
+    fn validate(&self) -> Result<(), String> {
+        let seconds = [
+            ("discovery_timeout", self.discovery_timeout),
+            ("search_interval", self.search_interval),
+            ("poll_interval", self.poll_interval),
+            ("connect_timeout", self.connect_timeout),
+            ("request_timeout", self.request_timeout),
+            ("fetch_timeout", self.fetch_timeout),
+            ("start_timeout", self.start_timeout),
+            ("wake_timeout", self.wake_timeout),
+        ];
+        for (key, value) in seconds {
+            if value.is_some_and(|v| !v.is_finite() || v <= 0.0) {
+                return Err(format!("{key}: must be a positive number of seconds"));
+            }
+        }
+        for (key, value) in [("seek_step", self.seek_step), ("seek_step_long", self.seek_step_long)] {
+            if value == Some(0) {
+                return Err(format!("{key}: must be at least 1 second"));
+            }
+        }
+
+        if self.server_name.as_ref().is_some_and(|name| name.trim().is_empty()) {
+            return Err("server_name: must not be empty".into());
+        }
+
+        if let Some(interface) = &self.interface {
+            interface
+                .parse::<InterfaceFilter>()
+                .map_err(|e| format!("interface: {e}"))?;
+        }
+
+        for (name, device) in &self.devices {
+            let key = |field: &str| format!("device.{name:?}.{field}");
+            if device.volume.is_some_and(|v| v > 100) {
+                return Err(format!("{}: must be between 0 and 100", key("volume")));
+            }
+            if device.seek_step == Some(0) {
+                return Err(format!("{}: must be at least 1 second", key("seek_step")));
+            }
+            if let Some(language) = &device.subtitle_language {
+                if language.is_empty() || !language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                    return Err(format!(
+                        "{}: expected a language tag such as \"en\" or \"pt-BR\"",
+                        key("subtitle_language")
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings for `device`, matched by UDN (with or without `uuid:`),
+    /// case-insensitive friendly name, or the IP address in its URL.
+    pub fn device(&self, device: &Device) -> Option<&DeviceConfig> {
+        let udn = device.udn.strip_prefix("uuid:").unwrap_or(&device.udn);
+        let ip = device.device_url.host().and_then(|host| host.parse::<Host>().ok()).map(|host| host.ip);
+        self.devices.iter().find_map(|(key, config)| {
+            let key_udn = key.strip_prefix("uuid:").unwrap_or(key);
+            let key_ip = key.parse::<Host>().ok().map(|host| host.ip);
+            (key_udn.eq_ignore_ascii_case(udn)
+                || key.eq_ignore_ascii_case(&device.friendly_name)
+                || (key_ip.is_some() && key_ip == ip))
+                .then_some(config)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::Protocol;
+
+    fn device(name: &str, udn: &str, url: &str) -> Device {
+        Device {
+            friendly_name: name.into(),
+            udn: udn.into(),
+            protocol: Protocol::Dlna,
+            device_url: url.parse().unwrap(),
+            interface: None,
+            stale: false,
+            mac: None,
+            dlna: None,
+        }
+    }
+
+    fn error(text: &str) -> String {
+        Config::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn parses_globals_and_devices() {
+        let config = Config::parse(
+            r#"
+            discovery_timeout = 2.5
+            interface = "192.168.1.0/24"
+            api_bind = "0.0.0.0:8080"
+
+            [device."Living Room TV"]
+            volume = 20
+            subtitle_language = "pt-BR"
+            quirks = ["no-gapless", "no-play-mode"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.discovery_timeout, Some(2.5));
+        assert_eq!(config.api_bind, Some("0.0.0.0:8080".parse().unwrap()));
+        let tv = &config.devices["Living Room TV"];
+        assert_eq!(tv.volume, Some(20));
+        assert!(tv.has_quirk(Quirk::NoGapless) && tv.has_quirk(Quirk::NoPlayMode));
+        assert_eq!(Config::parse("").unwrap().devices.len(), 0);
+    }
+
+    #[test]
+    fn bad_timeouts_name_the_key() {
+        assert!(error("discovery_timeout = 0").starts_with("discovery_timeout:"));
+        assert!(error("request_timeout = -1.5").starts_with("request_timeout:"));
+        assert!(error("wake_timeout = nan").starts_with("wake_timeout:"));
+        assert!(error("poll_interval = inf").starts_with("poll_interval:"));
+        assert!(error("seek_step_long = 0").starts_with("seek_step_long:"));
+        // Wrong types are caught by the parser, which names the key too
+        assert!(error(r#"connect_timeout = "5""#).contains("connect_timeout"));
+    }
+
+    #[test]
+    fn bad_device_settings_name_the_key() {
+        let quirk = error("[device.tv]\nquirks = [\"no-subtitles\"]");
+        assert!(quirk.contains("no-subtitles"), "{quirk}");
+        assert!(error("[device.tv]\nvolume = 101").starts_with(r#"device."tv".volume:"#));
+        assert!(error("[device.tv]\nseek_step = 0").starts_with(r#"device."tv".seek_step:"#));
+        assert!(error("[device.tv]\nsubtitle_language = \"en us\"").starts_with(r#"device."tv".subtitle_language:"#));
+        assert!(error("[device.tv]\nbrightness = 3").contains("brightness"));
+    }
+
+    #[test]
+    fn other_bad_values_are_refused() {
+        assert!(error("colour = true").contains("colour"));
+        assert!(error(r#"interface = "10.0.0.0/40""#).starts_with("interface:"));
+        assert!(error(r#"server_name = "  ""#).starts_with("server_name:"));
+    }
+
+    #[test]
+    fn devices_match_by_udn_name_or_address() {
+        let config = Config::parse(
+            r#"
+            [device."uuid:AAAA-1111"]
+            volume = 1
+            [device."bedroom tv"]
+            volume = 2
+            [device."192.168.1.40"]
+            volume = 3
+            [device."fe80::1"]
+            volume = 4
+            "#,
+        )
+        .unwrap();
+        let volume = |d: &Device| config.device(d).and_then(|c| c.volume);
+
+        assert_eq!(volume(&device("Living Room", "uuid:aaaa-1111", "http://192.168.1.5:8000/")), Some(1));
+        assert_eq!(volume(&device("Living Room", "AAAA-1111", "http://192.168.1.5:8000/")), Some(1));
+        assert_eq!(volume(&device("Bedroom TV", "uuid:bbbb", "http://192.168.1.6:8000/")), Some(2));
+        assert_eq!(volume(&device("Kodi", "kodi-x", "kodi://192.168.1.40:8080")), Some(3));
+        assert_eq!(volume(&device("Kodi", "kodi-y", "kodi://[fe80::1%eth0]:8080")), Some(4));
+        assert_eq!(volume(&device("Bedroom", "uuid:cccc", "http://192.168.1.41:8000/")), None);
+    }
+}
@@ -0,0 +1,436 @@
+use std::fs::DirBuilder;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use localcast::dlna::types::PlayMode;
+use localcast::{CastSession, Device, DeviceRef, SessionBuilder, SessionCommand, SessionError, SessionEvent};
+
+use crate::api::state;
+
+/// Default socket path: `$XDG_RUNTIME_DIR/localcast.sock`. Only that
+/// directory is sure to be the user's alone; in the shared temp directory
+/// other local users could reach the socket, so without it a path must be
+/// given.
+pub fn default_socket_path() -> Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("localcast.sock")),
+        _ => bail!("XDG_RUNTIME_DIR is not set; pass --socket with a path in a directory only you can open"),
+    }
+}
+
+/// Keep a session running in the background and serve newline-delimited JSON
+/// commands on a Unix socket until a client sends `quit` or we get Ctrl-C.
+pub async fn run(builder: SessionBuilder, socket: Option<PathBuf>) -> Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => default_socket_path()?,
+    };
+    let listener = bind(&socket).await?;
+    let session = builder.start().await.context("Failed to start session")?;
+    let mut events = session.subscribe();
+    session.send(SessionCommand::Discover);
+
+    tracing::info!("Daemon listening on {}", socket.display());
+    eprintln!("LocalCast daemon listening on {}", socket.display());
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(stream, session.clone()));
+                }
+                Err(e) => tracing::warn!("Failed to accept daemon connection: {e}"),
+            },
+            event = events.recv() => {
+                if let Ok(SessionEvent::Closed) | Err(broadcast::error::RecvError::Closed) = event {
+                    break;
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                let _ = session.request(SessionCommand::Quit).await;
+                break;
+            }
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket);
+    tracing::info!("Daemon stopped");
+    Ok(())
+}
+
+/// Bind the socket, replacing a stale one left by a daemon that crashed.
+async fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).await.is_ok() {
+            bail!("A localcast daemon is already listening on {}", socket.display());
+        }
+        std::fs::remove_file(socket)
+            .with_context(|| format!("Cannot remove stale socket {}", socket.display()))?;
+    }
+
+    // Only the current user may control the TV through us. Bind in a private
+    // directory and move the socket into place once its permissions are
+    // set, so nobody else can connect in between.
+    let parent = socket.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    let private = parent.join(format!(".localcast-{}-{nanos}", std::process::id()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Cannot create {}", private.display()))?;
+    let staged = private.join("socket");
+    let listener = UnixListener::bind(&staged)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, socket)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Cannot listen on {}", socket.display()));
+    let _ = std::fs::remove_dir_all(&private);
+    listener
+}
+
+/// A request line: `{"command": ["seek", 90], "request_id": 1}`.
+#[derive(Deserialize)]
+struct Request {
+    command: Vec<Value>,
+    #[serde(default)]
+    request_id: Option<Value>,
+}
+
+/// Serve one client. Requests run concurrently, so a slow `cast` does not
+/// hold up a `cancel` sent after it; replies carry the request's `request_id`.
+async fn serve_client(stream: UnixStream, session: CastSession) {
+    let (reader, mut writer) = stream.into_split();
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+
+    let write_task = tokio::spawn(async move {
+        while let Some(message) = out_rx.recv().await {
+            let mut line = message.to_string();
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subscription: Option<JoinHandle<()>> = None;
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = out_tx.send(json!({ "error": format!("invalid request: {e}") }));
+                continue;
+            }
+        };
+        let request_id = request.request_id.unwrap_or(Value::Null);
+        let name = request.command.first().and_then(Value::as_str).unwrap_or_default();
+
+        // Subscriptions belong to the connection, so they are handled here
+        match name {
+            "subscribe" => {
+                if subscription.is_none() {
+                    subscription = Some(tokio::spawn(forward_events(session.clone(), out_tx.clone())));
+                }
+                let _ = out_tx.send(reply(request_id, Ok(Value::Null)));
+                continue;
+            }
+            "unsubscribe" => {
+                if let Some(task) = subscription.take() {
+                    task.abort();
+                }
+                let _ = out_tx.send(reply(request_id, Ok(Value::Null)));
+                continue;
+            }
+            _ => {}
+        }
+
+        let session = session.clone();
+        let out_tx = out_tx.clone();
+        tokio::spawn(async move {
+            let result = execute(&session, &request.command).await;
+            let _ = out_tx.send(reply(request_id, result));
+        });
+    }
+
+    if let Some(task) = subscription {
+        task.abort();
+    }
+    drop(out_tx);
+    let _ = write_task.await;
+}
+
+/// Build a reply in mpv's style: `error` is "success" or the failure message.
+fn reply(request_id: Value, result: Result<Value, String>) -> Value {
+    match result {
+        Ok(data) => json!({ "request_id": request_id, "error": "success", "data": data }),
+        Err(message) => json!({ "request_id": request_id, "error": message }),
+    }
+}
+
+/// Stream status changes and session events to a subscribed client.
+async fn forward_events(session: CastSession, out_tx: mpsc::UnboundedSender<Value>) {
+    let mut watch = session.watch();
+    let mut events = session.subscribe();
+    loop {
+        let message = tokio::select! {
+            changed = watch.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let status = state::status_response(&watch.borrow_and_update());
+                json!({ "event": "status", "data": status })
+            }
+            event = events.recv() => match event {
+                Ok(SessionEvent::CastStarted) => json!({ "event": "cast-started" }),
+                Ok(SessionEvent::Disconnected) => json!({ "event": "disconnected" }),
+                Ok(SessionEvent::Notice(message)) => json!({ "event": "notice", "message": message }),
+                Ok(SessionEvent::Error(message)) => json!({ "event": "error", "message": message }),
+                Ok(SessionEvent::RecoveryFailed(message)) => json!({ "event": "recovery-failed", "message": message }),
+                Ok(SessionEvent::DeviceAdded(d)) => device_event("device-added", &d),
+                Ok(SessionEvent::DeviceUpdated(d)) => device_event("device-updated", &d),
+                Ok(SessionEvent::DeviceRemoved(d)) => device_event("device-removed", &d),
+                Ok(SessionEvent::Closed) => json!({ "event": "shutdown" }),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if out_tx.send(message).is_err() {
+            break;
+        }
+    }
+}
+
+fn device_event(event: &str, device: &Device) -> Value {
+    json!({
+        "event": event,
+        "udn": device.udn,
+        "name": device.friendly_name,
+        "protocol": device.protocol.id(),
+        "interface": device.interface,
+        "stale": device.stale,
+        "mac": device.mac.map(|mac| mac.to_string()),
+    })
+}
+
+fn arg_index(args: &[Value], i: usize) -> Result<usize, String> {
+    args.get(i)
+        .and_then(Value::as_u64)
+        .map(|n| n as usize)
+        .ok_or_else(|| format!("argument {i} must be a non-negative integer"))
+}
+
+/// A device by index in the device list, or by UDN.
+fn arg_device(args: &[Value], i: usize) -> Result<DeviceRef, String> {
+    match args.get(i) {
+        Some(Value::String(udn)) => Ok(DeviceRef::Udn(udn.clone())),
+        _ => arg_index(args, i).map(DeviceRef::Index),
+    }
+}
+
+fn arg_str(args: &[Value], i: usize) -> Result<&str, String> {
+    args.get(i)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("argument {i} must be a string"))
+}
+
+fn arg_seconds(args: &[Value], i: usize) -> Result<Duration, String> {
+    args.get(i)
+        .and_then(Value::as_f64)
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("argument {i} must be a positive number of seconds"))
+}
+
+/// Run one command against the session and return the reply data.
+async fn execute(session: &CastSession, command: &[Value]) -> Result<Value, String> {
+    let name = command.first().and_then(Value::as_str).unwrap_or_default();
+    let session_command = match name {
+        "get_status" => {
+            return Ok(json!(state::status_response(&session.state())));
+        }
+        "get_devices" => {
+            return Ok(json!(state::device_list_response(&session.state()).devices));
+        }
+        "get_queue" => return Ok(json!(state::queue_response(&session.state()))),
+        "cancel" => {
+            session.cancel();
+            return Ok(Value::Null);
+        }
+        _ => session_command(name, command)?,
+    };
+
+    let discover = matches!(
+        session_command,
+        SessionCommand::Discover | SessionCommand::DiscoverFor(_)
+    );
+    match session.request(session_command).await {
+        Ok(s) if discover => Ok(json!(state::device_list_response(&s).devices)),
+        Ok(s) => Ok(json!(state::status_response(&s))),
+        Err(SessionError::Cast(failure)) => Err(format!("{failure} [{}]", failure.step.id())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// The session command for request `command`, named `name`.
+fn session_command(name: &str, command: &[Value]) -> Result<SessionCommand, String> {
+    Ok(match name {
+        "discover" => match command.get(1) {
+            None => SessionCommand::Discover,
+            Some(_) => SessionCommand::DiscoverFor(arg_seconds(command, 1)?),
+        },
+        "select_device" => SessionCommand::SelectDevice(arg_index(command, 1)?),
+        "cast" => SessionCommand::Cast {
+            device: command.get(1).map(|_| arg_device(command, 1)).transpose()?,
+        },
+        "wake_and_cast" => SessionCommand::WakeAndCast {
+            device: command.get(1).map(|_| arg_device(command, 1)).transpose()?,
+        },
+        "load" => {
+            let files = command[1..]
+                .iter()
+                .map(|v| v.as_str().map(PathBuf::from))
+                .collect::<Option<Vec<_>>>()
+                .filter(|files| !files.is_empty())
+                .ok_or("load takes one or more file paths")?;
+            SessionCommand::SetFiles(files)
+        }
+        "enqueue" => SessionCommand::Enqueue(PathBuf::from(arg_str(command, 1)?)),
+        "remove" => SessionCommand::RemoveItem(arg_index(command, 1)?),
+        "move" => SessionCommand::MoveItem {
+            from: arg_index(command, 1)?,
+            to: arg_index(command, 2)?,
+        },
+        "clear" => SessionCommand::ClearQueue,
+        "jump" => SessionCommand::Jump(arg_index(command, 1)?),
+        "next" => SessionCommand::Next,
+        "previous" => SessionCommand::Previous,
+        "play" => SessionCommand::Play,
+        "pause" => SessionCommand::Pause,
+        "toggle_pause" => SessionCommand::TogglePlayPause,
+        "stop" => SessionCommand::Stop,
+        "seek" => {
+            // mpv clients send fractions, e.g. ["seek", 12.5]
+            let secs = command
+                .get(1)
+                .and_then(Value::as_f64)
+                .filter(|secs| secs.is_finite())
+                .ok_or("seek takes a number of seconds")?
+                .round() as i64;
+            match command.get(2).and_then(Value::as_str) {
+                Some("relative") => SessionCommand::SeekBy(secs),
+                None | Some("absolute") => SessionCommand::Seek(secs.max(0) as u64),
+                Some(other) => return Err(format!("unknown seek mode '{other}'")),
+            }
+        }
+        "set_play_mode" => {
+            let mode = arg_str(command, 1)?;
+            SessionCommand::SetPlayMode(
+                PlayMode::from_upnp(mode).ok_or_else(|| format!("unknown play mode '{mode}'"))?,
+            )
+        }
+        "set_speed" => SessionCommand::SetSpeed(arg_str(command, 1)?.to_string()),
+        "disconnect" => SessionCommand::Disconnect,
+        "quit" => SessionCommand::Quit,
+        "" => return Err("missing command".into()),
+        other => return Err(format!("unknown command '{other}'")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command: Value) -> Result<SessionCommand, String> {
+        let command = command.as_array().unwrap().clone();
+        let name = command.first().and_then(Value::as_str).unwrap_or_default();
+        session_command(name, &command)
+    }
+
+    #[test]
+    fn seek_rounds_fractional_seconds() {
+        assert!(matches!(parse(json!(["seek", 90])), Ok(SessionCommand::Seek(90))));
+        assert!(matches!(parse(json!(["seek", 12.5])), Ok(SessionCommand::Seek(13))));
+        assert!(matches!(parse(json!(["seek", 12.4, "absolute"])), Ok(SessionCommand::Seek(12))));
+        assert!(matches!(parse(json!(["seek", -10])), Ok(SessionCommand::Seek(0))));
+        assert!(matches!(parse(json!(["seek", -5.6, "relative"])), Ok(SessionCommand::SeekBy(-6))));
+        assert!(matches!(parse(json!(["seek", 30, "relative"])), Ok(SessionCommand::SeekBy(30))));
+    }
+
+    #[test]
+    fn bad_seeks_are_rejected() {
+        assert_eq!(parse(json!(["seek"])).unwrap_err(), "seek takes a number of seconds");
+        assert_eq!(parse(json!(["seek", "90"])).unwrap_err(), "seek takes a number of seconds");
+        assert_eq!(parse(json!(["seek", 90, "sideways"])).unwrap_err(), "unknown seek mode 'sideways'");
+    }
+
+    #[test]
+    fn devices_are_named_by_index_or_udn() {
+        assert!(matches!(parse(json!(["cast"])), Ok(SessionCommand::Cast { device: None })));
+        assert!(matches!(
+            parse(json!(["cast", 2])),
+            Ok(SessionCommand::Cast { device: Some(DeviceRef::Index(2)) })
+        ));
+        match parse(json!(["wake_and_cast", "uuid:tv"])) {
+            Ok(SessionCommand::WakeAndCast { device: Some(DeviceRef::Udn(udn)) }) => assert_eq!(udn, "uuid:tv"),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(parse(json!(["cast", -1])).unwrap_err(), "argument 1 must be a non-negative integer");
+        assert!(parse(json!(["cast", true])).is_err());
+        assert!(parse(json!(["select_device", "uuid:tv"])).is_err());
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        assert!(matches!(parse(json!(["discover"])), Ok(SessionCommand::Discover)));
+        match parse(json!(["discover", 1.5])) {
+            Ok(SessionCommand::DiscoverFor(timeout)) => assert_eq!(timeout, Duration::from_millis(1500)),
+            other => panic!("{other:?}"),
+        }
+        assert!(parse(json!(["discover", 0])).is_err());
+
+        match parse(json!(["load", "a.mp4", "b.mkv"])) {
+            Ok(SessionCommand::SetFiles(files)) => assert_eq!(files, [PathBuf::from("a.mp4"), PathBuf::from("b.mkv")]),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(parse(json!(["load"])).unwrap_err(), "load takes one or more file paths");
+        assert!(parse(json!(["load", "a.mp4", 2])).is_err());
+
+        assert!(matches!(parse(json!(["move", 3, 0])), Ok(SessionCommand::MoveItem { from: 3, to: 0 })));
+        assert!(parse(json!(["move", 3])).is_err());
+        assert!(matches!(
+            parse(json!(["set_play_mode", "REPEAT_ALL"])),
+            Ok(SessionCommand::SetPlayMode(PlayMode::RepeatAll))
+        ));
+        assert_eq!(parse(json!(["set_play_mode", "LOOP"])).unwrap_err(), "unknown play mode 'LOOP'");
+    }
+
+    #[test]
+    fn unknown_and_missing_commands_are_errors() {
+        assert_eq!(parse(json!(["rewind"])).unwrap_err(), "unknown command 'rewind'");
+        assert_eq!(parse(json!([])).unwrap_err(), "missing command");
+        assert_eq!(parse(json!([42])).unwrap_err(), "missing command");
+    }
+
+    #[test]
+    fn replies_follow_mpv() {
+        assert_eq!(
+            reply(json!(7), Ok(Value::Null)),
+            json!({ "request_id": 7, "error": "success", "data": null })
+        );
+        assert_eq!(reply(Value::Null, Err("no".into())), json!({ "request_id": null, "error": "no" }));
+    }
+}
@@ -0,0 +1,104 @@
+use async_trait::async_trait;
+use mdns_sd::ServiceInfo;
+
+use super::mdns::{MdnsDiscovery, MdnsService};
+use super::{route_interface, DESCRIPTION_TIMEOUT, PROBE_TIMEOUT};
+use crate::airplay::client::AirPlayClient;
+use crate::airplay::{self, FEATURE_VIDEO, SERVICE_TYPE};
+use crate::error::AppError;
+use crate::net::{self, Host};
+use crate::renderer::{Device, DeviceUrl, Protocol};
+use crate::wol;
+
+/// Keeps the list of AirPlay video receivers current from their mDNS
+/// `_airplay._tcp` announcements.
+pub type AirPlayDiscovery = MdnsDiscovery<AirPlay>;
+
+/// AirPlay video as found over mDNS.
+pub struct AirPlay;
+
+#[async_trait]
+impl MdnsService for AirPlay {
+    const PROTOCOL: Protocol = Protocol::AirPlay;
+    const SERVICE_TYPE: &'static str = SERVICE_TYPE;
+
+    fn device_from(info: &ServiceInfo) -> Option<Device> {
+        device_from(info)
+    }
+
+    async fn device_at(address: &str) -> Result<Device, AppError> {
+        device_at(address).await
+    }
+
+    async fn probe(device: &Device) -> Result<(), AppError> {
+        let base_url = airplay::base_url(&device.device_url)?;
+        AirPlayClient::new(base_url, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT)
+            .server_info()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Build an AirPlay device from its address alone, without mDNS: an
+/// `airplay://host[:port]` URL or an IP address, optionally with a port. The
+/// device is asked what it is to make sure it plays video.
+pub async fn device_at(address: &str) -> Result<Device, AppError> {
+    let address = address.trim();
+    let host_port = match address.split_once("://") {
+        Some(("airplay", rest)) => rest.trim_end_matches('/'),
+        Some(_) => return Err(AppError::DeviceNotFound(format!("{address} is not an AirPlay address"))),
+        None => address,
+    };
+    let (host, port) = net::parse_host_port(host_port).map_err(AppError::DeviceNotFound)?;
+    let port = port.unwrap_or(airplay::DEFAULT_PORT);
+    let device_url: DeviceUrl = airplay::device_url(&host, port)
+        .parse()
+        .map_err(|e| AppError::DeviceNotFound(format!("Invalid AirPlay address {address}: {e}")))?;
+    let client = AirPlayClient::new(airplay::base_url(&device_url)?, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT);
+    let info = client.server_info().await?;
+    if info.features.is_some_and(|f| f & FEATURE_VIDEO == 0) {
+        return Err(AppError::DeviceNotFound(format!("the AirPlay receiver at {host} doesn't play video")));
+    }
+
+    let device_id = info.device_id.as_deref();
+    let mut device = Device {
+        friendly_name: info.name.clone().unwrap_or_else(|| format!("Apple TV at {host}")),
+        udn: match device_id {
+            Some(id) => airplay::udn(id),
+            None => format!("airplay-{host}-{port}"),
+        },
+        protocol: Protocol::AirPlay,
+        device_url,
+        interface: None,
+        stale: false,
+        mac: wol::neighbor_mac(&host).await.or_else(|| device_id?.parse().ok()),
+        dlna: None,
+    };
+    device.interface = route_interface(&device);
+    tracing::info!("Found AirPlay device at {}", device.device_url);
+    Ok(device)
+}
+
+/// The device behind an mDNS record, if it plays video. IPv4 addresses are
+/// preferred.
+fn device_from(info: &ServiceInfo) -> Option<Device> {
+    let device_id = info.get_property_val_str("deviceid")?;
+    let features = airplay::parse_features(info.get_property_val_str("features")?)?;
+    if features & FEATURE_VIDEO == 0 {
+        return None;
+    }
+    let ip = info.get_addresses().iter().min_by_key(|ip| ip.is_ipv6())?;
+    let host = Host { ip: *ip, zone: None };
+    let instance = info.get_fullname().trim_end_matches(SERVICE_TYPE).trim_end_matches('.');
+    Some(Device {
+        friendly_name: instance.to_string(),
+        udn: airplay::udn(device_id),
+        protocol: Protocol::AirPlay,
+        device_url: airplay::device_url(&host, info.get_port()).parse().ok()?,
+        interface: None,
+        stale: false,
+        // The device ID is one of its MAC addresses
+        mac: device_id.parse().ok(),
+        dlna: None,
+    })
+}
@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+
+use super::ssdp_discovery::{SsdpDiscovery, SsdpService};
+use super::{describe, dlna_device_at, is_candidate_target, SEARCH_TARGETS};
+use crate::error::AppError;
+use crate::renderer::{Device, Protocol};
+
+/// Keeps the list of DLNA renderers current from their SSDP announcements.
+pub type DlnaDiscovery = SsdpDiscovery<Dlna>;
+
+/// DLNA renderers: devices with an AVTransport service.
+pub struct Dlna;
+
+#[async_trait]
+impl SsdpService for Dlna {
+    const PROTOCOL: Protocol = Protocol::Dlna;
+    const SEARCH_TARGETS: &'static [&'static str] = SEARCH_TARGETS;
+
+    fn is_candidate_target(target: &str) -> bool {
+        is_candidate_target(target)
+    }
+
+    async fn describe(location: &str) -> Result<Option<Device>, AppError> {
+        describe(location).await
+    }
+
+    async fn device_at(address: &str) -> Result<Device, AppError> {
+        dlna_device_at(address).await
+    }
+}
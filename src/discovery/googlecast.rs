@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use mdns_sd::ServiceInfo;
+use serde_json::json;
+
+use super::mdns::{MdnsDiscovery, MdnsService};
+use super::{route_interface, DESCRIPTION_TIMEOUT, PROBE_TIMEOUT};
+use crate::error::AppError;
+use crate::googlecast::client::CastClient;
+use crate::googlecast::{self, NS_RECEIVER, RECEIVER_ID, SERVICE_TYPE};
+use crate::net::{self, Host};
+use crate::renderer::{Device, Protocol};
+use crate::wol;
+
+/// Keeps the list of Google Cast devices current from their mDNS
+/// `_googlecast._tcp` announcements.
+pub type GoogleCastDiscovery = MdnsDiscovery<GoogleCast>;
+
+/// Google Cast as found over mDNS.
+pub struct GoogleCast;
+
+#[async_trait]
+impl MdnsService for GoogleCast {
+    const PROTOCOL: Protocol = Protocol::GoogleCast;
+    const SERVICE_TYPE: &'static str = SERVICE_TYPE;
+
+    fn device_from(info: &ServiceInfo) -> Option<Device> {
+        device_from(info)
+    }
+
+    async fn device_at(address: &str) -> Result<Device, AppError> {
+        device_at(address).await
+    }
+
+    /// Connecting is enough: the channel only opens on a Cast device.
+    async fn probe(device: &Device) -> Result<(), AppError> {
+        let addr = googlecast::socket_addr(&device.device_url)?;
+        CastClient::connect(addr, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT).await?;
+        Ok(())
+    }
+}
+
+/// Build a Cast device from its address alone, without mDNS: a
+/// `cast://host[:port]` URL or an IP address, optionally with a port. The
+/// device is asked for its status to make sure it speaks Cast.
+pub async fn device_at(address: &str) -> Result<Device, AppError> {
+    let address = address.trim();
+    let host_port = match address.split_once("://") {
+        Some(("cast", rest)) => rest.trim_end_matches('/'),
+        Some(_) => return Err(AppError::DeviceNotFound(format!("{address} is not a Cast address"))),
+        None => address,
+    };
+    let (host, port) = net::parse_host_port(host_port).map_err(AppError::DeviceNotFound)?;
+    let port = port.unwrap_or(googlecast::DEFAULT_PORT);
+    let client = CastClient::connect(host.socket_addr(port)?, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT).await?;
+    client
+        .request(RECEIVER_ID, NS_RECEIVER, json!({ "type": "GET_STATUS" }))
+        .await?;
+
+    // The Cast channel doesn't say what the device is called
+    let mut device = Device {
+        friendly_name: format!("Chromecast at {host}"),
+        udn: format!("cast-{host}-{port}"),
+        protocol: Protocol::GoogleCast,
+        device_url: googlecast::device_url(&host, port)
+            .parse()
+            .map_err(|e| AppError::DeviceNotFound(format!("Invalid Cast address {address}: {e}")))?,
+        interface: None,
+        stale: false,
+        mac: wol::neighbor_mac(&host).await,
+        dlna: None,
+    };
+    device.interface = route_interface(&device);
+    tracing::info!("Found Cast device at {}", device.device_url);
+    Ok(device)
+}
+
+/// The device behind an mDNS record, if it has the fields every Cast
+/// device sends. IPv4 addresses are preferred.
+fn device_from(info: &ServiceInfo) -> Option<Device> {
+    let id = info.get_property_val_str("id")?;
+    let ip = info.get_addresses().iter().min_by_key(|ip| ip.is_ipv6())?;
+    let host = Host { ip: *ip, zone: None };
+    let instance = info.get_fullname().trim_end_matches(SERVICE_TYPE).trim_end_matches('.');
+    let friendly_name = info.get_property_val_str("fn").unwrap_or(instance);
+    Some(Device {
+        friendly_name: friendly_name.to_string(),
+        udn: googlecast::udn(id),
+        protocol: Protocol::GoogleCast,
+        device_url: googlecast::device_url(&host, info.get_port()).parse().ok()?,
+        interface: None,
+        stale: false,
+        mac: None,
+        dlna: None,
+    })
+}
@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use mdns_sd::ServiceInfo;
+use serde_json::json;
+
+use super::mdns::{MdnsDiscovery, MdnsService};
+use super::{route_interface, DESCRIPTION_TIMEOUT, PROBE_TIMEOUT};
+use crate::error::AppError;
+use crate::kodi::client::KodiClient;
+use crate::kodi::{self, SERVICE_TYPE};
+use crate::net::{self, Host};
+use crate::renderer::{Device, DeviceUrl, Protocol};
+use crate::wol;
+
+/// Keeps the list of Kodi instances current from their mDNS
+/// `_xbmc-jsonrpc-h._tcp` announcements.
+pub type KodiDiscovery = MdnsDiscovery<Kodi>;
+
+/// Kodi as found over mDNS.
+pub struct Kodi;
+
+#[async_trait]
+impl MdnsService for Kodi {
+    const PROTOCOL: Protocol = Protocol::Kodi;
+    const SERVICE_TYPE: &'static str = SERVICE_TYPE;
+
+    fn device_from(info: &ServiceInfo) -> Option<Device> {
+        device_from(info)
+    }
+
+    async fn device_at(address: &str) -> Result<Device, AppError> {
+        device_at(address).await
+    }
+
+    async fn probe(device: &Device) -> Result<(), AppError> {
+        KodiClient::new(&device.device_url, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT)?
+            .call("JSONRPC.Ping", json!({}))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Build a Kodi device from its address alone, without mDNS: a
+/// `kodi://[user:password@]host[:port][?websocket=<port>]` URL or an IP
+/// address, optionally with a port. Kodi is asked for its name to make sure
+/// it answers JSON-RPC.
+pub async fn device_at(address: &str) -> Result<Device, AppError> {
+    let address = address.trim();
+    let (address_without_query, query) = match address.split_once('?') {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (address, None),
+    };
+    let (user_info, host_port) = match address_without_query.split_once("://") {
+        Some(("kodi", rest)) => {
+            let rest = rest.trim_end_matches('/');
+            match rest.rsplit_once('@') {
+                Some((user_info, host_port)) => (Some(user_info), host_port),
+                None => (None, rest),
+            }
+        }
+        Some(_) => return Err(AppError::DeviceNotFound(format!("{address} is not a Kodi address"))),
+        None => (None, address_without_query),
+    };
+    let (host, port) = net::parse_host_port(host_port).map_err(AppError::DeviceNotFound)?;
+    let port = port.unwrap_or(kodi::DEFAULT_PORT);
+    let url = kodi::device_url(&host, port);
+    let url = match user_info {
+        Some(user_info) => url.replacen("://", &format!("://{user_info}@"), 1),
+        None => url,
+    };
+    let url = match query {
+        Some(query) => format!("{url}?{query}"),
+        None => url,
+    };
+    let device_url: DeviceUrl = url
+        .parse()
+        .map_err(|e| AppError::DeviceNotFound(format!("Invalid Kodi address {address}: {e}")))?;
+    let client = KodiClient::new(&device_url, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT)?;
+    let labels = client
+        .call("XBMC.GetInfoLabels", json!({ "labels": ["System.FriendlyName"] }))
+        .await?;
+    let name = labels["System.FriendlyName"].as_str().filter(|name| !name.is_empty());
+
+    // JSON-RPC doesn't say the uuid Kodi advertises
+    let mut device = Device {
+        friendly_name: name.map(str::to_string).unwrap_or_else(|| format!("Kodi at {host}")),
+        udn: format!("kodi-{host}-{port}"),
+        protocol: Protocol::Kodi,
+        device_url,
+        interface: None,
+        stale: false,
+        mac: wol::neighbor_mac(&host).await,
+        dlna: None,
+    };
+    device.interface = route_interface(&device);
+    tracing::info!("Found Kodi at {}", device.device_url);
+    Ok(device)
+}
+
+/// The instance behind an mDNS record. IPv4 addresses are preferred.
+fn device_from(info: &ServiceInfo) -> Option<Device> {
+    let ip = info.get_addresses().iter().min_by_key(|ip| ip.is_ipv6())?;
+    let host = Host { ip: *ip, zone: None };
+    let port = info.get_port();
+    let instance = info.get_fullname().trim_end_matches(SERVICE_TYPE).trim_end_matches('.');
+    Some(Device {
+        friendly_name: instance.to_string(),
+        udn: match info.get_property_val_str("uuid") {
+            Some(uuid) => kodi::udn(uuid),
+            None => format!("kodi-{host}-{port}"),
+        },
+        protocol: Protocol::Kodi,
+        device_url: kodi::device_url(&host, port).parse().ok()?,
+        interface: None,
+        stale: false,
+        mac: None,
+        dlna: None,
+    })
+}
@@ -0,0 +1,445 @@
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+use super::{route_interface, DiscoveryEvent, DiscoveryProvider};
+use crate::error::AppError;
+use crate::net::{self, Host, InterfaceFilter};
+use crate::renderer::{Device, Protocol};
+use crate::wol;
+
+/// A protocol whose devices announce themselves over mDNS, tracked by
+/// [`MdnsDiscovery`].
+#[async_trait]
+pub trait MdnsService: Send + Sync + 'static {
+    const PROTOCOL: Protocol;
+
+    /// The service type its devices announce, e.g. `_googlecast._tcp.local.`.
+    const SERVICE_TYPE: &'static str;
+
+    /// The device behind an mDNS record, if it is one we can cast to.
+    fn device_from(info: &ServiceInfo) -> Option<Device>;
+
+    /// Build a device from its address alone, without mDNS.
+    async fn device_at(address: &str) -> Result<Device, AppError>;
+
+    /// Check that a device remembered from an earlier run is up.
+    async fn probe(device: &Device) -> Result<(), AppError>;
+}
+
+/// Keeps the list of devices of one [`MdnsService`] current in the
+/// background from their mDNS announcements, querying again periodically.
+/// Devices leave the list when they say goodbye or their records expire.
+///
+/// Cloning gives another handle to the same service; it stops when the last
+/// handle is dropped.
+pub struct MdnsDiscovery<S> {
+    commands: mpsc::UnboundedSender<Command>,
+    devices: watch::Receiver<Vec<Device>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+    service: PhantomData<fn() -> S>,
+}
+
+impl<S> Clone for MdnsDiscovery<S> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+            devices: self.devices.clone(),
+            events: self.events.clone(),
+            service: PhantomData,
+        }
+    }
+}
+
+impl<S: MdnsService> MdnsDiscovery<S> {
+    /// Start the service. A query goes out right away and then every
+    /// `search_interval`, on every interface matching `interface`, or every
+    /// non-loopback interface if `None`.
+    pub fn start(search_interval: Duration, interface: Option<&InterfaceFilter>) -> Result<Self, AppError> {
+        let daemon = ServiceDaemon::new().map_err(|e| AppError::NetworkError(format!("Cannot start mDNS: {e}")))?;
+        let interfaces = match interface {
+            Some(filter) => {
+                let names: Vec<String> = net::eligible_interfaces(Some(filter))?
+                    .into_iter()
+                    .map(|i| i.name)
+                    .collect();
+                daemon
+                    .disable_interface(IfKind::All)
+                    .and_then(|()| daemon.enable_interface(names.iter().cloned().map(IfKind::Name).collect::<Vec<_>>()))
+                    .map_err(|e| AppError::NetworkError(format!("Cannot pick mDNS interfaces: {e}")))?;
+                Some(names)
+            }
+            None => None,
+        };
+
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (devices_tx, devices) = watch::channel(Vec::new());
+        let (events, _) = broadcast::channel(64);
+        let (resolved_tx, resolved_rx) = mpsc::unbounded_channel();
+        let monitor = Monitor::<S> {
+            daemon,
+            browse: None,
+            interfaces,
+            tracked: Vec::new(),
+            searches: Vec::new(),
+            search_interval,
+            next_search: Instant::now(),
+            devices: devices_tx,
+            events: events.clone(),
+            resolved_tx,
+            service: PhantomData,
+        };
+        tokio::spawn(monitor.run(command_rx, resolved_rx));
+
+        Ok(Self {
+            commands,
+            devices,
+            events,
+            service: PhantomData,
+        })
+    }
+
+    fn send(&self, command: Command) -> Result<(), AppError> {
+        self.commands
+            .send(command)
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+}
+
+#[async_trait]
+impl<S: MdnsService> DiscoveryProvider for MdnsDiscovery<S> {
+    fn protocol(&self) -> Protocol {
+        S::PROTOCOL
+    }
+
+    fn devices(&self) -> Vec<Device> {
+        self.devices.borrow().clone()
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    async fn search_for(&self, timeout: Duration) -> Result<Vec<Device>, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::Search(timeout, SearchReply::List(reply)))?;
+        rx.await
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+
+    fn search_stream(&self, timeout: Duration) -> Result<mpsc::UnboundedReceiver<Device>, AppError> {
+        let (found, rx) = mpsc::unbounded_channel();
+        self.send(Command::Search(timeout, SearchReply::Stream(found)))?;
+        Ok(rx)
+    }
+
+    async fn device_at(&self, address: &str) -> Result<Device, AppError> {
+        S::device_at(address).await
+    }
+
+    /// Devices added by hand stay listed until they announce a goodbye.
+    async fn add(&self, device: Device) -> Result<(), AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::Add(device, reply))?;
+        rx.await
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+
+    /// Each cached device is checked with [`MdnsService::probe`].
+    async fn add_cached(&self, devices: Vec<Device>) -> Result<(), AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::AddCached(devices, reply))?;
+        rx.await
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+}
+
+/// Requests from [`MdnsDiscovery`] handles to the monitor task.
+enum Command {
+    Search(Duration, SearchReply),
+    /// Track a device added by hand; replies once it is listed.
+    Add(Device, oneshot::Sender<()>),
+    /// List stale devices from the cache and check them; replies once they
+    /// are listed.
+    AddCached(Vec<Device>, oneshot::Sender<()>),
+}
+
+/// Where the results of a search go.
+enum SearchReply {
+    /// Every known device, once the search is over.
+    List(oneshot::Sender<Vec<Device>>),
+    /// Each device as it answers.
+    Stream(mpsc::UnboundedSender<Device>),
+}
+
+/// A search waiting for its time to be up.
+struct PendingSearch {
+    deadline: Instant,
+    reply: SearchReply,
+    /// UDNs already streamed to the reply.
+    seen: HashSet<String>,
+}
+
+impl PendingSearch {
+    /// Pass `device` on to a streaming search, once per device.
+    fn found(&mut self, device: &Device) {
+        if let SearchReply::Stream(found) = &self.reply {
+            if self.seen.insert(device.udn.clone()) {
+                let _ = found.send(device.clone());
+            }
+        }
+    }
+
+    fn abandoned(&self) -> bool {
+        match &self.reply {
+            SearchReply::List(reply) => reply.is_closed(),
+            SearchReply::Stream(found) => found.is_closed(),
+        }
+    }
+}
+
+/// A device being tracked.
+struct Tracked {
+    device: Device,
+    /// mDNS instance name, for goodbyes; `None` until announced.
+    fullname: Option<String>,
+}
+
+/// A device from an announcement or a check, ready to be listed.
+struct Resolved {
+    device: Device,
+    fullname: Option<String>,
+}
+
+/// The task behind [`MdnsDiscovery`].
+struct Monitor<S> {
+    daemon: ServiceDaemon,
+    /// Events of the latest query; each new query replaces the last.
+    browse: Option<mdns_sd::Receiver<ServiceEvent>>,
+    /// Interfaces queried on, when the user picked some.
+    interfaces: Option<Vec<String>>,
+    tracked: Vec<Tracked>,
+    searches: Vec<PendingSearch>,
+    search_interval: Duration,
+    next_search: Instant,
+    devices: watch::Sender<Vec<Device>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+    resolved_tx: mpsc::UnboundedSender<Resolved>,
+    service: PhantomData<fn() -> S>,
+}
+
+impl<S: MdnsService> Monitor<S> {
+    async fn run(
+        mut self,
+        mut commands: mpsc::UnboundedReceiver<Command>,
+        mut resolved: mpsc::UnboundedReceiver<Resolved>,
+    ) {
+        loop {
+            let browse = self.browse.clone();
+            let next_event = async {
+                match &browse {
+                    Some(browse) => browse.recv_async().await.ok(),
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Search(timeout, reply)) => {
+                        self.search();
+                        let mut search = PendingSearch {
+                            deadline: Instant::now() + timeout,
+                            reply,
+                            seen: HashSet::new(),
+                        };
+                        for tracked in self.tracked.iter().filter(|t| !t.device.stale) {
+                            search.found(&tracked.device);
+                        }
+                        self.searches.push(search);
+                    }
+                    Some(Command::Add(device, reply)) => {
+                        self.track(device, None);
+                        let _ = reply.send(());
+                    }
+                    Some(Command::AddCached(devices, reply)) => {
+                        for device in devices {
+                            self.add_cached(device);
+                        }
+                        let _ = reply.send(());
+                    }
+                    None => break,
+                },
+                event = next_event => match event {
+                    Some(event) => self.on_event(event),
+                    None => {
+                        tracing::warn!("mDNS daemon stopped; {} devices are no longer tracked", S::PROTOCOL.label());
+                        self.browse = None;
+                    }
+                },
+                Some(resolved) = resolved.recv() => self.on_resolved(resolved),
+                _ = tokio::time::sleep_until(self.next_wake()) => {
+                    if Instant::now() >= self.next_search {
+                        self.search();
+                    }
+                }
+            }
+            self.answer_searches();
+        }
+        let _ = self.daemon.shutdown();
+    }
+
+    /// Query for devices. Browsing again replaces the last query and
+    /// replays the records already cached.
+    fn search(&mut self) {
+        self.next_search = Instant::now() + self.search_interval;
+        match self.daemon.browse(S::SERVICE_TYPE) {
+            Ok(browse) => self.browse = Some(browse),
+            Err(e) => tracing::warn!("mDNS query for {} failed: {e}", S::SERVICE_TYPE),
+        }
+    }
+
+    /// The next time something is due: a query or a search reply.
+    fn next_wake(&self) -> Instant {
+        self.searches
+            .iter()
+            .map(|s| s.deadline)
+            .fold(self.next_search, Instant::min)
+    }
+
+    fn on_event(&mut self, event: ServiceEvent) {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                let Some(mut device) = S::device_from(&info) else {
+                    tracing::debug!("Ignoring {}: not a {} device record", info.get_fullname(), S::PROTOCOL.label());
+                    return;
+                };
+                device.interface = route_interface(&device);
+                if let Some(names) = &self.interfaces {
+                    if !device.interface.as_ref().is_some_and(|name| names.contains(name)) {
+                        tracing::debug!("Ignoring {} outside the selected interfaces", device.friendly_name);
+                        return;
+                    }
+                }
+                // Read the MAC address off the main loop
+                let fullname = info.get_fullname().to_string();
+                let resolved = self.resolved_tx.clone();
+                tokio::spawn(async move {
+                    if let Some(host) = device.device_url.host().and_then(|h| h.parse::<Host>().ok()) {
+                        device.mac = wol::neighbor_mac(&host).await.or(device.mac);
+                    }
+                    let _ = resolved.send(Resolved {
+                        device,
+                        fullname: Some(fullname),
+                    });
+                });
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                if let Some(index) = self.tracked.iter().position(|t| t.fullname.as_ref() == Some(&fullname)) {
+                    let gone = self.tracked.remove(index);
+                    tracing::info!("Renderer left: {}", gone.device.friendly_name);
+                    self.publish(DiscoveryEvent::Removed(gone.device));
+                }
+            }
+            other => tracing::trace!("mDNS: {other:?}"),
+        }
+    }
+
+    fn on_resolved(&mut self, resolved: Resolved) {
+        let now = Instant::now();
+        for search in self.searches.iter_mut().filter(|s| now < s.deadline) {
+            search.found(&resolved.device);
+        }
+        self.track(resolved.device, resolved.fullname);
+    }
+
+    /// List a device from the cache and probe it in the background; it
+    /// stays listed, stale, if it doesn't answer.
+    fn add_cached(&mut self, device: Device) {
+        if self.tracked.iter().any(|t| t.device.udn == device.udn) {
+            return;
+        }
+        let outside = match (&self.interfaces, &device.interface) {
+            (Some(names), Some(name)) => !names.contains(name),
+            _ => false,
+        };
+        if outside {
+            return;
+        }
+        self.track(device.clone(), None);
+        let resolved = self.resolved_tx.clone();
+        tokio::spawn(async move {
+            match S::probe(&device).await {
+                Ok(()) => {
+                    let _ = resolved.send(Resolved {
+                        device: Device { stale: false, ..device },
+                        fullname: None,
+                    });
+                }
+                Err(e) => tracing::debug!("Cached {} is not up: {e}", device.friendly_name),
+            }
+        });
+    }
+
+    /// Start tracking `device`, or update it if it is already tracked.
+    fn track(&mut self, mut device: Device, fullname: Option<String>) {
+        match self.tracked.iter_mut().find(|t| t.device.udn == device.udn) {
+            Some(tracked) => {
+                device.mac = device.mac.or(tracked.device.mac);
+                let was_stale = tracked.device.stale;
+                let changed = tracked.device.friendly_name != device.friendly_name
+                    || tracked.device.device_url != device.device_url
+                    || tracked.device.stale != device.stale
+                    || tracked.device.mac != device.mac;
+                tracked.device = device.clone();
+                if fullname.is_some() {
+                    tracked.fullname = fullname;
+                }
+                if changed {
+                    if was_stale && !device.stale {
+                        tracing::info!("Cached renderer is up: {}", device.friendly_name);
+                    } else {
+                        tracing::info!("Renderer changed: {}", device.friendly_name);
+                    }
+                    self.publish(DiscoveryEvent::Updated(device));
+                }
+            }
+            None => {
+                if device.stale {
+                    tracing::info!("Listing cached renderer: {} ({})", device.friendly_name, device.device_url);
+                } else {
+                    tracing::info!("Renderer found: {} ({})", device.friendly_name, device.device_url);
+                }
+                self.tracked.push(Tracked {
+                    device: device.clone(),
+                    fullname,
+                });
+                self.publish(DiscoveryEvent::Added(device));
+            }
+        }
+    }
+
+    /// Reply to searches whose time is up. Streaming searches end by
+    /// closing the channel.
+    fn answer_searches(&mut self) {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.searches)
+            .into_iter()
+            .partition(|s| now >= s.deadline || s.abandoned());
+        self.searches = waiting;
+        for search in due {
+            if let SearchReply::List(reply) = search.reply {
+                let _ = reply.send(self.devices.borrow().clone());
+            }
+        }
+    }
+
+    fn publish(&self, event: DiscoveryEvent) {
+        self.devices
+            .send_replace(self.tracked.iter().map(|t| t.device.clone()).collect());
+        let _ = self.events.send(event);
+    }
+}
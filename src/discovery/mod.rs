@@ -0,0 +1,277 @@
+mod airplay;
+mod dlna;
+mod googlecast;
+mod kodi;
+mod mdns;
+mod roku;
+mod service;
+pub(crate) mod ssdp;
+mod ssdp_discovery;
+
+use std::time::Duration;
+
+use futures::future::{self, FutureExt};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use rupnp::ssdp::URN;
+
+use crate::error::AppError;
+use crate::net::{self, Host, InterfaceFilter};
+use crate::renderer::{Device, DeviceUrl, DlnaDevice, Protocol};
+use crate::wol;
+
+pub use airplay::AirPlayDiscovery;
+pub use dlna::DlnaDiscovery;
+pub use googlecast::GoogleCastDiscovery;
+pub use kodi::KodiDiscovery;
+pub use roku::RokuDiscovery;
+pub use service::{DiscoveryEvent, DiscoveryProvider, DiscoveryService};
+
+/// Targets each search asks for. Renderers should answer a search for any
+/// version up to their own, but some only answer the exact version they
+/// implement, and some only the generic `upnp:rootdevice` or `ssdp:all`.
+const SEARCH_TARGETS: &[&str] = &[
+    "urn:schemas-upnp-org:device:MediaRenderer:1",
+    "urn:schemas-upnp-org:device:MediaRenderer:2",
+    "urn:schemas-upnp-org:device:MediaRenderer:3",
+    "urn:schemas-upnp-org:service:AVTransport:1",
+    "upnp:rootdevice",
+    "ssdp:all",
+];
+
+/// Search interval for services started for a single search.
+const ONE_SHOT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How long to wait for a device description.
+const DESCRIPTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for each location tried when probing an address.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Ports and paths where renderers commonly serve their description, tried
+/// when only an IP address is known.
+const COMMON_LOCATIONS: &[(u16, &str)] = &[
+    (49152, "/description.xml"),
+    (49153, "/description.xml"),
+    (49154, "/description.xml"),
+    (49494, "/description.xml"),
+    (49152, "/rootDesc.xml"),
+    (9197, "/dmr"),
+    (55000, "/dmr/ddd.xml"),
+    (1400, "/xml/device_description.xml"),
+    (8080, "/description.xml"),
+    (80, "/description.xml"),
+];
+
+/// Discover DLNA devices with an AVTransport service of any version, in the
+/// root device or an embedded one, Google Cast devices, AirPlay video
+/// receivers, Kodi instances and Roku devices.
+/// Returns a list of devices found within the given timeout.
+pub async fn discover_devices(timeout: Duration) -> Result<Vec<Device>, AppError> {
+    discover_devices_on(timeout, None).await
+}
+
+/// Like [`discover_devices`], searching only on the interfaces matching
+/// `interface`.
+pub async fn discover_devices_on(
+    timeout: Duration,
+    interface: Option<&InterfaceFilter>,
+) -> Result<Vec<Device>, AppError> {
+    // One search is all we need; keep the background one from repeating it
+    let service = DiscoveryService::start(timeout, ONE_SHOT_INTERVAL, interface).await?;
+    service.search().await
+}
+
+/// Whether an SSDP notification or search type may belong to a renderer:
+/// a MediaRenderer device or AVTransport service of any version, or a root
+/// device whose description has to be read to tell.
+fn is_candidate_target(target: &str) -> bool {
+    if target == "upnp:rootdevice" {
+        return true;
+    }
+    match target.parse::<URN>() {
+        Ok(urn @ URN::Device(..)) => is_upnp_type(&urn, "MediaRenderer"),
+        Ok(urn @ URN::Service(..)) => is_upnp_type(&urn, "AVTransport"),
+        Err(_) => false,
+    }
+}
+
+/// Whether `urn` is the standard UPnP device or service type `typ`, any version.
+fn is_upnp_type(urn: &URN, typ: &str) -> bool {
+    urn.domain_name() == "schemas-upnp-org" && urn.typ() == typ && urn.version() >= 1
+}
+
+/// The renderer behind a device description, if it or one of its embedded
+/// devices has an AVTransport service. The newest version wins when there
+/// are several.
+fn renderer_from(device: &rupnp::Device) -> Option<Device> {
+    let service = device
+        .services_iter()
+        .filter(|s| is_upnp_type(s.service_type(), "AVTransport"))
+        .max_by_key(|s| s.service_type().version())?;
+    Some(Device {
+        friendly_name: device.friendly_name().to_string(),
+        udn: device.udn().to_string(),
+        protocol: Protocol::Dlna,
+        device_url: DeviceUrl::from_uri(device.url().clone()),
+        interface: None,
+        stale: false,
+        mac: None,
+        dlna: Some(DlnaDevice {
+            service_type: service.service_type().to_string(),
+        }),
+    })
+}
+
+/// Fetch the description at `location` and return the renderer it describes,
+/// with its MAC address if the neighbor table has it by now.
+/// `Ok(None)` means the device is not a renderer we can cast to.
+async fn describe(location: &str) -> Result<Option<Device>, AppError> {
+    let url: http02::Uri = location
+        .parse()
+        .map_err(|e| AppError::NetworkError(format!("Invalid device location {location}: {e}")))?;
+    let device = tokio::time::timeout(DESCRIPTION_TIMEOUT, rupnp::Device::from_url(url))
+        .await
+        .map_err(|_| AppError::Timeout(format!("device description at {location}")))?
+        .map_err(|e| AppError::NetworkError(format!("Cannot read device description at {location}: {e}")))?;
+    let Some(mut renderer) = renderer_from(&device) else {
+        return Ok(None);
+    };
+    if let Some(host) = device.url().host().and_then(|h| h.parse::<Host>().ok()) {
+        renderer.mac = wol::neighbor_mac(&host).await;
+    }
+    Ok(Some(renderer))
+}
+
+/// Build a renderer from its address alone, without searching: the URL of a
+/// DLNA device description, a `cast://`, `airplay://`, `kodi://` or `roku://`
+/// URL with a host and optional port, or an IP address (optionally with a port, and a zone for link-local
+/// IPv6 such as `fe80::1%eth0`), which every protocol is tried on.
+pub async fn device_at(address: &str) -> Result<Device, AppError> {
+    let address = address.trim();
+    match address.split_once("://") {
+        Some(("cast", _)) => googlecast::device_at(address).await,
+        Some(("airplay", _)) => airplay::device_at(address).await,
+        Some(("kodi", _)) => kodi::device_at(address).await,
+        Some(("roku", _)) => roku::device_at(address).await,
+        Some(_) => dlna_device_at(address).await,
+        None => {
+            let attempts = [
+                dlna_device_at(address).boxed(),
+                googlecast::device_at(address).boxed(),
+                airplay::device_at(address).boxed(),
+                kodi::device_at(address).boxed(),
+                roku::device_at(address).boxed(),
+            ];
+            future::select_ok(attempts).await.map(|(device, _)| device)
+        }
+    }
+}
+
+/// Build a DLNA renderer from its address alone, without SSDP: either the
+/// URL of its device description or an IP address, in which case common
+/// description locations are probed.
+async fn dlna_device_at(address: &str) -> Result<Device, AppError> {
+    let address = address.trim();
+    let mut device = if address.contains("://") {
+        let location = unescape_zone(address);
+        describe(&location).await?.ok_or_else(|| {
+            AppError::DeviceNotFound(format!("{address} does not describe a renderer with AVTransport"))
+        })?
+    } else if let Ok((host, port)) = net::parse_host_port(address) {
+        probe(&host, port).await?
+    } else {
+        return Err(AppError::DeviceNotFound(format!(
+            "'{address}' is neither an IP address nor a description URL"
+        )));
+    };
+    device.interface = route_interface(&device);
+    Ok(device)
+}
+
+/// Rewrite a URL's `%25`-escaped IPv6 zone (RFC 6874) to the bare `%` the
+/// system resolver expects.
+fn unescape_zone(location: &str) -> String {
+    let Some(host) = location.parse::<http02::Uri>().ok().and_then(|uri| uri.host().map(str::to_string)) else {
+        return location.to_string();
+    };
+    match host.parse::<Host>() {
+        Ok(parsed) if parsed.zone.is_some() => location.replacen(&host, &parsed.to_string(), 1),
+        _ => location.to_string(),
+    }
+}
+
+/// The interface the kernel routes to `device` through.
+fn route_interface(device: &Device) -> Option<String> {
+    net::interface_for(device.device_url.host()?).map(|i| i.name)
+}
+
+/// Try the common description locations on `host` (only those paths on
+/// `port` if given) at once and return the first renderer found.
+async fn probe(host: &Host, port: Option<u16>) -> Result<Device, AppError> {
+    let mut locations: Vec<String> = Vec::new();
+    for &(common_port, path) in COMMON_LOCATIONS {
+        let location = format!("http://{host}:{}{path}", port.unwrap_or(common_port));
+        if !locations.contains(&location) {
+            locations.push(location);
+        }
+    }
+
+    let tried = locations.len();
+    let mut attempts: FuturesUnordered<_> = locations
+        .into_iter()
+        .map(|location| async move {
+            let result = tokio::time::timeout(PROBE_TIMEOUT, describe(&location)).await;
+            (location, result)
+        })
+        .collect();
+    while let Some((location, result)) = attempts.next().await {
+        match result {
+            Ok(Ok(Some(device))) => {
+                tracing::info!("Found {} at {location}", device.friendly_name);
+                return Ok(device);
+            }
+            Ok(Ok(None)) => tracing::debug!("{location} is not a renderer"),
+            Ok(Err(e)) => tracing::debug!("Probing {location}: {e}"),
+            Err(_) => tracing::debug!("Probing {location}: timed out"),
+        }
+    }
+    Err(AppError::DeviceNotFound(format!(
+        "no renderer description at {host} (tried {tried} common locations); give its description URL instead"
+    )))
+}
+
+/// Pick a device by UDN (with or without the `uuid:` prefix), IP address or
+/// friendly name. Names match case-insensitively, and a unique partial name is
+/// accepted too.
+pub fn find_device<'a>(devices: &'a [Device], query: &str) -> Result<&'a Device, AppError> {
+    let query = query.trim();
+    let wanted_udn = query.strip_prefix("uuid:").unwrap_or(query);
+    let query_ip = query.parse::<Host>().ok().map(|host| host.ip);
+    let host_ip = |d: &Device| d.device_url.host()?.parse::<Host>().ok().map(|host| host.ip);
+    let exact = devices.iter().find(|d| {
+        d.udn.strip_prefix("uuid:").unwrap_or(&d.udn).eq_ignore_ascii_case(wanted_udn)
+            || (query_ip.is_some() && host_ip(d) == query_ip)
+            || d.friendly_name.eq_ignore_ascii_case(query)
+    });
+    if let Some(device) = exact {
+        return Ok(device);
+    }
+
+    let lower = query.to_lowercase();
+    let partial: Vec<&Device> = devices
+        .iter()
+        .filter(|d| d.friendly_name.to_lowercase().contains(&lower))
+        .collect();
+    match partial.as_slice() {
+        [device] => Ok(device),
+        [] => Err(AppError::DeviceNotFound(format!("no renderer matches '{query}'"))),
+        several => {
+            let names: Vec<&str> = several.iter().map(|d| d.friendly_name.as_str()).collect();
+            Err(AppError::DeviceNotFound(format!(
+                "'{query}' matches several renderers: {}",
+                names.join(", ")
+            )))
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+
+use super::ssdp_discovery::{SsdpDiscovery, SsdpService};
+use super::{route_interface, DESCRIPTION_TIMEOUT, PROBE_TIMEOUT};
+use crate::error::AppError;
+use crate::net::{self, Host};
+use crate::renderer::{Device, DeviceUrl, Protocol};
+use crate::roku::client::RokuClient;
+use crate::roku::{self, SEARCH_TARGET};
+use crate::wol;
+
+/// Keeps the list of Roku devices current from their SSDP `roku:ecp`
+/// announcements.
+pub type RokuDiscovery = SsdpDiscovery<Roku>;
+
+/// Roku as found over SSDP.
+pub struct Roku;
+
+#[async_trait]
+impl SsdpService for Roku {
+    const PROTOCOL: Protocol = Protocol::Roku;
+    const SEARCH_TARGETS: &'static [&'static str] = &[SEARCH_TARGET];
+
+    fn is_candidate_target(target: &str) -> bool {
+        target == SEARCH_TARGET
+    }
+
+    /// `location` is the ECP base URL from a search response, or the
+    /// `roku://` URL of a cached device.
+    async fn describe(location: &str) -> Result<Option<Device>, AppError> {
+        let url: DeviceUrl = location
+            .parse()
+            .map_err(|e| AppError::NetworkError(format!("Invalid device location {location}: {e}")))?;
+        let host: Host = url
+            .host()
+            .ok_or_else(|| AppError::NetworkError(format!("{location} has no host")))?
+            .parse()
+            .map_err(AppError::NetworkError)?;
+        let port = url.port().unwrap_or(roku::DEFAULT_PORT);
+        let client = RokuClient::new(roku::base_url(&url)?, PROBE_TIMEOUT, DESCRIPTION_TIMEOUT);
+        let info = client.device_info().await?;
+        Ok(Some(Device {
+            friendly_name: info.name,
+            udn: roku::udn(&info.serial_number),
+            protocol: Protocol::Roku,
+            device_url: roku::device_url(&host, port)
+                .parse()
+                .map_err(|e| AppError::NetworkError(format!("Invalid Roku address {host}: {e}")))?,
+            interface: None,
+            stale: false,
+            mac: wol::neighbor_mac(&host).await.or_else(|| info.mac?.parse().ok()),
+            dlna: None,
+        }))
+    }
+
+    async fn device_at(address: &str) -> Result<Device, AppError> {
+        device_at(address).await
+    }
+}
+
+/// Build a Roku device from its address alone, without SSDP: a
+/// `roku://host[:port]` URL or an IP address, optionally with a port. The
+/// device is asked for its device info to make sure it speaks ECP.
+pub async fn device_at(address: &str) -> Result<Device, AppError> {
+    let address = address.trim();
+    let host_port = match address.split_once("://") {
+        Some(("roku", rest)) => rest.trim_end_matches('/'),
+        Some(_) => return Err(AppError::DeviceNotFound(format!("{address} is not a Roku address"))),
+        None => address,
+    };
+    let (host, port) = net::parse_host_port(host_port).map_err(AppError::DeviceNotFound)?;
+    let location = roku::device_url(&host, port.unwrap_or(roku::DEFAULT_PORT));
+    let mut device = Roku::describe(&location)
+        .await?
+        .ok_or_else(|| AppError::DeviceNotFound(format!("{address} is not a Roku device")))?;
+    device.interface = route_interface(&device);
+    tracing::info!("Found Roku device at {}", device.device_url);
+    Ok(device)
+}
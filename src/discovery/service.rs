@@ -0,0 +1,240 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future;
+use tokio::sync::{broadcast, mpsc};
+
+use super::{AirPlayDiscovery, DlnaDiscovery, GoogleCastDiscovery, KodiDiscovery, RokuDiscovery};
+use crate::error::AppError;
+use crate::net::InterfaceFilter;
+use crate::renderer::{Device, Protocol};
+
+/// A change to the set of renderers on the network.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum DiscoveryEvent {
+    Added(Device),
+    /// The device moved to a new address or was renamed.
+    Updated(Device),
+    /// The device left the network or its announcement expired.
+    Removed(Device),
+}
+
+/// Finds the renderers of one protocol and keeps track of them.
+#[async_trait]
+pub trait DiscoveryProvider: Send + Sync {
+    fn protocol(&self) -> Protocol;
+
+    /// The renderers currently known, in the order they were found.
+    fn devices(&self) -> Vec<Device>;
+
+    fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent>;
+
+    /// Search now, listening for `timeout`, and return the known renderers
+    /// once responses are in.
+    async fn search_for(&self, timeout: Duration) -> Result<Vec<Device>, AppError>;
+
+    /// Search now and receive each renderer as it answers, including ones
+    /// already known. The channel closes when the search ends; dropping the
+    /// receiver ends it early.
+    fn search_stream(&self, timeout: Duration) -> Result<mpsc::UnboundedReceiver<Device>, AppError>;
+
+    /// Build a renderer from its address alone, without searching.
+    async fn device_at(&self, address: &str) -> Result<Device, AppError>;
+
+    /// Track a renderer a search cannot find, e.g. one built by
+    /// [`device_at`](Self::device_at).
+    async fn add(&self, device: Device) -> Result<(), AppError>;
+
+    /// List devices remembered from an earlier run, marked stale, and check
+    /// each in the background. Devices already listed are left alone; those
+    /// that don't answer stay listed, stale. Returns once they are listed.
+    async fn add_cached(&self, devices: Vec<Device>) -> Result<(), AppError>;
+}
+
+/// Keeps the list of renderers current in the background, for every
+/// protocol with a [`DiscoveryProvider`].
+///
+/// Cloning gives another handle to the same service; it stops when the last
+/// handle is dropped.
+#[derive(Clone)]
+pub struct DiscoveryService {
+    providers: Arc<[Arc<dyn DiscoveryProvider>]>,
+    search_timeout: Duration,
+    events: broadcast::Sender<DiscoveryEvent>,
+}
+
+impl DiscoveryService {
+    /// Start discovery for every protocol localcast speaks. Each search
+    /// listens for `search_timeout`; a search runs right away and then every
+    /// `search_interval`.
+    ///
+    /// Searches go out of every interface matching `interface`, or every
+    /// non-loopback interface if `None`. Renderers reached through other
+    /// interfaces are ignored when a filter is given.
+    pub async fn start(
+        search_timeout: Duration,
+        search_interval: Duration,
+        interface: Option<&InterfaceFilter>,
+    ) -> Result<Self, AppError> {
+        let dlna = DlnaDiscovery::start(search_timeout, search_interval, interface).await?;
+        let mut providers: Vec<Arc<dyn DiscoveryProvider>> = vec![Arc::new(dlna)];
+        match GoogleCastDiscovery::start(search_interval, interface) {
+            Ok(cast) => providers.push(Arc::new(cast)),
+            Err(e) => tracing::warn!("Not looking for Google Cast devices: {e}"),
+        }
+        match AirPlayDiscovery::start(search_interval, interface) {
+            Ok(airplay) => providers.push(Arc::new(airplay)),
+            Err(e) => tracing::warn!("Not looking for AirPlay devices: {e}"),
+        }
+        match KodiDiscovery::start(search_interval, interface) {
+            Ok(kodi) => providers.push(Arc::new(kodi)),
+            Err(e) => tracing::warn!("Not looking for Kodi devices: {e}"),
+        }
+        match RokuDiscovery::start(search_timeout, search_interval, interface).await {
+            Ok(roku) => providers.push(Arc::new(roku)),
+            Err(e) => tracing::warn!("Not looking for Roku devices: {e}"),
+        }
+        Ok(Self::new(providers, search_timeout))
+    }
+
+    /// Combine running providers into one service.
+    pub fn new(providers: Vec<Arc<dyn DiscoveryProvider>>, search_timeout: Duration) -> Self {
+        let (events, _) = broadcast::channel(64);
+        for provider in &providers {
+            tokio::spawn(forward_events(provider.subscribe(), events.clone()));
+        }
+        Self {
+            providers: providers.into(),
+            search_timeout,
+            events,
+        }
+    }
+
+    /// The renderers currently known, by protocol and then in the order
+    /// they were found.
+    pub fn devices(&self) -> Vec<Device> {
+        self.providers.iter().flat_map(|p| p.devices()).collect()
+    }
+
+    /// How long a search listens unless told otherwise.
+    pub fn search_timeout(&self) -> Duration {
+        self.search_timeout
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    /// Search now and return the known renderers once responses are in.
+    pub async fn search(&self) -> Result<Vec<Device>, AppError> {
+        self.search_for(self.search_timeout).await
+    }
+
+    /// Like [`search`](Self::search), listening for `timeout` instead of the
+    /// service's search timeout. Fails only if every provider does.
+    pub async fn search_for(&self, timeout: Duration) -> Result<Vec<Device>, AppError> {
+        let results = future::join_all(self.providers.iter().map(|p| p.search_for(timeout))).await;
+        let mut devices = Vec::new();
+        let mut answered = false;
+        let mut first_error = None;
+        for (provider, result) in self.providers.iter().zip(results) {
+            match result {
+                Ok(found) => {
+                    answered = true;
+                    devices.extend(found);
+                }
+                Err(e) => {
+                    tracing::warn!("{} discovery failed: {e}", provider.protocol().label());
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if !answered => Err(e),
+            _ => Ok(devices),
+        }
+    }
+
+    /// Search now and receive each renderer as it answers, including ones
+    /// already known. The channel closes when every provider's search ends;
+    /// dropping the receiver ends them early.
+    pub fn search_stream(&self, timeout: Duration) -> Result<mpsc::UnboundedReceiver<Device>, AppError> {
+        let (found, rx) = mpsc::unbounded_channel();
+        for provider in self.providers.iter() {
+            let mut answers = provider.search_stream(timeout)?;
+            let found = found.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        device = answers.recv() => {
+                            let Some(device) = device else { break };
+                            if found.send(device).is_err() {
+                                break;
+                            }
+                        }
+                        _ = found.closed() => break,
+                    }
+                }
+            });
+        }
+        Ok(rx)
+    }
+
+    /// Build a renderer from its address alone, asking every provider; the
+    /// first to recognise it wins.
+    pub async fn device_at(&self, address: &str) -> Result<Device, AppError> {
+        if self.providers.is_empty() {
+            return Err(AppError::DeviceNotFound(format!("nothing can reach '{address}'")));
+        }
+        let attempts = self.providers.iter().map(|p| p.device_at(address));
+        future::select_ok(attempts).await.map(|(device, _)| device)
+    }
+
+    /// Track a renderer a search cannot find, e.g. one built by
+    /// [`device_at`](Self::device_at), with the provider of its protocol.
+    pub async fn add(&self, device: Device) -> Result<(), AppError> {
+        self.provider(device.protocol)?.add(device).await
+    }
+
+    /// List devices remembered from an earlier run, marked stale, and check
+    /// each in the background. Devices of protocols without a provider are
+    /// left out.
+    pub async fn add_cached(&self, devices: Vec<Device>) -> Result<(), AppError> {
+        for provider in self.providers.iter() {
+            let own: Vec<Device> = devices
+                .iter()
+                .filter(|d| d.protocol == provider.protocol())
+                .cloned()
+                .collect();
+            if !own.is_empty() {
+                provider.add_cached(own).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn provider(&self, protocol: Protocol) -> Result<&Arc<dyn DiscoveryProvider>, AppError> {
+        self.providers
+            .iter()
+            .find(|p| p.protocol() == protocol)
+            .ok_or_else(|| AppError::Unsupported(format!("no discovery for {} renderers", protocol.label())))
+    }
+}
+
+/// Pass a provider's events on to the service's subscribers until the
+/// provider stops.
+async fn forward_events(mut events: broadcast::Receiver<DiscoveryEvent>, to: broadcast::Sender<DiscoveryEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                let _ = to.send(event);
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::debug!("Missed {missed} discovery events");
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Just enough SSDP to follow renderers coming and going: parsing `NOTIFY`
+//! announcements and M-SEARCH responses, and the sockets that receive them.
+//! The media server mode uses the same sockets to announce itself and to
+//! answer searches.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+pub const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+/// The link-local IPv6 SSDP group, `FF02::C`.
+const SSDP_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc);
+pub const SSDP_PORT: u16 = 1900;
+
+/// Lifetime assumed when an announcement has no usable `CACHE-CONTROL`.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(1800);
+
+/// Pause after a failed receive, doubled while failures keep coming.
+const RECEIVE_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECEIVE_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnouncementKind {
+    /// `ssdp:alive`, `ssdp:update` or a search response.
+    Alive,
+    /// `ssdp:byebye`: the device is leaving the network.
+    ByeBye,
+}
+
+/// A device announcing itself, from a `NOTIFY` or an M-SEARCH response.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub kind: AnnouncementKind,
+    /// The `NT` (notification) or `ST` (search response) type.
+    pub target: String,
+    /// UDN from the `USN` header, e.g. `uuid:4d696e69-444c-164e-9d41-b827eb1d2b8f`.
+    pub udn: String,
+    /// URL of the device description; absent in byebye messages.
+    pub location: Option<String>,
+    /// How long the announcement stays valid (`CACHE-CONTROL: max-age`).
+    pub max_age: Duration,
+}
+
+/// Parse an SSDP datagram. Returns `None` for M-SEARCH requests from other
+/// control points and anything malformed.
+pub fn parse(message: &str) -> Option<Announcement> {
+    let mut lines = message.lines();
+    let start = lines.next()?.trim();
+    let is_notify = start.starts_with("NOTIFY ");
+    let is_response = start.starts_with("HTTP/1.1 200") || start.starts_with("HTTP/1.0 200");
+    if !is_notify && !is_response {
+        return None;
+    }
+
+    let (mut nt, mut nts, mut st, mut usn, mut location, mut cache_control) =
+        (None, None, None, None, None, None);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_uppercase().as_str() {
+            "NT" => nt = Some(value),
+            "NTS" => nts = Some(value),
+            "ST" => st = Some(value),
+            "USN" => usn = Some(value),
+            "LOCATION" => location = Some(value),
+            "CACHE-CONTROL" => cache_control = Some(value),
+            _ => {}
+        }
+    }
+
+    let (kind, target) = if is_notify {
+        let kind = match nts? {
+            "ssdp:alive" | "ssdp:update" => AnnouncementKind::Alive,
+            "ssdp:byebye" => AnnouncementKind::ByeBye,
+            _ => return None,
+        };
+        (kind, nt?)
+    } else {
+        (AnnouncementKind::Alive, st?)
+    };
+
+    let udn = usn?.split("::").next()?.trim();
+    if !udn.starts_with("uuid:") {
+        return None;
+    }
+    let location = location.filter(|l| !l.is_empty()).map(str::to_string);
+    if kind == AnnouncementKind::Alive && location.is_none() {
+        return None;
+    }
+
+    Some(Announcement {
+        kind,
+        target: target.to_string(),
+        udn: udn.to_string(),
+        location,
+        max_age: cache_control.and_then(parse_max_age).unwrap_or(DEFAULT_MAX_AGE),
+    })
+}
+
+/// An M-SEARCH request from a control point.
+#[derive(Debug, Clone)]
+pub struct Search {
+    /// The `ST` header: what the control point is looking for.
+    pub target: String,
+    /// Seconds the control point waits for answers (`MX`); 0 for unicast searches.
+    pub mx: u64,
+}
+
+/// Parse an M-SEARCH request; `None` for anything else.
+pub fn parse_search(message: &str) -> Option<Search> {
+    let mut lines = message.lines();
+    if !lines.next()?.trim().starts_with("M-SEARCH ") {
+        return None;
+    }
+    let (mut man, mut st, mut mx) = (None, None, None);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_uppercase().as_str() {
+            "MAN" => man = Some(value),
+            "ST" => st = Some(value),
+            "MX" => mx = value.parse().ok(),
+            _ => {}
+        }
+    }
+    if man? != "\"ssdp:discover\"" {
+        return None;
+    }
+    Some(Search {
+        target: st?.to_string(),
+        mx: mx.unwrap_or(0),
+    })
+}
+
+/// Read `max-age` from a `CACHE-CONTROL` value such as `max-age = 1800`.
+fn parse_max_age(cache_control: &str) -> Option<Duration> {
+    let rest = &cache_control[cache_control.to_ascii_lowercase().find("max-age")? + "max-age".len()..];
+    let digits: String = rest
+        .trim_start()
+        .strip_prefix('=')?
+        .trim_start()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok().map(Duration::from_secs)
+}
+
+/// Socket joined to the SSDP multicast group on each of `interfaces` (or the
+/// default one if empty), receiving `NOTIFY` messages. The port is shared
+/// with other control points on this machine.
+pub fn notify_socket(interfaces: &[Ipv4Addr]) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    if interfaces.is_empty() {
+        socket.join_multicast_v4(&SSDP_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    }
+    for interface in interfaces {
+        if let Err(e) = socket.join_multicast_v4(&SSDP_ADDR, interface) {
+            tracing::warn!("Cannot join SSDP multicast group on {interface}: {e}");
+        }
+    }
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Like [`notify_socket`] for IPv6: joined to `FF02::C` on each interface
+/// index in `interfaces`.
+pub fn notify_socket_v6(interfaces: &[u32]) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    if interfaces.is_empty() {
+        socket.join_multicast_v6(&SSDP_ADDR_V6, 0)?;
+    }
+    for &index in interfaces {
+        if let Err(e) = socket.join_multicast_v6(&SSDP_ADDR_V6, index) {
+            tracing::warn!("Cannot join SSDP multicast group on interface {index}: {e}");
+        }
+    }
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Socket for sending M-SEARCH requests out of `interface` (the default
+/// route if unspecified) and receiving the unicast responses.
+pub fn search_socket(interface: Ipv4Addr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.bind(&SocketAddr::from((interface, 0)).into())?;
+    if !interface.is_unspecified() {
+        socket.set_multicast_if_v4(&interface)?;
+    }
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Socket for sending M-SEARCH requests to `FF02::C` out of the interface
+/// with index `interface`, and receiving the unicast responses.
+pub fn search_socket_v6(interface: u32) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+    socket.set_multicast_if_v6(interface)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Multicast an M-SEARCH for `target` to the group of the socket's address
+/// family; devices answer within `mx` seconds.
+pub async fn send_search(socket: &UdpSocket, target: &str, mx: u64) -> std::io::Result<()> {
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: {mx}\r\n\
+         ST: {target}\r\n\r\n",
+        group(socket)?
+    );
+    multicast(socket, &request).await
+}
+
+/// Multicast `message` to the SSDP group of the socket's address family.
+pub async fn multicast(socket: &UdpSocket, message: &str) -> std::io::Result<()> {
+    let dest = group(socket)?;
+    // UDP may drop a datagram; a second copy is customary
+    for _ in 0..2 {
+        socket.send_to(message.as_bytes(), dest).await?;
+    }
+    Ok(())
+}
+
+/// Wait for a datagram on `socket`. Failed receives are retried after a
+/// pause, so a socket that keeps failing doesn't spin.
+pub async fn receive(socket: &UdpSocket, buf: &mut [u8]) -> (usize, SocketAddr) {
+    let mut backoff = RECEIVE_BACKOFF;
+    loop {
+        match socket.recv_from(buf).await {
+            Ok(received) => return received,
+            Err(e) => {
+                tracing::debug!("SSDP receive error: {e}; retrying in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECEIVE_BACKOFF);
+            }
+        }
+    }
+}
+
+fn group(socket: &UdpSocket) -> std::io::Result<SocketAddr> {
+    Ok(match socket.local_addr()? {
+        SocketAddr::V4(_) => SocketAddrV4::new(SSDP_ADDR, SSDP_PORT).into(),
+        SocketAddr::V6(_) => SocketAddrV6::new(SSDP_ADDR_V6, SSDP_PORT, 0, 0).into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIVE: &str = "NOTIFY * HTTP/1.1\r\n\
+                         HOST: 239.255.255.250:1900\r\n\
+                         cache-control: max-age = 900\r\n\
+                         location: http://192.168.1.5:49152/description.xml\r\n\
+                         nt: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+                         Nts: ssdp:alive\r\n\
+                         usn: uuid:4d696e69-444c::urn:schemas-upnp-org:device:MediaRenderer:1\r\n\r\n";
+
+    #[test]
+    fn alive_headers_are_case_insensitive() {
+        let announcement = parse(ALIVE).unwrap();
+        assert_eq!(announcement.kind, AnnouncementKind::Alive);
+        assert_eq!(announcement.target, "urn:schemas-upnp-org:device:MediaRenderer:1");
+        assert_eq!(announcement.udn, "uuid:4d696e69-444c");
+        assert_eq!(announcement.location.as_deref(), Some("http://192.168.1.5:49152/description.xml"));
+        assert_eq!(announcement.max_age, Duration::from_secs(900));
+    }
+
+    #[test]
+    fn byebye_needs_no_location() {
+        let byebye = "NOTIFY * HTTP/1.1\r\n\
+                      NT: upnp:rootdevice\r\n\
+                      NTS: ssdp:byebye\r\n\
+                      USN: uuid:4d696e69-444c::upnp:rootdevice\r\n\r\n";
+        let announcement = parse(byebye).unwrap();
+        assert_eq!(announcement.kind, AnnouncementKind::ByeBye);
+        assert_eq!(announcement.udn, "uuid:4d696e69-444c");
+        assert_eq!(announcement.location, None);
+        assert_eq!(announcement.max_age, DEFAULT_MAX_AGE);
+    }
+
+    #[test]
+    fn alive_without_location_is_ignored() {
+        let without = ALIVE.replace("location: http://192.168.1.5:49152/description.xml\r\n", "");
+        assert!(parse(&without).is_none());
+        let empty = ALIVE.replace("http://192.168.1.5:49152/description.xml", "");
+        assert!(parse(&empty).is_none());
+    }
+
+    #[test]
+    fn malformed_announcements_are_ignored() {
+        assert!(parse(&ALIVE.replace("ssdp:alive", "ssdp:propchange")).is_none());
+        assert!(parse(&ALIVE.replace("uuid:4d696e69-444c", "4d696e69-444c")).is_none());
+        assert!(parse(&ALIVE.replace("NOTIFY *", "SUBSCRIBE *")).is_none());
+        assert!(parse("").is_none());
+    }
+
+    #[test]
+    fn search_responses_are_alive() {
+        let response = "HTTP/1.1 200 OK\r\n\
+                        CACHE-CONTROL: max-age=1800\r\n\
+                        EXT:\r\n\
+                        LOCATION: http://192.168.1.5:49152/description.xml\r\n\
+                        ST: urn:schemas-upnp-org:service:AVTransport:1\r\n\
+                        USN: uuid:abcd::urn:schemas-upnp-org:service:AVTransport:1\r\n\r\n";
+        let announcement = parse(response).unwrap();
+        assert_eq!(announcement.kind, AnnouncementKind::Alive);
+        assert_eq!(announcement.target, "urn:schemas-upnp-org:service:AVTransport:1");
+        assert_eq!(announcement.max_age, Duration::from_secs(1800));
+        // Other control points' searches aren't announcements
+        assert!(parse("M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn max_age_with_spaces_and_other_directives() {
+        assert_eq!(parse_max_age("max-age = 1800"), Some(Duration::from_secs(1800)));
+        assert_eq!(parse_max_age("MAX-AGE=60"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_max_age("no-cache, max-age =  120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_max_age("max-age"), None);
+        assert_eq!(parse_max_age("max-age = soon"), None);
+        assert_eq!(parse_max_age("no-cache"), None);
+    }
+
+    #[test]
+    fn m_search_with_mx_and_st() {
+        let request = "M-SEARCH * HTTP/1.1\r\n\
+                       Host: 239.255.255.250:1900\r\n\
+                       Man: \"ssdp:discover\"\r\n\
+                       mx: 3\r\n\
+                       st: urn:schemas-upnp-org:device:MediaServer:1\r\n\r\n";
+        let search = parse_search(request).unwrap();
+        assert_eq!(search.target, "urn:schemas-upnp-org:device:MediaServer:1");
+        assert_eq!(search.mx, 3);
+
+        let unicast = request.replace("mx: 3\r\n", "");
+        assert_eq!(parse_search(&unicast).unwrap().mx, 0);
+        assert!(parse_search(&request.replace("\"ssdp:discover\"", "ssdp:discover")).is_none());
+        assert!(parse_search(&request.replace("st: urn:schemas-upnp-org:device:MediaServer:1\r\n", "")).is_none());
+        assert!(parse_search(ALIVE).is_none());
+    }
+
+    #[tokio::test]
+    async fn search_responses_arrive_over_ipv6_loopback() {
+        let socket = search_socket_v6(0).unwrap();
+        assert_eq!(group(&socket).unwrap().to_string(), "[ff02::c]:1900");
+        let port = socket.local_addr().unwrap().port();
+
+        let device = UdpSocket::bind("[::1]:0").await.unwrap();
+        let response = "HTTP/1.1 200 OK\r\n\
+                        ST: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+                        USN: uuid:1234::urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+                        LOCATION: http://[::1]:49152/description.xml\r\n\r\n";
+        device.send_to(response.as_bytes(), ("::1", port)).await.unwrap();
+
+        let mut buf = [0u8; 1024];
+        let (len, from) = receive(&socket, &mut buf).await;
+        assert_eq!(from, device.local_addr().unwrap());
+        let announcement = parse(std::str::from_utf8(&buf[..len]).unwrap()).unwrap();
+        assert_eq!(announcement.location.as_deref(), Some("http://[::1]:49152/description.xml"));
+    }
+}
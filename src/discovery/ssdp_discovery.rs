@@ -0,0 +1,699 @@
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use super::ssdp::{self, Announcement, AnnouncementKind};
+use super::{route_interface, DiscoveryEvent, DiscoveryProvider};
+use crate::error::AppError;
+use crate::net::{self, Interface, InterfaceFilter};
+use crate::renderer::{Device, Protocol};
+
+/// Extra time a search waits for device descriptions still being fetched.
+const DESCRIBE_GRACE: Duration = Duration::from_secs(5);
+
+/// Max-age passed along when checking a cached device; unused, since cached
+/// devices never expire.
+const CACHED_MAX_AGE: Duration = Duration::from_secs(1800);
+
+/// A protocol whose devices announce themselves over SSDP, tracked by
+/// [`SsdpDiscovery`].
+#[async_trait]
+pub trait SsdpService: Send + Sync + 'static {
+    const PROTOCOL: Protocol;
+
+    /// Targets each search asks for.
+    const SEARCH_TARGETS: &'static [&'static str];
+
+    /// Whether an announcement or search response of type `target` may come
+    /// from one of its devices.
+    fn is_candidate_target(target: &str) -> bool;
+
+    /// Fetch what `location` describes and return the device, with its MAC
+    /// address if the neighbor table has it by now. `Ok(None)` means it is
+    /// not a device we can cast to. Cached devices are checked with their
+    /// device URL as the location.
+    async fn describe(location: &str) -> Result<Option<Device>, AppError>;
+
+    /// Build a device from its address alone, without SSDP.
+    async fn device_at(address: &str) -> Result<Device, AppError>;
+}
+
+/// Keeps the list of devices of one [`SsdpService`] current in the
+/// background: listens for SSDP `NOTIFY` alive/byebye announcements,
+/// searches again periodically and drops devices whose
+/// `CACHE-CONTROL: max-age` runs out.
+///
+/// Cloning gives another handle to the same service; it stops when the last
+/// handle is dropped.
+pub struct SsdpDiscovery<S> {
+    commands: mpsc::UnboundedSender<Command>,
+    devices: watch::Receiver<Vec<Device>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+    service: PhantomData<fn() -> S>,
+}
+
+impl<S> Clone for SsdpDiscovery<S> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+            devices: self.devices.clone(),
+            events: self.events.clone(),
+            service: PhantomData,
+        }
+    }
+}
+
+impl<S: SsdpService> SsdpDiscovery<S> {
+    /// Start the service. Each search listens for `search_timeout`; a search
+    /// runs right away and then every `search_interval`.
+    ///
+    /// Searches go out of every interface matching `interface`, or every
+    /// non-loopback interface if `None`, over IPv4 and, on interfaces with an
+    /// IPv6 address, to `FF02::C`. Devices reached through other interfaces
+    /// are ignored when a filter is given.
+    pub async fn start(
+        search_timeout: Duration,
+        search_interval: Duration,
+        interface: Option<&InterfaceFilter>,
+    ) -> Result<Self, AppError> {
+        let interfaces = net::eligible_interfaces(interface)?;
+        let mut search_sockets = Vec::new();
+        for interface in &interfaces {
+            let IpAddr::V4(addr) = interface.addr else { continue };
+            match ssdp::search_socket(addr) {
+                Ok(socket) => search_sockets.push((Some(interface.name.clone()), Arc::new(socket))),
+                Err(e) => tracing::warn!("Cannot search on {} ({addr}): {e}", interface.name),
+            }
+        }
+        // IPv6 multicast goes out of an interface, not an address
+        let mut v6_indexes: HashMap<u32, String> = HashMap::new();
+        for interface in interfaces.iter().filter(|i| i.addr.is_ipv6()) {
+            match net::interface_index(&interface.name) {
+                Some(index) => {
+                    v6_indexes.insert(index, interface.name.clone());
+                }
+                None => tracing::debug!("No index for interface {}", interface.name),
+            }
+        }
+        for (&index, name) in &v6_indexes {
+            match ssdp::search_socket_v6(index) {
+                Ok(socket) => search_sockets.push((Some(name.clone()), Arc::new(socket))),
+                Err(e) => tracing::warn!("Cannot search on {name} over IPv6: {e}"),
+            }
+        }
+        if search_sockets.is_empty() {
+            if interface.is_some() {
+                return Err(AppError::NetworkError("Cannot open SSDP socket on any matching interface".into()));
+            }
+            // No usable interface found; let the kernel route
+            let socket = ssdp::search_socket(Ipv4Addr::UNSPECIFIED)
+                .map_err(|e| AppError::NetworkError(format!("Cannot open SSDP socket: {e}")))?;
+            search_sockets.push((None, Arc::new(socket)));
+        }
+        for (name, socket) in &search_sockets {
+            let addr = socket.local_addr().map(|a| a.ip().to_string()).unwrap_or_default();
+            tracing::info!(
+                "Searching for {} renderers on {} ({addr})",
+                S::PROTOCOL.label(),
+                name.as_deref().unwrap_or("default route")
+            );
+        }
+
+        // Without NOTIFY, devices are still found by the periodic searches
+        let multicast_addrs: Vec<Ipv4Addr> = interfaces
+            .iter()
+            .filter_map(|i| match i.addr {
+                IpAddr::V4(addr) => Some(addr),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        let notify_socket = ssdp::notify_socket(&multicast_addrs)
+            .inspect_err(|e| tracing::warn!("Not listening for SSDP announcements: {e}"))
+            .ok();
+        let notify_socket_v6 = if v6_indexes.is_empty() {
+            None
+        } else {
+            let indexes: Vec<u32> = v6_indexes.keys().copied().collect();
+            ssdp::notify_socket_v6(&indexes)
+                .inspect_err(|e| tracing::warn!("Not listening for IPv6 SSDP announcements: {e}"))
+                .ok()
+        };
+        let v6_indexes = Arc::new(v6_indexes);
+
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (devices_tx, devices) = watch::channel(Vec::new());
+        let (events, _) = broadcast::channel(64);
+        let (described_tx, described_rx) = mpsc::unbounded_channel();
+        let (datagram_tx, datagram_rx) = mpsc::unbounded_channel();
+
+        // One reader per socket, tagging datagrams with the interface
+        let reader = |socket: Arc<UdpSocket>, name: Option<String>| {
+            tokio::spawn(read_datagrams(socket, name, v6_indexes.clone(), datagram_tx.clone()))
+        };
+        let mut readers: Vec<JoinHandle<()>> = search_sockets
+            .iter()
+            .map(|(name, socket)| reader(socket.clone(), name.clone()))
+            .collect();
+        for socket in notify_socket.into_iter().chain(notify_socket_v6) {
+            readers.push(reader(Arc::new(socket), None));
+        }
+
+        let monitor = Monitor::<S> {
+            search_sockets: search_sockets.into_iter().map(|(_, socket)| socket).collect(),
+            readers,
+            interfaces,
+            filtered: interface.is_some(),
+            tracked: Vec::new(),
+            describing: HashMap::new(),
+            ignored: HashMap::new(),
+            searches: Vec::new(),
+            search_timeout,
+            search_interval,
+            next_search: Instant::now(),
+            devices: devices_tx,
+            events: events.clone(),
+            described_tx,
+            service: PhantomData,
+        };
+        tokio::spawn(monitor.run(command_rx, datagram_rx, described_rx));
+
+        Ok(Self {
+            commands,
+            devices,
+            events,
+            service: PhantomData,
+        })
+    }
+
+    fn start_search(&self, timeout: Duration, reply: SearchReply) -> Result<(), AppError> {
+        self.send(Command::Search(Search { timeout, reply }))
+    }
+
+    fn send(&self, command: Command) -> Result<(), AppError> {
+        self.commands
+            .send(command)
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+}
+
+#[async_trait]
+impl<S: SsdpService> DiscoveryProvider for SsdpDiscovery<S> {
+    fn protocol(&self) -> Protocol {
+        S::PROTOCOL
+    }
+
+    fn devices(&self) -> Vec<Device> {
+        self.devices.borrow().clone()
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    async fn search_for(&self, timeout: Duration) -> Result<Vec<Device>, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.start_search(timeout, SearchReply::List(reply))?;
+        rx.await
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+
+    fn search_stream(&self, timeout: Duration) -> Result<mpsc::UnboundedReceiver<Device>, AppError> {
+        let (found, rx) = mpsc::unbounded_channel();
+        self.start_search(timeout, SearchReply::Stream(found))?;
+        Ok(rx)
+    }
+
+    async fn device_at(&self, address: &str) -> Result<Device, AppError> {
+        S::device_at(address).await
+    }
+
+    /// Devices added by hand stay listed until they say byebye; there are no
+    /// announcements to let them expire.
+    async fn add(&self, device: Device) -> Result<(), AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::Add(device, reply))?;
+        rx.await
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+
+    /// Each cached device is checked with [`SsdpService::describe`].
+    async fn add_cached(&self, devices: Vec<Device>) -> Result<(), AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::AddCached(devices, reply))?;
+        rx.await
+            .map_err(|_| AppError::NetworkError("Discovery service stopped".into()))
+    }
+}
+
+/// Requests from [`SsdpDiscovery`] handles to the monitor task.
+enum Command {
+    Search(Search),
+    /// Track a device added by hand; replies once it is listed.
+    Add(Device, oneshot::Sender<()>),
+    /// List stale devices from the cache and check them; replies once they
+    /// are listed.
+    AddCached(Vec<Device>, oneshot::Sender<()>),
+}
+
+/// A search requested through a [`SsdpDiscovery`] handle.
+struct Search {
+    timeout: Duration,
+    reply: SearchReply,
+}
+
+/// Where the results of a search go.
+enum SearchReply {
+    /// Every known renderer, once the search is over.
+    List(oneshot::Sender<Vec<Device>>),
+    /// Each renderer as it answers.
+    Stream(mpsc::UnboundedSender<Device>),
+}
+
+/// A renderer being tracked, with when its last announcement runs out.
+struct Tracked {
+    device: Device,
+    location: String,
+    /// `None` for devices added by hand, which never expire.
+    expires: Option<Instant>,
+}
+
+impl Tracked {
+    /// Keep the device listed for at least another `max_age`.
+    fn refresh(&mut self, max_age: Duration) {
+        if let Some(expires) = &mut self.expires {
+            *expires = (*expires).max(Instant::now() + max_age);
+        }
+    }
+}
+
+/// A device that isn't a renderer, not described again at the same
+/// location until its announcements run out.
+struct Ignored {
+    location: String,
+    expires: Instant,
+}
+
+impl Ignored {
+    fn new(location: String, max_age: Duration) -> Self {
+        Self {
+            location,
+            expires: Instant::now() + max_age,
+        }
+    }
+
+    fn refresh(&mut self, max_age: Duration) {
+        self.expires = self.expires.max(Instant::now() + max_age);
+    }
+}
+
+/// A search waiting to reply once its responses are in.
+struct PendingSearch {
+    deadline: Instant,
+    /// When to reply even if descriptions are still being fetched.
+    give_up: Instant,
+    reply: SearchReply,
+    /// UDNs already streamed to the reply.
+    seen: HashSet<String>,
+}
+
+impl PendingSearch {
+    /// Pass `device` on to a streaming search, once per device.
+    fn found(&mut self, device: &Device) {
+        if let SearchReply::Stream(found) = &self.reply {
+            if self.seen.insert(device.udn.clone()) {
+                let _ = found.send(device.clone());
+            }
+        }
+    }
+
+    /// Whether nobody is waiting for the result any more.
+    fn abandoned(&self) -> bool {
+        match &self.reply {
+            SearchReply::List(reply) => reply.is_closed(),
+            SearchReply::Stream(found) => found.is_closed(),
+        }
+    }
+}
+
+/// A received SSDP datagram and the interface it came in on, if known:
+/// `NOTIFY` messages over IPv4 all arrive on one socket and can't tell.
+struct Datagram {
+    interface: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Result of fetching a device description.
+struct Described {
+    udn: String,
+    location: String,
+    max_age: Duration,
+    interface: Option<String>,
+    result: Result<Option<Device>, AppError>,
+}
+
+/// The task behind [`SsdpDiscovery`].
+struct Monitor<S> {
+    search_sockets: Vec<Arc<UdpSocket>>,
+    /// Tasks reading the SSDP sockets, stopped with the monitor.
+    readers: Vec<JoinHandle<()>>,
+    /// Interfaces searched on.
+    interfaces: Vec<Interface>,
+    /// Whether the user restricted discovery to some interfaces.
+    filtered: bool,
+    tracked: Vec<Tracked>,
+    /// Locations being fetched, keyed by UDN.
+    describing: HashMap<String, String>,
+    /// Devices described and found not to be renderers, by UDN.
+    ignored: HashMap<String, Ignored>,
+    searches: Vec<PendingSearch>,
+    search_timeout: Duration,
+    search_interval: Duration,
+    next_search: Instant,
+    devices: watch::Sender<Vec<Device>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+    described_tx: mpsc::UnboundedSender<Described>,
+    service: PhantomData<fn() -> S>,
+}
+
+impl<S: SsdpService> Monitor<S> {
+    async fn run(
+        mut self,
+        mut commands: mpsc::UnboundedReceiver<Command>,
+        mut datagrams: mpsc::UnboundedReceiver<Datagram>,
+        mut described: mpsc::UnboundedReceiver<Described>,
+    ) {
+        loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Search(Search { timeout, reply })) => {
+                        self.search(timeout).await;
+                        let deadline = Instant::now() + timeout;
+                        self.searches.push(PendingSearch {
+                            deadline,
+                            give_up: deadline + DESCRIBE_GRACE,
+                            reply,
+                            seen: HashSet::new(),
+                        });
+                    }
+                    Some(Command::Add(device, reply)) => {
+                        let location = device.device_url.to_string();
+                        self.track(device, location, None);
+                        let _ = reply.send(());
+                    }
+                    Some(Command::AddCached(devices, reply)) => {
+                        for device in devices {
+                            self.add_cached(device);
+                        }
+                        let _ = reply.send(());
+                    }
+                    None => break,
+                },
+                Some(datagram) = datagrams.recv() => self.on_datagram(datagram),
+                Some(described) = described.recv() => self.on_described(described),
+                _ = tokio::time::sleep_until(self.next_wake()) => {
+                    if Instant::now() >= self.next_search {
+                        self.search(self.search_timeout).await;
+                    }
+                }
+            }
+            self.expire();
+            self.answer_searches();
+        }
+    }
+
+    async fn search(&mut self, timeout: Duration) {
+        self.next_search = Instant::now() + self.search_interval;
+        let mx = timeout.as_secs().clamp(1, 5);
+        for socket in &self.search_sockets {
+            for target in S::SEARCH_TARGETS {
+                if let Err(e) = ssdp::send_search(socket, target, mx).await {
+                    tracing::warn!("SSDP search for {target} failed: {e}");
+                }
+            }
+        }
+    }
+
+    /// The next time something is due: a search, an expiry or a search reply.
+    fn next_wake(&self) -> Instant {
+        let now = Instant::now();
+        let expiries = self.tracked.iter().filter_map(|t| t.expires);
+        let replies = self
+            .searches
+            .iter()
+            .map(|s| if now < s.deadline { s.deadline } else { s.give_up });
+        expiries
+            .chain(replies)
+            .fold(self.next_search, Instant::min)
+    }
+
+    fn on_datagram(&mut self, datagram: Datagram) {
+        let Some(mut announcement) = std::str::from_utf8(&datagram.data).ok().and_then(ssdp::parse) else {
+            return;
+        };
+        if let Some(location) = &mut announcement.location {
+            *location = net::scoped_location(location, datagram.interface.as_deref());
+        }
+        self.on_announcement(announcement, datagram.interface);
+    }
+
+    fn on_announcement(&mut self, a: Announcement, interface: Option<String>) {
+        // Embedded devices announce their own UDN at the root device's location
+        let known = self.tracked.iter().position(|t| {
+            t.device.udn == a.udn || a.location.as_deref() == Some(t.location.as_str())
+        });
+        match (a.kind, known) {
+            (AnnouncementKind::ByeBye, Some(index)) => {
+                let gone = self.tracked.remove(index);
+                tracing::info!("Renderer left: {}", gone.device.friendly_name);
+                self.publish(DiscoveryEvent::Removed(gone.device));
+            }
+            (AnnouncementKind::ByeBye, None) => {}
+            // Devices announce every embedded device and service; any of them
+            // keeps the device alive
+            (AnnouncementKind::Alive, Some(index)) => {
+                let tracked = &mut self.tracked[index];
+                tracked.refresh(a.max_age);
+                let location = a.location.unwrap_or_default();
+                if S::is_candidate_target(&a.target) && location != tracked.location {
+                    self.describe(a.udn, location, a.max_age, interface);
+                } else if tracked.device.stale {
+                    // A cached device answering at the address we had for it
+                    tracked.device.stale = false;
+                    let device = tracked.device.clone();
+                    tracing::info!("Cached renderer is up: {}", device.friendly_name);
+                    self.found(&device);
+                    self.publish(DiscoveryEvent::Updated(device));
+                } else {
+                    let device = tracked.device.clone();
+                    self.found(&device);
+                }
+            }
+            (AnnouncementKind::Alive, None) if S::is_candidate_target(&a.target) => {
+                let location = a.location.unwrap_or_default();
+                match self.ignored.get_mut(&a.udn) {
+                    Some(ignored) if ignored.location == location => ignored.refresh(a.max_age),
+                    _ => self.describe(a.udn, location, a.max_age, interface),
+                }
+            }
+            (AnnouncementKind::Alive, None) => {}
+        }
+    }
+
+    /// Fetch a device description in the background.
+    fn describe(&mut self, udn: String, location: String, max_age: Duration, interface: Option<String>) {
+        if self.describing.get(&udn) == Some(&location) {
+            return;
+        }
+        self.describing.insert(udn.clone(), location.clone());
+        let described = self.described_tx.clone();
+        tokio::spawn(async move {
+            let result = S::describe(&location).await;
+            let _ = described.send(Described {
+                udn,
+                location,
+                max_age,
+                interface,
+                result,
+            });
+        });
+    }
+
+    fn on_described(&mut self, described: Described) {
+        let Described {
+            udn,
+            location,
+            max_age,
+            interface,
+            result,
+        } = described;
+        if self.describing.get(&udn) == Some(&location) {
+            self.describing.remove(&udn);
+        }
+        let mut device = match result {
+            Ok(Some(device)) => device,
+            Ok(None) => {
+                tracing::debug!("{udn} at {location} is not a {} renderer", S::PROTOCOL.label());
+                self.ignored.insert(udn, Ignored::new(location, max_age));
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("Cannot describe {udn}: {e}");
+                return;
+            }
+        };
+
+        // Announcements don't say which interface they came in on
+        device.interface = interface.or_else(|| route_interface(&device));
+        if self.filtered && !self.interfaces.iter().any(|i| Some(&i.name) == device.interface.as_ref()) {
+            tracing::debug!("Ignoring {} outside the selected interfaces", device.friendly_name);
+            self.ignored.insert(udn, Ignored::new(location, max_age));
+            return;
+        }
+
+        self.found(&device);
+        self.track(device, location, Some(Instant::now() + max_age));
+    }
+
+    /// List a device from the cache until it shows up or its description
+    /// answers. Like devices added by hand, it never expires.
+    fn add_cached(&mut self, device: Device) {
+        if self.tracked.iter().any(|t| t.device.udn == device.udn) {
+            return;
+        }
+        let outside = self.filtered
+            && device.interface.as_ref().is_some_and(|name| !self.interfaces.iter().any(|i| &i.name == name));
+        if outside {
+            return;
+        }
+        let location = device.device_url.to_string();
+        let udn = device.udn.clone();
+        self.track(device, location.clone(), None);
+        self.describe(udn, location, CACHED_MAX_AGE, None);
+    }
+
+    /// Start tracking `device`, or update it if it is already tracked.
+    fn track(&mut self, mut device: Device, location: String, expires: Option<Instant>) {
+        match self.tracked.iter_mut().find(|t| t.device.udn == device.udn) {
+            Some(tracked) => {
+                // The neighbor table may have lost the entry since
+                device.mac = device.mac.or(tracked.device.mac);
+                let changed = tracked.device.friendly_name != device.friendly_name
+                    || tracked.device.device_url != device.device_url
+                    || tracked.device.stale != device.stale
+                    || tracked.device.mac != device.mac;
+                tracked.device = device.clone();
+                tracked.location = location;
+                tracked.expires = match (tracked.expires, expires) {
+                    (Some(old), Some(new)) => Some(old.max(new)),
+                    // Added by hand, now or before
+                    _ => None,
+                };
+                if changed {
+                    tracing::info!("Renderer changed: {}", device.friendly_name);
+                    self.publish(DiscoveryEvent::Updated(device));
+                }
+            }
+            None => {
+                if device.stale {
+                    tracing::info!("Listing cached renderer: {} ({})", device.friendly_name, device.device_url);
+                } else {
+                    tracing::info!("Renderer found: {} ({})", device.friendly_name, device.device_url);
+                }
+                self.tracked.push(Tracked {
+                    device: device.clone(),
+                    location,
+                    expires,
+                });
+                self.publish(DiscoveryEvent::Added(device));
+            }
+        }
+    }
+
+    /// Drop devices whose announcements ran out.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.ignored.retain(|_, ignored| ignored.expires > now);
+        while let Some(index) = self
+            .tracked
+            .iter()
+            .position(|t| t.expires.is_some_and(|expires| expires <= now))
+        {
+            let gone = self.tracked.remove(index);
+            tracing::info!("Renderer expired: {}", gone.device.friendly_name);
+            self.publish(DiscoveryEvent::Removed(gone.device));
+        }
+    }
+
+    /// Tell streaming searches in progress about a renderer that answered.
+    fn found(&mut self, device: &Device) {
+        let now = Instant::now();
+        for search in self.searches.iter_mut().filter(|s| now < s.give_up) {
+            search.found(device);
+        }
+    }
+
+    /// Reply to searches whose time is up, giving descriptions still being
+    /// fetched a little longer. Streaming searches end by closing the channel.
+    fn answer_searches(&mut self) {
+        let now = Instant::now();
+        let describing = !self.describing.is_empty();
+        let (due, waiting): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.searches).into_iter().partition(|s| {
+                now >= s.give_up || (now >= s.deadline && !describing) || s.abandoned()
+            });
+        self.searches = waiting;
+        for search in due {
+            if let SearchReply::List(reply) = search.reply {
+                let _ = reply.send(self.devices.borrow().clone());
+            }
+        }
+    }
+
+    fn publish(&self, event: DiscoveryEvent) {
+        self.devices
+            .send_replace(self.tracked.iter().map(|t| t.device.clone()).collect());
+        let _ = self.events.send(event);
+    }
+}
+
+impl<S> Drop for Monitor<S> {
+    fn drop(&mut self) {
+        for reader in &self.readers {
+            reader.abort();
+        }
+    }
+}
+
+/// Forward datagrams from `socket` to the monitor until it stops. Those from
+/// link-local IPv6 senders are tagged with their scope's interface when the
+/// socket isn't tied to one.
+async fn read_datagrams(
+    socket: Arc<UdpSocket>,
+    interface: Option<String>,
+    v6_indexes: Arc<HashMap<u32, String>>,
+    datagrams: mpsc::UnboundedSender<Datagram>,
+) {
+    let mut buf = vec![0u8; 2048];
+    loop {
+        let (len, from) = ssdp::receive(&socket, &mut buf).await;
+        let scope = match from {
+            SocketAddr::V6(from) => v6_indexes.get(&from.scope_id()).cloned(),
+            SocketAddr::V4(_) => None,
+        };
+        let datagram = Datagram {
+            interface: interface.clone().or(scope),
+            data: buf[..len].to_vec(),
+        };
+        if datagrams.send(datagram).is_err() {
+            break;
+        }
+    }
+}
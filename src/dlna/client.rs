@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use hyper014::client::HttpConnector;
+use hyper014::body::Bytes;
+
+use crate::error::AppError;
+
+/// Timeouts and retry policy for requests to renderers.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct SoapConfig {
+    /// Time allowed to open a TCP connection.
+    pub connect_timeout: Duration,
+    /// Time allowed for a whole request, including reading the body.
+    pub request_timeout: Duration,
+    /// Extra attempts for idempotent queries (GetPositionInfo, ...).
+    pub retries: u32,
+    /// Delay between attempts.
+    pub retry_delay: Duration,
+}
+
+impl Default for SoapConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(3),
+            request_timeout: Duration::from_secs(5),
+            retries: 2,
+            retry_delay: Duration::from_millis(300),
+        }
+    }
+}
+
+/// HTTP client for SOAP and description requests. Each session has its
+/// own, so its timeouts apply only there, and clones share connections, so
+/// they are kept alive between polls.
+#[derive(Clone)]
+pub struct SoapClient {
+    http: hyper014::Client<HttpConnector>,
+    config: SoapConfig,
+}
+
+impl Default for SoapClient {
+    fn default() -> Self {
+        Self::new(SoapConfig::default())
+    }
+}
+
+impl SoapClient {
+    pub fn new(config: SoapConfig) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(config.connect_timeout));
+        connector.set_keepalive(Some(Duration::from_secs(60)));
+        connector.set_nodelay(true);
+        let http = hyper014::Client::builder()
+            .pool_idle_timeout(Duration::from_secs(30))
+            .pool_max_idle_per_host(4)
+            .build(connector);
+        Self { http, config }
+    }
+
+    pub fn config(&self) -> SoapConfig {
+        self.config
+    }
+
+    /// GET a URL and return its body. Descriptions are static, so the request
+    /// is retried like an idempotent query.
+    pub async fn get(&self, what: &str, url: &str) -> Result<Bytes, AppError> {
+        let uri: http02::Uri = url
+            .parse()
+            .map_err(|e| AppError::DlnaAction(format!("Invalid {what} URL: {e}")))?;
+        let (_, body) = self
+            .send(what, true, || {
+                http02::Request::get(uri.clone())
+                    .body(hyper014::Body::empty())
+                    .map_err(|e| AppError::DlnaAction(format!("Failed to build {what} request: {e}")))
+            })
+            .await?;
+        Ok(body)
+    }
+
+    /// Send the request built by `build`, reading the whole body within the
+    /// request timeout. Failed attempts are retried only when `idempotent`.
+    pub(crate) async fn send(
+        &self,
+        what: &str,
+        idempotent: bool,
+        build: impl Fn() -> Result<http02::Request<hyper014::Body>, AppError>,
+    ) -> Result<(http02::StatusCode, Bytes), AppError> {
+        let attempts = if idempotent { self.config.retries + 1 } else { 1 };
+        let mut attempt = 1;
+        loop {
+            match self.send_once(what, build()?).await {
+                Ok(response) => return Ok(response),
+                Err(e) if attempt < attempts => {
+                    tracing::debug!("{what} attempt {attempt}/{attempts} failed: {e}");
+                    tokio::time::sleep(self.config.retry_delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        what: &str,
+        req: http02::Request<hyper014::Body>,
+    ) -> Result<(http02::StatusCode, Bytes), AppError> {
+        let exchange = async {
+            let response = self
+                .http
+                .request(req)
+                .await
+                .map_err(|e| AppError::DlnaAction(format!("{what} HTTP error: {e}")))?;
+            let status = response.status();
+            let body = hyper014::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| AppError::DlnaAction(format!("{what} read body error: {e}")))?;
+            Ok((status, body))
+        };
+        tokio::time::timeout(self.config.request_timeout, exchange)
+            .await
+            .map_err(|_| {
+                AppError::Timeout(format!(
+                    "{what} after {}s",
+                    self.config.request_timeout.as_secs_f32()
+                ))
+            })?
+    }
+}
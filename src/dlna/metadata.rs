@@ -1,9 +1,25 @@
 /// Generate DIDL-Lite XML metadata for SetAVTransportURI.
 ///
 /// Includes DLNA protocol info flags required by many TVs (especially Xiaomi, Samsung, LG).
-pub fn didl_metadata(title: &str, media_url: &str, mime_type: &str, file_size: u64) -> String {
+/// Subtitles are announced both as `sec:CaptionInfoEx` (Samsung) and as an
+/// extra `res` element (most others).
+pub fn didl_metadata(
+    title: &str,
+    media_url: &str,
+    mime_type: &str,
+    file_size: u64,
+    subtitle_url: Option<&str>,
+) -> String {
     let title_escaped = xml_escape(title);
     let url_escaped = xml_escape(media_url);
+    let subtitles = subtitle_url
+        .map(|url| {
+            let url = xml_escape(url);
+            format!(
+                r#"<sec:CaptionInfoEx sec:type="srt">{url}</sec:CaptionInfoEx><res protocolInfo="http-get:*:text/srt:*">{url}</res>"#
+            )
+        })
+        .unwrap_or_default();
 
     // DLNA.ORG_OP=01 means the server supports Range requests (byte seek)
     // DLNA.ORG_FLAGS: streaming mode flags
@@ -11,7 +27,7 @@ pub fn didl_metadata(title: &str, media_url: &str, mime_type: &str, file_size: u
     let protocol_info = format!("http-get:*:{mime_type}:{dlna_features}");
 
     format!(
-        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/"><item id="0" parentID="-1" restricted="1"><dc:title>{title_escaped}</dc:title><upnp:class>object.item.videoItem</upnp:class><res protocolInfo="{protocol_info}" size="{file_size}">{url_escaped}</res></item></DIDL-Lite>"#
+        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" xmlns:sec="http://www.sec.co.kr/"><item id="0" parentID="-1" restricted="1"><dc:title>{title_escaped}</dc:title><upnp:class>object.item.videoItem</upnp:class><res protocolInfo="{protocol_info}" size="{file_size}">{url_escaped}</res>{subtitles}</item></DIDL-Lite>"#
     )
 }
 
@@ -1,3 +1,4 @@
+pub mod client;
 pub mod metadata;
 pub mod transport;
 pub mod types;
@@ -1,8 +1,13 @@
 use std::collections::HashMap;
 
+use crate::dlna::client::SoapClient;
 use crate::dlna::metadata::didl_metadata;
-use crate::dlna::types::{parse_duration, DlnaDevice, PlaybackState, PositionInfo};
+use crate::dlna::types::{
+    parse_duration, MediaInfo, PlayMode, PlaybackState, PositionInfo, RendererCapabilities,
+    TransportActions,
+};
 use crate::error::AppError;
+use crate::renderer::{Device, Media};
 
 fn xml_escape(s: &str) -> String {
     s.replace('&', "&amp;")
@@ -12,6 +17,14 @@ fn xml_escape(s: &str) -> String {
         .replace('\'', "&apos;")
 }
 
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
 /// Build an XML payload from key-value pairs for SOAP actions.
 fn xml_payload(pairs: &[(&str, &str)]) -> String {
     let mut s = String::new();
@@ -27,24 +40,55 @@ fn xml_payload(pairs: &[(&str, &str)]) -> String {
     s
 }
 
-/// Resolve the AVTransport control URL for a device.
-/// Fetches the device description XML and extracts the controlURL,
-/// combining it with the URLBase or device URL authority.
-pub async fn resolve_control_url(device: &DlnaDevice) -> Result<String, AppError> {
-    let client = hyper014::Client::new();
+/// Service type of the RenderingControl service, which handles volume, minus
+/// the version.
+const RENDERING_CONTROL: &str = "urn:schemas-upnp-org:service:RenderingControl:";
+
+/// Service type of the ConnectionManager service, minus the version.
+const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:";
+
+/// The RenderingControl service of a renderer: where to send volume actions
+/// and the service type (with the version the renderer declares) to send.
+#[derive(Debug, Clone)]
+pub struct RenderingControl {
+    pub control_url: String,
+    pub service_type: String,
+}
+
+/// Type of the AVTransport service found when `device` was discovered.
+fn av_transport(device: &Device) -> Result<String, AppError> {
+    device
+        .dlna
+        .as_ref()
+        .map(|dlna| dlna.service_type.clone())
+        .ok_or_else(|| AppError::DlnaAction(format!("{} is not a DLNA renderer", device.friendly_name)))
+}
+
+/// Fetch the device description and return the URL base together with the
+/// `<service>` block of the AVTransport service found at discovery.
+async fn fetch_av_transport_service(
+    client: &SoapClient,
+    device: &Device,
+) -> Result<(String, String), AppError> {
+    let service_type = av_transport(device)?;
+    fetch_service(client, device, &service_type, |t| t == service_type).await
+}
+
+/// Fetch the device description and return the URL base together with the
+/// first `<service>` block whose service type satisfies `matches`, searching
+/// embedded devices too. `wanted` names the service in errors.
+async fn fetch_service(
+    client: &SoapClient,
+    device: &Device,
+    wanted: &str,
+    matches: impl Fn(&str) -> bool,
+) -> Result<(String, String), AppError> {
     let device_url_str = device.device_url.to_string();
     let uri: http02::Uri = device_url_str
         .parse()
         .map_err(|e| AppError::DlnaAction(format!("Invalid device URL: {e}")))?;
 
-    let response = client
-        .get(uri.clone())
-        .await
-        .map_err(|e| AppError::DlnaAction(format!("Failed to fetch device description: {e}")))?;
-
-    let body = hyper014::body::to_bytes(response.into_body())
-        .await
-        .map_err(|e| AppError::DlnaAction(format!("Failed to read device description: {e}")))?;
+    let body = client.get("device description", &device_url_str).await?;
 
     let body_str = std::str::from_utf8(&body)
         .map_err(|e| AppError::DlnaAction(format!("Device description is not UTF-8: {e}")))?;
@@ -66,46 +110,150 @@ pub async fn resolve_control_url(device: &DlnaDevice) -> Result<String, AppError
         format!("{scheme}://{authority}")
     };
 
-    // Find the AVTransport service block and extract controlURL
-    let search = "urn:schemas-upnp-org:service:AVTransport:1";
-    let type_pos = body_str
-        .find(search)
-        .ok_or_else(|| AppError::DlnaAction("AVTransport:1 not found in description".into()))?;
-
-    let before = &body_str[..type_pos];
-    let service_start = before
-        .rfind("<service>")
-        .ok_or_else(|| AppError::DlnaAction("No <service> block found".into()))?;
-    let after_start = &body_str[service_start..];
-    let service_end = after_start
-        .find("</service>")
-        .ok_or_else(|| AppError::DlnaAction("No </service> found".into()))?;
-    let service_block = &after_start[..service_end];
-
-    let control_tag = "<controlURL>";
-    let control_start = service_block
-        .find(control_tag)
-        .ok_or_else(|| AppError::DlnaAction("No <controlURL> in AVTransport service".into()))?;
-    let control_content = &service_block[control_start + control_tag.len()..];
-    let control_end = control_content
-        .find("</controlURL>")
-        .ok_or_else(|| AppError::DlnaAction("No </controlURL> found".into()))?;
-    let control_path = control_content[..control_end].trim();
-
-    let url = if control_path.starts_with("http://") || control_path.starts_with("https://") {
-        control_path.to_string()
-    } else if control_path.starts_with('/') {
-        format!("{base}{control_path}")
+    // Find the service block, in the root device or an embedded one
+    let service_block = xml_blocks(body_str, "service")
+        .find(|block| {
+            extract_between(block, "<serviceType>", "</serviceType>")
+                .is_some_and(|t| matches(t.trim()))
+        })
+        .ok_or_else(|| AppError::DlnaAction(format!("{wanted} not found in description")))?
+        .to_string();
+
+    Ok((base, service_block))
+}
+
+/// Whether `service_type` is `prefix` followed by a version number, so any
+/// version of a service is accepted.
+fn is_service_version(service_type: &str, prefix: &str) -> bool {
+    service_type
+        .strip_prefix(prefix)
+        .is_some_and(|version| version.parse::<u32>().is_ok())
+}
+
+/// Extract a URL element (controlURL, SCPDURL, ...) from a service block and
+/// make it absolute against `base`.
+fn service_url(base: &str, service_block: &str, tag: &str) -> Result<String, AppError> {
+    let open_tag = format!("<{tag}>");
+    let close_tag = format!("</{tag}>");
+    let path = extract_between(service_block, &open_tag, &close_tag)
+        .ok_or_else(|| AppError::DlnaAction(format!("No <{tag}> in service description")))?
+        .trim();
+
+    let url = if path.starts_with("http://") || path.starts_with("https://") {
+        path.to_string()
+    } else if path.starts_with('/') {
+        format!("{base}{path}")
     } else {
-        format!("{base}/{control_path}")
+        format!("{base}/{path}")
     };
+    Ok(url)
+}
+
+/// Resolve the AVTransport control URL for a device.
+/// Fetches the device description XML and extracts the controlURL,
+/// combining it with the URLBase or device URL authority.
+pub async fn resolve_control_url(client: &SoapClient, device: &Device) -> Result<String, AppError> {
+    let (base, service_block) = fetch_av_transport_service(client, device).await?;
+    let url = service_url(&base, &service_block, "controlURL")?;
 
     tracing::info!("Resolved AVTransport control URL: {url}");
     Ok(url)
 }
 
-/// Send a SOAP action directly via hyper, properly handling non-200 responses.
+/// Resolve the RenderingControl service of a device, used for volume.
+pub async fn resolve_rendering_control(
+    client: &SoapClient,
+    device: &Device,
+) -> Result<RenderingControl, AppError> {
+    let (base, service_block) = fetch_service(client, device, "RenderingControl", |t| {
+        is_service_version(t, RENDERING_CONTROL)
+    })
+    .await?;
+    let control_url = service_url(&base, &service_block, "controlURL")?;
+    let service_type = extract_between(&service_block, "<serviceType>", "</serviceType>")
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+
+    tracing::info!("Resolved {service_type} control URL: {control_url}");
+    Ok(RenderingControl {
+        control_url,
+        service_type,
+    })
+}
+
+/// Fetch the AVTransport SCPD and read the actions, play modes and play
+/// speeds the renderer declares.
+pub async fn fetch_capabilities(
+    client: &SoapClient,
+    device: &Device,
+) -> Result<RendererCapabilities, AppError> {
+    let (base, service_block) = fetch_av_transport_service(client, device).await?;
+    let scpd_url = service_url(&base, &service_block, "SCPDURL")?;
+
+    let body = client.get("SCPD", &scpd_url).await?;
+    let scpd = String::from_utf8_lossy(&body);
+
+    // Action names live in <action><name>...</name>...</action>
+    let actions = xml_blocks(&scpd, "action")
+        .filter_map(|block| extract_between(block, "<name>", "</name>"))
+        .map(|name| name.trim().to_string())
+        .collect();
+
+    // Allowed values of the state variables backing PlayMode and Speed
+    let mut play_modes = Vec::new();
+    let mut play_speeds = Vec::new();
+    for block in xml_blocks(&scpd, "stateVariable") {
+        let name = extract_between(block, "<name>", "</name>").unwrap_or_default().trim();
+        let target = match name {
+            "CurrentPlayMode" => &mut play_modes,
+            "TransportPlaySpeed" => &mut play_speeds,
+            _ => continue,
+        };
+        *target = xml_blocks(block, "allowedValue")
+            .map(|v| v.trim().to_string())
+            .collect();
+    }
+
+    let caps = RendererCapabilities {
+        actions,
+        play_modes,
+        play_speeds,
+    };
+    tracing::info!("Renderer capabilities: {caps:?}");
+    Ok(caps)
+}
+
+/// Iterate over the inner contents of every `<tag>...</tag>` element in `s`.
+fn xml_blocks<'a>(s: &'a str, tag: &str) -> impl Iterator<Item = &'a str> + 'a {
+    let open_tag = format!("<{tag}");
+    let close_tag = format!("</{tag}>");
+    let mut rest = s;
+    std::iter::from_fn(move || loop {
+        let start = rest.find(&open_tag)?;
+        let after = &rest[start + open_tag.len()..];
+        // Make sure we matched the whole tag name, not a prefix (e.g. <actionList>)
+        if !after.starts_with('>') && !after.starts_with(char::is_whitespace) {
+            rest = after;
+            continue;
+        }
+        let content_start = after.find('>')? + 1;
+        let content = &after[content_start..];
+        let end = content.find(&close_tag)?;
+        rest = &content[end + close_tag.len()..];
+        return Some(&content[..end]);
+    })
+}
+
+/// Whether an action only reads renderer state and can safely be resent.
+/// Play, Seek and the other commands are never retried.
+fn is_query(action: &str) -> bool {
+    action.starts_with("Get")
+}
+
+/// Send a SOAP action with `client`, properly handling non-200 responses.
 async fn soap_action(
+    client: &SoapClient,
     control_url: &str,
     service_type: &str,
     action: &str,
@@ -128,24 +276,17 @@ async fn soap_action(
         .parse()
         .map_err(|e| AppError::DlnaAction(format!("Invalid control URL: {e}")))?;
 
-    let req = http02::Request::builder()
-        .method("POST")
-        .uri(uri)
-        .header("Content-Type", "text/xml; charset=\"utf-8\"")
-        .header("SOAPAction", &soap_action_header)
-        .body(hyper014::Body::from(body))
-        .map_err(|e| AppError::DlnaAction(format!("Failed to build SOAP request: {e}")))?;
-
-    let client = hyper014::Client::new();
-    let response = client
-        .request(req)
-        .await
-        .map_err(|e| AppError::DlnaAction(format!("{action} HTTP error: {e}")))?;
-
-    let status = response.status();
-    let body_bytes = hyper014::body::to_bytes(response.into_body())
-        .await
-        .map_err(|e| AppError::DlnaAction(format!("{action} read body error: {e}")))?;
+    let (status, body_bytes) = client
+        .send(action, is_query(action), || {
+            http02::Request::builder()
+                .method("POST")
+                .uri(uri.clone())
+                .header("Content-Type", "text/xml; charset=\"utf-8\"")
+                .header("SOAPAction", &soap_action_header)
+                .body(hyper014::Body::from(body.clone()))
+                .map_err(|e| AppError::DlnaAction(format!("Failed to build SOAP request: {e}")))
+        })
+        .await?;
 
     let body_str = String::from_utf8_lossy(&body_bytes);
 
@@ -232,17 +373,22 @@ fn extract_between<'a>(s: &'a str, start_tag: &str, end_tag: &str) -> Option<&'a
 
 /// Set the media URI on the device and provide DIDL-Lite metadata.
 pub async fn set_av_transport_uri(
-    device: &DlnaDevice,
+    client: &SoapClient,
+    device: &Device,
     control_url: &str,
-    media_url: &str,
-    title: &str,
-    mime_type: &str,
-    file_size: u64,
+    media: &Media,
 ) -> Result<(), AppError> {
-    let service_type = device.service.service_type().to_string();
+    let service_type = av_transport(device)?;
+    let media_url = media.url.as_str();
 
     // Try with full DIDL-Lite metadata
-    let metadata = didl_metadata(title, media_url, mime_type, file_size);
+    let metadata = didl_metadata(
+        &media.title,
+        media_url,
+        &media.mime_type,
+        media.file_size,
+        media.subtitle_url.as_deref(),
+    );
     let escaped_metadata = xml_escape(&metadata);
 
     let payload = format!(
@@ -254,7 +400,7 @@ pub async fn set_av_transport_uri(
     );
 
     tracing::debug!("SetAVTransportURI with metadata");
-    match soap_action(control_url, &service_type, "SetAVTransportURI", &payload).await {
+    match soap_action(client, control_url, &service_type, "SetAVTransportURI", &payload).await {
         Ok(_) => return Ok(()),
         Err(e) => tracing::warn!("SetAVTransportURI with metadata failed: {e}"),
     }
@@ -267,41 +413,87 @@ pub async fn set_av_transport_uri(
     );
 
     tracing::debug!("SetAVTransportURI with empty metadata");
-    soap_action(control_url, &service_type, "SetAVTransportURI", &payload)
+    soap_action(client, control_url, &service_type, "SetAVTransportURI", &payload)
         .await
         .map(|_| ())
 }
 
-/// Send Play action.
-pub async fn play(device: &DlnaDevice, control_url: &str) -> Result<(), AppError> {
-    let service_type = device.service.service_type().to_string();
-    let payload = xml_payload(&[("InstanceID", "0"), ("Speed", "1")]);
-    soap_action(control_url, &service_type, "Play", &payload)
+/// Hand the renderer the item to play after the current one (gapless playback).
+pub async fn set_next_av_transport_uri(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+    media: &Media,
+) -> Result<(), AppError> {
+    let service_type = av_transport(device)?;
+    let media_url = media.url.as_str();
+    let metadata = didl_metadata(
+        &media.title,
+        media_url,
+        &media.mime_type,
+        media.file_size,
+        media.subtitle_url.as_deref(),
+    );
+
+    let payload = format!(
+        "<InstanceID>0</InstanceID>\
+         <NextURI>{uri}</NextURI>\
+         <NextURIMetaData>{meta}</NextURIMetaData>",
+        uri = xml_escape(media_url),
+        meta = xml_escape(&metadata),
+    );
+
+    tracing::debug!("SetNextAVTransportURI {media_url}");
+    soap_action(client, control_url, &service_type, "SetNextAVTransportURI", &payload)
+        .await
+        .map(|_| ())
+}
+
+/// Send Play action at normal speed.
+pub async fn play(client: &SoapClient, device: &Device, control_url: &str) -> Result<(), AppError> {
+    play_at_speed(client, device, control_url, "1").await
+}
+
+/// Send Play action with a `TransportPlaySpeed` value such as "2" or "1/2".
+pub async fn play_at_speed(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+    speed: &str,
+) -> Result<(), AppError> {
+    let service_type = av_transport(device)?;
+    let payload = xml_payload(&[("InstanceID", "0"), ("Speed", speed)]);
+    soap_action(client, control_url, &service_type, "Play", &payload)
         .await
         .map(|_| ())
 }
 
 /// Send Pause action.
-pub async fn pause(device: &DlnaDevice, control_url: &str) -> Result<(), AppError> {
-    let service_type = device.service.service_type().to_string();
+pub async fn pause(client: &SoapClient, device: &Device, control_url: &str) -> Result<(), AppError> {
+    let service_type = av_transport(device)?;
     let payload = xml_payload(&[("InstanceID", "0")]);
-    soap_action(control_url, &service_type, "Pause", &payload)
+    soap_action(client, control_url, &service_type, "Pause", &payload)
         .await
         .map(|_| ())
 }
 
 /// Send Stop action.
-pub async fn stop(device: &DlnaDevice, control_url: &str) -> Result<(), AppError> {
-    let service_type = device.service.service_type().to_string();
+pub async fn stop(client: &SoapClient, device: &Device, control_url: &str) -> Result<(), AppError> {
+    let service_type = av_transport(device)?;
     let payload = xml_payload(&[("InstanceID", "0")]);
-    soap_action(control_url, &service_type, "Stop", &payload)
+    soap_action(client, control_url, &service_type, "Stop", &payload)
         .await
         .map(|_| ())
 }
 
 /// Seek to an absolute position (HH:MM:SS).
-pub async fn seek(device: &DlnaDevice, control_url: &str, target_secs: u64) -> Result<(), AppError> {
-    let service_type = device.service.service_type().to_string();
+pub async fn seek(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+    target_secs: u64,
+) -> Result<(), AppError> {
+    let service_type = av_transport(device)?;
     let h = target_secs / 3600;
     let m = (target_secs % 3600) / 60;
     let s = target_secs % 60;
@@ -312,16 +504,20 @@ pub async fn seek(device: &DlnaDevice, control_url: &str, target_secs: u64) -> R
         ("Unit", "REL_TIME"),
         ("Target", &target),
     ]);
-    soap_action(control_url, &service_type, "Seek", &payload)
+    soap_action(client, control_url, &service_type, "Seek", &payload)
         .await
         .map(|_| ())
 }
 
 /// Query the device for current position info.
-pub async fn get_position_info(device: &DlnaDevice, control_url: &str) -> Result<PositionInfo, AppError> {
-    let service_type = device.service.service_type().to_string();
+pub async fn get_position_info(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+) -> Result<PositionInfo, AppError> {
+    let service_type = av_transport(device)?;
     let payload = xml_payload(&[("InstanceID", "0")]);
-    let response = soap_action(control_url, &service_type, "GetPositionInfo", &payload).await?;
+    let response = soap_action(client, control_url, &service_type, "GetPositionInfo", &payload).await?;
 
     let elapsed = response
         .get("RelTime")
@@ -331,18 +527,27 @@ pub async fn get_position_info(device: &DlnaDevice, control_url: &str) -> Result
         .get("TrackDuration")
         .map(|s| parse_duration(s))
         .unwrap_or(0);
+    let track_uri = response
+        .get("TrackURI")
+        .map(|s| xml_unescape(s.trim()))
+        .unwrap_or_default();
 
     Ok(PositionInfo {
         elapsed_secs: elapsed,
         duration_secs: duration,
+        track_uri,
     })
 }
 
 /// Query the device for transport state.
-pub async fn get_transport_info(device: &DlnaDevice, control_url: &str) -> Result<PlaybackState, AppError> {
-    let service_type = device.service.service_type().to_string();
+pub async fn get_transport_info(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+) -> Result<PlaybackState, AppError> {
+    let service_type = av_transport(device)?;
     let payload = xml_payload(&[("InstanceID", "0")]);
-    let response = soap_action(control_url, &service_type, "GetTransportInfo", &payload).await?;
+    let response = soap_action(client, control_url, &service_type, "GetTransportInfo", &payload).await?;
 
     let state = response
         .get("CurrentTransportState")
@@ -351,3 +556,301 @@ pub async fn get_transport_info(device: &DlnaDevice, control_url: &str) -> Resul
 
     Ok(state)
 }
+
+/// Set the renderer's play mode (repeat/shuffle).
+pub async fn set_play_mode(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+    mode: PlayMode,
+) -> Result<(), AppError> {
+    let service_type = av_transport(device)?;
+    let payload = xml_payload(&[("InstanceID", "0"), ("NewPlayMode", mode.as_upnp())]);
+    soap_action(client, control_url, &service_type, "SetPlayMode", &payload)
+        .await
+        .map(|_| ())
+}
+
+/// Query the renderer's current play mode.
+pub async fn get_transport_settings(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+) -> Result<PlayMode, AppError> {
+    let service_type = av_transport(device)?;
+    let payload = xml_payload(&[("InstanceID", "0")]);
+    let response = soap_action(client, control_url, &service_type, "GetTransportSettings", &payload).await?;
+
+    let mode = response
+        .get("PlayMode")
+        .and_then(|s| PlayMode::from_upnp(s.trim()))
+        .unwrap_or_default();
+
+    Ok(mode)
+}
+
+/// Query which transport actions the renderer allows right now.
+pub async fn get_current_transport_actions(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+) -> Result<TransportActions, AppError> {
+    let service_type = av_transport(device)?;
+    let payload = xml_payload(&[("InstanceID", "0")]);
+    let response = soap_action(client, control_url, &service_type, "GetCurrentTransportActions", &payload).await?;
+
+    Ok(TransportActions(
+        response
+            .get("Actions")
+            .map(|s| parse_transport_actions(s))
+            .unwrap_or_default(),
+    ))
+}
+
+/// Query what media the renderer has loaded.
+pub async fn get_media_info(
+    client: &SoapClient,
+    device: &Device,
+    control_url: &str,
+) -> Result<MediaInfo, AppError> {
+    let service_type = av_transport(device)?;
+    let payload = xml_payload(&[("InstanceID", "0")]);
+    let response = soap_action(client, control_url, &service_type, "GetMediaInfo", &payload).await?;
+
+    let current_uri_metadata = response
+        .get("CurrentURIMetaData")
+        .map(|s| xml_unescape(s.trim()))
+        .unwrap_or_default();
+    // The metadata is escaped DIDL-Lite; after unescaping, dc:title is plain XML
+    let title = extract_between(&current_uri_metadata, "<dc:title>", "</dc:title>")
+        .map(xml_unescape);
+
+    Ok(MediaInfo {
+        nr_tracks: response
+            .get("NrTracks")
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0),
+        media_duration_secs: response
+            .get("MediaDuration")
+            .map(|s| parse_duration(s))
+            .unwrap_or(0),
+        current_uri: response
+            .get("CurrentURI")
+            .map(|s| xml_unescape(s.trim()))
+            .unwrap_or_default(),
+        current_uri_metadata,
+        title,
+    })
+}
+
+/// Query the master volume (0-100) through RenderingControl.
+pub async fn get_volume(client: &SoapClient, rc: &RenderingControl) -> Result<u32, AppError> {
+    let payload = xml_payload(&[("InstanceID", "0"), ("Channel", "Master")]);
+    let response = soap_action(client, &rc.control_url, &rc.service_type, "GetVolume", &payload).await?;
+
+    response
+        .get("CurrentVolume")
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| AppError::DlnaAction("GetVolume returned no CurrentVolume".into()))
+}
+
+/// Set the master volume (0-100) through RenderingControl.
+pub async fn set_volume(client: &SoapClient, rc: &RenderingControl, volume: u32) -> Result<(), AppError> {
+    let volume = volume.to_string();
+    let payload = xml_payload(&[
+        ("InstanceID", "0"),
+        ("Channel", "Master"),
+        ("DesiredVolume", &volume),
+    ]);
+    soap_action(client, &rc.control_url, &rc.service_type, "SetVolume", &payload)
+        .await
+        .map(|_| ())
+}
+
+/// Ask the ConnectionManager which formats the renderer plays: its `Sink`
+/// protocol info entries, e.g. `http-get:*:video/mp4:*`.
+pub async fn get_protocol_info(client: &SoapClient, device: &Device) -> Result<Vec<String>, AppError> {
+    let (base, service_block) =
+        fetch_service(client, device, "ConnectionManager", |t| is_service_version(t, CONNECTION_MANAGER)).await?;
+    let control_url = service_url(&base, &service_block, "controlURL")?;
+    let service_type = extract_between(&service_block, "<serviceType>", "</serviceType>")
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    let response = soap_action(client, &control_url, &service_type, "GetProtocolInfo", "").await?;
+    Ok(response
+        .get("Sink")
+        .map(|sink| {
+            sink.split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Split a CSV list of transport actions. Commas escaped with a backslash
+/// (as in `X_DLNA_PS=2\,1/2`) belong to the same entry. `NOT_IMPLEMENTED`
+/// says nothing about the actions, so it gives an empty list.
+fn parse_transport_actions(s: &str) -> Vec<String> {
+    if s.trim() == "NOT_IMPLEMENTED" {
+        return Vec::new();
+    }
+    let mut actions = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            ',' if !escaped => {
+                let action = current.trim();
+                if !action.is_empty() {
+                    actions.push(action.to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+        escaped = c == '\\';
+    }
+    let action = current.trim();
+    if !action.is_empty() {
+        actions.push(action.to_string());
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlna::client::SoapConfig;
+    use crate::dlna::types::{PlayMode, RendererCapabilities, TransportActions};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+
+    /// A control URL whose connections are dropped unanswered, and how many
+    /// were made to it.
+    async fn hanging_up() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/control", listener.local_addr().unwrap());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counted.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+        (url, attempts)
+    }
+
+    fn client(retries: u32) -> SoapClient {
+        SoapClient::new(SoapConfig {
+            retries,
+            retry_delay: Duration::from_millis(10),
+            ..SoapConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn commands_are_never_retried() {
+        for action in ["Play", "Seek", "SetAVTransportURI", "Stop", "SetPlayMode"] {
+            let (url, attempts) = hanging_up().await;
+            let result = soap_action(&client(3), &url, AV_TRANSPORT, action, "").await;
+            assert!(result.is_err(), "{action} succeeded");
+            assert_eq!(attempts.load(Ordering::SeqCst), 1, "{action} was resent");
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_are_retried_up_to_the_limit() {
+        for retries in [0, 2] {
+            let (url, attempts) = hanging_up().await;
+            let result = soap_action(&client(retries), &url, AV_TRANSPORT, "GetPositionInfo", "").await;
+            assert!(result.is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), retries as usize + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn retried_query_succeeds_once_answered() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/control", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            // Hang up on the first attempt, answer the second
+            let (first, _) = listener.accept().await.unwrap();
+            drop(first);
+            let (mut second, _) = listener.accept().await.unwrap();
+            let body = "<s:Envelope><s:Body><u:GetTransportInfoResponse>\
+                        <CurrentTransportState>PLAYING</CurrentTransportState>\
+                        </u:GetTransportInfoResponse></s:Body></s:Envelope>";
+            let mut request = [0u8; 4096];
+            let _ = second.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            second.write_all(response.as_bytes()).await.unwrap();
+        });
+        let values = soap_action(&client(2), &url, AV_TRANSPORT, "GetTransportInfo", "").await.unwrap();
+        assert_eq!(values["CurrentTransportState"], "PLAYING");
+    }
+
+    #[test]
+    fn only_get_actions_are_queries() {
+        assert!(is_query("GetPositionInfo") && is_query("GetCurrentTransportActions"));
+        for action in ["Play", "Pause", "Stop", "Seek", "SetAVTransportURI", "SetNextAVTransportURI", "Next"] {
+            assert!(!is_query(action), "{action}");
+        }
+    }
+
+    #[test]
+    fn transport_actions_ignore_whitespace_and_empty_entries() {
+        assert_eq!(parse_transport_actions(" Play , Stop,,Seek ,"), ["Play", "Stop", "Seek"]);
+        assert_eq!(parse_transport_actions(""), Vec::<String>::new());
+        assert_eq!(parse_transport_actions("  ,  "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn escaped_commas_stay_in_the_entry() {
+        let actions = parse_transport_actions(r"Play,X_DLNA_PS=2\,1/2,Pause");
+        assert_eq!(actions, ["Play", r"X_DLNA_PS=2\,1/2", "Pause"]);
+        let actions = TransportActions(actions);
+        assert_eq!(actions.play_speeds().collect::<Vec<_>>(), ["2", "1/2"]);
+    }
+
+    #[test]
+    fn not_implemented_allows_everything() {
+        let actions = TransportActions(parse_transport_actions(" NOT_IMPLEMENTED "));
+        assert!(actions.can_play() && actions.can_pause() && actions.can_seek());
+
+        let actions = TransportActions(parse_transport_actions("Play,Stop"));
+        assert!(actions.can_play() && !actions.can_pause());
+    }
+
+    #[test]
+    fn play_modes_need_set_play_mode() {
+        let capabilities = RendererCapabilities {
+            actions: vec!["Play".into(), "SetPlayMode".into()],
+            play_modes: vec!["NORMAL".into(), "REPEAT_ONE".into(), "NOT_IMPLEMENTED".into()],
+            play_speeds: Vec::new(),
+        };
+        assert!(capabilities.supports_play_mode(PlayMode::RepeatOne));
+        assert!(!capabilities.supports_play_mode(PlayMode::Shuffle));
+
+        let without_action = RendererCapabilities {
+            actions: vec!["Play".into()],
+            ..capabilities
+        };
+        assert!(!without_action.supports_play_mode(PlayMode::RepeatOne));
+        // Normal needs nothing from the renderer
+        assert!(without_action.supports_play_mode(PlayMode::Normal));
+        assert!(RendererCapabilities::default().supports_play_mode(PlayMode::Normal));
+        assert!(!RendererCapabilities::default().supports_play_mode(PlayMode::RepeatAll));
+    }
+}
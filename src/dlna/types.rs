@@ -1,17 +1,6 @@
-use std::sync::Arc;
-
-use http02::Uri;
-
-/// Represents a discovered DLNA MediaRenderer device.
-#[derive(Debug, Clone)]
-pub struct DlnaDevice {
-    pub friendly_name: String,
-    pub service: Arc<rupnp::Service>,
-    pub device_url: Uri,
-}
-
 /// Playback transport state as reported by the TV.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum PlaybackState {
     Stopped,
     Playing,
@@ -45,11 +34,202 @@ impl PlaybackState {
     }
 }
 
+/// Play mode of the AVTransport service (`CurrentPlayMode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum PlayMode {
+    #[default]
+    Normal,
+    RepeatOne,
+    RepeatAll,
+    Shuffle,
+}
+
+impl PlayMode {
+    pub const ALL: [PlayMode; 4] = [
+        PlayMode::Normal,
+        PlayMode::RepeatOne,
+        PlayMode::RepeatAll,
+        PlayMode::Shuffle,
+    ];
+
+    /// The value used in SetPlayMode / GetTransportSettings.
+    pub fn as_upnp(&self) -> &'static str {
+        match self {
+            Self::Normal => "NORMAL",
+            Self::RepeatOne => "REPEAT_ONE",
+            Self::RepeatAll => "REPEAT_ALL",
+            Self::Shuffle => "SHUFFLE",
+        }
+    }
+
+    pub fn from_upnp(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_upnp() == s)
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Normal => "Normal",
+            Self::RepeatOne => "Repeat one",
+            Self::RepeatAll => "Repeat all",
+            Self::Shuffle => "Shuffle",
+        }
+    }
+
+    /// The next mode when cycling through modes in the UI.
+    pub fn cycle(&self) -> Self {
+        match self {
+            Self::Normal => Self::RepeatAll,
+            Self::RepeatAll => Self::RepeatOne,
+            Self::RepeatOne => Self::Shuffle,
+            Self::Shuffle => Self::Normal,
+        }
+    }
+}
+
+/// Play speeds localcast offers, slowest first, as `TransportPlaySpeed` values.
+pub const PLAY_SPEEDS: [&str; 3] = ["1/2", "1", "2"];
+
+/// Human-readable label for a `TransportPlaySpeed` value, e.g. "2x".
+pub fn speed_label(speed: &str) -> String {
+    match speed {
+        "1/2" => "0.5x".into(),
+        other => format!("{other}x"),
+    }
+}
+
+/// What a renderer's AVTransport service declares in its SCPD.
+#[derive(Debug, Clone, Default)]
+pub struct RendererCapabilities {
+    /// Names of the actions the service implements.
+    pub actions: Vec<String>,
+    /// Allowed values of `CurrentPlayMode`.
+    pub play_modes: Vec<String>,
+    /// Allowed values of `TransportPlaySpeed`.
+    pub play_speeds: Vec<String>,
+}
+
+impl RendererCapabilities {
+    /// Whether the SCPD lists `action`. Unknown (empty SCPD) counts as supported.
+    pub fn supports_action(&self, action: &str) -> bool {
+        self.actions.is_empty() || self.actions.iter().any(|a| a == action)
+    }
+
+    /// Whether the renderer can apply `mode` itself.
+    pub fn supports_play_mode(&self, mode: PlayMode) -> bool {
+        mode == PlayMode::Normal
+            || (self.supports_action("SetPlayMode")
+                && self.play_modes.iter().any(|m| m == mode.as_upnp()))
+    }
+
+    /// Whether `speed` is listed by the SCPD or in the DLNA play-speed set
+    /// (`X_DLNA_PS`) of the current transport actions.
+    pub fn supports_speed(&self, speed: &str, transport_actions: &TransportActions) -> bool {
+        speed == "1"
+            || self.play_speeds.iter().any(|s| s == speed)
+            || transport_actions.play_speeds().any(|s| s == speed)
+    }
+
+    /// The next supported speed above (`faster`) or below `current`.
+    pub fn step_speed(
+        &self,
+        current: &str,
+        faster: bool,
+        transport_actions: &TransportActions,
+    ) -> Option<&'static str> {
+        let pos = PLAY_SPEEDS.iter().position(|s| *s == current).unwrap_or(1);
+        let mut candidates: Vec<&'static str> = if faster {
+            PLAY_SPEEDS[pos + 1..].to_vec()
+        } else {
+            PLAY_SPEEDS[..pos].iter().rev().copied().collect()
+        };
+        candidates.retain(|s| self.supports_speed(s, transport_actions));
+        candidates.first().copied()
+    }
+}
+
+/// Actions the renderer currently allows, from GetCurrentTransportActions.
+///
+/// An empty list means the renderer didn't answer (many don't implement the
+/// action), in which case every action is assumed to be allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransportActions(pub Vec<String>);
+
+impl TransportActions {
+    pub fn allows(&self, action: &str) -> bool {
+        self.0.is_empty() || self.0.iter().any(|a| a.eq_ignore_ascii_case(action))
+    }
+
+    pub fn can_play(&self) -> bool {
+        self.allows("Play")
+    }
+
+    pub fn can_pause(&self) -> bool {
+        self.allows("Pause")
+    }
+
+    pub fn can_stop(&self) -> bool {
+        self.allows("Stop")
+    }
+
+    pub fn can_seek(&self) -> bool {
+        self.allows("Seek")
+    }
+
+    /// The standard actions that are currently allowed, lower-cased, for UIs.
+    pub fn allowed(&self) -> Vec<String> {
+        ["Play", "Pause", "Stop", "Seek", "Next", "Previous"]
+            .into_iter()
+            .filter(|a| self.allows(a))
+            .map(|a| a.to_lowercase())
+            .collect()
+    }
+
+    /// Play speeds from an `X_DLNA_PS=2\,1/2` entry.
+    pub fn play_speeds(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter_map(|a| a.strip_prefix("X_DLNA_PS="))
+            .flat_map(|speeds| speeds.split(['\\', ',']))
+            .filter(|s| !s.is_empty())
+    }
+}
+
+/// What the renderer reports as loaded, from GetMediaInfo.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaInfo {
+    pub nr_tracks: u32,
+    pub media_duration_secs: u64,
+    pub current_uri: String,
+    pub current_uri_metadata: String,
+    /// `dc:title` from the DIDL-Lite metadata, if any.
+    pub title: Option<String>,
+}
+
+impl MediaInfo {
+    /// Whether the renderer has media loaded that localcast didn't serve.
+    /// `own_paths` are the serve paths of the items we handed it.
+    pub fn is_foreign(&self, own_paths: &[&str]) -> bool {
+        !self.current_uri.is_empty() && !own_paths.iter().any(|p| self.current_uri.ends_with(p))
+    }
+
+    /// Short description of what is loaded, for display.
+    pub fn display_name(&self) -> String {
+        match &self.title {
+            Some(title) if !title.is_empty() => title.clone(),
+            _ => self.current_uri.clone(),
+        }
+    }
+}
+
 /// Position and duration info from GetPositionInfo.
 #[derive(Debug, Clone, Default)]
+#[non_exhaustive]
 pub struct PositionInfo {
     pub elapsed_secs: u64,
     pub duration_secs: u64,
+    /// URI of the track the renderer is currently playing.
+    pub track_uri: String,
 }
 
 impl PositionInfo {
@@ -61,6 +241,11 @@ impl PositionInfo {
         format_duration(self.duration_secs)
     }
 
+    /// Whether playback is within a few seconds of the end of the track.
+    pub fn near_end(&self) -> bool {
+        self.duration_secs > 0 && self.elapsed_secs + 5 >= self.duration_secs
+    }
+
     pub fn progress_ratio(&self) -> f64 {
         if self.duration_secs == 0 {
             0.0
@@ -92,3 +277,80 @@ pub fn parse_duration(time_str: &str) -> u64 {
     let s: u64 = parts[2].parse().unwrap_or(0);
     h * 3600 + m * 60 + s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(play_speeds: &[&str]) -> RendererCapabilities {
+        RendererCapabilities {
+            play_speeds: play_speeds.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn actions(actions: &[&str]) -> TransportActions {
+        TransportActions(actions.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn fractional_speeds_come_from_the_scpd_or_transport_actions() {
+        let none = TransportActions::default();
+        assert!(capabilities(&["1", "1/2"]).supports_speed("1/2", &none));
+        assert!(!capabilities(&["1", "2"]).supports_speed("1/2", &none));
+        assert!(capabilities(&[]).supports_speed("1/2", &actions(&["Play", r"X_DLNA_PS=1/2\,2"])));
+        assert!(capabilities(&[]).supports_speed("1", &none));
+        assert_eq!(speed_label("1/2"), "0.5x");
+        assert_eq!(speed_label("2"), "2x");
+    }
+
+    #[test]
+    fn stepping_skips_unsupported_speeds() {
+        let none = TransportActions::default();
+        let half_and_double = capabilities(&["1/2", "1", "2"]);
+        assert_eq!(half_and_double.step_speed("1", true, &none), Some("2"));
+        assert_eq!(half_and_double.step_speed("1", false, &none), Some("1/2"));
+        assert_eq!(half_and_double.step_speed("1/2", true, &none), Some("1"));
+
+        let double_only = capabilities(&["1", "2"]);
+        assert_eq!(double_only.step_speed("2", false, &none), Some("1"));
+        assert_eq!(double_only.step_speed("1", false, &none), None);
+        // Via X_DLNA_PS, escaped comma and all
+        let dlna = actions(&[r"X_DLNA_PS=1/2\,2"]);
+        assert_eq!(capabilities(&[]).step_speed("1", false, &dlna), Some("1/2"));
+    }
+
+    #[test]
+    fn stepping_stops_at_either_end() {
+        let none = TransportActions::default();
+        let all = capabilities(&PLAY_SPEEDS);
+        assert_eq!(all.step_speed("2", true, &none), None);
+        assert_eq!(all.step_speed("1/2", false, &none), None);
+        // An unknown current speed counts as normal
+        assert_eq!(all.step_speed("8", true, &none), Some("2"));
+        assert_eq!(all.step_speed("8", false, &none), Some("1/2"));
+    }
+
+    #[test]
+    fn foreign_media_is_any_uri_we_did_not_serve() {
+        let info = |uri: &str| MediaInfo {
+            current_uri: uri.into(),
+            ..Default::default()
+        };
+        let ours = ["/media/0/stream.mp4", "/media/1/stream.mkv"];
+        assert!(!info("http://192.168.1.2:8000/media/1/stream.mkv").is_foreign(&ours));
+        assert!(info("http://192.168.1.2:8000/media/2/stream.mp4").is_foreign(&ours));
+        assert!(info("https://www.youtube.com/watch?v=x").is_foreign(&ours));
+        assert!(info("http://192.168.1.2:8000/media/0/stream.mp4").is_foreign(&[]));
+        // Nothing loaded is nobody's media
+        assert!(!info("").is_foreign(&ours));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("01:02:03"), 3723);
+        assert_eq!(parse_duration("0:00:05.500"), 5);
+        assert_eq!(parse_duration("NOT_IMPLEMENTED"), 0);
+        assert_eq!(format_duration(3723), "01:02:03");
+    }
+}
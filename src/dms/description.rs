@@ -0,0 +1,168 @@
+//! The device description and the service descriptions (SCPDs) control
+//! points read before sending actions.
+
+use super::soap::xml_escape;
+use super::{CONNECTION_MANAGER, CONTENT_DIRECTORY, DEVICE_TYPE};
+
+/// The root device description for a server called `name`.
+pub fn device(name: &str, udn: &str) -> String {
+    let name = xml_escape(name);
+    let version = env!("CARGO_PKG_VERSION");
+    let services = [(CONTENT_DIRECTORY, "ContentDirectory"), (CONNECTION_MANAGER, "ConnectionManager")]
+        .iter()
+        .map(|(service_type, id)| {
+            format!(
+                "<service><serviceType>{service_type}</serviceType><serviceId>urn:upnp-org:serviceId:{id}</serviceId>\
+                 <SCPDURL>/{id}/scpd.xml</SCPDURL><controlURL>/{id}/control</controlURL><eventSubURL>/{id}/event</eventSubURL></service>"
+            )
+        })
+        .collect::<String>();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0" xmlns:dlna="urn:schemas-dlna-org:device-1-0"><specVersion><major>1</major><minor>0</minor></specVersion><device><deviceType>{DEVICE_TYPE}</deviceType><friendlyName>{name}</friendlyName><manufacturer>localcast</manufacturer><modelName>localcast</modelName><modelNumber>{version}</modelNumber><UDN>{udn}</UDN><dlna:X_DLNADOC>DMS-1.50</dlna:X_DLNADOC><serviceList>{services}</serviceList></device></root>"#
+    )
+}
+
+/// An argument of an action: name, direction and related state variable.
+type Argument = (&'static str, &'static str, &'static str);
+
+/// A state variable: name, data type and allowed values.
+type Variable = (&'static str, &'static str, &'static [&'static str]);
+
+const CONTENT_DIRECTORY_ACTIONS: &[(&str, &[Argument])] = &[
+    (
+        "Browse",
+        &[
+            ("ObjectID", "in", "A_ARG_TYPE_ObjectID"),
+            ("BrowseFlag", "in", "A_ARG_TYPE_BrowseFlag"),
+            ("Filter", "in", "A_ARG_TYPE_Filter"),
+            ("StartingIndex", "in", "A_ARG_TYPE_Index"),
+            ("RequestedCount", "in", "A_ARG_TYPE_Count"),
+            ("SortCriteria", "in", "A_ARG_TYPE_SortCriteria"),
+            ("Result", "out", "A_ARG_TYPE_Result"),
+            ("NumberReturned", "out", "A_ARG_TYPE_Count"),
+            ("TotalMatches", "out", "A_ARG_TYPE_Count"),
+            ("UpdateID", "out", "A_ARG_TYPE_UpdateID"),
+        ],
+    ),
+    (
+        "Search",
+        &[
+            ("ContainerID", "in", "A_ARG_TYPE_ObjectID"),
+            ("SearchCriteria", "in", "A_ARG_TYPE_SearchCriteria"),
+            ("Filter", "in", "A_ARG_TYPE_Filter"),
+            ("StartingIndex", "in", "A_ARG_TYPE_Index"),
+            ("RequestedCount", "in", "A_ARG_TYPE_Count"),
+            ("SortCriteria", "in", "A_ARG_TYPE_SortCriteria"),
+            ("Result", "out", "A_ARG_TYPE_Result"),
+            ("NumberReturned", "out", "A_ARG_TYPE_Count"),
+            ("TotalMatches", "out", "A_ARG_TYPE_Count"),
+            ("UpdateID", "out", "A_ARG_TYPE_UpdateID"),
+        ],
+    ),
+    ("GetSearchCapabilities", &[("SearchCaps", "out", "SearchCapabilities")]),
+    ("GetSortCapabilities", &[("SortCaps", "out", "SortCapabilities")]),
+    ("GetSystemUpdateID", &[("Id", "out", "SystemUpdateID")]),
+];
+
+const CONTENT_DIRECTORY_VARIABLES: &[Variable] = &[
+    ("A_ARG_TYPE_ObjectID", "string", &[]),
+    ("A_ARG_TYPE_Result", "string", &[]),
+    ("A_ARG_TYPE_BrowseFlag", "string", &["BrowseMetadata", "BrowseDirectChildren"]),
+    ("A_ARG_TYPE_Filter", "string", &[]),
+    ("A_ARG_TYPE_SortCriteria", "string", &[]),
+    ("A_ARG_TYPE_SearchCriteria", "string", &[]),
+    ("A_ARG_TYPE_Index", "ui4", &[]),
+    ("A_ARG_TYPE_Count", "ui4", &[]),
+    ("A_ARG_TYPE_UpdateID", "ui4", &[]),
+    ("SearchCapabilities", "string", &[]),
+    ("SortCapabilities", "string", &[]),
+    ("SystemUpdateID", "ui4", &[]),
+];
+
+const CONNECTION_MANAGER_ACTIONS: &[(&str, &[Argument])] = &[
+    (
+        "GetProtocolInfo",
+        &[("Source", "out", "SourceProtocolInfo"), ("Sink", "out", "SinkProtocolInfo")],
+    ),
+    ("GetCurrentConnectionIDs", &[("ConnectionIDs", "out", "CurrentConnectionIDs")]),
+    (
+        "GetCurrentConnectionInfo",
+        &[
+            ("ConnectionID", "in", "A_ARG_TYPE_ConnectionID"),
+            ("RcsID", "out", "A_ARG_TYPE_RcsID"),
+            ("AVTransportID", "out", "A_ARG_TYPE_AVTransportID"),
+            ("ProtocolInfo", "out", "A_ARG_TYPE_ProtocolInfo"),
+            ("PeerConnectionManager", "out", "A_ARG_TYPE_ConnectionManager"),
+            ("PeerConnectionID", "out", "A_ARG_TYPE_ConnectionID"),
+            ("Direction", "out", "A_ARG_TYPE_Direction"),
+            ("Status", "out", "A_ARG_TYPE_ConnectionStatus"),
+        ],
+    ),
+];
+
+const CONNECTION_MANAGER_VARIABLES: &[Variable] = &[
+    ("SourceProtocolInfo", "string", &[]),
+    ("SinkProtocolInfo", "string", &[]),
+    ("CurrentConnectionIDs", "string", &[]),
+    (
+        "A_ARG_TYPE_ConnectionStatus",
+        "string",
+        &["OK", "ContentFormatMismatch", "InsufficientBandwidth", "UnreliableChannel", "Unknown"],
+    ),
+    ("A_ARG_TYPE_ConnectionManager", "string", &[]),
+    ("A_ARG_TYPE_Direction", "string", &["Input", "Output"]),
+    ("A_ARG_TYPE_ProtocolInfo", "string", &[]),
+    ("A_ARG_TYPE_ConnectionID", "i4", &[]),
+    ("A_ARG_TYPE_AVTransportID", "i4", &[]),
+    ("A_ARG_TYPE_RcsID", "i4", &[]),
+];
+
+pub fn content_directory() -> String {
+    scpd(CONTENT_DIRECTORY_ACTIONS, CONTENT_DIRECTORY_VARIABLES)
+}
+
+pub fn connection_manager() -> String {
+    scpd(CONNECTION_MANAGER_ACTIONS, CONNECTION_MANAGER_VARIABLES)
+}
+
+fn scpd(actions: &[(&str, &[Argument])], variables: &[Variable]) -> String {
+    let actions: String = actions
+        .iter()
+        .map(|(name, arguments)| {
+            let arguments: String = arguments
+                .iter()
+                .map(|(name, direction, variable)| {
+                    format!(
+                        "<argument><name>{name}</name><direction>{direction}</direction>\
+                         <relatedStateVariable>{variable}</relatedStateVariable></argument>"
+                    )
+                })
+                .collect();
+            format!("<action><name>{name}</name><argumentList>{arguments}</argumentList></action>")
+        })
+        .collect();
+    let variables: String = variables
+        .iter()
+        .map(|(name, data_type, allowed)| {
+            // Only SystemUpdateID is evented, and only nominally: see `subscribe`
+            let events = if *name == "SystemUpdateID" { "yes" } else { "no" };
+            let allowed = if allowed.is_empty() {
+                String::new()
+            } else {
+                let values: String = allowed
+                    .iter()
+                    .map(|value| format!("<allowedValue>{value}</allowedValue>"))
+                    .collect();
+                format!("<allowedValueList>{values}</allowedValueList>")
+            };
+            format!(
+                r#"<stateVariable sendEvents="{events}"><name>{name}</name><dataType>{data_type}</dataType>{allowed}</stateVariable>"#
+            )
+        })
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0"><specVersion><major>1</major><minor>0</minor></specVersion><actionList>{actions}</actionList><serviceStateTable>{variables}</serviceStateTable></scpd>"#
+    )
+}
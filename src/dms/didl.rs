@@ -0,0 +1,147 @@
+//! DIDL-Lite for Browse and Search results.
+
+use super::library::{Object, ObjectKind};
+use super::soap::xml_escape;
+
+/// DLNA.ORG_OP=01: byte seeks (Range requests) are supported.
+const DLNA_FEATURES: &str = "DLNA.ORG_OP=01;DLNA.ORG_FLAGS=01700000000000000000000000000000";
+
+/// A DIDL-Lite document listing `objects`, with resources served from
+/// `base_url`.
+pub fn didl(objects: &[Object], base_url: &str) -> String {
+    let mut didl = String::from(
+        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" xmlns:dlna="urn:schemas-dlna-org:metadata-1-0/" xmlns:sec="http://www.sec.co.kr/">"#,
+    );
+    for object in objects {
+        didl.push_str(&element(object, base_url));
+    }
+    didl.push_str("</DIDL-Lite>");
+    didl
+}
+
+fn element(object: &Object, base_url: &str) -> String {
+    let id = xml_escape(&object.id);
+    let parent_id = xml_escape(&object.parent_id);
+    let title = xml_escape(&object.title);
+    let class = object.class();
+    match &object.kind {
+        ObjectKind::Container { child_count } => format!(
+            r#"<container id="{id}" parentID="{parent_id}" restricted="1" searchable="1" childCount="{child_count}"><dc:title>{title}</dc:title><upnp:class>{class}</upnp:class></container>"#
+        ),
+        ObjectKind::Item {
+            size,
+            mime_type,
+            subtitle,
+            ..
+        } => {
+            let date = object
+                .date()
+                .map(|date| format!("<dc:date>{date}</dc:date>"))
+                .unwrap_or_default();
+            let url = xml_escape(&media_url(base_url, object));
+            let subtitles = match subtitle {
+                Some(_) => {
+                    let url = xml_escape(&subtitle_url(base_url, &object.id));
+                    format!(
+                        r#"<sec:CaptionInfoEx sec:type="srt">{url}</sec:CaptionInfoEx><res protocolInfo="http-get:*:text/srt:*">{url}</res>"#
+                    )
+                }
+                None => String::new(),
+            };
+            format!(
+                r#"<item id="{id}" parentID="{parent_id}" restricted="1"><dc:title>{title}</dc:title><upnp:class>{class}</upnp:class>{date}<res protocolInfo="http-get:*:{mime_type}:{DLNA_FEATURES}" size="{size}">{url}</res>{subtitles}</item>"#
+            )
+        }
+    }
+}
+
+/// Where an item is served: its ID in hex, so it needs no escaping, and
+/// the file's extension, which some TVs go by.
+fn media_url(base_url: &str, object: &Object) -> String {
+    let ext = object
+        .path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("mp4")
+        .to_lowercase();
+    format!("{base_url}/content/{}/stream.{ext}", hex(&object.id))
+}
+
+fn subtitle_url(base_url: &str, id: &str) -> String {
+    format!("{base_url}/content/{}/subtitle.srt", hex(id))
+}
+
+fn hex(id: &str) -> String {
+    id.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The object ID in a media or subtitle URL.
+pub fn unhex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn item(id: &str, title: &str, subtitle: Option<PathBuf>) -> Object {
+        Object {
+            id: id.into(),
+            parent_id: "1".into(),
+            title: title.into(),
+            path: PathBuf::from(format!("/videos/{title}.MKV")),
+            kind: ObjectKind::Item {
+                size: 42,
+                modified: None,
+                mime_type: "video/x-matroska".into(),
+                subtitle,
+            },
+        }
+    }
+
+    #[test]
+    fn titles_and_ids_are_escaped() {
+        let objects = [
+            item(r#"1/Tom & "Jerry" <1>.mkv"#, r#"Tom & "Jerry" <1>"#, None),
+            Object {
+                id: "1/R&D".into(),
+                parent_id: "1".into(),
+                title: "R&D <'old'>".into(),
+                path: PathBuf::from("/videos/R&D"),
+                kind: ObjectKind::Container { child_count: 2 },
+            },
+        ];
+        let didl = didl(&objects, "http://192.168.1.2:8200");
+        assert!(didl.contains("<dc:title>Tom &amp; &quot;Jerry&quot; &lt;1&gt;</dc:title>"));
+        assert!(didl.contains(r#"id="1/Tom &amp; &quot;Jerry&quot; &lt;1&gt;.mkv""#));
+        assert!(didl.contains("<dc:title>R&amp;D &lt;&apos;old&apos;&gt;</dc:title>"));
+        assert!(didl.contains(r#"childCount="2""#));
+        // Nothing unescaped left between the tags
+        let text: String = didl.split('>').filter_map(|s| s.split('<').next()).collect();
+        assert!(!text.contains(['<', '"']));
+    }
+
+    #[test]
+    fn resources_are_served_by_hex_id() {
+        let objects = [item("1/a b.mkv", "a b", Some(PathBuf::from("/videos/a b.srt")))];
+        let didl = didl(&objects, "http://h:1");
+        let hex = hex("1/a b.mkv");
+        assert_eq!(unhex(&hex).as_deref(), Some("1/a b.mkv"));
+        assert!(didl.contains(&format!(r#"size="42">http://h:1/content/{hex}/stream.mkv</res>"#)));
+        assert!(didl.contains(&format!("http://h:1/content/{hex}/subtitle.srt</sec:CaptionInfoEx>")));
+        assert!(didl.contains("http-get:*:video/x-matroska:DLNA.ORG_OP=01;"));
+    }
+
+    #[test]
+    fn bad_hex_is_no_id() {
+        assert_eq!(unhex("3"), None);
+        assert_eq!(unhex("zz"), None);
+        assert_eq!(unhex("ff"), None);
+    }
+}
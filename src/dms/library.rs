@@ -0,0 +1,474 @@
+//! The shared folders as a ContentDirectory tree.
+//!
+//! Object IDs are paths: `0` is the root, `1`, `2`, ... the shared folders
+//! in the order given, and `1/Films/movie.mp4` something inside the first.
+//! Nothing is indexed; every request reads the file system, so what TVs see
+//! is always current.
+
+use std::cmp::Ordering;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::search::{Criteria, Properties};
+use crate::queue::{self, SUPPORTED_EXTENSIONS};
+
+/// ID of the root container.
+pub const ROOT_ID: &str = "0";
+
+/// How deep Search looks below the container it was given.
+const MAX_SEARCH_DEPTH: usize = 16;
+
+/// A shared folder.
+#[derive(Debug, Clone)]
+struct Folder {
+    title: String,
+    path: PathBuf,
+}
+
+/// A container or item, as Browse and Search return it.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub id: String,
+    pub parent_id: String,
+    pub title: String,
+    /// Where the object is on disk; empty for the root.
+    pub path: PathBuf,
+    pub kind: ObjectKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjectKind {
+    /// A folder.
+    Container { child_count: usize },
+    /// A video file.
+    Item {
+        size: u64,
+        modified: Option<SystemTime>,
+        mime_type: String,
+        /// A `.srt` file next to the video.
+        subtitle: Option<PathBuf>,
+    },
+}
+
+impl Object {
+    pub fn is_container(&self) -> bool {
+        matches!(self.kind, ObjectKind::Container { .. })
+    }
+
+    /// The `upnp:class` of the object.
+    pub fn class(&self) -> &'static str {
+        match self.kind {
+            ObjectKind::Container { .. } => "object.container.storageFolder",
+            ObjectKind::Item { .. } => "object.item.videoItem",
+        }
+    }
+
+    /// The `dc:date` of an item: when the file was last modified.
+    pub fn date(&self) -> Option<String> {
+        match self.kind {
+            ObjectKind::Item { modified: Some(modified), .. } => Some(iso_date(modified)),
+            _ => None,
+        }
+    }
+}
+
+impl Properties for Object {
+    fn property(&self, property: &str) -> Option<String> {
+        match property {
+            "@id" => Some(self.id.clone()),
+            "@parentID" => Some(self.parent_id.clone()),
+            "dc:title" => Some(self.title.clone()),
+            "upnp:class" => Some(self.class().to_string()),
+            "dc:date" => self.date(),
+            "res@size" => match self.kind {
+                ObjectKind::Item { size, .. } => Some(size.to_string()),
+                ObjectKind::Container { .. } => None,
+            },
+            "@childCount" => match self.kind {
+                ObjectKind::Container { child_count } => Some(child_count.to_string()),
+                ObjectKind::Item { .. } => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Why an object ID or sort order was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LibraryError {
+    NoSuchObject,
+    /// The object exists but isn't a container.
+    NotAContainer,
+    InvalidSort(String),
+}
+
+/// The folders shared by the media server.
+#[derive(Debug, Clone)]
+pub struct Library {
+    title: String,
+    folders: Vec<Folder>,
+}
+
+impl Library {
+    /// A library titled `title` sharing `folders`, which should be canonical.
+    pub fn new(title: &str, folders: &[PathBuf]) -> Self {
+        let folders = folders
+            .iter()
+            .map(|path| Folder {
+                title: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string()),
+                path: path.clone(),
+            })
+            .collect();
+        Self {
+            title: title.to_string(),
+            folders,
+        }
+    }
+
+    /// The object with ID `id`.
+    pub fn object(&self, id: &str) -> Result<Object, LibraryError> {
+        if id == ROOT_ID {
+            return Ok(Object {
+                id: ROOT_ID.to_string(),
+                parent_id: "-1".to_string(),
+                title: self.title.clone(),
+                path: PathBuf::new(),
+                kind: ObjectKind::Container {
+                    child_count: self.folders.len(),
+                },
+            });
+        }
+        let (index, relative) = match id.split_once('/') {
+            Some((index, relative)) => (index, Some(relative)),
+            None => (id, None),
+        };
+        let folder = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| self.folders.get(i))
+            .ok_or(LibraryError::NoSuchObject)?;
+        let parent_id = match id.rsplit_once('/') {
+            Some((parent, _)) => parent.to_string(),
+            None => ROOT_ID.to_string(),
+        };
+        let Some(relative) = relative else {
+            return Ok(Object {
+                id: id.to_string(),
+                parent_id,
+                title: folder.title.clone(),
+                path: folder.path.clone(),
+                kind: ObjectKind::Container {
+                    child_count: entries(&folder.path).len(),
+                },
+            });
+        };
+        // Only plain names: no way out of the shared folder, nothing hidden
+        let relative = Path::new(relative);
+        let plain = relative.components().all(|c| match c {
+            Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+            _ => false,
+        });
+        if !plain {
+            return Err(LibraryError::NoSuchObject);
+        }
+        // Nor through symlinks pointing elsewhere
+        let path = folder.path.join(relative);
+        let inside = path.canonicalize().is_ok_and(|real| real.starts_with(&folder.path));
+        if !inside {
+            return Err(LibraryError::NoSuchObject);
+        }
+        to_object(&path, id.to_string(), parent_id).ok_or(LibraryError::NoSuchObject)
+    }
+
+    /// The children of container `id`, in `sort` order (a `SortCriteria`
+    /// such as `+dc:title,-dc:date`; empty for folders first, then by title).
+    pub fn children(&self, id: &str, sort: &str) -> Result<Vec<Object>, LibraryError> {
+        let sort = parse_sort(sort)?;
+        let container = self.object(id)?;
+        if !container.is_container() {
+            return Err(LibraryError::NotAContainer);
+        }
+        let mut children = if id == ROOT_ID {
+            (1..=self.folders.len())
+                .filter_map(|i| self.object(&i.to_string()).ok())
+                .collect()
+        } else {
+            entries(&container.path)
+                .into_iter()
+                .filter_map(|(name, path)| to_object(&path, format!("{id}/{name}"), id.to_string()))
+                .collect::<Vec<_>>()
+        };
+        if id != ROOT_ID {
+            children.sort_by(default_order);
+        }
+        sort_objects(&mut children, &sort);
+        Ok(children)
+    }
+
+    /// Everything below container `id` that matches `criteria`, in `sort`
+    /// order.
+    pub fn search(&self, id: &str, criteria: &Criteria, sort: &str) -> Result<Vec<Object>, LibraryError> {
+        let sort = parse_sort(sort)?;
+        let mut found = Vec::new();
+        let mut pending = vec![(self.children(id, "")?, 0)];
+        while let Some((objects, depth)) = pending.pop() {
+            for object in objects {
+                if object.is_container() && depth < MAX_SEARCH_DEPTH {
+                    if let Ok(children) = self.children(&object.id, "") {
+                        pending.push((children, depth + 1));
+                    }
+                }
+                if criteria.matches(&object) {
+                    found.push(object);
+                }
+            }
+        }
+        found.sort_by(|a, b| default_order(a, b).then_with(|| a.id.cmp(&b.id)));
+        sort_objects(&mut found, &sort);
+        Ok(found)
+    }
+}
+
+/// The object for `path`, if it is a folder or a supported video.
+fn to_object(path: &Path, id: String, parent_id: String) -> Option<Object> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let name = path.file_name()?.to_string_lossy();
+    if metadata.is_dir() {
+        return Some(Object {
+            id,
+            parent_id,
+            title: name.into_owned(),
+            path: path.to_path_buf(),
+            kind: ObjectKind::Container {
+                child_count: entries(path).len(),
+            },
+        });
+    }
+    if !metadata.is_file() || !is_video(path) {
+        return None;
+    }
+    let title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.into_owned());
+    Some(Object {
+        id,
+        parent_id,
+        title,
+        path: path.to_path_buf(),
+        kind: ObjectKind::Item {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            mime_type: mime_guess::from_path(path).first_or_octet_stream().to_string(),
+            subtitle: queue::find_subtitles(path).into_iter().next().map(|(_, subtitle)| subtitle),
+        },
+    })
+}
+
+/// The folders and videos in `dir` that are worth showing, by name.
+fn entries(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    read_dir
+        .flatten()
+        .filter_map(|entry| {
+            // IDs are built from names, so they have to be valid UTF-8
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') {
+                return None;
+            }
+            let path = entry.path();
+            (path.is_dir() || (path.is_file() && is_video(&path))).then_some((name, path))
+        })
+        .collect()
+}
+
+fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Folders before videos, each by title regardless of case.
+fn default_order(a: &Object, b: &Object) -> Ordering {
+    b.is_container()
+        .cmp(&a.is_container())
+        .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+}
+
+/// Properties objects can be sorted by, for GetSortCapabilities.
+pub const SORT_CAPABILITIES: &str = "dc:title,dc:date,upnp:class,res@size";
+
+/// One key of a `SortCriteria`: the property and whether it is ascending.
+type SortKey = (String, bool);
+
+fn parse_sort(sort: &str) -> Result<Vec<SortKey>, LibraryError> {
+    sort.split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let (ascending, property) = match key.as_bytes()[0] {
+                b'+' => (true, &key[1..]),
+                b'-' => (false, &key[1..]),
+                _ => (true, key),
+            };
+            if SORT_CAPABILITIES.split(',').any(|p| p == property) {
+                Ok((property.to_string(), ascending))
+            } else {
+                Err(LibraryError::InvalidSort(key.to_string()))
+            }
+        })
+        .collect()
+}
+
+/// Stable sort by each key in turn; objects without the property come last.
+fn sort_objects(objects: &mut [Object], sort: &[SortKey]) {
+    if sort.is_empty() {
+        return;
+    }
+    objects.sort_by(|a, b| {
+        sort.iter().fold(Ordering::Equal, |ordering, (property, ascending)| {
+            ordering.then_with(|| {
+                let ordering = match (sort_value(a, property), sort_value(b, property)) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => return Ordering::Less,
+                    (None, Some(_)) => return Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                };
+                if *ascending {
+                    ordering
+                } else {
+                    ordering.reverse()
+                }
+            })
+        })
+    });
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Number(u64),
+    Text(String),
+}
+
+fn sort_value(object: &Object, property: &str) -> Option<SortValue> {
+    match (property, &object.kind) {
+        ("res@size", ObjectKind::Item { size, .. }) => Some(SortValue::Number(*size)),
+        ("dc:date", ObjectKind::Item { modified, .. }) => modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| SortValue::Number(d.as_secs())),
+        _ => object.property(property).map(|value| SortValue::Text(value.to_lowercase())),
+    }
+}
+
+/// `time` in UTC as `YYYY-MM-DDThh:mm:ss`, the form `dc:date` takes.
+pub fn iso_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shared folder with a subfolder and three videos of different sizes.
+    fn library() -> (tempfile::TempDir, Library) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("Series")).unwrap();
+        std::fs::write(root.join("b.mp4"), [0; 30]).unwrap();
+        std::fs::write(root.join("A.mkv"), [0; 20]).unwrap();
+        std::fs::write(root.join("c.avi"), [0; 10]).unwrap();
+        std::fs::write(root.join("notes.txt"), "not a video").unwrap();
+        std::fs::write(root.join(".hidden.mp4"), [0; 5]).unwrap();
+        let library = Library::new("Test", &[root]);
+        (dir, library)
+    }
+
+    fn titles(objects: &[Object]) -> Vec<&str> {
+        objects.iter().map(|o| o.title.as_str()).collect()
+    }
+
+    #[test]
+    fn children_list_folders_first_then_titles_regardless_of_case() {
+        let (_dir, library) = library();
+        let children = library.children("1", "").unwrap();
+        assert_eq!(titles(&children), ["Series", "A", "b", "c"]);
+        assert!(children.iter().all(|child| child.parent_id == "1"));
+        assert_eq!(children[1].id, "1/A.mkv");
+    }
+
+    #[test]
+    fn sort_criteria_order_children() {
+        let (_dir, library) = library();
+        let children = library.children("1", "-dc:title").unwrap();
+        assert_eq!(titles(&children), ["Series", "c", "b", "A"]);
+
+        // The folder has no size, so it comes last either way
+        let children = library.children("1", "+res@size").unwrap();
+        assert_eq!(titles(&children), ["c", "A", "b", "Series"]);
+        let children = library.children("1", "-res@size").unwrap();
+        assert_eq!(titles(&children), ["b", "A", "c", "Series"]);
+
+        let children = library.children("1", "upnp:class, -dc:title").unwrap();
+        assert_eq!(titles(&children), ["Series", "c", "b", "A"]);
+    }
+
+    #[test]
+    fn unknown_sort_properties_are_refused() {
+        let (_dir, library) = library();
+        assert_eq!(
+            library.children("1", "+upnp:artist").unwrap_err(),
+            LibraryError::InvalidSort("+upnp:artist".into())
+        );
+    }
+
+    #[test]
+    fn items_are_not_containers() {
+        let (_dir, library) = library();
+        assert_eq!(library.children("1/b.mp4", "").unwrap_err(), LibraryError::NotAContainer);
+        assert_eq!(library.children("2", "").unwrap_err(), LibraryError::NoSuchObject);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn ids_cannot_leave_the_shared_folder() {
+        let (dir, library) = library();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.mp4"), [0; 10]).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.mp4"), dir.path().join("link.mp4")).unwrap();
+
+        for id in ["1/../b.mp4", "1/.hidden.mp4", "1//etc/passwd", "1/link.mp4", "0/b.mp4"] {
+            assert_eq!(library.object(id).unwrap_err(), LibraryError::NoSuchObject, "{id}");
+        }
+        assert!(library.object("1/b.mp4").is_ok());
+    }
+
+    #[test]
+    fn dates_are_utc() {
+        assert_eq!(iso_date(UNIX_EPOCH), "1970-01-01T00:00:00");
+        let leap_day = UNIX_EPOCH + std::time::Duration::from_secs(951_782_400 + 3_723);
+        assert_eq!(iso_date(leap_day), "2000-02-29T01:02:03");
+    }
+}
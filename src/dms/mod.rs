@@ -0,0 +1,553 @@
+//! A UPnP MediaServer (DMS), so TVs can browse shared folders from their own
+//! media browser and play what they pick.
+//!
+//! [`ContentServer`] announces a `MediaServer:1` root device over SSDP,
+//! serves its description and SCPDs, answers ContentDirectory Browse and
+//! Search over the shared folders, and serves the videos with the same
+//! range-capable streaming the [`MediaServer`](crate::MediaServer) uses.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), localcast::AppError> {
+//! let server = localcast::dms::ContentServer::builder()
+//!     .folder("/srv/videos")
+//!     .name("Living room videos")
+//!     .start()
+//!     .await?;
+//! tokio::signal::ctrl_c().await.ok();
+//! server.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+mod description;
+mod didl;
+mod library;
+pub mod search;
+mod soap;
+mod ssdp;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{any, get, post};
+use axum::Router;
+
+use self::library::{Library, LibraryError, Object, ObjectKind};
+use self::search::Criteria;
+use self::soap::Fault;
+use self::ssdp::Advertiser;
+use crate::error::AppError;
+use crate::net::{self, InterfaceFilter};
+use crate::queue::SUPPORTED_EXTENSIONS;
+use crate::server;
+
+pub const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+pub const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+pub const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+
+/// Properties Search can look at, for GetSearchCapabilities.
+const SEARCH_CAPABILITIES: &str = "@id,@parentID,dc:title,dc:date,upnp:class,res@size";
+
+/// Configuration for a [`ContentServer`].
+#[derive(Debug, Clone, Default)]
+pub struct ContentServerBuilder {
+    name: Option<String>,
+    folders: Vec<PathBuf>,
+    port: u16,
+    interface: Option<InterfaceFilter>,
+}
+
+impl ContentServerBuilder {
+    /// The name TVs list the server under; `localcast on <host name>` by
+    /// default.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// A folder to share. May be given several times; each appears at the
+    /// top level of the server.
+    pub fn folder(mut self, folder: impl Into<PathBuf>) -> Self {
+        self.folders.push(folder.into());
+        self
+    }
+
+    /// Port for the description, control and media URLs; 0 (the default)
+    /// picks a free one.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Announce the server only on the interfaces matching `filter`, and
+    /// answer searches with one of their addresses.
+    pub fn interface(mut self, filter: InterfaceFilter) -> Self {
+        self.interface = Some(filter);
+        self
+    }
+
+    /// Start serving and announce the server.
+    pub async fn start(self) -> Result<ContentServer, AppError> {
+        if self.folders.is_empty() {
+            return Err(AppError::Config("no folders to share".into()));
+        }
+        let mut folders = Vec::new();
+        for folder in &self.folders {
+            let path = folder
+                .canonicalize()
+                .map_err(|_| AppError::FileNotFound(folder.display().to_string()))?;
+            if !path.is_dir() {
+                return Err(AppError::FileNotFound(format!("{} is not a folder", path.display())));
+            }
+            folders.push(path);
+        }
+        let name = self.name.unwrap_or_else(default_name);
+        let udn = udn(&name);
+
+        let listener = server::listen(self.port).await?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| AppError::ServerError(e.to_string()))?;
+        let state = Arc::new(ServerState {
+            library: Library::new(&name, &folders),
+            name: name.clone(),
+            udn: udn.clone(),
+            port: addr.port(),
+            // Changes with every start, so TVs don't trust what they cached
+            update_id: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32),
+            subscriptions: AtomicU64::new(0),
+        });
+        let app = Router::new()
+            .route("/description.xml", get(device_description))
+            .route("/ContentDirectory/scpd.xml", get(|| async { xml(description::content_directory()) }))
+            .route("/ContentDirectory/control", post(content_directory))
+            .route("/ContentDirectory/event", any(subscribe))
+            .route("/ConnectionManager/scpd.xml", get(|| async { xml(description::connection_manager()) }))
+            .route("/ConnectionManager/control", post(connection_manager))
+            .route("/ConnectionManager/event", any(subscribe))
+            .route("/content/{id}/{name}", get(serve_content))
+            .with_state(state);
+        let handle = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("Media server error: {e}");
+            }
+        });
+        let advertiser = Advertiser::start(&udn, addr.port(), self.interface.as_ref())?;
+
+        tracing::info!("Sharing {} folder(s) as \"{name}\" on {addr}", folders.len());
+        Ok(ContentServer {
+            addr,
+            name,
+            udn,
+            advertiser,
+            handle,
+        })
+    }
+}
+
+/// A running media server. Dropping it stops serving; [`shutdown`](Self::shutdown)
+/// also tells control points it is gone.
+pub struct ContentServer {
+    addr: SocketAddr,
+    name: String,
+    udn: String,
+    advertiser: Advertiser,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl ContentServer {
+    /// Start configuring a new server.
+    pub fn builder() -> ContentServerBuilder {
+        ContentServerBuilder::default()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The device's UDN, e.g. `uuid:6c4c1b4e-...`; the same for the same name.
+    pub fn udn(&self) -> &str {
+        &self.udn
+    }
+
+    /// Send `ssdp:byebye` and stop serving.
+    pub async fn shutdown(self) {
+        self.advertiser.byebye().await;
+    }
+}
+
+impl Drop for ContentServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+struct ServerState {
+    library: Library,
+    name: String,
+    udn: String,
+    port: u16,
+    update_id: u32,
+    subscriptions: AtomicU64,
+}
+
+/// `localcast on <host name>`.
+fn default_name() -> String {
+    let host = ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty());
+    match host {
+        Some(host) => format!("localcast on {host}"),
+        None => "localcast".to_string(),
+    }
+}
+
+/// A UDN derived from the server name, so TVs recognize the server across
+/// restarts.
+fn udn(name: &str) -> String {
+    // FNV-1a, twice over for 128 bits
+    let fnv = |salt: u8| {
+        name.bytes().chain([salt]).fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    };
+    let (high, low) = (fnv(0), fnv(1));
+    format!(
+        "uuid:{:08x}-{:04x}-4{:03x}-8{:03x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xfff,
+        (low >> 48) & 0xfff,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+fn xml(body: String) -> Response {
+    ([(header::CONTENT_TYPE, soap::XML)], body).into_response()
+}
+
+async fn device_description(State(state): State<Arc<ServerState>>) -> Response {
+    xml(description::device(&state.name, &state.udn))
+}
+
+/// Where the client reached us, for the URLs in results.
+fn base_url(headers: &HeaderMap, port: u16) -> String {
+    match headers.get(header::HOST).and_then(|host| host.to_str().ok()) {
+        Some(host) => format!("http://{host}"),
+        None => {
+            let ip = local_ip_address::local_ip().unwrap_or(std::net::Ipv4Addr::LOCALHOST.into());
+            net::http_base(ip, port)
+        }
+    }
+}
+
+async fn content_directory(State(state): State<Arc<ServerState>>, headers: HeaderMap, body: String) -> Response {
+    let Some(action) = soap::action(&headers, &body) else {
+        return soap::fault(Fault::InvalidAction);
+    };
+    tracing::debug!("ContentDirectory {action}");
+    let base_url = base_url(&headers, state.port);
+    match action.as_str() {
+        "Browse" | "Search" => {
+            // Both read the file system, Search possibly a lot of it
+            let state = state.clone();
+            tokio::task::spawn_blocking(move || {
+                if action == "Browse" {
+                    browse(&state, &body, &base_url)
+                } else {
+                    search(&state, &body, &base_url)
+                }
+            })
+            .await
+            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+        "GetSearchCapabilities" => soap::response(
+            CONTENT_DIRECTORY,
+            &action,
+            &[("SearchCaps", SEARCH_CAPABILITIES.to_string())],
+        ),
+        "GetSortCapabilities" => soap::response(
+            CONTENT_DIRECTORY,
+            &action,
+            &[("SortCaps", library::SORT_CAPABILITIES.to_string())],
+        ),
+        "GetSystemUpdateID" => soap::response(CONTENT_DIRECTORY, &action, &[("Id", state.update_id.to_string())]),
+        _ => soap::fault(Fault::InvalidAction),
+    }
+}
+
+/// StartingIndex and RequestedCount, the latter 0 for everything.
+fn paging(body: &str) -> Result<(usize, usize), Fault> {
+    let number = |name| match soap::argument(body, name) {
+        Some(value) if value.trim().is_empty() => Ok(0),
+        Some(value) => value.trim().parse::<u32>().map(|n| n as usize).map_err(|_| Fault::InvalidArgs),
+        None => Ok(0),
+    };
+    Ok((number("StartingIndex")?, number("RequestedCount")?))
+}
+
+fn browse(state: &ServerState, body: &str, base_url: &str) -> Response {
+    let (Some(id), Some(flag)) = (soap::argument(body, "ObjectID"), soap::argument(body, "BrowseFlag")) else {
+        return soap::fault(Fault::InvalidArgs);
+    };
+    let (start, count) = match paging(body) {
+        Ok(paging) => paging,
+        Err(fault) => return soap::fault(fault),
+    };
+    let sort = soap::argument(body, "SortCriteria").unwrap_or_default();
+    let objects = match flag.as_str() {
+        "BrowseMetadata" => state.library.object(&id).map(|object| vec![object]),
+        "BrowseDirectChildren" => state.library.children(&id, &sort),
+        _ => return soap::fault(Fault::InvalidArgs),
+    };
+    match objects {
+        Ok(objects) => results(state, "Browse", objects, start, count, base_url),
+        Err(LibraryError::InvalidSort(_)) => soap::fault(Fault::UnsupportedSortCriteria),
+        Err(LibraryError::NoSuchObject | LibraryError::NotAContainer) => soap::fault(Fault::NoSuchObject),
+    }
+}
+
+fn search(state: &ServerState, body: &str, base_url: &str) -> Response {
+    let Some(id) = soap::argument(body, "ContainerID") else {
+        return soap::fault(Fault::InvalidArgs);
+    };
+    let criteria = match Criteria::parse(&soap::argument(body, "SearchCriteria").unwrap_or_default()) {
+        Ok(criteria) => criteria,
+        Err(e) => {
+            tracing::debug!("Bad search criteria: {e}");
+            return soap::fault(Fault::UnsupportedSearchCriteria);
+        }
+    };
+    let (start, count) = match paging(body) {
+        Ok(paging) => paging,
+        Err(fault) => return soap::fault(fault),
+    };
+    let sort = soap::argument(body, "SortCriteria").unwrap_or_default();
+    match state.library.search(&id, &criteria, &sort) {
+        Ok(objects) => results(state, "Search", objects, start, count, base_url),
+        Err(LibraryError::InvalidSort(_)) => soap::fault(Fault::UnsupportedSortCriteria),
+        Err(LibraryError::NoSuchObject | LibraryError::NotAContainer) => soap::fault(Fault::NoSuchContainer),
+    }
+}
+
+/// The page of `objects` a Browse or Search asked for.
+fn results(
+    state: &ServerState,
+    action: &str,
+    objects: Vec<Object>,
+    start: usize,
+    count: usize,
+    base_url: &str,
+) -> Response {
+    let total = objects.len();
+    let count = if count == 0 { usize::MAX } else { count };
+    let page: Vec<Object> = objects.into_iter().skip(start).take(count).collect();
+    soap::response(
+        CONTENT_DIRECTORY,
+        action,
+        &[
+            ("Result", didl::didl(&page, base_url)),
+            ("NumberReturned", page.len().to_string()),
+            ("TotalMatches", total.to_string()),
+            ("UpdateID", state.update_id.to_string()),
+        ],
+    )
+}
+
+async fn connection_manager(headers: HeaderMap, body: String) -> Response {
+    let Some(action) = soap::action(&headers, &body) else {
+        return soap::fault(Fault::InvalidAction);
+    };
+    match action.as_str() {
+        "GetProtocolInfo" => {
+            let source = SUPPORTED_EXTENSIONS
+                .iter()
+                .filter_map(|ext| mime_guess::from_ext(ext).first())
+                .map(|mime| format!("http-get:*:{mime}:*"))
+                .chain(["http-get:*:text/srt:*".to_string()])
+                .collect::<Vec<_>>()
+                .join(",");
+            soap::response(CONNECTION_MANAGER, &action, &[("Source", source), ("Sink", String::new())])
+        }
+        "GetCurrentConnectionIDs" => {
+            soap::response(CONNECTION_MANAGER, &action, &[("ConnectionIDs", "0".to_string())])
+        }
+        "GetCurrentConnectionInfo" => {
+            if soap::argument(&body, "ConnectionID").as_deref().map(str::trim) != Some("0") {
+                return soap::fault(Fault::InvalidArgs);
+            }
+            let info = [
+                ("RcsID", "-1"),
+                ("AVTransportID", "-1"),
+                ("ProtocolInfo", ""),
+                ("PeerConnectionManager", ""),
+                ("PeerConnectionID", "-1"),
+                ("Direction", "Output"),
+                ("Status", "OK"),
+            ]
+            .map(|(name, value)| (name, value.to_string()));
+            soap::response(CONNECTION_MANAGER, &action, &info)
+        }
+        _ => soap::fault(Fault::InvalidAction),
+    }
+}
+
+/// Event subscriptions are accepted but no events are ever sent: nothing
+/// evented changes while the server runs. Some TVs won't browse a server
+/// that refuses them.
+async fn subscribe(State(state): State<Arc<ServerState>>, request: Request) -> Response {
+    match request.method().as_str() {
+        "SUBSCRIBE" => {
+            let n = state.subscriptions.fetch_add(1, Ordering::Relaxed);
+            let sid = match request.headers().get("SID").and_then(|sid| sid.to_str().ok()) {
+                // Renewal
+                Some(sid) => sid.to_string(),
+                None => format!("{}-{n}", state.udn),
+            };
+            (StatusCode::OK, [("SID", sid), ("TIMEOUT", "Second-1800".to_string())]).into_response()
+        }
+        "UNSUBSCRIBE" => StatusCode::OK.into_response(),
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    }
+}
+
+/// A video, or the subtitles found next to it, by object ID.
+async fn serve_content(
+    State(state): State<Arc<ServerState>>,
+    Path((hex, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(id) = didl::unhex(&hex) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // Finding the object reads the file system, like Browse
+    let object = {
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || state.library.object(&id)).await
+    };
+    let Ok(Ok(Object {
+        path,
+        kind: ObjectKind::Item {
+            size,
+            mime_type,
+            subtitle,
+            ..
+        },
+        ..
+    })) = object
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    if name == "subtitle.srt" {
+        let Some(subtitle) = subtitle else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let Ok(metadata) = tokio::fs::metadata(&subtitle).await else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        return server::serve_file(&subtitle, metadata.len(), "text/srt", range).await;
+    }
+    tracing::info!("Streaming {} (range: {range:?})", path.display());
+    server::serve_file(&path, size, &mime_type, range).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(folder: &std::path::Path) -> ServerState {
+        ServerState {
+            library: Library::new("Test", &[folder.canonicalize().unwrap()]),
+            name: "Test".into(),
+            udn: udn("Test"),
+            port: 8200,
+            update_id: 7,
+            subscriptions: AtomicU64::new(0),
+        }
+    }
+
+    fn browse_request(id: &str, start: &str, count: &str, sort: &str) -> String {
+        format!(
+            r#"<s:Envelope><s:Body><u:Browse xmlns:u="{CONTENT_DIRECTORY}"><ObjectID>{id}</ObjectID><BrowseFlag>BrowseDirectChildren</BrowseFlag><Filter>*</Filter><StartingIndex>{start}</StartingIndex><RequestedCount>{count}</RequestedCount><SortCriteria>{sort}</SortCriteria></u:Browse></s:Body></s:Envelope>"#
+        )
+    }
+
+    /// The status and body of `response`.
+    async fn read(response: Response) -> (StatusCode, String) {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// NumberReturned, TotalMatches and the titles in Result.
+    fn page(body: &str) -> (usize, usize, Vec<String>) {
+        let number = |name| soap::argument(body, name).unwrap().parse().unwrap();
+        let result = soap::argument(body, "Result").unwrap();
+        let titles = result
+            .split("<dc:title>")
+            .skip(1)
+            .map(|rest| rest[..rest.find("</dc:title>").unwrap()].to_string())
+            .collect();
+        (number("NumberReturned"), number("TotalMatches"), titles)
+    }
+
+    fn titles(titles: &[&str]) -> Vec<String> {
+        titles.iter().map(|title| title.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn browse_pages_through_children() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c", "d", "e"] {
+            std::fs::write(dir.path().join(format!("{name}.mp4")), name).unwrap();
+        }
+        let state = state(dir.path());
+        let browse = |start, count, sort| {
+            let response = browse(&state, &browse_request("1", start, count, sort), "http://h:8200");
+            async { page(&read(response).await.1) }
+        };
+
+        assert_eq!(browse("0", "0", "").await, (5, 5, titles(&["a", "b", "c", "d", "e"])));
+        assert_eq!(browse("1", "2", "").await, (2, 5, titles(&["b", "c"])));
+        assert_eq!(browse("4", "10", "").await, (1, 5, titles(&["e"])));
+        assert_eq!(browse("9", "2", "").await, (0, 5, titles(&[])));
+        // Empty means from the start, everything
+        assert_eq!(browse("", "", "").await.0, 5);
+        // Paging applies after sorting
+        assert_eq!(browse("0", "2", "-dc:title").await, (2, 5, titles(&["e", "d"])));
+    }
+
+    #[tokio::test]
+    async fn browse_refuses_bad_arguments() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let fault = |body: &str| {
+            let response = browse(&state, body, "http://h:8200");
+            async { read(response).await }
+        };
+
+        let (status, body) = fault(&browse_request("1", "-1", "0", "")).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("<errorCode>402</errorCode>"));
+        let (_, body) = fault(&browse_request("1", "0", "0", "+upnp:artist")).await;
+        assert!(body.contains("<errorCode>709</errorCode>"));
+        let (_, body) = fault(&browse_request("3", "0", "0", "")).await;
+        assert!(body.contains("<errorCode>701</errorCode>"));
+    }
+}
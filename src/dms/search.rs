@@ -0,0 +1,349 @@
+//! ContentDirectory search criteria, such as
+//! `upnp:class derivedfrom "object.item.videoItem" and dc:title contains "day"`.
+
+use std::cmp::Ordering;
+
+/// A parsed `SearchCriteria` argument.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Criteria {
+    /// `*`: every object.
+    All,
+    And(Box<Criteria>, Box<Criteria>),
+    Or(Box<Criteria>, Box<Criteria>),
+    /// `property op "value"`.
+    Compare { property: String, op: Op, value: String },
+    /// `property exists true|false`.
+    Exists { property: String, exists: bool },
+}
+
+/// A comparison in a search criteria.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    DoesNotContain,
+    DerivedFrom,
+}
+
+/// What search criteria are evaluated against: an object's properties by
+/// their ContentDirectory names (`dc:title`, `upnp:class`, `res@size`, ...).
+pub trait Properties {
+    /// The value of `property`, or `None` if the object has none.
+    fn property(&self, property: &str) -> Option<String>;
+}
+
+impl Criteria {
+    /// Parse a `SearchCriteria` string; `Err` says what is wrong with it.
+    ///
+    /// ```
+    /// use localcast::dms::search::Criteria;
+    ///
+    /// assert_eq!(Criteria::parse("*"), Ok(Criteria::All));
+    /// assert!(Criteria::parse(r#"upnp:class derivedfrom "object.item" and (dc:title contains "a" or @refID exists false)"#).is_ok());
+    /// assert!(Criteria::parse(r#"dc:title = "unterminated"#).is_err());
+    /// ```
+    pub fn parse(criteria: &str) -> Result<Self, String> {
+        let criteria = criteria.trim();
+        if criteria.is_empty() || criteria == "*" {
+            return Ok(Self::All);
+        }
+        let tokens = tokenize(criteria)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let parsed = parser.or()?;
+        match parser.tokens.get(parser.next) {
+            None => Ok(parsed),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    /// Whether `object` matches. String comparisons ignore case; properties
+    /// the object doesn't have match nothing but `exists false`.
+    pub fn matches(&self, object: &impl Properties) -> bool {
+        match self {
+            Self::All => true,
+            Self::And(left, right) => left.matches(object) && right.matches(object),
+            Self::Or(left, right) => left.matches(object) || right.matches(object),
+            Self::Exists { property, exists } => object.property(property).is_some() == *exists,
+            Self::Compare { property, op, value } => match object.property(property) {
+                Some(actual) => compare(property, &actual, *op, value),
+                None => false,
+            },
+        }
+    }
+}
+
+fn compare(property: &str, actual: &str, op: Op, value: &str) -> bool {
+    let actual_lower = actual.to_lowercase();
+    let value_lower = value.to_lowercase();
+    let ordering = || match (actual.parse::<u64>(), value.parse::<u64>()) {
+        (Ok(a), Ok(b)) if property.ends_with("@size") => a.cmp(&b),
+        _ => actual_lower.cmp(&value_lower),
+    };
+    match op {
+        Op::Eq => actual_lower == value_lower,
+        Op::Ne => actual_lower != value_lower,
+        Op::Lt => ordering() == Ordering::Less,
+        Op::Le => ordering() != Ordering::Greater,
+        Op::Gt => ordering() == Ordering::Greater,
+        Op::Ge => ordering() != Ordering::Less,
+        Op::Contains => actual_lower.contains(&value_lower),
+        Op::DoesNotContain => !actual_lower.contains(&value_lower),
+        // `object.item` is derived from `object` but `object.itemX` is not
+        Op::DerivedFrom => {
+            actual_lower == value_lower || actual_lower.starts_with(&format!("{value_lower}."))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    /// A property, operator, `and`/`or` or boolean.
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.push(chars.next().ok_or("unterminated string")?),
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens; `and` binds tighter than `or`.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.next), Some(Token::Word(w)) if w.eq_ignore_ascii_case(word))
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Criteria, String> {
+        let mut left = self.and()?;
+        while self.peek_word("or") {
+            self.next += 1;
+            left = Criteria::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Criteria, String> {
+        let mut left = self.primary()?;
+        while self.peek_word("and") {
+            self.next += 1;
+            left = Criteria::And(Box::new(left), Box::new(self.primary()?));
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Criteria, String> {
+        match self.take() {
+            Some(Token::Open) => {
+                let inner = self.or()?;
+                match self.take() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err("missing )".into()),
+                }
+            }
+            Some(Token::Word(property)) => self.relation(property),
+            Some(token) => Err(format!("expected a property, found {token:?}")),
+            None => Err("unexpected end".into()),
+        }
+    }
+
+    fn relation(&mut self, property: String) -> Result<Criteria, String> {
+        let Some(Token::Word(op)) = self.take() else {
+            return Err(format!("expected an operator after {property}"));
+        };
+        if op.eq_ignore_ascii_case("exists") {
+            let exists = match self.take() {
+                Some(Token::Word(b)) if b.eq_ignore_ascii_case("true") => true,
+                Some(Token::Word(b)) if b.eq_ignore_ascii_case("false") => false,
+                _ => return Err(format!("expected true or false after {property} exists")),
+            };
+            return Ok(Criteria::Exists { property, exists });
+        }
+        let op = match op.as_str() {
+            "=" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            op if op.eq_ignore_ascii_case("contains") => Op::Contains,
+            op if op.eq_ignore_ascii_case("doesNotContain") => Op::DoesNotContain,
+            op if op.eq_ignore_ascii_case("derivedfrom") => Op::DerivedFrom,
+            op => return Err(format!("unknown operator {op}")),
+        };
+        match self.take() {
+            Some(Token::Quoted(value)) => Ok(Criteria::Compare { property, op, value }),
+            _ => Err(format!("expected a quoted value after {property}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn compare(property: &str, op: Op, value: &str) -> Criteria {
+        Criteria::Compare {
+            property: property.into(),
+            op,
+            value: value.into(),
+        }
+    }
+
+    impl Properties for HashMap<&str, &str> {
+        fn property(&self, property: &str) -> Option<String> {
+            self.get(property).map(|value| value.to_string())
+        }
+    }
+
+    #[test]
+    fn star_and_empty_match_everything() {
+        assert_eq!(Criteria::parse("*"), Ok(Criteria::All));
+        assert_eq!(Criteria::parse("  * "), Ok(Criteria::All));
+        assert_eq!(Criteria::parse(""), Ok(Criteria::All));
+        assert!(Criteria::All.matches(&HashMap::new()));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let parsed = Criteria::parse(r#"dc:title = "a" or dc:title = "b" AND res@size > "10""#).unwrap();
+        let expected = Criteria::Or(
+            Box::new(compare("dc:title", Op::Eq, "a")),
+            Box::new(Criteria::And(
+                Box::new(compare("dc:title", Op::Eq, "b")),
+                Box::new(compare("res@size", Op::Gt, "10")),
+            )),
+        );
+        assert_eq!(parsed, expected);
+
+        let parsed = Criteria::parse(r#"(dc:title = "a" or dc:title = "b") and res@size > "10""#).unwrap();
+        assert!(matches!(parsed, Criteria::And(..)));
+    }
+
+    #[test]
+    fn contains_ignores_case() {
+        let criteria = Criteria::parse(r#"dc:title contains "DAY""#).unwrap();
+        assert_eq!(criteria, compare("dc:title", Op::Contains, "DAY"));
+        assert!(criteria.matches(&HashMap::from([("dc:title", "Groundhog Day")])));
+        assert!(!criteria.matches(&HashMap::from([("dc:title", "Alien")])));
+        // Nor does an object without a title
+        assert!(!criteria.matches(&HashMap::new()));
+
+        let criteria = Criteria::parse(r#"dc:title doesNotContain "day""#).unwrap();
+        assert!(criteria.matches(&HashMap::from([("dc:title", "Alien")])));
+    }
+
+    #[test]
+    fn derivedfrom_matches_whole_class_segments() {
+        let criteria = Criteria::parse(r#"upnp:class derivedfrom "object.item""#).unwrap();
+        assert_eq!(criteria, compare("upnp:class", Op::DerivedFrom, "object.item"));
+        assert!(criteria.matches(&HashMap::from([("upnp:class", "object.item.videoItem")])));
+        assert!(criteria.matches(&HashMap::from([("upnp:class", "object.item")])));
+        assert!(!criteria.matches(&HashMap::from([("upnp:class", "object.itemX")])));
+        assert!(!criteria.matches(&HashMap::from([("upnp:class", "object.container.storageFolder")])));
+    }
+
+    #[test]
+    fn sizes_compare_as_numbers() {
+        let criteria = Criteria::parse(r#"res@size >= "900""#).unwrap();
+        assert!(criteria.matches(&HashMap::from([("res@size", "1000")])));
+        assert!(!criteria.matches(&HashMap::from([("res@size", "899")])));
+    }
+
+    #[test]
+    fn quoted_values_keep_spaces_parentheses_and_escapes() {
+        let parsed = Criteria::parse(r#"dc:title = "The (\"Good\") and the bad""#).unwrap();
+        assert_eq!(parsed, compare("dc:title", Op::Eq, r#"The ("Good") and the bad"#));
+    }
+
+    #[test]
+    fn exists_takes_a_boolean() {
+        assert_eq!(
+            Criteria::parse("@refID exists false"),
+            Ok(Criteria::Exists {
+                property: "@refID".into(),
+                exists: false
+            })
+        );
+        assert!(Criteria::parse("@refID exists maybe").is_err());
+    }
+
+    #[test]
+    fn malformed_criteria_are_refused() {
+        for criteria in [
+            r#"dc:title = "unterminated"#,
+            r#"dc:title = "escape at the end\"#,
+            r#"dc:title = unquoted"#,
+            r#"dc:title like "a""#,
+            r#"dc:title"#,
+            r#"(dc:title = "a""#,
+            r#"dc:title = "a")"#,
+            r#"dc:title = "a" and"#,
+            r#"dc:title = "a" dc:title = "b""#,
+            r#""a" = dc:title"#,
+        ] {
+            assert!(Criteria::parse(criteria).is_err(), "{criteria} was accepted");
+        }
+    }
+}
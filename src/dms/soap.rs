@@ -0,0 +1,118 @@
+//! SOAP as UPnP control points send it: the action, its arguments, and the
+//! response or fault that goes back.
+
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+/// A UPnP error to return as a SOAP fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    InvalidAction = 401,
+    InvalidArgs = 402,
+    NoSuchObject = 701,
+    UnsupportedSearchCriteria = 708,
+    UnsupportedSortCriteria = 709,
+    NoSuchContainer = 710,
+}
+
+impl Fault {
+    fn description(self) -> &'static str {
+        match self {
+            Self::InvalidAction => "Invalid Action",
+            Self::InvalidArgs => "Invalid Args",
+            Self::NoSuchObject => "No such object",
+            Self::UnsupportedSearchCriteria => "Unsupported or invalid search criteria",
+            Self::UnsupportedSortCriteria => "Unsupported or invalid sort criteria",
+            Self::NoSuchContainer => "No such container",
+        }
+    }
+}
+
+/// The action named in the `SOAPACTION` header (`"urn:...:ContentDirectory:1#Browse"`),
+/// or failing that, the first element of the body.
+pub fn action(headers: &HeaderMap, body: &str) -> Option<String> {
+    let from_header = headers
+        .get("SOAPACTION")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().trim_matches('"').rsplit_once('#'))
+        .map(|(_, action)| action.to_string());
+    from_header.or_else(|| {
+        let inner = &body[body.find(":Body")?..];
+        let element = &inner[inner.find('>')? + 1..];
+        let name = element.trim_start().strip_prefix('<')?;
+        let name = &name[..name.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?];
+        Some(name.rsplit(':').next()?.to_string())
+    })
+}
+
+/// The value of argument `name` in the action element, unescaped; empty for
+/// `<Name/>`.
+pub fn argument(body: &str, name: &str) -> Option<String> {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut rest = body;
+    loop {
+        let after = &rest[rest.find(&open)? + open.len()..];
+        rest = after;
+        // The whole name, not a prefix of a longer one
+        if after.starts_with("/>") {
+            return Some(String::new());
+        }
+        if !after.starts_with('>') && !after.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let start_tag = &after[..after.find('>')?];
+        if start_tag.ends_with('/') {
+            return Some(String::new());
+        }
+        let content = &after[start_tag.len() + 1..];
+        return Some(xml_unescape(&content[..content.find(&close)?]));
+    }
+}
+
+/// A `<u:{action}Response>` envelope with `arguments`, which are escaped.
+pub fn response(service_type: &str, action: &str, arguments: &[(&str, String)]) -> Response {
+    let arguments: String = arguments
+        .iter()
+        .map(|(name, value)| format!("<{name}>{}</{name}>", xml_escape(value)))
+        .collect();
+    let body = envelope(&format!(
+        r#"<u:{action}Response xmlns:u="{service_type}">{arguments}</u:{action}Response>"#
+    ));
+    (StatusCode::OK, [(header::CONTENT_TYPE, XML)], body).into_response()
+}
+
+/// A SOAP fault carrying a UPnP error.
+pub fn fault(fault: Fault) -> Response {
+    let body = envelope(&format!(
+        r#"<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError></detail></s:Fault>"#,
+        fault as u16,
+        fault.description()
+    ));
+    (StatusCode::INTERNAL_SERVER_ERROR, [(header::CONTENT_TYPE, XML)], body).into_response()
+}
+
+pub const XML: &str = r#"text/xml; charset="utf-8""#;
+
+fn envelope(body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body>{body}</s:Body></s:Envelope>"#
+    )
+}
+
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
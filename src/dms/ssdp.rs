@@ -0,0 +1,211 @@
+//! Announcing the media server over SSDP and answering searches for it, on
+//! IPv4.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use super::{CONNECTION_MANAGER, CONTENT_DIRECTORY, DEVICE_TYPE};
+use crate::discovery::ssdp::{self, SSDP_ADDR, SSDP_PORT};
+use crate::error::AppError;
+use crate::net::{self, InterfaceFilter};
+
+/// How long announcements stay valid.
+const MAX_AGE: Duration = Duration::from_secs(1800);
+
+/// How often to announce; well within `MAX_AGE` so one lost round doesn't
+/// make the server disappear.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Longest wait before answering a search, whatever its `MX`.
+const MAX_RESPONSE_DELAY: Duration = Duration::from_secs(5);
+
+/// Keeps the server announced until dropped.
+pub struct Advertiser {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    udn: String,
+    port: u16,
+    filter: Option<InterfaceFilter>,
+    /// A socket sending out of each interface, with its address.
+    senders: Vec<(Ipv4Addr, UdpSocket)>,
+}
+
+impl Advertiser {
+    /// Announce the server on the IPv4 interfaces matching `filter` (every
+    /// eligible one if `None`) and answer searches for it.
+    pub fn start(udn: &str, port: u16, filter: Option<&InterfaceFilter>) -> Result<Self, AppError> {
+        let interfaces: Vec<Ipv4Addr> = net::eligible_interfaces(filter)?
+            .into_iter()
+            .filter_map(|i| match i.addr {
+                IpAddr::V4(addr) => Some(addr),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        let listener = ssdp::notify_socket(&interfaces)
+            .map_err(|e| AppError::NetworkError(format!("Cannot listen for SSDP searches: {e}")))?;
+        let mut senders = Vec::new();
+        for addr in &interfaces {
+            match ssdp::search_socket(*addr) {
+                Ok(socket) => senders.push((*addr, socket)),
+                Err(e) => tracing::warn!("Cannot announce the media server on {addr}: {e}"),
+            }
+        }
+        let shared = Arc::new(Shared {
+            udn: udn.to_string(),
+            port,
+            filter: filter.cloned(),
+            senders,
+        });
+        let task = tokio::spawn(run(shared.clone(), Arc::new(listener)));
+        Ok(Self { shared, task })
+    }
+
+    /// Tell control points the server is leaving.
+    pub async fn byebye(&self) {
+        self.task.abort();
+        for (addr, socket) in &self.shared.senders {
+            for (nt, usn) in notifications(&self.shared.udn) {
+                let message = format!(
+                    "NOTIFY * HTTP/1.1\r\n\
+                     HOST: {SSDP_ADDR}:{SSDP_PORT}\r\n\
+                     NT: {nt}\r\n\
+                     NTS: ssdp:byebye\r\n\
+                     USN: {usn}\r\n\r\n"
+                );
+                if let Err(e) = ssdp::multicast(socket, &message).await {
+                    tracing::warn!("Cannot say byebye on {addr}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(shared: Arc<Shared>, listener: Arc<UdpSocket>) {
+    let mut announce = tokio::time::interval(ANNOUNCE_INTERVAL);
+    let mut buf = [0u8; 2048];
+    loop {
+        tokio::select! {
+            _ = announce.tick() => shared.announce().await,
+            (len, from) = ssdp::receive(&listener, &mut buf) => {
+                let Some(search) = ssdp::parse_search(&String::from_utf8_lossy(&buf[..len])) else {
+                    continue;
+                };
+                let answers: Vec<(String, String)> = notifications(&shared.udn)
+                    .into_iter()
+                    .filter(|(nt, _)| search.target == "ssdp:all" || search.target == *nt)
+                    .collect();
+                if answers.is_empty() {
+                    continue;
+                }
+                tracing::debug!("{from} searched for {}", search.target);
+                let delay = response_delay(search.mx);
+                tokio::spawn(shared.clone().answer(listener.clone(), from, answers, delay));
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn location(&self, ip: IpAddr) -> String {
+        format!("{}/description.xml", net::http_base(ip, self.port))
+    }
+
+    /// `ssdp:alive` for everything the server offers, on every interface.
+    async fn announce(&self) {
+        for (addr, socket) in &self.senders {
+            for (nt, usn) in notifications(&self.udn) {
+                let message = format!(
+                    "NOTIFY * HTTP/1.1\r\n\
+                     HOST: {SSDP_ADDR}:{SSDP_PORT}\r\n\
+                     CACHE-CONTROL: max-age={}\r\n\
+                     LOCATION: {}\r\n\
+                     NT: {nt}\r\n\
+                     NTS: ssdp:alive\r\n\
+                     SERVER: {}\r\n\
+                     USN: {usn}\r\n\r\n",
+                    MAX_AGE.as_secs(),
+                    self.location(IpAddr::V4(*addr)),
+                    server(),
+                );
+                if let Err(e) = ssdp::multicast(socket, &message).await {
+                    tracing::warn!("Cannot announce the media server on {addr}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Answer a search from `from` after `delay`, with the address it can
+    /// reach us at.
+    async fn answer(self: Arc<Self>, socket: Arc<UdpSocket>, from: SocketAddr, answers: Vec<(String, String)>, delay: Duration) {
+        tokio::time::sleep(delay).await;
+        let ip = match net::advertised_ip(&from.ip().to_string(), self.filter.as_ref(), None) {
+            Ok(ip) => ip,
+            Err(e) => {
+                tracing::debug!("Not answering {from}: {e}");
+                return;
+            }
+        };
+        for (st, usn) in answers {
+            let message = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 CACHE-CONTROL: max-age={}\r\n\
+                 EXT:\r\n\
+                 LOCATION: {}\r\n\
+                 SERVER: {}\r\n\
+                 ST: {st}\r\n\
+                 USN: {usn}\r\n\r\n",
+                MAX_AGE.as_secs(),
+                self.location(ip),
+                server(),
+            );
+            if let Err(e) = socket.send_to(message.as_bytes(), from).await {
+                tracing::debug!("Cannot answer {from}: {e}");
+                return;
+            }
+        }
+    }
+}
+
+/// The `NT` and `USN` of each announcement a root device with two services
+/// makes.
+fn notifications(udn: &str) -> Vec<(String, String)> {
+    let mut notifications = vec![
+        ("upnp:rootdevice".to_string(), format!("{udn}::upnp:rootdevice")),
+        (udn.to_string(), udn.to_string()),
+    ];
+    for nt in [DEVICE_TYPE, CONTENT_DIRECTORY, CONNECTION_MANAGER] {
+        notifications.push((nt.to_string(), format!("{udn}::{nt}")));
+    }
+    notifications
+}
+
+fn server() -> String {
+    format!("{}/1.0 UPnP/1.0 localcast/{}", std::env::consts::OS, env!("CARGO_PKG_VERSION"))
+}
+
+/// A random wait of up to `mx` seconds, so that answers from every device
+/// don't arrive at once.
+fn response_delay(mx: u64) -> Duration {
+    let limit = Duration::from_secs(mx).min(MAX_RESPONSE_DELAY);
+    if limit.is_zero() {
+        return Duration::ZERO;
+    }
+    // Random enough for spreading answers out
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    Duration::from_millis(u64::from(nanos) % limit.as_millis() as u64)
+}
@@ -1,6 +1,8 @@
 use thiserror::Error;
 
+/// Errors from discovery, the media server and renderer requests.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum AppError {
     #[error("File not found: {0}")]
     FileNotFound(String),
@@ -8,18 +10,49 @@ pub enum AppError {
     #[error("Unsupported file type: {0}. Supported: mp4, mkv, avi, webm")]
     UnsupportedFormat(String),
 
-    #[error("No DLNA devices found on the network")]
+    #[error("No renderers found on the network")]
     NoDevicesFound,
 
+    #[error("Device not found: {0}")]
+    DeviceNotFound(String),
+
     #[error("DLNA action failed: {0}")]
     DlnaAction(String),
 
+    #[error("Cast request failed: {0}")]
+    CastAction(String),
+
+    #[error("AirPlay request failed: {0}")]
+    AirPlayAction(String),
+
+    #[error("Kodi request failed: {0}")]
+    KodiAction(String),
+
+    #[error("Roku request failed: {0}")]
+    RokuAction(String),
+
+    /// The renderer's protocol has no way to do this.
+    #[error("Not supported by the renderer: {0}")]
+    Unsupported(String),
+
     #[error("HTTP server error: {0}")]
     ServerError(String),
 
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
     #[error("Network error: {0}")]
     NetworkError(String),
 
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Device cache error: {0}")]
+    Cache(String),
+
+    #[error("Wake-on-LAN failed: {0}")]
+    WakeOnLan(String),
+
     #[error("TUI error: {0}")]
     TuiError(String),
 }
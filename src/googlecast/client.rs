@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{self, ClientConfig, DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::TlsConnector;
+use tokio_util::sync::CancellationToken;
+
+use super::proto::{self, CastMessage};
+use super::{NS_CONNECTION, NS_HEARTBEAT, RECEIVER_ID, SENDER_ID};
+use crate::error::AppError;
+
+/// How often to ping the device; it drops connections that go quiet.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reply types that mean a request was refused.
+const ERROR_REPLIES: &[&str] = &[
+    "INVALID_REQUEST",
+    "INVALID_PLAYER_STATE",
+    "LOAD_FAILED",
+    "LOAD_CANCELLED",
+    "LAUNCH_ERROR",
+];
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+/// A Cast v2 channel to one device.
+///
+/// Requests get a `requestId` and are answered by the reply carrying it
+/// back; everything else the device sends, such as status broadcasts, is
+/// dropped. Heartbeat pings are answered, and sent, in the background.
+pub struct CastClient {
+    outgoing: mpsc::UnboundedSender<CastMessage>,
+    pending: Pending,
+    next_request: AtomicU64,
+    request_timeout: Duration,
+    closed: CancellationToken,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl CastClient {
+    /// Open the channel and the virtual connection to the device itself.
+    pub async fn connect(
+        addr: SocketAddr,
+        connect_timeout: Duration,
+        request_timeout: Duration,
+    ) -> Result<Self, AppError> {
+        let handshake = async {
+            let tcp = TcpStream::connect(addr)
+                .await
+                .map_err(|e| AppError::NetworkError(format!("Cannot connect to {addr}: {e}")))?;
+            tcp.set_nodelay(true).ok();
+            TlsConnector::from(tls_config())
+                .connect(ServerName::from(addr.ip()), tcp)
+                .await
+                .map_err(|e| AppError::NetworkError(format!("TLS handshake with {addr} failed: {e}")))
+        };
+        let stream = tokio::time::timeout(connect_timeout, handshake)
+            .await
+            .map_err(|_| AppError::Timeout(format!("connecting to {addr}")))??;
+        let (reader, writer) = tokio::io::split(stream);
+
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let pending: Pending = Arc::default();
+        let closed = CancellationToken::new();
+        let tasks = [
+            tokio::spawn(read_loop(reader, outgoing.clone(), pending.clone(), closed.clone())),
+            tokio::spawn(write_loop(writer, outgoing_rx, closed.clone())),
+        ];
+        let client = Self {
+            outgoing,
+            pending,
+            next_request: AtomicU64::new(1),
+            request_timeout,
+            closed,
+            tasks,
+        };
+        client.open(RECEIVER_ID)?;
+        Ok(client)
+    }
+
+    /// Whether the device hung up or the connection failed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_cancelled()
+    }
+
+    /// Open a virtual connection to `destination`, e.g. an app's transport.
+    pub fn open(&self, destination: &str) -> Result<(), AppError> {
+        self.send(destination, NS_CONNECTION, json!({ "type": "CONNECT" }))
+    }
+
+    /// Send a message that expects no reply.
+    pub fn send(&self, destination: &str, namespace: &str, payload: Value) -> Result<(), AppError> {
+        if self.is_closed() {
+            return Err(AppError::NetworkError("Cast channel closed".into()));
+        }
+        self.outgoing
+            .send(CastMessage::json(SENDER_ID, destination, namespace, &payload))
+            .map_err(|_| AppError::NetworkError("Cast channel closed".into()))
+    }
+
+    /// Send a request and wait for its reply. Replies that refuse the
+    /// request, such as `LOAD_FAILED`, are errors.
+    pub async fn request(&self, destination: &str, namespace: &str, mut payload: Value) -> Result<Value, AppError> {
+        let what = payload["type"].as_str().unwrap_or("request").to_string();
+        let request_id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        payload["requestId"] = request_id.into();
+        let (reply, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request_id, reply);
+
+        let sent = self.send(destination, namespace, payload);
+        let reply = match sent {
+            Ok(()) => tokio::time::timeout(self.request_timeout, rx).await,
+            Err(e) => {
+                self.pending.lock().unwrap().remove(&request_id);
+                return Err(e);
+            }
+        };
+        let reply = match reply {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => return Err(AppError::NetworkError(format!("Cast channel closed during {what}"))),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&request_id);
+                return Err(AppError::Timeout(format!(
+                    "{what} after {}s",
+                    self.request_timeout.as_secs_f32()
+                )));
+            }
+        };
+        match reply["type"].as_str() {
+            Some(refusal) if ERROR_REPLIES.contains(&refusal) => {
+                let reason = reply["reason"].as_str().unwrap_or("no reason given");
+                Err(AppError::CastAction(format!("{what} refused with {refusal}: {reason}")))
+            }
+            _ => Ok(reply),
+        }
+    }
+}
+
+impl Drop for CastClient {
+    fn drop(&mut self) {
+        self.closed.cancel();
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Hand replies to the requests waiting for them and answer pings, until
+/// the connection closes.
+async fn read_loop(
+    mut reader: ReadHalf<TlsStream<TcpStream>>,
+    outgoing: mpsc::UnboundedSender<CastMessage>,
+    pending: Pending,
+    closed: CancellationToken,
+) {
+    loop {
+        let message = tokio::select! {
+            message = proto::read_message(&mut reader) => message,
+            _ = closed.cancelled() => break,
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::debug!("{e}");
+                break;
+            }
+        };
+        let Some(payload) = message.payload() else {
+            continue;
+        };
+        match (message.namespace.as_str(), payload["type"].as_str()) {
+            (NS_HEARTBEAT, Some("PING")) => {
+                let pong = CastMessage::json(
+                    &message.destination_id,
+                    &message.source_id,
+                    NS_HEARTBEAT,
+                    &json!({ "type": "PONG" }),
+                );
+                let _ = outgoing.send(pong);
+                continue;
+            }
+            (NS_CONNECTION, Some("CLOSE")) if message.source_id == RECEIVER_ID => {
+                tracing::info!("Cast device closed the connection");
+                break;
+            }
+            _ => {}
+        }
+        let waiting = payload["requestId"]
+            .as_u64()
+            .filter(|&id| id != 0)
+            .and_then(|id| pending.lock().unwrap().remove(&id));
+        match waiting {
+            Some(reply) => {
+                let _ = reply.send(payload);
+            }
+            None => tracing::trace!("Cast {} from {}: {payload}", message.namespace, message.source_id),
+        }
+    }
+    closed.cancel();
+    // Requests still waiting fail as their senders drop
+    pending.lock().unwrap().clear();
+}
+
+/// Send queued messages and a ping every [`HEARTBEAT_INTERVAL`], until the
+/// connection closes.
+async fn write_loop(
+    mut writer: WriteHalf<TlsStream<TcpStream>>,
+    mut outgoing: mpsc::UnboundedReceiver<CastMessage>,
+    closed: CancellationToken,
+) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        let message = tokio::select! {
+            message = outgoing.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = heartbeat.tick() => {
+                CastMessage::json(SENDER_ID, RECEIVER_ID, NS_HEARTBEAT, &json!({ "type": "PING" }))
+            }
+            _ = closed.cancelled() => break,
+        };
+        if let Err(e) = proto::write_message(&mut writer, &message).await {
+            tracing::debug!("{e}");
+            break;
+        }
+    }
+    closed.cancel();
+}
+
+/// TLS settings for Cast devices, which present self-signed certificates.
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let provider = Arc::new(crypto::ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider.clone())
+                .with_safe_default_protocol_versions()
+                .expect("ring supports the default TLS versions")
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+                .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+/// Accepts whatever certificate the device presents, still checking that
+/// the handshake is signed with it. Cast devices sign with a certificate
+/// of their own that no public CA vouches for.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,56 @@
+//! Google Cast (Chromecast, Google TV): the Cast v2 channel and what is
+//! sent over it to have the Default Media Receiver play our media.
+
+#[doc(hidden)]
+pub mod client;
+#[doc(hidden)]
+pub mod proto;
+#[doc(hidden)]
+pub mod types;
+
+use std::net::SocketAddr;
+
+
+use crate::error::AppError;
+use crate::net::Host;
+use crate::renderer::DeviceUrl;
+
+/// Port the Cast channel listens on.
+pub const DEFAULT_PORT: u16 = 8009;
+
+/// mDNS service type Cast devices advertise.
+pub const SERVICE_TYPE: &str = "_googlecast._tcp.local.";
+
+/// Google's stock receiver app for plain media URLs.
+pub const DEFAULT_MEDIA_RECEIVER: &str = "CC1AD845";
+
+/// Our end of every virtual connection.
+pub const SENDER_ID: &str = "sender-0";
+/// The device itself, as opposed to an app running on it.
+pub const RECEIVER_ID: &str = "receiver-0";
+
+pub const NS_CONNECTION: &str = "urn:x-cast:com.google.cast.tp.connection";
+pub const NS_HEARTBEAT: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+pub const NS_RECEIVER: &str = "urn:x-cast:com.google.cast.receiver";
+pub const NS_MEDIA: &str = "urn:x-cast:com.google.cast.media";
+
+/// URL a Cast device is listed with, e.g. `cast://192.168.1.20:8009`.
+pub fn device_url(host: &Host, port: u16) -> String {
+    format!("cast://{host}:{port}")
+}
+
+/// Where to open the Cast channel for a device listed with `device_url`.
+pub fn socket_addr(device_url: &DeviceUrl) -> Result<SocketAddr, AppError> {
+    let host: Host = device_url
+        .host()
+        .ok_or_else(|| AppError::NetworkError(format!("{device_url} has no host")))?
+        .parse()
+        .map_err(AppError::NetworkError)?;
+    host.socket_addr(device_url.port().unwrap_or(DEFAULT_PORT))
+}
+
+/// A device's unique name from the `id` it advertises, which some devices
+/// send with dashes and some without.
+pub fn udn(id: &str) -> String {
+    id.replace('-', "").to_lowercase()
+}
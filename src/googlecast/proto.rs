@@ -0,0 +1,85 @@
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::AppError;
+
+/// Largest message the receiver sends or accepts.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// `CastMessage.protocol_version`: the only version there is, CASTV2_1_0.
+const CASTV2_1_0: i32 = 0;
+
+/// `CastMessage.payload_type` for JSON payloads.
+const PAYLOAD_STRING: i32 = 0;
+
+/// The envelope of every message on the channel (`cast_channel.proto`).
+#[derive(Clone, PartialEq, Message)]
+pub struct CastMessage {
+    #[prost(int32, required, tag = "1")]
+    pub protocol_version: i32,
+    #[prost(string, required, tag = "2")]
+    pub source_id: String,
+    #[prost(string, required, tag = "3")]
+    pub destination_id: String,
+    #[prost(string, required, tag = "4")]
+    pub namespace: String,
+    #[prost(int32, required, tag = "5")]
+    pub payload_type: i32,
+    #[prost(string, optional, tag = "6")]
+    pub payload_utf8: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "7")]
+    pub payload_binary: Option<Vec<u8>>,
+}
+
+impl CastMessage {
+    /// A message with a JSON payload.
+    pub fn json(source: &str, destination: &str, namespace: &str, payload: &serde_json::Value) -> Self {
+        Self {
+            protocol_version: CASTV2_1_0,
+            source_id: source.to_string(),
+            destination_id: destination.to_string(),
+            namespace: namespace.to_string(),
+            payload_type: PAYLOAD_STRING,
+            payload_utf8: Some(payload.to_string()),
+            payload_binary: None,
+        }
+    }
+
+    /// The JSON payload, if the message has one that parses.
+    pub fn payload(&self) -> Option<serde_json::Value> {
+        serde_json::from_str(self.payload_utf8.as_deref()?).ok()
+    }
+}
+
+/// Read one message: a 4-byte big-endian length, then the protobuf.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<CastMessage, AppError> {
+    let len = reader
+        .read_u32()
+        .await
+        .map_err(|e| AppError::NetworkError(format!("Cast channel closed: {e}")))? as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(AppError::CastAction(format!("message of {len} bytes is too long")));
+    }
+    let mut buf = vec![0u8; len];
+    reader
+        .read_exact(&mut buf)
+        .await
+        .map_err(|e| AppError::NetworkError(format!("Cast channel closed: {e}")))?;
+    CastMessage::decode(buf.as_slice()).map_err(|e| AppError::CastAction(format!("malformed message: {e}")))
+}
+
+/// Write one message with its length prefix.
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &CastMessage) -> Result<(), AppError> {
+    let body = message.encode_to_vec();
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    writer
+        .write_all(&frame)
+        .await
+        .map_err(|e| AppError::NetworkError(format!("Cannot write to Cast channel: {e}")))?;
+    writer
+        .flush()
+        .await
+        .map_err(|e| AppError::NetworkError(format!("Cannot write to Cast channel: {e}")))
+}
@@ -0,0 +1,151 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::dlna::types::{MediaInfo, PlaybackState, PositionInfo, TransportActions};
+
+/// `supportedMediaCommands` bit for PAUSE.
+const COMMAND_PAUSE: u64 = 1;
+/// `supportedMediaCommands` bit for SEEK.
+const COMMAND_SEEK: u64 = 2;
+
+/// The `status` of a RECEIVER_STATUS message: what the device is running.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiverStatus {
+    #[serde(default)]
+    pub applications: Vec<Application>,
+    #[serde(default)]
+    pub volume: Volume,
+}
+
+impl ReceiverStatus {
+    pub fn from_reply(reply: &Value) -> Self {
+        serde_json::from_value(reply["status"].clone()).unwrap_or_default()
+    }
+
+    /// The running app with id `app_id`, if any.
+    pub fn app(&self, app_id: &str) -> Option<&Application> {
+        self.applications.iter().find(|a| a.app_id == app_id)
+    }
+
+    /// The app in the foreground, unless it's the idle screen.
+    pub fn foreground(&self) -> Option<&Application> {
+        self.applications.iter().find(|a| !a.is_idle_screen)
+    }
+}
+
+/// An app running on the device.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Application {
+    pub app_id: String,
+    #[serde(default)]
+    pub display_name: String,
+    /// Where to send the app's messages.
+    #[serde(default)]
+    pub transport_id: String,
+    /// The backdrop shown when nothing is casting.
+    #[serde(default)]
+    pub is_idle_screen: bool,
+}
+
+/// Device volume, as a level from 0.0 to 1.0.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Volume {
+    pub level: Option<f64>,
+    pub muted: Option<bool>,
+}
+
+impl Volume {
+    /// The level as a percentage, like a DLNA master volume.
+    pub fn percent(&self) -> u32 {
+        (self.level.unwrap_or_default().clamp(0.0, 1.0) * 100.0).round() as u32
+    }
+}
+
+/// One entry of a MEDIA_STATUS message: the media session of an app.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaStatus {
+    pub media_session_id: u64,
+    /// IDLE, BUFFERING, LOADING, PLAYING or PAUSED.
+    #[serde(default)]
+    pub player_state: String,
+    /// Why the player is IDLE: FINISHED, CANCELLED, INTERRUPTED or ERROR.
+    pub idle_reason: Option<String>,
+    #[serde(default)]
+    pub current_time: f64,
+    /// Often left out of updates once the receiver has sent it.
+    pub media: Option<MediaInformation>,
+    #[serde(default)]
+    pub supported_media_commands: u64,
+}
+
+impl MediaStatus {
+    /// The first media session in a MEDIA_STATUS reply; none if nothing is loaded.
+    pub fn from_reply(reply: &Value) -> Option<Self> {
+        let status = reply["status"].as_array()?.first()?;
+        serde_json::from_value(status.clone()).ok()
+    }
+
+    pub fn playback_state(&self) -> PlaybackState {
+        match self.player_state.as_str() {
+            "PLAYING" => PlaybackState::Playing,
+            "PAUSED" => PlaybackState::Paused,
+            "BUFFERING" | "LOADING" => PlaybackState::Transitioning,
+            "IDLE" => PlaybackState::Stopped,
+            other => PlaybackState::Unknown(other.to_string()),
+        }
+    }
+
+    /// Position in the loaded media; `media` fills in what this update
+    /// left out.
+    pub fn position(&self, media: Option<&MediaInformation>) -> PositionInfo {
+        let media = self.media.as_ref().or(media);
+        PositionInfo {
+            elapsed_secs: self.current_time.max(0.0) as u64,
+            duration_secs: media.and_then(|m| m.duration).unwrap_or_default().max(0.0) as u64,
+            track_uri: media.map(|m| m.content_id.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// The media commands the player takes, by their AVTransport names.
+    pub fn transport_actions(&self) -> TransportActions {
+        let mut actions = vec!["Play".to_string(), "Stop".to_string()];
+        if self.supported_media_commands & COMMAND_PAUSE != 0 {
+            actions.push("Pause".into());
+        }
+        if self.supported_media_commands & COMMAND_SEEK != 0 {
+            actions.push("Seek".into());
+        }
+        TransportActions(actions)
+    }
+}
+
+/// The media a session has loaded.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInformation {
+    /// The URL the receiver fetches.
+    pub content_id: String,
+    /// In seconds; absent until the receiver knows it.
+    pub duration: Option<f64>,
+    pub metadata: Option<Metadata>,
+}
+
+impl MediaInformation {
+    pub fn media_info(&self) -> MediaInfo {
+        MediaInfo {
+            nr_tracks: 1,
+            media_duration_secs: self.duration.unwrap_or_default().max(0.0) as u64,
+            current_uri: self.content_id.clone(),
+            current_uri_metadata: String::new(),
+            title: self.metadata.as_ref().and_then(|m| m.title.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+}
@@ -0,0 +1,127 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hyper014::client::HttpConnector;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+
+use super::base64;
+use super::websocket::{KodiSocket, Notification};
+use crate::error::AppError;
+use crate::net::Host;
+use crate::renderer::DeviceUrl;
+
+/// JSON-RPC requests to one Kodi instance, over its WebSocket once
+/// [`open_websocket`](Self::open_websocket) succeeds and over HTTP
+/// otherwise.
+pub struct KodiClient {
+    http: hyper014::Client<HttpConnector>,
+    endpoint: String,
+    host: Host,
+    /// `Authorization` header, when the device URL carries credentials.
+    authorization: Option<String>,
+    socket: Option<KodiSocket>,
+    next_id: AtomicU64,
+    request_timeout: Duration,
+}
+
+impl KodiClient {
+    /// A client for the instance listed with `device_url`.
+    pub fn new(device_url: &DeviceUrl, connect_timeout: Duration, request_timeout: Duration) -> Result<Self, AppError> {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(connect_timeout));
+        connector.set_nodelay(true);
+        let http = hyper014::Client::builder()
+            .pool_idle_timeout(Duration::from_secs(30))
+            .build(connector);
+        let authorization = super::credentials(device_url)
+            .map(|(user, password)| format!("Basic {}", base64(format!("{user}:{password}").as_bytes())));
+        let host = device_url
+            .host()
+            .ok_or_else(|| AppError::NetworkError(format!("{device_url} has no host")))?
+            .parse()
+            .map_err(AppError::NetworkError)?;
+        Ok(Self {
+            http,
+            endpoint: super::endpoint(device_url)?,
+            host,
+            authorization,
+            socket: None,
+            next_id: AtomicU64::new(1),
+            request_timeout,
+        })
+    }
+
+    /// Send requests over the WebSocket on `port` from now on, and hear
+    /// Kodi's notifications.
+    pub async fn open_websocket(&mut self, port: u16, connect_timeout: Duration) -> Result<(), AppError> {
+        self.socket = Some(KodiSocket::connect(&self.host, port, connect_timeout).await?);
+        Ok(())
+    }
+
+    /// Notifications from the WebSocket, if it is open.
+    pub fn notifications(&self) -> Option<broadcast::Receiver<Notification>> {
+        self.socket.as_ref().filter(|socket| socket.is_open()).map(KodiSocket::notifications)
+    }
+
+    /// Call `method` and return its `result`. A WebSocket that has closed
+    /// leaves requests to HTTP.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, AppError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let mut reply = match self.socket.as_ref().filter(|socket| socket.is_open()) {
+            Some(socket) => socket.call(id, &body, method, self.request_timeout).await?,
+            None => self.post(method, &body).await?,
+        };
+        if let Some(error) = reply.get("error") {
+            let message = error["message"].as_str().unwrap_or("no message");
+            return Err(AppError::KodiAction(format!("{method} failed: {message} ({})", error["code"])));
+        }
+        Ok(reply["result"].take())
+    }
+
+    /// POST `body` to the web server and return the whole reply.
+    async fn post(&self, method: &str, body: &Value) -> Result<Value, AppError> {
+        let mut request = http02::Request::post(&self.endpoint).header("Content-Type", "application/json");
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization);
+        }
+        let request = request
+            .body(hyper014::Body::from(body.to_string()))
+            .map_err(|e| AppError::KodiAction(format!("Failed to build {method} request: {e}")))?;
+
+        let exchange = async {
+            let response = self
+                .http
+                .request(request)
+                .await
+                .map_err(|e| AppError::NetworkError(format!("Kodi {method}: {e}")))?;
+            let status = response.status();
+            let body = hyper014::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| AppError::NetworkError(format!("Kodi {method}: {e}")))?;
+            Ok::<_, AppError>((status, body))
+        };
+        let (status, body) = tokio::time::timeout(self.request_timeout, exchange)
+            .await
+            .map_err(|_| AppError::Timeout(format!("Kodi {method} after {}s", self.request_timeout.as_secs_f32())))??;
+        match status.as_u16() {
+            200..=299 => {}
+            401 => {
+                return Err(AppError::KodiAction(format!(
+                    "{method} refused with HTTP {status}: give the web server's user and password in the device URL"
+                )))
+            }
+            _ => return Err(AppError::KodiAction(format!("{method} refused with HTTP {status}"))),
+        }
+
+        serde_json::from_slice(&body).map_err(|e| AppError::KodiAction(format!("Unreadable {method} reply: {e}")))
+    }
+
+    /// Call `method` and read its `result` as `T`.
+    pub async fn call_as<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, AppError> {
+        let result = self.call(method, params).await?;
+        serde_json::from_value(result).map_err(|e| AppError::KodiAction(format!("Unexpected {method} reply: {e}")))
+    }
+}
@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+use crate::dlna::types::PlaybackState;
+
+/// An entry of `Player.GetActivePlayers`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActivePlayer {
+    #[serde(rename = "playerid")]
+    pub player_id: i64,
+    /// `video`, `audio` or `picture`.
+    #[serde(rename = "type", default)]
+    pub kind: String,
+}
+
+/// The `Player.GetProperties` we ask for.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub struct PlayerProperties {
+    #[serde(default)]
+    pub time: Time,
+    #[serde(default)]
+    pub totaltime: Time,
+    /// 1 while playing, 0 while paused; other values fast forward or rewind.
+    #[serde(default)]
+    pub speed: f64,
+}
+
+impl PlayerProperties {
+    pub fn playback_state(&self) -> PlaybackState {
+        if self.speed == 0.0 {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        }
+    }
+}
+
+/// A `Player.Position.Time`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Time {
+    #[serde(default)]
+    pub hours: u64,
+    #[serde(default)]
+    pub minutes: u64,
+    #[serde(default)]
+    pub seconds: u64,
+    #[serde(default)]
+    pub milliseconds: u64,
+}
+
+impl Time {
+    pub fn secs(&self) -> u64 {
+        self.hours * 3600 + self.minutes * 60 + self.seconds
+    }
+
+    /// The `time` to seek to `secs`.
+    pub fn json(secs: u64) -> serde_json::Value {
+        serde_json::json!({
+            "hours": secs / 3600,
+            "minutes": secs / 60 % 60,
+            "seconds": secs % 60,
+            "milliseconds": 0,
+        })
+    }
+}
+
+/// The `item` of `Player.GetItem`: what a player has open.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Item {
+    /// The path or URL played; empty for items from add-ons.
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub title: String,
+}
+
+impl Item {
+    /// Title if Kodi knows one, else its label.
+    pub fn name(&self) -> Option<String> {
+        [&self.title, &self.label]
+            .into_iter()
+            .find(|s| !s.is_empty())
+            .cloned()
+    }
+}
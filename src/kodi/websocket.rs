@@ -0,0 +1,173 @@
+//! JSON-RPC over Kodi's WebSocket: requests and replies like over HTTP,
+//! plus the notifications Kodi sends when playback changes, such as
+//! `Player.OnPause`. The protocol itself is tokio-tungstenite's, which also
+//! answers Kodi's pings.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::error::AppError;
+use crate::net::Host;
+
+/// Largest message accepted; Kodi's replies to our requests are small.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// A message Kodi sends unasked, e.g. `Player.OnPause`.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub method: String,
+    pub params: Value,
+}
+
+/// Replies awaited, by request ID.
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+/// An open WebSocket to Kodi. Closed when dropped.
+pub struct KodiSocket {
+    /// Messages for the writer task.
+    messages: mpsc::UnboundedSender<Message>,
+    pending: Pending,
+    notifications: broadcast::Sender<Notification>,
+    open: Arc<AtomicBool>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl KodiSocket {
+    /// Open the WebSocket Kodi serves on `port` of `host`.
+    pub async fn connect(host: &Host, port: u16, timeout: Duration) -> Result<Self, AppError> {
+        let error = |e: std::io::Error| AppError::NetworkError(format!("Kodi WebSocket at {host}:{port}: {e}"));
+        let addr = host.socket_addr(port)?;
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| AppError::Timeout(format!("Kodi WebSocket at {host}:{port}")))?
+            .map_err(error)?;
+        stream.set_nodelay(true).map_err(error)?;
+
+        // The zone means nothing to Kodi, so the Host header goes without it
+        let url = format!("ws://{}/jsonrpc", SocketAddr::new(host.ip, port));
+        let config = WebSocketConfig::default().max_message_size(Some(MAX_MESSAGE));
+        let handshake = tokio_tungstenite::client_async_with_config(url, stream, Some(config));
+        let (socket, _) = tokio::time::timeout(timeout, handshake)
+            .await
+            .map_err(|_| AppError::Timeout(format!("Kodi WebSocket handshake at {host}:{port}")))?
+            .map_err(|e| match e {
+                WsError::Io(e) => error(e),
+                e => AppError::KodiAction(format!("WebSocket refused at {host}:{port}: {e}")),
+            })?;
+
+        let (sink, stream) = socket.split();
+        let (messages, outgoing) = mpsc::unbounded_channel();
+        let pending = Pending::default();
+        let (notifications, _) = broadcast::channel(32);
+        let open = Arc::new(AtomicBool::new(true));
+        let tasks = [
+            tokio::spawn(write_messages(sink, outgoing)),
+            tokio::spawn(read_messages(stream, pending.clone(), notifications.clone(), open.clone())),
+        ];
+        Ok(Self {
+            messages,
+            pending,
+            notifications,
+            open,
+            tasks,
+        })
+    }
+
+    /// Whether requests can still be sent.
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    pub fn notifications(&self) -> broadcast::Receiver<Notification> {
+        self.notifications.subscribe()
+    }
+
+    /// Send `request`, whose `id` is `id`, and return the whole reply.
+    pub async fn call(&self, id: u64, request: &Value, method: &str, timeout: Duration) -> Result<Value, AppError> {
+        let (reply_tx, reply) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, reply_tx);
+        let closed = || AppError::NetworkError(format!("Kodi {method}: the WebSocket closed"));
+        if self.messages.send(Message::text(request.to_string())).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(closed());
+        }
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(closed()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(AppError::Timeout(format!("Kodi {method} after {}s", timeout.as_secs_f32())))
+            }
+        }
+    }
+}
+
+impl Drop for KodiSocket {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+type Sink = futures::stream::SplitSink<WebSocketStream<TcpStream>, Message>;
+type Stream = futures::stream::SplitStream<WebSocketStream<TcpStream>>;
+
+async fn write_messages(mut sink: Sink, mut messages: mpsc::UnboundedReceiver<Message>) {
+    while let Some(message) = messages.recv().await {
+        if let Err(e) = sink.send(message).await {
+            tracing::debug!("Kodi WebSocket write failed: {e}");
+            break;
+        }
+    }
+}
+
+/// Hand replies to their callers and pass notifications on until the
+/// socket closes, then fail the calls still waiting.
+async fn read_messages(
+    mut stream: Stream,
+    pending: Pending,
+    notifications: broadcast::Sender<Notification>,
+    open: Arc<AtomicBool>,
+) {
+    while let Some(message) = stream.next().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::debug!("Kodi WebSocket closed: {e}");
+                break;
+            }
+        };
+        let Ok(value) = serde_json::from_str::<Value>(&text) else {
+            tracing::debug!("Unreadable Kodi WebSocket message");
+            continue;
+        };
+        if let Some(id) = value["id"].as_u64() {
+            if let Some(reply) = pending.lock().unwrap().remove(&id) {
+                let _ = reply.send(value);
+            }
+        } else if let Some(method) = value["method"].as_str() {
+            let _ = notifications.send(Notification {
+                method: method.to_string(),
+                params: value["params"].clone(),
+            });
+        }
+    }
+    open.store(false, Ordering::Relaxed);
+    // Dropping the senders fails the calls still waiting
+    pending.lock().unwrap().clear();
+}
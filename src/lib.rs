@@ -0,0 +1,71 @@
+//! Cast local video files to DLNA/UPnP renderers (smart TVs, media players),
+//! Chromecasts, Apple TVs, Kodi and Roku.
+//!
+//! The main entry point is [`CastSession`]: it owns the media server, the
+//! connection to the selected renderer, the play queue and a poller that
+//! tracks playback. Front-ends send it [`SessionCommand`]s and read its
+//! [`SessionState`]; the `localcast` TUI and its HTTP API are both built this way.
+//!
+//! ```no_run
+//! use localcast::{CastSession, DeviceRef, SessionCommand};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let session = CastSession::builder().start().await?;
+//! session.request(SessionCommand::SetFiles(vec!["movie.mp4".into()])).await?;
+//! let state = session.request(SessionCommand::Discover).await?;
+//! let first = state.devices.first().ok_or("no renderers found")?;
+//! let device = Some(DeviceRef::Udn(first.udn.clone()));
+//! session.request(SessionCommand::Cast { device }).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Lower-level building blocks are available too: [`discovery`] finds
+//! renderers, [`renderer::connect`] gives a [`Renderer`] to control each one
+//! whatever its protocol, [`dlna::transport`] sends AVTransport actions to
+//! DLNA renderers, [`googlecast`], [`airplay`], [`kodi`] and [`roku`] name
+//! the services and ports of the Cast v2, AirPlay video, Kodi JSON-RPC and
+//! Roku ECP protocols, and [`server::MediaServer`] serves files over HTTP.
+//! [`dms::ContentServer`] turns things around: it shares folders as a UPnP
+//! media server that TVs browse from their own menus.
+//!
+//! Items reachable from the crate root follow semantic versioning, except
+//! those hidden from the documentation: the protocols' clients and wire
+//! formats are public for the bundled stand-in examples only. Enums and
+//! structs that are expected to grow are `#[non_exhaustive]`.
+
+pub mod airplay;
+pub mod cache;
+pub mod config;
+pub mod discovery;
+pub mod dlna;
+pub mod dms;
+pub mod error;
+pub mod googlecast;
+pub mod kodi;
+pub mod net;
+pub mod renderer;
+pub mod roku;
+pub mod server;
+pub mod session;
+pub mod wol;
+
+mod cast;
+mod queue;
+mod recovery;
+
+pub use cache::{CachedDevice, DeviceCache};
+pub use cast::{CastFailure, CastStep};
+pub use config::{Config, DeviceConfig, Quirk};
+pub use discovery::discover_devices;
+pub use dlna::types::{PlayMode, PlaybackState, PositionInfo};
+pub use error::AppError;
+pub use net::InterfaceFilter;
+pub use queue::{PlayQueue, QueueItem, Subtitle, SUPPORTED_EXTENSIONS};
+pub use recovery::RecoveryPolicy;
+pub use renderer::{Device, DeviceUrl, Media, Protocol, Renderer, RendererEvent};
+pub use server::MediaServer;
+pub use session::{
+    CastSession, DeviceRef, SessionBuilder, SessionCommand, SessionError, SessionEvent, SessionState,
+};
+pub use wol::{MacAddress, WakeOptions};
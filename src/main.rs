@@ -1,43 +1,44 @@
 mod api;
 mod app;
 mod cli;
-mod discovery;
-mod dlna;
-mod error;
-mod server;
+mod commands;
+#[cfg(unix)]
+mod daemon;
 mod tui;
 
-use std::net::{SocketAddr, UdpSocket};
+use std::net::SocketAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
 use clap::Parser;
-use tokio::sync::mpsc;
+use tokio::sync::broadcast;
 
-use crate::app::{App, AppScreen, PollerMessage};
-use crate::cli::Args;
-use crate::dlna::transport;
-use crate::dlna::types::PlaybackState;
-use crate::tui::event::AppAction;
+use localcast::{CastSession, DeviceCache, DeviceRef, SessionCommand, SessionEvent};
 
-const SUPPORTED_EXTENSIONS: &[&str] = &["mp4", "mkv", "avi", "webm"];
+use crate::app::{App, AppScreen};
+use crate::cli::{Args, Command};
+use crate::tui::event::{AppAction, PromptKey};
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let args = Args::parse();
+    let settings = match args.settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("localcast: {e}");
+            return Ok(ExitCode::from(2));
+        }
+    };
 
-    // Set up file-based logging, truncate on each run.
-    // Use $TMPDIR so this works when launched from inside a .app bundle (cwd = /).
-    let log_path = std::env::var("TMPDIR")
-        .map(std::path::PathBuf::from)
-        .unwrap_or_else(|_| std::env::temp_dir())
-        .join("localcast.log");
-    let _ = std::fs::write(&log_path, ""); // truncate
+    // Set up file-based logging. Append, since a daemon may be writing to
+    // the same file.
+    let log_path = &settings.log_file;
     let log_file = std::fs::OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&log_path)
+        .open(log_path)
         .context("Failed to open log file")?;
     tracing_subscriber::fmt()
         .with_writer(std::sync::Mutex::new(log_file))
@@ -45,105 +46,89 @@ async fn main() -> Result<()> {
         .with_ansi(false)
         .init();
 
-    tracing::info!("LocalCast starting");
+    tracing::info!("LocalCast starting (pid {})", std::process::id());
 
-    if args.api {
-        return run_api_server().await;
+    match args.command.clone() {
+        #[cfg(unix)]
+        Some(Command::Daemon { socket }) => {
+            daemon::run(settings.session_builder(), socket).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(command) => return Ok(commands::run(command, &settings).await),
+        None => {}
     }
 
-    // TUI mode: file is required
-    let file = args
-        .file
-        .context("A video file path is required in TUI mode")?;
+    // Device control runs in the session actor, shared by both front-ends
+    let session = settings
+        .session_builder()
+        .start()
+        .await
+        .context("Failed to start session")?;
 
-    // Validate file
-    let file_path = file.canonicalize().context("File not found")?;
-    if !file_path.is_file() {
-        bail!("Not a file: {}", file_path.display());
+    if args.api {
+        run_api_server(session, settings.api_bind).await?;
+        return Ok(ExitCode::SUCCESS);
     }
 
-    let ext = file_path
-        .extension()
-        .and_then(|e| e.to_str())
-        .map(|e| e.to_lowercase())
-        .unwrap_or_default();
-
-    if !SUPPORTED_EXTENSIONS.contains(&ext.as_str()) {
-        bail!(
-            "Unsupported file type: .{}. Supported: {}",
-            ext,
-            SUPPORTED_EXTENSIONS.join(", ")
-        );
+    // TUI mode: at least one file is required
+    if args.files.is_empty() {
+        bail!("A video file path is required in TUI mode");
     }
 
-    let file_name = file_path
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-
-    let file_size = std::fs::metadata(&file_path)?.len();
-
-    let mime_type = mime_guess::from_path(&file_path)
-        .first_or_octet_stream()
-        .to_string();
-
-    // Start HTTP media server (bind on all interfaces)
-    let (addr, serve_path, _server_handle) = server::start_server(file_path.clone(), args.port)
-        .await
-        .context("Failed to start HTTP server")?;
-
-    let server_port = addr.port();
+    session.request(SessionCommand::SetFiles(args.files.clone())).await?;
+    let mut events = session.subscribe();
+    session.send(SessionCommand::Discover);
+    if args.last {
+        // Cached devices are listed from the start
+        let last = settings
+            .cache_file
+            .as_deref()
+            .and_then(|path| DeviceCache::load(path).last_used().cloned())
+            .context("No TV was cast to before")?;
+        if !session.state().devices.iter().any(|d| d.udn == last.udn) {
+            bail!("{} is not listed", last.friendly_name);
+        }
+        // Found by UDN when the session gets to it, wherever discovery
+        // has moved it in the list by then
+        let device = Some(DeviceRef::Udn(last.udn));
+        session.send(if args.wake {
+            SessionCommand::WakeAndCast { device }
+        } else {
+            SessionCommand::Cast { device }
+        });
+    }
 
-    // Initialize TUI
+    // Initialize TUI. The event loop only renders and forwards key presses,
+    // so it never waits on the network.
     let mut terminal = tui::init_terminal().context("Failed to initialize terminal")?;
-    // media_url will be determined per-device based on which network interface reaches it
-    let mut app = App::new(file_name, String::new(), mime_type.clone(), file_size);
-
-    // Initial device discovery
-    let devices = discovery::discover_devices(Duration::from_secs(5)).await?;
-    app.devices = devices;
-    app.scanning = false;
-
-    // Channel for playback poller -> TUI
-    let (poller_tx, mut poller_rx) = mpsc::channel::<PollerMessage>(32);
-    let mut poller_handle: Option<tokio::task::JoinHandle<()>> = None;
+    let mut app = App::new(session.state(), settings.seek_step, settings.seek_step_long);
 
     // Main TUI event loop
-    let result = run_event_loop(
-        &mut terminal,
-        &mut app,
-        &serve_path,
-        server_port,
-        &mime_type,
-        file_size,
-        &poller_tx,
-        &mut poller_rx,
-        &mut poller_handle,
-    )
-    .await;
+    let result = run_event_loop(&mut terminal, &mut app, &session, &mut events).await;
 
     // Cleanup
-    if let Some(handle) = poller_handle.take() {
-        handle.abort();
-    }
     tui::restore_terminal(&mut terminal)?;
 
     if let Err(e) = result {
         eprintln!("Error: {e:#}");
-        std::process::exit(1);
+        return Ok(ExitCode::FAILURE);
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 /// Run the HTTP API server for the Flutter GUI.
-async fn run_api_server() -> Result<()> {
-    let state = Arc::new(tokio::sync::Mutex::new(api::state::ApiState::new()));
+async fn run_api_server(session: CastSession, addr: SocketAddr) -> Result<()> {
+    let state = Arc::new(api::state::ApiState::new(session));
     let router = api::api_router(state);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let listener = tokio::net::TcpListener::bind(addr).await.with_context(|| {
+        if addr.port() == localcast::kodi::DEFAULT_PORT {
+            format!("Cannot listen on {addr}; if Kodi runs here, its web server may have the port: choose another with --api-bind")
+        } else {
+            format!("Cannot listen on {addr}")
+        }
+    })?;
     tracing::info!("API server listening on {addr}");
     eprintln!("LocalCast API server listening on http://{addr}");
 
@@ -151,167 +136,122 @@ async fn run_api_server() -> Result<()> {
     Ok(())
 }
 
-/// Determine the local IP that can reach a given target IP by
-/// connecting a UDP socket (no actual traffic is sent).
-fn local_ip_for(target: &str) -> Result<std::net::IpAddr> {
-    let target_addr: SocketAddr = if target.contains(':') {
-        target.parse().context("Invalid target address")?
-    } else {
-        format!("{target}:80").parse().context("Invalid target address")?
-    };
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    socket.connect(target_addr)?;
-    let local_addr = socket.local_addr()?;
-    Ok(local_addr.ip())
-}
-
-/// Build the media URL using the local IP that can reach the device.
-fn media_url_for_device(
-    device: &dlna::types::DlnaDevice,
-    server_port: u16,
-    serve_path: &str,
-) -> Result<String> {
-    let device_host = device
-        .device_url
-        .host()
-        .context("Device URL has no host")?;
-    let local_ip = local_ip_for(device_host)?;
-    let url = format!("http://{}:{}{}", local_ip, server_port, serve_path);
-    tracing::info!("Media URL for {}: {}", device.friendly_name, url);
-    Ok(url)
-}
-
 async fn run_event_loop(
     terminal: &mut tui::Tui,
     app: &mut App,
-    serve_path: &str,
-    server_port: u16,
-    mime_type: &str,
-    file_size: u64,
-    poller_tx: &mpsc::Sender<PollerMessage>,
-    poller_rx: &mut mpsc::Receiver<PollerMessage>,
-    poller_handle: &mut Option<tokio::task::JoinHandle<()>>,
+    session: &CastSession,
+    events: &mut broadcast::Receiver<SessionEvent>,
 ) -> Result<()> {
     loop {
+        // Drain session events (non-blocking)
+        loop {
+            match events.try_recv() {
+                Ok(event) => app.apply_session_event(event),
+                Err(broadcast::error::TryRecvError::Empty) => break,
+                Err(broadcast::error::TryRecvError::Lagged(n)) => {
+                    tracing::warn!("TUI missed {n} session events");
+                }
+                Err(broadcast::error::TryRecvError::Closed) => {
+                    app.should_quit = true;
+                    break;
+                }
+            }
+        }
+
         // Render current state
+        app.set_session_state(session.state());
+        app.on_tick();
         tui::render(terminal, app)?;
-
-        // Drain poller messages (non-blocking)
-        while let Ok(msg) = poller_rx.try_recv() {
-            app.apply_poller_message(msg);
+        if app.should_quit {
+            break;
         }
 
         // Poll for key events (100ms timeout)
-        let maybe_key = tui::read_key_event(Duration::from_millis(100))?;
-        let action = match maybe_key {
-            Some(key) => tui::map_key(&app.screen, key),
-            None => continue,
+        let Some(key) = tui::read_key_event(Duration::from_millis(100))? else {
+            continue;
         };
 
+        // An open prompt takes every key
+        if let Some(input) = app.device_prompt.as_mut() {
+            match tui::event::map_prompt_key(key) {
+                PromptKey::Char(c) => input.push(c),
+                PromptKey::Backspace => {
+                    input.pop();
+                }
+                PromptKey::Submit => {
+                    let address = input.trim().to_string();
+                    app.device_prompt = None;
+                    if !address.is_empty() {
+                        app.push_toast(format!("Looking for a TV at {address}..."), false);
+                        session.send(SessionCommand::AddDevice(address));
+                    }
+                }
+                PromptKey::Cancel => app.device_prompt = None,
+                PromptKey::None => {}
+            }
+            continue;
+        }
+        let action = tui::map_key(&app.screen, key);
+
         match (&app.screen, &action) {
-            // --- Device Browser actions ---
-            (AppScreen::DeviceBrowser, AppAction::Quit) => {
-                app.should_quit = true;
-                break;
+            // Stop playback and wait for the session to close
+            (_, AppAction::Quit) => {
+                session.cancel();
+                session.send(SessionCommand::Quit);
             }
+            (_, AppAction::Cancel) => session.cancel(),
+
+            // --- Device Browser actions ---
             (AppScreen::DeviceBrowser, AppAction::MoveUp) => app.select_prev(),
             (AppScreen::DeviceBrowser, AppAction::MoveDown) => app.select_next(),
-            (AppScreen::DeviceBrowser, AppAction::Rescan) => {
-                app.scanning = true;
-                tui::render(terminal, app)?;
-                let devices = discovery::discover_devices(Duration::from_secs(5)).await?;
-                app.devices = devices;
-                app.selected_device = 0;
-                app.scanning = false;
-            }
-            (AppScreen::DeviceBrowser, AppAction::Select) => {
-                if let Some(device) = app.current_device().cloned() {
-                    // Resolve the AVTransport control URL from the device description
-                    let control_url = transport::resolve_control_url(&device).await?;
-
-                    // Determine the correct local IP for this device
-                    let media_url =
-                        media_url_for_device(&device, server_port, serve_path)?;
-
-                    // Set URI and play
-                    transport::set_av_transport_uri(
-                        &device,
-                        &control_url,
-                        &media_url,
-                        &app.file_name,
-                        mime_type,
-                        file_size,
-                    )
-                    .await?;
-                    transport::play(&device, &control_url).await?;
-                    app.media_url = media_url;
-                    app.control_url = control_url.clone();
-                    app.playback_state = PlaybackState::Playing;
-                    app.screen = AppScreen::Playback;
-
-                    // Start playback poller
-                    let poller_device = device.clone();
-                    let poller_control_url = control_url;
-                    let tx = poller_tx.clone();
-                    let handle = tokio::spawn(async move {
-                        playback_poller(poller_device, poller_control_url, tx).await;
+            (AppScreen::DeviceBrowser, AppAction::Rescan) => session.send(SessionCommand::Discover),
+            (AppScreen::DeviceBrowser, AppAction::AddDevice) => app.device_prompt = Some(String::new()),
+            (AppScreen::DeviceBrowser, AppAction::Select | AppAction::WakeAndCast) => {
+                if app.session.cast_step.is_some() {
+                    app.push_toast("A cast is already in progress (Esc to cancel)", false);
+                } else if let Some(device) = app.current_device() {
+                    let device = Some(DeviceRef::Udn(device.udn.clone()));
+                    session.send(match action {
+                        AppAction::WakeAndCast => SessionCommand::WakeAndCast { device },
+                        _ => SessionCommand::Cast { device },
                     });
-                    *poller_handle = Some(handle);
                 }
             }
 
             // --- Playback actions ---
-            (AppScreen::Playback, AppAction::Quit) => {
-                // Stop playback before quitting
-                if let Some(device) = app.current_device() {
-                    let _ = transport::stop(device, &app.control_url).await;
-                }
-                app.should_quit = true;
-                break;
-            }
             (AppScreen::Playback, AppAction::TogglePlayPause) => {
-                if let Some(device) = app.current_device() {
-                    match app.playback_state {
-                        PlaybackState::Playing => {
-                            transport::pause(device, &app.control_url).await?;
-                            app.playback_state = PlaybackState::Paused;
-                        }
-                        PlaybackState::Paused | PlaybackState::Stopped => {
-                            transport::play(device, &app.control_url).await?;
-                            app.playback_state = PlaybackState::Playing;
-                        }
-                        _ => {}
-                    }
-                }
+                session.send(SessionCommand::TogglePlayPause);
             }
-            (AppScreen::Playback, AppAction::Stop) => {
-                if let Some(device) = app.current_device() {
-                    transport::stop(device, &app.control_url).await?;
-                    app.playback_state = PlaybackState::Stopped;
-                }
+            (AppScreen::Playback, AppAction::Stop) => session.send(SessionCommand::Stop),
+            (AppScreen::Playback, AppAction::NextItem) => session.send(SessionCommand::Next),
+            (AppScreen::Playback, AppAction::PreviousItem) => {
+                session.send(SessionCommand::Previous);
+            }
+            (AppScreen::Playback, AppAction::CyclePlayMode) => {
+                session.send(SessionCommand::CyclePlayMode);
+            }
+            (AppScreen::Playback, AppAction::SpeedUp) => {
+                session.send(SessionCommand::ChangeSpeed { faster: true });
             }
-            (AppScreen::Playback, AppAction::SeekForward30) => {
-                seek_relative(app, 30).await?;
+            (AppScreen::Playback, AppAction::SlowDown) => {
+                session.send(SessionCommand::ChangeSpeed { faster: false });
             }
-            (AppScreen::Playback, AppAction::SeekBackward30) => {
-                seek_relative(app, -30).await?;
+            (AppScreen::Playback, AppAction::SeekForward) => {
+                session.send(SessionCommand::SeekBy(app.seek_step() as i64));
             }
-            (AppScreen::Playback, AppAction::SeekForward5Min) => {
-                seek_relative(app, 300).await?;
+            (AppScreen::Playback, AppAction::SeekBackward) => {
+                session.send(SessionCommand::SeekBy(-(app.seek_step() as i64)));
             }
-            (AppScreen::Playback, AppAction::SeekBackward5Min) => {
-                seek_relative(app, -300).await?;
+            (AppScreen::Playback, AppAction::SeekForwardLong) => {
+                session.send(SessionCommand::SeekBy(app.seek_step_long as i64));
+            }
+            (AppScreen::Playback, AppAction::SeekBackwardLong) => {
+                session.send(SessionCommand::SeekBy(-(app.seek_step_long as i64)));
             }
             (AppScreen::Playback, AppAction::BackToDevices) => {
-                // Stop playback and go back
-                if let Some(device) = app.current_device() {
-                    let _ = transport::stop(device, &app.control_url).await;
-                }
-                if let Some(handle) = poller_handle.take() {
-                    handle.abort();
-                }
-                app.playback_state = PlaybackState::Stopped;
-                app.position = Default::default();
+                // Abandon any recovery in progress, then stop and go back
+                session.cancel();
+                session.send(SessionCommand::Disconnect);
                 app.screen = AppScreen::DeviceBrowser;
             }
 
@@ -320,45 +260,3 @@ async fn run_event_loop(
     }
     Ok(())
 }
-
-async fn seek_relative(app: &mut App, delta_secs: i64) -> Result<()> {
-    if let Some(device) = app.current_device() {
-        let current = app.position.elapsed_secs as i64;
-        let target = (current + delta_secs).max(0) as u64;
-        let target = if app.position.duration_secs > 0 {
-            target.min(app.position.duration_secs)
-        } else {
-            target
-        };
-        transport::seek(device, &app.control_url, target).await?;
-    }
-    Ok(())
-}
-
-/// Background task that polls the device for position and transport state.
-async fn playback_poller(device: dlna::types::DlnaDevice, control_url: String, tx: mpsc::Sender<PollerMessage>) {
-    let mut interval = tokio::time::interval(Duration::from_secs(1));
-    loop {
-        interval.tick().await;
-
-        // Get position info
-        match transport::get_position_info(&device, &control_url).await {
-            Ok(pos) => {
-                if tx.send(PollerMessage::PositionUpdate(pos)).await.is_err() {
-                    break; // receiver dropped
-                }
-            }
-            Err(e) => tracing::warn!("Poller GetPositionInfo error: {e}"),
-        }
-
-        // Get transport state
-        match transport::get_transport_info(&device, &control_url).await {
-            Ok(state) => {
-                if tx.send(PollerMessage::StateUpdate(state)).await.is_err() {
-                    break;
-                }
-            }
-            Err(e) => tracing::warn!("Poller GetTransportInfo error: {e}"),
-        }
-    }
-}
@@ -0,0 +1,422 @@
+//! Local network interfaces: which ones discovery searches on and which
+//! address the media server advertises to a renderer.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+
+use crate::error::AppError;
+
+/// An address of a local network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    /// Interface name, e.g. `en0` or `eth0`.
+    pub name: String,
+    pub addr: IpAddr,
+}
+
+impl Interface {
+    /// Interfaces discovery may use without being told: up and not loopback.
+    fn is_default_eligible(&self) -> bool {
+        !self.addr.is_loopback() && !self.addr.is_unspecified()
+    }
+}
+
+/// Every address of every local interface.
+pub fn interfaces() -> Result<Vec<Interface>, AppError> {
+    let list = local_ip_address::list_afinet_netifas()
+        .map_err(|e| AppError::NetworkError(format!("Cannot list network interfaces: {e}")))?;
+    Ok(list
+        .into_iter()
+        .map(|(name, addr)| Interface { name, addr })
+        .collect())
+}
+
+/// Which interfaces to use: a name such as `en0`, an address such as
+/// `192.168.1.20`, or a subnet such as `192.168.1.0/24`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InterfaceFilter {
+    Name(String),
+    Subnet { network: IpAddr, prefix_len: u8 },
+}
+
+impl InterfaceFilter {
+    pub fn matches(&self, interface: &Interface) -> bool {
+        match self {
+            Self::Name(name) => interface.name == *name,
+            Self::Subnet {
+                network,
+                prefix_len,
+            } => in_subnet(interface.addr, *network, *prefix_len),
+        }
+    }
+}
+
+impl FromStr for InterfaceFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some((network, prefix_len)) = s.split_once('/') {
+            let network: IpAddr = network
+                .parse()
+                .map_err(|_| format!("'{network}' is not an IP address"))?;
+            let max = if network.is_ipv4() { 32 } else { 128 };
+            let prefix_len = prefix_len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max)
+                .ok_or_else(|| format!("prefix length must be between 0 and {max}"))?;
+            return Ok(Self::Subnet {
+                network,
+                prefix_len,
+            });
+        }
+        if let Ok(addr) = s.parse::<IpAddr>() {
+            let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+            return Ok(Self::Subnet {
+                network: addr,
+                prefix_len,
+            });
+        }
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err("expected an interface name, IP address or subnet".into());
+        }
+        Ok(Self::Name(s.to_string()))
+    }
+}
+
+impl fmt::Display for InterfaceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => f.write_str(name),
+            Self::Subnet {
+                network,
+                prefix_len,
+            } => write!(f, "{network}/{prefix_len}"),
+        }
+    }
+}
+
+/// Whether `addr` lies in `network/prefix_len`.
+fn in_subnet(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix_len)).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// The interfaces discovery searches on: those matching `filter`, or every
+/// non-loopback interface if there is none.
+pub fn eligible_interfaces(filter: Option<&InterfaceFilter>) -> Result<Vec<Interface>, AppError> {
+    let all = interfaces()?;
+    let eligible: Vec<Interface> = match filter {
+        Some(filter) => all.into_iter().filter(|i| filter.matches(i)).collect(),
+        None => all.into_iter().filter(Interface::is_default_eligible).collect(),
+    };
+    match (filter, eligible.is_empty()) {
+        (Some(filter), true) => Err(AppError::NetworkError(format!(
+            "no network interface matches '{filter}'"
+        ))),
+        _ => Ok(eligible),
+    }
+}
+
+/// Index of the interface named `name`, as used in IPv6 scope IDs.
+pub fn interface_index(name: &str) -> Option<u32> {
+    // The resolver turns the zone of a link-local address into the index
+    match (format!("fe80::%{name}"), 0).to_socket_addrs().ok()?.next()? {
+        SocketAddr::V6(addr) if addr.scope_id() != 0 => Some(addr.scope_id()),
+        _ => None,
+    }
+}
+
+/// An IP address as written in a URL, with the zone a link-local IPv6
+/// address needs to say which interface it is on.
+///
+/// ```
+/// use localcast::net::Host;
+///
+/// let host: Host = "[fe80::1%25eth0]".parse()?;
+/// assert_eq!(host.zone.as_deref(), Some("eth0"));
+/// assert_eq!(host.to_string(), "[fe80::1%eth0]");
+/// assert_eq!("::1".parse::<Host>()?.to_string(), "[::1]");
+/// assert_eq!("192.168.1.5".parse::<Host>()?.to_string(), "192.168.1.5");
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    pub ip: IpAddr,
+    /// Interface name or index after the `%`; IPv6 only.
+    pub zone: Option<String>,
+}
+
+impl Host {
+    /// The address to connect to on `port`, with the zone resolved to a
+    /// scope ID.
+    pub fn socket_addr(&self, port: u16) -> Result<SocketAddr, AppError> {
+        match (self.ip, &self.zone) {
+            (IpAddr::V6(ip), Some(zone)) => {
+                let scope_id = zone
+                    .parse()
+                    .ok()
+                    .or_else(|| interface_index(zone))
+                    .ok_or_else(|| AppError::NetworkError(format!("no network interface named '{zone}'")))?;
+                Ok(SocketAddrV6::new(ip, port, 0, scope_id).into())
+            }
+            _ => Ok(SocketAddr::new(self.ip, port)),
+        }
+    }
+}
+
+impl FromStr for Host {
+    type Err = String;
+
+    /// Accepts `192.168.1.5`, `::1`, `[::1]`, `fe80::1%eth0`, and the URL
+    /// form `[fe80::1%25eth0]` where the `%` is escaped.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bracketed = s.strip_prefix('[').and_then(|s| s.strip_suffix(']'));
+        let (addr, zone) = match bracketed.unwrap_or(s).split_once('%') {
+            Some((addr, zone)) => {
+                let zone = match zone.strip_prefix("25") {
+                    Some(unescaped) if bracketed.is_some() && !unescaped.is_empty() => unescaped,
+                    _ => zone,
+                };
+                (addr, Some(zone))
+            }
+            None => (bracketed.unwrap_or(s), None),
+        };
+        let ip: IpAddr = addr.parse().map_err(|_| format!("'{s}' is not an IP address"))?;
+        if bracketed.is_some() && ip.is_ipv4() {
+            return Err(format!("'{s}': only IPv6 addresses go in brackets"));
+        }
+        let zone = match zone {
+            Some(zone) if zone.is_empty() || ip.is_ipv4() => {
+                return Err(format!("'{s}': only IPv6 addresses take a %zone"))
+            }
+            zone => zone.map(str::to_string),
+        };
+        Ok(Self { ip, zone })
+    }
+}
+
+impl fmt::Display for Host {
+    /// The URL form, with the zone left unescaped since that is what the
+    /// system resolver understands.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ip, &self.zone) {
+            (IpAddr::V4(ip), _) => write!(f, "{ip}"),
+            (IpAddr::V6(ip), Some(zone)) => write!(f, "[{ip}%{zone}]"),
+            (IpAddr::V6(ip), None) => write!(f, "[{ip}]"),
+        }
+    }
+}
+
+/// Split `host` or `host:port`, where an IPv6 host with a port is bracketed:
+/// `[fe80::1%eth0]:8000`.
+pub fn parse_host_port(s: &str) -> Result<(Host, Option<u16>), String> {
+    let s = s.trim();
+    if let Ok(host) = s.parse() {
+        return Ok((host, None));
+    }
+    let (host, port) = match s.strip_prefix('[') {
+        Some(rest) => {
+            let (host, port) = rest.split_once("]:").ok_or_else(|| format!("'{s}' is not an address"))?;
+            (format!("[{host}]"), port)
+        }
+        None => {
+            let (host, port) = s.rsplit_once(':').ok_or_else(|| format!("'{s}' is not an address"))?;
+            (host.to_string(), port)
+        }
+    };
+    let port = port.parse().map_err(|_| format!("'{port}' is not a port"))?;
+    Ok((host.parse()?, Some(port)))
+}
+
+/// Base URL of an HTTP server at `ip`. IPv6 addresses are bracketed and
+/// never carry a zone, which would mean nothing to the other end.
+///
+/// ```
+/// assert_eq!(localcast::net::http_base("::1".parse().unwrap(), 8000), "http://[::1]:8000");
+/// ```
+pub fn http_base(ip: IpAddr, port: u16) -> String {
+    format!("http://{}", SocketAddr::new(ip, port))
+}
+
+/// Add the zone of the interface a device answered on to a description URL
+/// with a link-local IPv6 host, which can't be reached without one.
+///
+/// ```
+/// use localcast::net::scoped_location;
+///
+/// let location = "http://[fe80::2]:49152/description.xml";
+/// assert_eq!(scoped_location(location, Some("eth0")), "http://[fe80::2%eth0]:49152/description.xml");
+/// assert_eq!(scoped_location("http://[::1]:49152/", Some("lo")), "http://[::1]:49152/");
+/// ```
+pub fn scoped_location(location: &str, interface: Option<&str>) -> String {
+    let Some(interface) = interface else {
+        return location.to_string();
+    };
+    let Some(host) = location.parse::<http02::Uri>().ok().and_then(|uri| uri.host().map(str::to_string)) else {
+        return location.to_string();
+    };
+    match host.parse::<Host>() {
+        Ok(Host {
+            ip: IpAddr::V6(ip),
+            zone: None,
+        }) if ip.is_unicast_link_local() => location.replacen(&host, &format!("[{ip}%{interface}]"), 1),
+        _ => location.to_string(),
+    }
+}
+
+/// The interface whose address the kernel would send from to reach `target`.
+pub fn interface_for(target: &str) -> Option<Interface> {
+    let local = local_ip_for(target).ok()?;
+    interfaces().ok()?.into_iter().find(|i| i.addr == local)
+}
+
+/// The local address to give `target` for reaching back to us, e.g. in media
+/// URLs. With a `filter`, the address comes from a matching interface,
+/// preferring the route's own and then `interface` (the one the device was
+/// found on); otherwise it is the address the route to `target` uses.
+pub fn advertised_ip(
+    target: &str,
+    filter: Option<&InterfaceFilter>,
+    interface: Option<&str>,
+) -> Result<IpAddr, AppError> {
+    let routed = local_ip_for(target);
+    let Some(filter) = filter else {
+        return routed;
+    };
+    let routed = routed.ok();
+    let same_family = |addr: &IpAddr| routed.map_or(addr.is_ipv4(), |r| r.is_ipv4() == addr.is_ipv4());
+    let eligible: Vec<Interface> = eligible_interfaces(Some(filter))?
+        .into_iter()
+        .filter(|i| same_family(&i.addr))
+        .collect();
+    eligible
+        .iter()
+        .find(|i| Some(i.addr) == routed)
+        .or_else(|| eligible.iter().find(|i| Some(i.name.as_str()) == interface))
+        .or_else(|| eligible.first())
+        .map(|i| i.addr)
+        .ok_or_else(|| AppError::NetworkError(format!("no address on '{filter}' can reach {target}")))
+}
+
+/// Determine the local IP that can reach a given target IP by
+/// connecting a UDP socket (no actual traffic is sent). The target is an
+/// address as in a URL, optionally with a port: `192.168.1.5`,
+/// `[fe80::1%eth0]:8000`, `::1`.
+///
+/// ```
+/// use localcast::net::local_ip_for;
+///
+/// assert_eq!(local_ip_for("::1")?, "::1".parse::<std::net::IpAddr>().unwrap());
+/// assert_eq!(local_ip_for("[::1]:8000")?, "::1".parse::<std::net::IpAddr>().unwrap());
+/// # Ok::<(), localcast::AppError>(())
+/// ```
+pub fn local_ip_for(target: &str) -> Result<IpAddr, AppError> {
+    let (host, port) = parse_host_port(target)
+        .map_err(|e| AppError::NetworkError(format!("Invalid target address {target}: {e}")))?;
+    let target_addr = host.socket_addr(port.unwrap_or(80))?;
+    let unspecified = if target_addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+    let socket = UdpSocket::bind(unspecified).map_err(|e| AppError::NetworkError(e.to_string()))?;
+    socket
+        .connect(target_addr)
+        .map_err(|e| AppError::NetworkError(format!("No route to {target}: {e}")))?;
+    let local_addr = socket.local_addr().map_err(|e| AppError::NetworkError(e.to_string()))?;
+    Ok(local_addr.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_local(zone: &str) -> Host {
+        Host {
+            ip: "fe80::1".parse().unwrap(),
+            zone: Some(zone.into()),
+        }
+    }
+
+    #[test]
+    fn host_and_port_with_zone() {
+        assert_eq!(parse_host_port("[fe80::1%eth0]:80"), Ok((link_local("eth0"), Some(80))));
+        assert_eq!(parse_host_port("[fe80::1%25eth0]:80"), Ok((link_local("eth0"), Some(80))));
+        assert_eq!(parse_host_port("fe80::1%eth0"), Ok((link_local("eth0"), None)));
+        assert_eq!(parse_host_port(" [fe80::1%3] "), Ok((link_local("3"), None)));
+    }
+
+    #[test]
+    fn host_and_port_without_zone() {
+        let loopback = Host {
+            ip: "::1".parse().unwrap(),
+            zone: None,
+        };
+        assert_eq!(parse_host_port("[::1]:8000"), Ok((loopback.clone(), Some(8000))));
+        assert_eq!(parse_host_port("::1"), Ok((loopback, None)));
+        let (host, port) = parse_host_port("192.168.1.5:8000").unwrap();
+        assert_eq!((host.to_string(), port), ("192.168.1.5".to_string(), Some(8000)));
+    }
+
+    #[test]
+    fn malformed_hosts_are_refused() {
+        for s in ["[::1]8000", "[::1]:port", "::1]:80", "[192.168.1.5]:80", "192.168.1.5%eth0", "[fe80::1%]:80", "host name"] {
+            assert!(parse_host_port(s).is_err(), "{s} was accepted");
+        }
+    }
+
+    #[test]
+    fn urls_bracket_ipv6_hosts() {
+        assert_eq!(http_base("::1".parse().unwrap(), 8000), "http://[::1]:8000");
+        assert_eq!(http_base("192.168.1.5".parse().unwrap(), 8000), "http://192.168.1.5:8000");
+        assert_eq!(link_local("eth0").to_string(), "[fe80::1%eth0]");
+        assert_eq!(crate::kodi::device_url(&link_local("eth0"), 8080), "kodi://[fe80::1%eth0]:8080");
+    }
+
+    #[test]
+    fn escaped_zones_in_urls() {
+        // RFC 6874 escapes the % in URLs; the zone is the same either way
+        let host: Host = "[fe80::1%25eth0]".parse().unwrap();
+        assert_eq!(host, link_local("eth0"));
+        assert_eq!("[fe80::1%eth0]".parse::<Host>().unwrap(), host);
+        // Zones that merely start with 25 outside brackets are left alone
+        assert_eq!("fe80::1%25".parse::<Host>().unwrap(), link_local("25"));
+
+        let location = "http://[fe80::2]:49152/description.xml";
+        assert_eq!(scoped_location(location, Some("eth0")), "http://[fe80::2%eth0]:49152/description.xml");
+        assert_eq!(scoped_location(location, None), location);
+        assert_eq!(
+            scoped_location("http://192.168.1.5:49152/", Some("eth0")),
+            "http://192.168.1.5:49152/"
+        );
+    }
+
+    #[test]
+    fn numeric_zones_are_scope_ids() {
+        let addr = link_local("3").socket_addr(80).unwrap();
+        assert!(matches!(addr, SocketAddr::V6(addr) if addr.scope_id() == 3));
+        assert!(link_local("no-such-interface").socket_addr(80).is_err());
+    }
+
+    #[test]
+    fn subnets() {
+        let filter: InterfaceFilter = "fd00::/8".parse().unwrap();
+        let interface = |addr: &str| Interface {
+            name: "eth0".into(),
+            addr: addr.parse().unwrap(),
+        };
+        assert!(filter.matches(&interface("fd12::1")));
+        assert!(!filter.matches(&interface("fe80::1")));
+        assert!(!filter.matches(&interface("10.0.0.1")));
+        assert!("::1/129".parse::<InterfaceFilter>().is_err());
+    }
+}
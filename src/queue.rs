@@ -0,0 +1,508 @@
+use std::path::{Path, PathBuf};
+
+use crate::dlna::types::{PlayMode, RendererCapabilities};
+use crate::error::AppError;
+use crate::renderer::{Media, Renderer};
+
+/// File extensions localcast will serve.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["mp4", "mkv", "avi", "webm"];
+
+/// Check that a path is an existing file with a supported extension and
+/// return its canonical form.
+pub fn validate_media_file(file: &Path) -> Result<PathBuf, AppError> {
+    let file_path = file
+        .canonicalize()
+        .map_err(|_| AppError::FileNotFound(file.display().to_string()))?;
+    if !file_path.is_file() {
+        return Err(AppError::FileNotFound(format!("{} is not a file", file_path.display())));
+    }
+
+    let ext = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    if !SUPPORTED_EXTENSIONS.contains(&ext.as_str()) {
+        return Err(AppError::UnsupportedFormat(format!(".{ext}")));
+    }
+    Ok(file_path)
+}
+
+/// Find `.srt` files next to a video: `movie.srt` and `movie.<lang>.srt`.
+/// Returns each file with its language tag, if it has one.
+pub fn find_subtitles(video: &Path) -> Vec<(Option<String>, PathBuf)> {
+    let (Some(dir), Some(stem)) = (video.parent(), video.file_stem().and_then(|s| s.to_str())) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<(Option<String>, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let rest = name.strip_prefix(stem)?.strip_prefix('.')?;
+            let (language, ext) = match rest.rsplit_once('.') {
+                Some((language, ext)) if !language.contains('.') => (Some(language.to_string()), ext),
+                Some(_) => return None,
+                None => (None, rest),
+            };
+            ext.eq_ignore_ascii_case("srt").then(|| (language, entry.path()))
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// A sidecar subtitle file registered with the media server.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Subtitle {
+    /// Language tag from the file name (`movie.en.srt`), if any.
+    pub language: Option<String>,
+    pub serve_path: String,
+}
+
+/// A file in the play queue, registered with the media server.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct QueueItem {
+    pub path: PathBuf,
+    pub file_name: String,
+    pub mime_type: String,
+    pub file_size: u64,
+    pub serve_path: String,
+    /// Sidecar subtitles found next to the file.
+    pub subtitles: Vec<Subtitle>,
+    /// Index into `subtitles` of the one sent to the renderer.
+    pub subtitle: Option<usize>,
+}
+
+impl QueueItem {
+    pub fn new(path: &Path, file_size: u64, serve_path: String) -> Self {
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let mime_type = mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string();
+        Self {
+            path: path.to_path_buf(),
+            file_name,
+            mime_type,
+            file_size,
+            serve_path,
+            subtitles: Vec::new(),
+            subtitle: None,
+        }
+    }
+
+    /// Full URL of this item given the media server base URL (`http://ip:port`).
+    pub fn media_url(&self, media_base: &str) -> String {
+        format!("{media_base}{}", self.serve_path)
+    }
+
+    /// Full URL of the subtitles sent with this item, if any.
+    pub fn subtitle_url(&self, media_base: &str) -> Option<String> {
+        let subtitle = self.subtitles.get(self.subtitle?)?;
+        Some(format!("{media_base}{}", subtitle.serve_path))
+    }
+
+    /// The item as handed to a renderer, served from `media_base`.
+    pub fn media(&self, media_base: &str) -> Media {
+        Media::new(self.media_url(media_base), &self.file_name, &self.mime_type, self.file_size)
+            .with_subtitles(self.subtitle_url(media_base))
+    }
+
+    /// Pick the subtitles for `language`: an exact match, else the untagged
+    /// file. Without a preference the untagged file wins, else the first one.
+    pub fn select_subtitle(&mut self, language: Option<&str>) {
+        let untagged = self.subtitles.iter().position(|s| s.language.is_none());
+        self.subtitle = match language {
+            Some(language) => self
+                .subtitles
+                .iter()
+                .position(|s| s.language.as_deref().is_some_and(|l| l.eq_ignore_ascii_case(language)))
+                .or(untagged),
+            None => untagged.or((!self.subtitles.is_empty()).then_some(0)),
+        };
+    }
+}
+
+/// Ordered list of items to cast, with a cursor on the one currently loaded.
+///
+/// Gapless playback works by handing the renderer the next item with
+/// `SetNextAVTransportURI` once the current one has started, then watching
+/// `TrackURI` in `GetPositionInfo` to notice when the renderer switched.
+///
+/// Repeat and shuffle are emulated here by choosing which item follows the
+/// current one. Renderers only ever know the current and next item, so their
+/// own play mode is only used when the queue holds a single item.
+#[derive(Debug, Clone, Default)]
+pub struct PlayQueue {
+    items: Vec<QueueItem>,
+    current: usize,
+    play_mode: PlayMode,
+    /// Play order in shuffle mode, as indices into `items`.
+    shuffle_order: Vec<usize>,
+    /// Index of the item handed to the renderer with SetNextAVTransportURI.
+    preloaded: Option<usize>,
+    /// Whether the renderer accepts SetNextAVTransportURI; None until tried.
+    gapless: Option<bool>,
+    /// Preferred subtitle language of the selected renderer.
+    subtitle_language: Option<String>,
+}
+
+impl PlayQueue {
+    pub fn items(&self) -> &[QueueItem] {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        (self.current < self.items.len()).then_some(self.current)
+    }
+
+    pub fn current(&self) -> Option<&QueueItem> {
+        self.items.get(self.current)
+    }
+
+    pub fn play_mode(&self) -> PlayMode {
+        self.play_mode
+    }
+
+    pub fn set_play_mode(&mut self, mode: PlayMode) {
+        self.play_mode = mode;
+        self.reshuffle();
+        self.preloaded = None;
+    }
+
+    /// The play mode to set on the renderer itself. With more than one item
+    /// the renderer must play straight through and the mode is emulated here.
+    pub fn native_play_mode(&self) -> PlayMode {
+        if self.items.len() <= 1 {
+            self.play_mode
+        } else {
+            PlayMode::Normal
+        }
+    }
+
+    /// Set what the newly selected renderer's capabilities and quirks say
+    /// about SetNextAVTransportURI, forgetting what was learned about the
+    /// previous one. Preloading is skipped if they rule it out.
+    pub fn set_gapless_supported(&mut self, supported: bool) {
+        self.gapless = if supported { None } else { Some(false) };
+        self.preloaded = None;
+    }
+
+    /// Choose each item's subtitles by `language` (see [`QueueItem::select_subtitle`]).
+    pub fn set_subtitle_language(&mut self, language: Option<String>) {
+        for item in &mut self.items {
+            item.select_subtitle(language.as_deref());
+        }
+        self.subtitle_language = language;
+        self.preloaded = None;
+    }
+
+    pub fn push(&mut self, mut item: QueueItem) {
+        item.select_subtitle(self.subtitle_language.as_deref());
+        self.items.push(item);
+        self.reshuffle();
+        self.preloaded = None;
+    }
+
+    /// Remove the item at `index`, keeping the cursor on the same item where possible.
+    pub fn remove(&mut self, index: usize) -> Option<QueueItem> {
+        if index >= self.items.len() {
+            return None;
+        }
+        let item = self.items.remove(index);
+        if index < self.current {
+            self.current -= 1;
+        }
+        self.reshuffle();
+        self.preloaded = None;
+        Some(item)
+    }
+
+    /// Move the item at `from` to position `to`.
+    pub fn move_item(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.items.len() || to >= self.items.len() {
+            return false;
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        if from == self.current {
+            self.current = to;
+        } else if from < self.current && to >= self.current {
+            self.current -= 1;
+        } else if from > self.current && to <= self.current {
+            self.current += 1;
+        }
+        self.reshuffle();
+        self.preloaded = None;
+        true
+    }
+
+    /// Remove every item, returning them so their serve paths can be released.
+    pub fn clear(&mut self) -> Vec<QueueItem> {
+        self.current = 0;
+        self.shuffle_order.clear();
+        self.preloaded = None;
+        std::mem::take(&mut self.items)
+    }
+
+    /// Move the cursor to `index`. Returns the item now current.
+    pub fn jump(&mut self, index: usize) -> Option<&QueueItem> {
+        if index >= self.items.len() {
+            return None;
+        }
+        self.current = index;
+        self.preloaded = None;
+        self.items.get(index)
+    }
+
+    /// Move the cursor to the next item when the user skips forward.
+    pub fn advance(&mut self) -> Option<&QueueItem> {
+        self.following(false).and_then(|i| self.jump(i))
+    }
+
+    /// Move the cursor to the item that plays once the current one ended.
+    /// In repeat-one mode this is the current item again.
+    pub fn auto_advance(&mut self) -> Option<&QueueItem> {
+        self.following(true).and_then(|i| self.jump(i))
+    }
+
+    /// Move the cursor to the previous item, if any.
+    pub fn previous(&mut self) -> Option<&QueueItem> {
+        let index = match self.play_mode {
+            PlayMode::Shuffle => {
+                let pos = self.shuffle_position()?;
+                *self.shuffle_order.get(pos.checked_sub(1)?)?
+            }
+            PlayMode::RepeatAll if self.current == 0 => self.items.len().checked_sub(1)?,
+            _ => self.current.checked_sub(1)?,
+        };
+        self.jump(index)
+    }
+
+    /// Index of the item after the current one. `auto` is true when the
+    /// current item ended by itself rather than being skipped by the user.
+    fn following(&self, auto: bool) -> Option<usize> {
+        if self.items.is_empty() {
+            return None;
+        }
+        match self.play_mode {
+            PlayMode::RepeatOne if auto => Some(self.current),
+            PlayMode::RepeatAll => Some((self.current + 1) % self.items.len()),
+            PlayMode::Shuffle => {
+                let pos = self.shuffle_position()?;
+                self.shuffle_order.get(pos + 1).copied()
+            }
+            _ => (self.current + 1 < self.items.len()).then_some(self.current + 1),
+        }
+    }
+
+    fn shuffle_position(&self) -> Option<usize> {
+        self.shuffle_order.iter().position(|&i| i == self.current)
+    }
+
+    /// Build a new random play order that starts with the current item.
+    fn reshuffle(&mut self) {
+        self.shuffle_order.clear();
+        if self.play_mode != PlayMode::Shuffle || self.items.is_empty() {
+            return;
+        }
+        let mut rest: Vec<usize> = (0..self.items.len()).filter(|&i| i != self.current).collect();
+        // Fisher-Yates with a clock-seeded xorshift; good enough for a playlist
+        let mut seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15)
+            | 1;
+        for i in (1..rest.len()).rev() {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            rest.swap(i, (seed % (i as u64 + 1)) as usize);
+        }
+        self.shuffle_order.push(self.current.min(self.items.len() - 1));
+        self.shuffle_order.extend(rest);
+    }
+
+    /// The item that should be handed to the renderer with SetNextAVTransportURI,
+    /// or None if there is nothing to preload or the renderer doesn't support it.
+    /// Repeating the current item is left to the stop-then-set fallback, since a
+    /// preload of the same URI can't be told apart from the current track.
+    pub fn next_to_preload(&self) -> Option<QueueItem> {
+        if self.gapless == Some(false) || self.preloaded.is_some() {
+            return None;
+        }
+        let next = self.following(true)?;
+        if next == self.current {
+            return None;
+        }
+        self.items.get(next).cloned()
+    }
+
+    /// Record the outcome of a SetNextAVTransportURI attempt for `serve_path`.
+    /// Only an `Unsupported` answer stops later attempts; a failed one is
+    /// retried with the next item.
+    pub fn mark_preloaded(&mut self, serve_path: &str, outcome: Preload) {
+        match outcome {
+            Preload::Accepted => {
+                // Ignore results for an item that is no longer next.
+                if let Some(next) = self.following(true) {
+                    if self.items[next].serve_path == serve_path {
+                        self.preloaded = Some(next);
+                    }
+                }
+                self.gapless = Some(true);
+            }
+            Preload::Unsupported => self.gapless = Some(false),
+            Preload::Failed => {}
+        }
+    }
+
+    /// Forget the preloaded item, e.g. after the renderer was given a new URI.
+    pub fn reset_preload(&mut self) {
+        self.preloaded = None;
+    }
+
+    /// Check the renderer's current track URI. If it switched to the preloaded
+    /// item, advance the cursor and return true.
+    pub fn on_track_uri(&mut self, track_uri: &str) -> bool {
+        let Some(next) = self.preloaded else {
+            return false;
+        };
+        let switched = !track_uri.is_empty()
+            && self
+                .items
+                .get(next)
+                .is_some_and(|i| track_uri.ends_with(i.serve_path.as_str()));
+        if switched {
+            self.current = next;
+            self.preloaded = None;
+        }
+        switched
+    }
+}
+
+/// Load `item` on the renderer and start playing it.
+pub async fn start_item(renderer: &dyn Renderer, item: &QueueItem, media_base: &str) -> Result<(), AppError> {
+    renderer.load(&item.media(media_base)).await?;
+    renderer.play("1").await
+}
+
+/// Switch a renderer that is already playing to `item`: stop, load it, play.
+/// This is the fallback for renderers without gapless playback.
+pub async fn switch_to_item(renderer: &dyn Renderer, item: &QueueItem, media_base: &str) -> Result<(), AppError> {
+    if let Err(e) = renderer.stop().await {
+        tracing::debug!("Stop before switching item failed: {e}");
+    }
+    start_item(renderer, item, media_base).await
+}
+
+/// How a renderer answered a request to preload the next item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preload {
+    Accepted,
+    /// The renderer can't preload at all.
+    Unsupported,
+    /// This attempt failed (timeout, network error, fault), which says
+    /// nothing about the next one.
+    Failed,
+}
+
+/// Hand `item` to the renderer as the next track.
+pub async fn preload_item(renderer: &dyn Renderer, item: &QueueItem, media_base: &str) -> Preload {
+    match renderer.load_next(&item.media(media_base)).await {
+        Ok(()) => {
+            tracing::info!("Preloaded next item: {}", item.file_name);
+            Preload::Accepted
+        }
+        Err(AppError::Unsupported(e)) => {
+            tracing::info!("Gapless playback not supported, falling back to stop-then-set: {e}");
+            Preload::Unsupported
+        }
+        Err(e) => {
+            tracing::info!("Preloading the next item failed, falling back to stop-then-set: {e}");
+            Preload::Failed
+        }
+    }
+}
+
+/// Set `wanted` (usually [`PlayQueue::native_play_mode`]) on the renderer.
+/// Returns the mode the renderer itself runs in; anything else is emulated
+/// by the queue.
+pub async fn apply_play_mode(renderer: &dyn Renderer, wanted: PlayMode, caps: &RendererCapabilities) -> PlayMode {
+    let mode = if caps.supports_play_mode(wanted) { wanted } else { PlayMode::Normal };
+    if !caps.supports_action("SetPlayMode") {
+        return PlayMode::Normal;
+    }
+
+    if let Err(e) = renderer.set_play_mode(mode).await {
+        tracing::info!("SetPlayMode {} failed, emulating locally: {e}", mode.as_upnp());
+        return PlayMode::Normal;
+    }
+
+    // Some renderers accept SetPlayMode but ignore it; trust what they report.
+    match renderer.play_mode().await {
+        Ok(actual) => {
+            if actual != mode {
+                tracing::info!(
+                    "Renderer reports play mode {} after setting {}",
+                    actual.as_upnp(),
+                    mode.as_upnp()
+                );
+            }
+            actual
+        }
+        Err(_) => mode,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue() -> PlayQueue {
+        let mut queue = PlayQueue::default();
+        for name in ["a.mp4", "b.mp4", "c.mp4"] {
+            queue.push(QueueItem::new(Path::new(name), 1, format!("/media/{name}")));
+        }
+        queue
+    }
+
+    #[test]
+    fn failed_preload_is_retried() {
+        let mut queue = queue();
+        let next = queue.next_to_preload().unwrap();
+        queue.mark_preloaded(&next.serve_path, Preload::Failed);
+        assert_eq!(queue.next_to_preload().map(|i| i.serve_path), Some(next.serve_path));
+    }
+
+    #[test]
+    fn unsupported_preload_is_forgotten_with_the_device() {
+        let mut queue = queue();
+        let next = queue.next_to_preload().unwrap();
+        queue.mark_preloaded(&next.serve_path, Preload::Unsupported);
+        assert!(queue.next_to_preload().is_none());
+
+        queue.set_gapless_supported(true);
+        assert!(queue.next_to_preload().is_some());
+        queue.set_gapless_supported(false);
+        assert!(queue.next_to_preload().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::dlna::types::PlaybackState;
+use crate::error::AppError;
+use crate::queue::{self, QueueItem};
+use crate::renderer::Renderer;
+
+/// How hard to try to resume playback after the renderer drops it mid-item.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct RecoveryPolicy {
+    /// Attempts per drop; 0 disables recovery.
+    pub max_attempts: u32,
+    /// Delay before the first attempt; doubled after each failed one.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+    /// How long to wait for PLAYING before seeking back.
+    pub settle_timeout: Duration,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(30),
+            settle_timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+impl RecoveryPolicy {
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 0
+    }
+
+    /// Delay before the given attempt (1-based).
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Restart `item` on the renderer and seek back to `resume_secs` (if it can
+/// seek), retrying with backoff. `on_attempt` is called before each attempt.
+/// Returns the attempt that succeeded.
+pub async fn resume_playback(
+    renderer: &dyn Renderer,
+    item: &QueueItem,
+    media_base: &str,
+    resume_secs: u64,
+    policy: &RecoveryPolicy,
+    mut on_attempt: impl FnMut(u32),
+) -> Result<u32, AppError> {
+    let mut last_error = AppError::DlnaAction("Recovery is disabled".into());
+    for attempt in 1..=policy.max_attempts {
+        tokio::time::sleep(policy.backoff(attempt)).await;
+        on_attempt(attempt);
+        match try_resume(renderer, item, media_base, resume_secs, policy).await {
+            Ok(()) => return Ok(attempt),
+            Err(e) => {
+                tracing::warn!(
+                    "Recovery attempt {attempt}/{} for {} failed: {e}",
+                    policy.max_attempts,
+                    item.file_name
+                );
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// One recovery attempt: load, play, wait for PLAYING, seek.
+async fn try_resume(
+    renderer: &dyn Renderer,
+    item: &QueueItem,
+    media_base: &str,
+    resume_secs: u64,
+    policy: &RecoveryPolicy,
+) -> Result<(), AppError> {
+    queue::start_item(renderer, item, media_base).await?;
+
+    // Most renderers reject Seek until they are actually playing
+    let deadline = Instant::now() + policy.settle_timeout;
+    loop {
+        if let Ok(PlaybackState::Playing) = renderer.status().await {
+            break;
+        }
+        if Instant::now() >= deadline {
+            return Err(AppError::DlnaAction(format!(
+                "Renderer did not resume playing within {}s",
+                policy.settle_timeout.as_secs()
+            )));
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    if resume_secs > 0 {
+        match renderer.seek(resume_secs).await {
+            // Better restarted than not playing at all
+            Err(AppError::Unsupported(what)) => {
+                tracing::warn!("Restarted {} from the beginning: {what} is not supported", item.file_name);
+            }
+            result => result?,
+        }
+    }
+    Ok(())
+}
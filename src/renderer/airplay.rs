@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use super::{Device, Media, Renderer};
+use crate::airplay;
+use crate::airplay::client::AirPlayClient;
+use crate::airplay::types::PlaybackInfo;
+use crate::dlna::client::SoapConfig;
+use crate::dlna::types::{
+    MediaInfo, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+
+/// How long `/playback-info` answers queries. Polls ask for position, state
+/// and allowed actions back to back; one request serves them all.
+const SNAPSHOT_TTL: Duration = Duration::from_millis(250);
+
+/// How long a video may take to load before a device still reporting
+/// nothing counts as stopped.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An AirPlay video receiver, such as an Apple TV.
+///
+/// AirPlay has no way to load a video without playing it, so [`load`]
+/// only remembers the media and [`play`] sends `/play`.
+///
+/// [`load`]: Renderer::load
+/// [`play`]: Renderer::play
+pub struct AirPlayRenderer {
+    device: Device,
+    /// The device URL; AirPlay has no separate control endpoint.
+    control_url: String,
+    client: AirPlayClient,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Media loaded and not started yet.
+    pending: Option<Media>,
+    /// The media last started with `/play`.
+    started: Option<Started>,
+    snapshot: Option<(Instant, PlaybackInfo)>,
+}
+
+struct Started {
+    media: Media,
+    at: Instant,
+    /// Until the device reports the video loaded, or it is stopped.
+    loading: bool,
+}
+
+impl AirPlayRenderer {
+    /// Connect to `device` and check that it answers as an AirPlay receiver.
+    pub async fn connect(device: Device, config: SoapConfig) -> Result<Self, AppError> {
+        let client = AirPlayClient::new(
+            airplay::base_url(&device.device_url)?,
+            config.connect_timeout,
+            config.request_timeout,
+        );
+        client.server_info().await?;
+        Ok(Self {
+            control_url: device.device_url.to_string(),
+            device,
+            client,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// What the device is playing, reusing a reply from the last
+    /// [`SNAPSHOT_TTL`].
+    async fn snapshot(&self) -> Result<PlaybackInfo, AppError> {
+        let mut state = self.state.lock().await;
+        if let Some((taken, info)) = &state.snapshot {
+            if taken.elapsed() < SNAPSHOT_TTL {
+                return Ok(info.clone());
+            }
+        }
+        let info = self.client.playback_info().await?;
+        if info.is_loaded() {
+            if let Some(started) = &mut state.started {
+                started.loading = false;
+            }
+        }
+        state.snapshot = Some((Instant::now(), info.clone()));
+        Ok(info)
+    }
+}
+
+#[async_trait]
+impl Renderer for AirPlayRenderer {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn control_url(&self) -> &str {
+        &self.control_url
+    }
+
+    /// What AirPlay video does, by AVTransport action names.
+    async fn capabilities(&self) -> Result<RendererCapabilities, AppError> {
+        let actions = ["SetAVTransportURI", "Play", "Pause", "Stop", "Seek"];
+        Ok(RendererCapabilities {
+            actions: actions.into_iter().map(String::from).collect(),
+            play_modes: Vec::new(),
+            play_speeds: vec!["1".into()],
+        })
+    }
+
+    async fn load(&self, media: &Media) -> Result<(), AppError> {
+        if let Some(subtitles) = &media.subtitle_url {
+            tracing::debug!("Not sending subtitles {subtitles}: AirPlay video takes none");
+        }
+        self.state.lock().await.pending = Some(media.clone());
+        Ok(())
+    }
+
+    /// Starts loaded media from the beginning, or resumes what is playing.
+    async fn play(&self, speed: &str) -> Result<(), AppError> {
+        if speed != "1" {
+            return Err(AppError::Unsupported(format!("play speed {speed}")));
+        }
+        let mut state = self.state.lock().await;
+        state.snapshot = None;
+        match state.pending.take() {
+            Some(media) => {
+                self.client.play(&media.url, 0.0).await?;
+                state.started = Some(Started {
+                    media,
+                    at: Instant::now(),
+                    loading: true,
+                });
+                Ok(())
+            }
+            None => self.client.rate(1.0).await,
+        }
+    }
+
+    async fn pause(&self) -> Result<(), AppError> {
+        self.state.lock().await.snapshot = None;
+        self.client.rate(0.0).await
+    }
+
+    async fn stop(&self) -> Result<(), AppError> {
+        let mut state = self.state.lock().await;
+        state.snapshot = None;
+        state.pending = None;
+        if let Some(started) = &mut state.started {
+            started.loading = false;
+        }
+        self.client.stop().await
+    }
+
+    async fn seek(&self, target_secs: u64) -> Result<(), AppError> {
+        self.state.lock().await.snapshot = None;
+        self.client.scrub(target_secs as f64).await
+    }
+
+    async fn volume(&self) -> Result<u32, AppError> {
+        Err(AppError::Unsupported("volume over AirPlay video".into()))
+    }
+
+    async fn set_volume(&self, _volume: u32) -> Result<(), AppError> {
+        Err(AppError::Unsupported("volume over AirPlay video".into()))
+    }
+
+    /// A video still loading shows as transitioning for up to
+    /// [`LOAD_TIMEOUT`], since the device can't tell it from no video.
+    async fn status(&self) -> Result<PlaybackState, AppError> {
+        let info = self.snapshot().await?;
+        if info.is_loaded() {
+            return Ok(info.playback_state());
+        }
+        let state = self.state.lock().await;
+        Ok(match &state.started {
+            _ if state.pending.is_some() => PlaybackState::Stopped,
+            Some(started) if started.loading && started.at.elapsed() < LOAD_TIMEOUT => {
+                PlaybackState::Transitioning
+            }
+            Some(_) => PlaybackState::Stopped,
+            None => PlaybackState::NoMediaPresent,
+        })
+    }
+
+    async fn position(&self) -> Result<PositionInfo, AppError> {
+        let info = self.snapshot().await?;
+        if !info.is_loaded() {
+            return Ok(PositionInfo::default());
+        }
+        let state = self.state.lock().await;
+        Ok(PositionInfo {
+            elapsed_secs: info.elapsed_secs(),
+            duration_secs: info.duration_secs(),
+            track_uri: state.started.as_ref().map(|s| s.media.url.clone()).unwrap_or_default(),
+        })
+    }
+
+    async fn allowed_actions(&self) -> Result<TransportActions, AppError> {
+        let info = self.snapshot().await?;
+        let actions: &[&str] = if info.is_loaded() {
+            &["Play", "Pause", "Stop", "Seek"]
+        } else if self.state.lock().await.pending.is_some() {
+            &["Play", "Stop"]
+        } else {
+            &[]
+        };
+        Ok(TransportActions(actions.iter().map(|a| a.to_string()).collect()))
+    }
+
+    /// Only what we started: the device doesn't say what it is playing.
+    async fn media_info(&self) -> Result<MediaInfo, AppError> {
+        let info = self.snapshot().await?;
+        let state = self.state.lock().await;
+        match &state.started {
+            Some(started) if info.is_loaded() => Ok(MediaInfo {
+                nr_tracks: 1,
+                media_duration_secs: info.duration_secs(),
+                current_uri: started.media.url.clone(),
+                current_uri_metadata: String::new(),
+                title: Some(started.media.title.clone()),
+            }),
+            _ => Ok(MediaInfo::default()),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use tokio::sync::OnceCell;
+
+use super::{Device, Media, Renderer};
+use crate::dlna::client::SoapClient;
+use crate::dlna::transport::{self, RenderingControl};
+use crate::dlna::types::{
+    MediaInfo, PlayMode, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+
+/// What discovery learned about a DLNA renderer that other protocols have
+/// no use for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DlnaDevice {
+    /// Type of the AVTransport service, with its version, e.g.
+    /// `urn:schemas-upnp-org:service:AVTransport:1`.
+    pub service_type: String,
+}
+
+/// A DLNA renderer, controlled through its AVTransport service and, for
+/// volume, its RenderingControl service.
+pub struct DlnaRenderer {
+    device: Device,
+    client: SoapClient,
+    /// AVTransport control URL.
+    control_url: String,
+    /// Read from the SCPD on first use.
+    capabilities: OnceCell<RendererCapabilities>,
+    /// Resolved on first volume change.
+    rendering_control: OnceCell<RenderingControl>,
+}
+
+impl DlnaRenderer {
+    /// Connect to `device` with `client`, resolving its AVTransport control
+    /// URL from the device description unless `control_url` is already known.
+    pub async fn connect(device: Device, control_url: Option<&str>, client: SoapClient) -> Result<Self, AppError> {
+        let control_url = match control_url {
+            Some(control_url) => {
+                tracing::info!("Using cached control URL for {}: {control_url}", device.friendly_name);
+                control_url.to_string()
+            }
+            None => transport::resolve_control_url(&client, &device).await?,
+        };
+        Ok(Self {
+            device,
+            client,
+            control_url,
+            capabilities: OnceCell::new(),
+            rendering_control: OnceCell::new(),
+        })
+    }
+
+    async fn rendering_control(&self) -> Result<&RenderingControl, AppError> {
+        self.rendering_control
+            .get_or_try_init(|| transport::resolve_rendering_control(&self.client, &self.device))
+            .await
+    }
+}
+
+#[async_trait]
+impl Renderer for DlnaRenderer {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn control_url(&self) -> &str {
+        &self.control_url
+    }
+
+    async fn capabilities(&self) -> Result<RendererCapabilities, AppError> {
+        self.capabilities
+            .get_or_try_init(|| transport::fetch_capabilities(&self.client, &self.device))
+            .await
+            .cloned()
+    }
+
+    async fn protocol_info(&self) -> Result<Vec<String>, AppError> {
+        transport::get_protocol_info(&self.client, &self.device).await
+    }
+
+    async fn load(&self, media: &Media) -> Result<(), AppError> {
+        transport::set_av_transport_uri(&self.client, &self.device, &self.control_url, media).await
+    }
+
+    async fn load_next(&self, media: &Media) -> Result<(), AppError> {
+        transport::set_next_av_transport_uri(&self.client, &self.device, &self.control_url, media).await
+    }
+
+    async fn play(&self, speed: &str) -> Result<(), AppError> {
+        transport::play_at_speed(&self.client, &self.device, &self.control_url, speed).await
+    }
+
+    async fn pause(&self) -> Result<(), AppError> {
+        transport::pause(&self.client, &self.device, &self.control_url).await
+    }
+
+    async fn stop(&self) -> Result<(), AppError> {
+        transport::stop(&self.client, &self.device, &self.control_url).await
+    }
+
+    async fn seek(&self, target_secs: u64) -> Result<(), AppError> {
+        transport::seek(&self.client, &self.device, &self.control_url, target_secs).await
+    }
+
+    async fn volume(&self) -> Result<u32, AppError> {
+        transport::get_volume(&self.client, self.rendering_control().await?).await
+    }
+
+    async fn set_volume(&self, volume: u32) -> Result<(), AppError> {
+        transport::set_volume(&self.client, self.rendering_control().await?, volume).await
+    }
+
+    async fn status(&self) -> Result<PlaybackState, AppError> {
+        transport::get_transport_info(&self.client, &self.device, &self.control_url).await
+    }
+
+    async fn position(&self) -> Result<PositionInfo, AppError> {
+        transport::get_position_info(&self.client, &self.device, &self.control_url).await
+    }
+
+    async fn allowed_actions(&self) -> Result<TransportActions, AppError> {
+        transport::get_current_transport_actions(&self.client, &self.device, &self.control_url).await
+    }
+
+    async fn media_info(&self) -> Result<MediaInfo, AppError> {
+        transport::get_media_info(&self.client, &self.device, &self.control_url).await
+    }
+
+    async fn set_play_mode(&self, mode: PlayMode) -> Result<(), AppError> {
+        transport::set_play_mode(&self.client, &self.device, &self.control_url, mode).await
+    }
+
+    async fn play_mode(&self) -> Result<PlayMode, AppError> {
+        transport::get_transport_settings(&self.client, &self.device, &self.control_url).await
+    }
+}
@@ -0,0 +1,301 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use super::{Device, Media, Renderer};
+use crate::dlna::client::SoapConfig;
+use crate::dlna::types::{
+    MediaInfo, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+use crate::googlecast::client::CastClient;
+use crate::googlecast::types::{MediaInformation, MediaStatus, ReceiverStatus};
+use crate::googlecast::{self, DEFAULT_MEDIA_RECEIVER, NS_MEDIA, NS_RECEIVER, RECEIVER_ID};
+
+/// How long a status snapshot answers queries. Polls ask for position,
+/// state and allowed actions back to back; one round trip serves them all.
+const SNAPSHOT_TTL: Duration = Duration::from_millis(250);
+
+/// A Google Cast device, playing our media in the Default Media Receiver.
+pub struct GoogleCastRenderer {
+    device: Device,
+    /// The device URL; Cast has no separate control endpoint.
+    control_url: String,
+    addr: SocketAddr,
+    /// Timeouts for opening the channel and each request on it.
+    config: SoapConfig,
+    state: Mutex<State>,
+}
+
+/// The channel and what we know of the receiver app, kept across requests.
+#[derive(Default)]
+struct State {
+    client: Option<Arc<CastClient>>,
+    /// Transport of the Default Media Receiver, once connected to it.
+    transport_id: Option<String>,
+    media_session_id: Option<u64>,
+    /// The media last reported loaded; status updates often leave it out.
+    media: Option<MediaInformation>,
+    snapshot: Option<(Instant, Snapshot)>,
+}
+
+/// The receiver's and the media session's status at one moment.
+#[derive(Clone)]
+struct Snapshot {
+    receiver: ReceiverStatus,
+    /// `None` when the Default Media Receiver isn't running or has nothing loaded.
+    media: Option<MediaStatus>,
+}
+
+impl GoogleCastRenderer {
+    /// Connect to `device` and check that it answers as a Cast receiver.
+    pub async fn connect(device: Device, config: SoapConfig) -> Result<Self, AppError> {
+        let addr = googlecast::socket_addr(&device.device_url)?;
+        let renderer = Self {
+            control_url: device.device_url.to_string(),
+            device,
+            addr,
+            config,
+            state: Mutex::new(State::default()),
+        };
+        renderer.snapshot().await?;
+        Ok(renderer)
+    }
+
+    /// The open channel, reconnecting if the last one closed.
+    async fn client(&self, state: &mut State) -> Result<Arc<CastClient>, AppError> {
+        if let Some(client) = state.client.as_ref().filter(|c| !c.is_closed()) {
+            return Ok(client.clone());
+        }
+        let (connect_timeout, request_timeout) = (self.config.connect_timeout, self.config.request_timeout);
+        let client = Arc::new(CastClient::connect(self.addr, connect_timeout, request_timeout).await?);
+        tracing::debug!("Cast channel open to {}", self.device.friendly_name);
+        *state = State {
+            client: Some(client.clone()),
+            ..State::default()
+        };
+        Ok(client)
+    }
+
+    /// Find the Default Media Receiver's transport in `receiver` and connect
+    /// to it if it is new. `None` if the app isn't running.
+    fn join_app(&self, state: &mut State, client: &CastClient, receiver: &ReceiverStatus) -> Result<Option<String>, AppError> {
+        let Some(app) = receiver.app(DEFAULT_MEDIA_RECEIVER) else {
+            state.transport_id = None;
+            state.media_session_id = None;
+            return Ok(None);
+        };
+        if state.transport_id.as_deref() != Some(app.transport_id.as_str()) {
+            client.open(&app.transport_id)?;
+            state.transport_id = Some(app.transport_id.clone());
+            state.media_session_id = None;
+        }
+        Ok(Some(app.transport_id.clone()))
+    }
+
+    /// Current status of the device and of our media, reusing one taken in
+    /// the last [`SNAPSHOT_TTL`].
+    async fn snapshot(&self) -> Result<Snapshot, AppError> {
+        let mut state = self.state.lock().await;
+        if let Some((taken, snapshot)) = &state.snapshot {
+            if taken.elapsed() < SNAPSHOT_TTL {
+                return Ok(snapshot.clone());
+            }
+        }
+        let client = self.client(&mut state).await?;
+        let reply = client
+            .request(RECEIVER_ID, NS_RECEIVER, json!({ "type": "GET_STATUS" }))
+            .await?;
+        let receiver = ReceiverStatus::from_reply(&reply);
+        let media = match self.join_app(&mut state, &client, &receiver)? {
+            Some(transport_id) => {
+                let reply = client
+                    .request(&transport_id, NS_MEDIA, json!({ "type": "GET_STATUS" }))
+                    .await?;
+                MediaStatus::from_reply(&reply)
+            }
+            None => None,
+        };
+        if let Some(status) = &media {
+            state.media_session_id = Some(status.media_session_id);
+            if let Some(info) = &status.media {
+                state.media = Some(info.clone());
+            }
+        }
+        let snapshot = Snapshot { receiver, media };
+        state.snapshot = Some((Instant::now(), snapshot.clone()));
+        Ok(snapshot)
+    }
+
+    /// Send a command to the loaded media session and wait for its status.
+    async fn media_command(&self, mut payload: Value) -> Result<(), AppError> {
+        if self.state.lock().await.media_session_id.is_none() {
+            self.snapshot().await?;
+        }
+        let mut state = self.state.lock().await;
+        let (Some(transport_id), Some(session_id)) = (state.transport_id.clone(), state.media_session_id) else {
+            return Err(AppError::CastAction("nothing is loaded".into()));
+        };
+        let client = self.client(&mut state).await?;
+        payload["mediaSessionId"] = session_id.into();
+        state.snapshot = None;
+        drop(state);
+        client.request(&transport_id, NS_MEDIA, payload).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Renderer for GoogleCastRenderer {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn control_url(&self) -> &str {
+        &self.control_url
+    }
+
+    /// What the Default Media Receiver does, by AVTransport action names.
+    async fn capabilities(&self) -> Result<RendererCapabilities, AppError> {
+        let actions = ["SetAVTransportURI", "Play", "Pause", "Stop", "Seek"];
+        Ok(RendererCapabilities {
+            actions: actions.into_iter().map(String::from).collect(),
+            play_modes: Vec::new(),
+            play_speeds: vec!["1".into()],
+        })
+    }
+
+    /// Launches the Default Media Receiver unless it is already up.
+    async fn load(&self, media: &Media) -> Result<(), AppError> {
+        let mut state = self.state.lock().await;
+        let client = self.client(&mut state).await?;
+        let reply = client
+            .request(RECEIVER_ID, NS_RECEIVER, json!({ "type": "GET_STATUS" }))
+            .await?;
+        let mut receiver = ReceiverStatus::from_reply(&reply);
+        if receiver.app(DEFAULT_MEDIA_RECEIVER).is_none() {
+            tracing::info!("Launching the Default Media Receiver on {}", self.device.friendly_name);
+            let reply = client
+                .request(
+                    RECEIVER_ID,
+                    NS_RECEIVER,
+                    json!({ "type": "LAUNCH", "appId": DEFAULT_MEDIA_RECEIVER }),
+                )
+                .await?;
+            receiver = ReceiverStatus::from_reply(&reply);
+        }
+        let transport_id = self
+            .join_app(&mut state, &client, &receiver)?
+            .ok_or_else(|| AppError::CastAction("the Default Media Receiver did not start".into()))?;
+
+        if let Some(subtitles) = &media.subtitle_url {
+            tracing::debug!("Not sending subtitles {subtitles}: the Default Media Receiver only takes WebVTT");
+        }
+        let load = json!({
+            "type": "LOAD",
+            "media": {
+                "contentId": media.url,
+                "contentType": media.mime_type,
+                "streamType": "BUFFERED",
+                "metadata": { "metadataType": 0, "title": media.title },
+            },
+            "autoplay": false,
+            "currentTime": 0,
+        });
+        state.snapshot = None;
+        let reply = client.request(&transport_id, NS_MEDIA, load).await?;
+        let status = MediaStatus::from_reply(&reply)
+            .ok_or_else(|| AppError::CastAction("LOAD answered without a media session".into()))?;
+        state.media_session_id = Some(status.media_session_id);
+        state.media = status.media;
+        Ok(())
+    }
+
+    async fn play(&self, speed: &str) -> Result<(), AppError> {
+        if speed != "1" {
+            return Err(AppError::Unsupported(format!("play speed {speed}")));
+        }
+        self.media_command(json!({ "type": "PLAY" })).await
+    }
+
+    async fn pause(&self) -> Result<(), AppError> {
+        self.media_command(json!({ "type": "PAUSE" })).await
+    }
+
+    async fn stop(&self) -> Result<(), AppError> {
+        self.media_command(json!({ "type": "STOP" })).await
+    }
+
+    async fn seek(&self, target_secs: u64) -> Result<(), AppError> {
+        self.media_command(json!({ "type": "SEEK", "currentTime": target_secs })).await
+    }
+
+    async fn volume(&self) -> Result<u32, AppError> {
+        Ok(self.snapshot().await?.receiver.volume.percent())
+    }
+
+    async fn set_volume(&self, volume: u32) -> Result<(), AppError> {
+        let mut state = self.state.lock().await;
+        let client = self.client(&mut state).await?;
+        state.snapshot = None;
+        drop(state);
+        let level = f64::from(volume.min(100)) / 100.0;
+        client
+            .request(
+                RECEIVER_ID,
+                NS_RECEIVER,
+                json!({ "type": "SET_VOLUME", "volume": { "level": level } }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<PlaybackState, AppError> {
+        Ok(match self.snapshot().await?.media {
+            Some(status) => status.playback_state(),
+            None => PlaybackState::NoMediaPresent,
+        })
+    }
+
+    async fn position(&self) -> Result<PositionInfo, AppError> {
+        let snapshot = self.snapshot().await?;
+        let Some(status) = snapshot.media else {
+            return Ok(PositionInfo::default());
+        };
+        let known = self.state.lock().await.media.clone();
+        Ok(status.position(known.as_ref()))
+    }
+
+    async fn allowed_actions(&self) -> Result<TransportActions, AppError> {
+        Ok(self
+            .snapshot()
+            .await?
+            .media
+            .map(|status| status.transport_actions())
+            .unwrap_or_default())
+    }
+
+    /// Another app in the foreground counts as foreign media, named after the app.
+    async fn media_info(&self) -> Result<MediaInfo, AppError> {
+        let snapshot = self.snapshot().await?;
+        if let Some(app) = snapshot.receiver.foreground() {
+            if app.app_id != DEFAULT_MEDIA_RECEIVER {
+                return Ok(MediaInfo {
+                    current_uri: format!("cast-app:{}", app.app_id),
+                    title: Some(app.display_name.clone()),
+                    ..MediaInfo::default()
+                });
+            }
+        }
+        if snapshot.media.is_none() {
+            return Ok(MediaInfo::default());
+        }
+        let known = self.state.lock().await.media.clone();
+        Ok(known.map(|media| media.media_info()).unwrap_or_default())
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+
+use super::{Device, Media, Renderer, RendererEvent};
+use crate::dlna::client::SoapConfig;
+use crate::dlna::types::{
+    MediaInfo, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+use crate::kodi;
+use crate::kodi::client::KodiClient;
+use crate::kodi::types::{ActivePlayer, Item, PlayerProperties, Time};
+
+/// How long a playback snapshot answers queries. Polls ask for position,
+/// state and allowed actions back to back; one set of requests serves them all.
+const SNAPSHOT_TTL: Duration = Duration::from_millis(250);
+
+/// How long a video may take to open before Kodi still having no player
+/// counts as stopped.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Kodi instance, driven over JSON-RPC.
+///
+/// `Player.Open` starts playback straight away, so [`load`] only remembers
+/// the media and [`play`] opens it. With Kodi's WebSocket open, its player
+/// notifications are reported as they come, between polls.
+///
+/// [`load`]: Renderer::load
+/// [`play`]: Renderer::play
+pub struct KodiRenderer {
+    device: Device,
+    /// The device URL; requests all go to its `/jsonrpc`.
+    control_url: String,
+    client: KodiClient,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Media loaded and not opened yet.
+    pending: Option<Media>,
+    /// When media was last opened with `Player.Open`.
+    started: Option<Started>,
+    snapshot: Option<(Instant, Option<Playback>)>,
+}
+
+struct Started {
+    at: Instant,
+    /// Until Kodi reports a player for it, or it is stopped.
+    loading: bool,
+}
+
+/// What Kodi's active player is doing.
+#[derive(Clone)]
+struct Playback {
+    player_id: i64,
+    properties: PlayerProperties,
+    item: Item,
+}
+
+#[derive(Deserialize)]
+struct ItemReply {
+    #[serde(default)]
+    item: Item,
+}
+
+#[derive(Deserialize)]
+struct Volume {
+    volume: u32,
+}
+
+impl KodiRenderer {
+    /// Connect to `device` and check that it answers JSON-RPC. Its
+    /// WebSocket is used if it answers too.
+    pub async fn connect(device: Device, config: SoapConfig) -> Result<Self, AppError> {
+        let mut client = KodiClient::new(&device.device_url, config.connect_timeout, config.request_timeout)?;
+        client.call("JSONRPC.Ping", json!({})).await?;
+        let port = kodi::websocket_port(&device.device_url);
+        match client.open_websocket(port, config.connect_timeout).await {
+            Ok(()) => tracing::info!("Talking to {} over its WebSocket", device.friendly_name),
+            Err(e) => tracing::info!("Talking to {} over HTTP, without its WebSocket: {e}", device.friendly_name),
+        }
+        Ok(Self {
+            control_url: device.device_url.to_string(),
+            device,
+            client,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// What Kodi is playing, reusing a reply from the last [`SNAPSHOT_TTL`].
+    /// `None` if no player is active.
+    async fn snapshot(&self) -> Result<Option<Playback>, AppError> {
+        let mut state = self.state.lock().await;
+        if let Some((taken, playback)) = &state.snapshot {
+            if taken.elapsed() < SNAPSHOT_TTL {
+                return Ok(playback.clone());
+            }
+        }
+        let playback = self.playback().await?;
+        if playback.is_some() {
+            if let Some(started) = &mut state.started {
+                started.loading = false;
+            }
+        }
+        state.snapshot = Some((Instant::now(), playback.clone()));
+        Ok(playback)
+    }
+
+    /// Ask Kodi about its video player, or whichever player is active.
+    async fn playback(&self) -> Result<Option<Playback>, AppError> {
+        let players: Vec<ActivePlayer> = self.client.call_as("Player.GetActivePlayers", json!({})).await?;
+        let Some(player) = players.iter().find(|p| p.kind == "video").or(players.first()) else {
+            return Ok(None);
+        };
+        let player_id = player.player_id;
+        let properties = self
+            .client
+            .call_as(
+                "Player.GetProperties",
+                json!({ "playerid": player_id, "properties": ["time", "totaltime", "speed"] }),
+            )
+            .await?;
+        let reply: ItemReply = self
+            .client
+            .call_as("Player.GetItem", json!({ "playerid": player_id, "properties": ["file", "title"] }))
+            .await?;
+        Ok(Some(Playback {
+            player_id,
+            properties,
+            item: reply.item,
+        }))
+    }
+
+    /// Report the player's state and position as soon as Kodi says it
+    /// changed, until `tx` is closed.
+    async fn forward_notifications(self: Arc<Self>, tx: mpsc::Sender<RendererEvent>) {
+        let Some(mut notifications) = self.client.notifications() else {
+            return;
+        };
+        loop {
+            let notification = tokio::select! {
+                notification = notifications.recv() => notification,
+                _ = tx.closed() => break,
+            };
+            match notification {
+                Ok(notification) if notification.method.starts_with("Player.On") => {
+                    tracing::debug!("Kodi says {}", notification.method);
+                    self.state.lock().await.snapshot = None;
+                    if let Ok(state) = self.status().await {
+                        if tx.send(RendererEvent::StateUpdate(state)).await.is_err() {
+                            break;
+                        }
+                    }
+                    if let Ok(position) = self.position().await {
+                        if tx.send(RendererEvent::PositionUpdate(position)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// The active player, for commands that need one.
+    async fn player_id(&self) -> Result<i64, AppError> {
+        self.state.lock().await.snapshot = None;
+        match self.snapshot().await? {
+            Some(playback) => Ok(playback.player_id),
+            None => Err(AppError::KodiAction("nothing is playing".into())),
+        }
+    }
+}
+
+#[async_trait]
+impl Renderer for KodiRenderer {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn control_url(&self) -> &str {
+        &self.control_url
+    }
+
+    /// What Kodi's player does, by AVTransport action names.
+    async fn capabilities(&self) -> Result<RendererCapabilities, AppError> {
+        let actions = ["SetAVTransportURI", "Play", "Pause", "Stop", "Seek"];
+        Ok(RendererCapabilities {
+            actions: actions.into_iter().map(String::from).collect(),
+            play_modes: Vec::new(),
+            play_speeds: vec!["1".into()],
+        })
+    }
+
+    async fn load(&self, media: &Media) -> Result<(), AppError> {
+        if let Some(subtitles) = &media.subtitle_url {
+            tracing::debug!("Not sending subtitles {subtitles}: Player.Open takes none");
+        }
+        self.state.lock().await.pending = Some(media.clone());
+        Ok(())
+    }
+
+    /// Opens loaded media from the beginning, or resumes what is playing.
+    async fn play(&self, speed: &str) -> Result<(), AppError> {
+        if speed != "1" {
+            return Err(AppError::Unsupported(format!("play speed {speed}")));
+        }
+        let pending = {
+            let mut state = self.state.lock().await;
+            state.snapshot = None;
+            state.pending.take()
+        };
+        match pending {
+            Some(media) => {
+                self.client
+                    .call("Player.Open", json!({ "item": { "file": media.url } }))
+                    .await?;
+                let mut state = self.state.lock().await;
+                state.snapshot = None;
+                state.started = Some(Started {
+                    at: Instant::now(),
+                    loading: true,
+                });
+                Ok(())
+            }
+            None => {
+                let player_id = self.player_id().await?;
+                self.client
+                    .call("Player.PlayPause", json!({ "playerid": player_id, "play": true }))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn pause(&self) -> Result<(), AppError> {
+        let player_id = self.player_id().await?;
+        self.client
+            .call("Player.PlayPause", json!({ "playerid": player_id, "play": false }))
+            .await?;
+        self.state.lock().await.snapshot = None;
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), AppError> {
+        {
+            let mut state = self.state.lock().await;
+            state.pending = None;
+            if let Some(started) = &mut state.started {
+                started.loading = false;
+            }
+        }
+        self.state.lock().await.snapshot = None;
+        // Nothing to stop is as good as stopped
+        if let Some(playback) = self.snapshot().await? {
+            self.client
+                .call("Player.Stop", json!({ "playerid": playback.player_id }))
+                .await?;
+        }
+        self.state.lock().await.snapshot = None;
+        Ok(())
+    }
+
+    async fn seek(&self, target_secs: u64) -> Result<(), AppError> {
+        let player_id = self.player_id().await?;
+        self.client
+            .call(
+                "Player.Seek",
+                json!({ "playerid": player_id, "value": { "time": Time::json(target_secs) } }),
+            )
+            .await?;
+        self.state.lock().await.snapshot = None;
+        Ok(())
+    }
+
+    async fn volume(&self) -> Result<u32, AppError> {
+        let reply: Volume = self
+            .client
+            .call_as("Application.GetProperties", json!({ "properties": ["volume"] }))
+            .await?;
+        Ok(reply.volume)
+    }
+
+    async fn set_volume(&self, volume: u32) -> Result<(), AppError> {
+        self.client
+            .call("Application.SetVolume", json!({ "volume": volume.min(100) }))
+            .await?;
+        Ok(())
+    }
+
+    /// A video still opening shows as transitioning for up to
+    /// [`LOAD_TIMEOUT`], since Kodi has no player for it until it starts.
+    async fn status(&self) -> Result<PlaybackState, AppError> {
+        if let Some(playback) = self.snapshot().await? {
+            return Ok(playback.properties.playback_state());
+        }
+        let state = self.state.lock().await;
+        Ok(match &state.started {
+            _ if state.pending.is_some() => PlaybackState::Stopped,
+            Some(started) if started.loading && started.at.elapsed() < LOAD_TIMEOUT => {
+                PlaybackState::Transitioning
+            }
+            Some(_) => PlaybackState::Stopped,
+            None => PlaybackState::NoMediaPresent,
+        })
+    }
+
+    async fn position(&self) -> Result<PositionInfo, AppError> {
+        let Some(playback) = self.snapshot().await? else {
+            return Ok(PositionInfo::default());
+        };
+        Ok(PositionInfo {
+            elapsed_secs: playback.properties.time.secs(),
+            duration_secs: playback.properties.totaltime.secs(),
+            track_uri: playback.item.file,
+        })
+    }
+
+    async fn allowed_actions(&self) -> Result<TransportActions, AppError> {
+        let actions: &[&str] = if self.snapshot().await?.is_some() {
+            &["Play", "Pause", "Stop", "Seek"]
+        } else if self.state.lock().await.pending.is_some() {
+            &["Play", "Stop"]
+        } else {
+            &[]
+        };
+        Ok(TransportActions(actions.iter().map(|a| a.to_string()).collect()))
+    }
+
+    /// Polls like other renderers, and with the WebSocket open, also
+    /// reports player notifications as they come.
+    fn subscribe(self: Arc<Self>, interval: Duration) -> mpsc::Receiver<RendererEvent> {
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(super::poll(self.clone(), interval, tx.clone()));
+        tokio::spawn(self.forward_notifications(tx));
+        rx
+    }
+
+    /// Kodi says what file it plays, so media opened from its own library
+    /// or another app shows as foreign.
+    async fn media_info(&self) -> Result<MediaInfo, AppError> {
+        let Some(playback) = self.snapshot().await? else {
+            return Ok(MediaInfo::default());
+        };
+        Ok(MediaInfo {
+            nr_tracks: 1,
+            media_duration_secs: playback.properties.totaltime.secs(),
+            title: playback.item.name(),
+            current_uri: playback.item.file,
+            current_uri_metadata: String::new(),
+        })
+    }
+}
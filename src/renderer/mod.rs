@@ -0,0 +1,335 @@
+//! Renderers behind a common interface: whatever the protocol, a renderer
+//! loads media, plays, pauses, seeks, reports its state and takes volume
+//! changes. The session and front-ends only see [`Renderer`], implemented
+//! for DLNA, Google Cast, AirPlay, Kodi and Roku.
+
+mod airplay;
+mod dlna;
+mod googlecast;
+mod kodi;
+mod roku;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::dlna::client::SoapClient;
+use crate::dlna::types::{
+    MediaInfo, PlayMode, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+use crate::wol::MacAddress;
+
+pub use airplay::AirPlayRenderer;
+pub(crate) use dlna::DlnaDevice;
+pub use dlna::DlnaRenderer;
+pub use googlecast::GoogleCastRenderer;
+pub use kodi::KodiRenderer;
+pub use roku::RokuRenderer;
+
+/// How often (in poll ticks) to ask the renderer what media it has loaded.
+const MEDIA_INFO_EVERY: u64 = 5;
+
+/// The protocol a renderer is controlled with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Protocol {
+    /// UPnP AVTransport, found with SSDP.
+    #[default]
+    Dlna,
+    /// Cast v2 (Chromecast, Google TV), found with mDNS.
+    GoogleCast,
+    /// AirPlay video over HTTP (Apple TV), found with mDNS.
+    AirPlay,
+    /// Kodi's JSON-RPC over HTTP, found with mDNS.
+    Kodi,
+    /// Roku's External Control Protocol, found with SSDP.
+    Roku,
+}
+
+impl Protocol {
+    /// Stable identifier for APIs and the device cache.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Dlna => "dlna",
+            Self::GoogleCast => "googlecast",
+            Self::AirPlay => "airplay",
+            Self::Kodi => "kodi",
+            Self::Roku => "roku",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Dlna => "DLNA",
+            Self::GoogleCast => "Google Cast",
+            Self::AirPlay => "AirPlay",
+            Self::Kodi => "Kodi",
+            Self::Roku => "Roku",
+        }
+    }
+}
+
+/// A renderer found on the network or remembered from an earlier run.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Device {
+    pub friendly_name: String,
+    /// Unique device name, e.g. `uuid:4d696e69-444c-164e-9d41-b827eb1d2b8f`.
+    pub udn: String,
+    pub protocol: Protocol,
+    /// Where the device is reached; for DLNA, its device description, for
+    /// Google Cast a `cast://host:port` URL, for AirPlay `airplay://host:port`,
+    /// for Kodi `kodi://host:port` and for Roku `roku://host:port`.
+    pub device_url: DeviceUrl,
+    /// Local network interface the device was found on, e.g. `en0`.
+    pub interface: Option<String>,
+    /// Loaded from the device cache and not seen on the network since.
+    pub stale: bool,
+    /// Hardware address from the neighbor table, for Wake-on-LAN.
+    pub mac: Option<MacAddress>,
+    /// What only the DLNA backend needs; `None` for other protocols.
+    pub(crate) dlna: Option<DlnaDevice>,
+}
+
+/// The URL a [`Device`] is reached at, such as
+/// `http://10.0.0.5:49152/description.xml` or `cast://10.0.0.6:8009`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceUrl(http02::Uri);
+
+impl DeviceUrl {
+    pub(crate) fn from_uri(uri: http02::Uri) -> Self {
+        Self(uri)
+    }
+
+    /// The host, with brackets around an IPv6 address.
+    pub fn host(&self) -> Option<&str> {
+        self.0.host()
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.0.port_u16()
+    }
+
+    /// `[user[:password]@]host[:port]`.
+    pub fn authority(&self) -> Option<&str> {
+        self.0.authority().map(|authority| authority.as_str())
+    }
+
+    /// What follows the `?`, if anything.
+    pub fn query(&self) -> Option<&str> {
+        self.0.query()
+    }
+}
+
+impl FromStr for DeviceUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uri: http02::Uri = s.parse().map_err(|e| format!("{e}"))?;
+        if uri.host().is_none() {
+            return Err(format!("{s} has no host"));
+        }
+        Ok(Self(uri))
+    }
+}
+
+impl fmt::Display for DeviceUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Media to hand a renderer: a URL on our media server and what to say about it.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Media {
+    pub url: String,
+    pub title: String,
+    pub mime_type: String,
+    pub file_size: u64,
+    /// Sidecar subtitles, for renderers that take them alongside the video.
+    pub subtitle_url: Option<String>,
+}
+
+impl Media {
+    pub fn new(url: impl Into<String>, title: impl Into<String>, mime_type: impl Into<String>, file_size: u64) -> Self {
+        Self {
+            url: url.into(),
+            title: title.into(),
+            mime_type: mime_type.into(),
+            file_size,
+            subtitle_url: None,
+        }
+    }
+
+    pub fn with_subtitles(mut self, subtitle_url: Option<String>) -> Self {
+        self.subtitle_url = subtitle_url;
+        self
+    }
+}
+
+/// Something a subscribed renderer reports while casting.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum RendererEvent {
+    PositionUpdate(PositionInfo),
+    StateUpdate(PlaybackState),
+    AllowedActions(TransportActions),
+    MediaInfo(MediaInfo),
+}
+
+/// A connected renderer. Capabilities and allowed actions use AVTransport
+/// names (`SetNextAVTransportURI`, `Seek`, ...) whatever the protocol, so
+/// the session can reason about every renderer the same way.
+#[async_trait]
+pub trait Renderer: Send + Sync {
+    fn device(&self) -> &Device;
+
+    /// Where commands are sent, remembered in the device cache so the next
+    /// connection doesn't have to look it up.
+    fn control_url(&self) -> &str;
+
+    /// What the renderer supports: actions, play modes and play speeds.
+    async fn capabilities(&self) -> Result<RendererCapabilities, AppError>;
+
+    /// Formats the renderer plays, as DLNA protocol info entries such as
+    /// `http-get:*:video/mp4:*`. Empty if it doesn't say.
+    async fn protocol_info(&self) -> Result<Vec<String>, AppError> {
+        Ok(Vec::new())
+    }
+
+    /// Load `media`, replacing what the renderer has loaded. Doesn't start it.
+    async fn load(&self, media: &Media) -> Result<(), AppError>;
+
+    /// Hand the renderer the media to play after the current one (gapless playback).
+    async fn load_next(&self, _media: &Media) -> Result<(), AppError> {
+        Err(AppError::Unsupported("gapless playback".into()))
+    }
+
+    /// Play at a `TransportPlaySpeed` such as "1", "2" or "1/2".
+    async fn play(&self, speed: &str) -> Result<(), AppError>;
+    async fn pause(&self) -> Result<(), AppError>;
+    async fn stop(&self) -> Result<(), AppError>;
+    /// Seek to an absolute position.
+    async fn seek(&self, target_secs: u64) -> Result<(), AppError>;
+
+    /// Master volume, 0-100.
+    async fn volume(&self) -> Result<u32, AppError>;
+    async fn set_volume(&self, volume: u32) -> Result<(), AppError>;
+
+    async fn status(&self) -> Result<PlaybackState, AppError>;
+    async fn position(&self) -> Result<PositionInfo, AppError>;
+
+    /// Actions allowed right now; empty (the default) allows everything.
+    async fn allowed_actions(&self) -> Result<TransportActions, AppError> {
+        Ok(TransportActions::default())
+    }
+
+    /// What the renderer has loaded, to notice another app taking over.
+    async fn media_info(&self) -> Result<MediaInfo, AppError> {
+        Ok(MediaInfo::default())
+    }
+
+    async fn set_play_mode(&self, mode: PlayMode) -> Result<(), AppError> {
+        match mode {
+            PlayMode::Normal => Ok(()),
+            mode => Err(AppError::Unsupported(format!("play mode {}", mode.label()))),
+        }
+    }
+
+    async fn play_mode(&self) -> Result<PlayMode, AppError> {
+        Ok(PlayMode::Normal)
+    }
+
+    /// Report position, state, allowed actions and loaded media about every
+    /// `interval` until the receiver is dropped. The default polls; renderers
+    /// that push changes can send them as they come.
+    fn subscribe(self: Arc<Self>, interval: Duration) -> mpsc::Receiver<RendererEvent>
+    where
+        Self: 'static,
+    {
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(poll(self, interval, tx));
+        rx
+    }
+}
+
+/// Connect to `device` with the protocol it was found with. `control_url`
+/// is where it took commands before, from the device cache; without it,
+/// the renderer is asked. Requests use `client`'s timeouts and retries,
+/// and DLNA renderers share its connections.
+pub async fn connect(
+    device: &Device,
+    control_url: Option<&str>,
+    client: &SoapClient,
+) -> Result<Arc<dyn Renderer>, AppError> {
+    let config = client.config();
+    match device.protocol {
+        Protocol::Dlna => Ok(Arc::new(DlnaRenderer::connect(device.clone(), control_url, client.clone()).await?)),
+        Protocol::GoogleCast => Ok(Arc::new(GoogleCastRenderer::connect(device.clone(), config).await?)),
+        Protocol::AirPlay => Ok(Arc::new(AirPlayRenderer::connect(device.clone(), config).await?)),
+        Protocol::Kodi => Ok(Arc::new(KodiRenderer::connect(device.clone(), config).await?)),
+        Protocol::Roku => Ok(Arc::new(RokuRenderer::connect(device.clone(), config).await?)),
+    }
+}
+
+/// Poll `renderer` for position, transport state, allowed actions and
+/// loaded media until `tx` is closed.
+async fn poll<R: Renderer + ?Sized>(renderer: Arc<R>, interval: Duration, tx: mpsc::Sender<RendererEvent>) {
+    let mut interval = tokio::time::interval(interval);
+    let mut tick: u64 = 0;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tx.closed() => break,
+        }
+        tick += 1;
+
+        match renderer.position().await {
+            Ok(pos) => {
+                if tx.send(RendererEvent::PositionUpdate(pos)).await.is_err() {
+                    break; // receiver dropped
+                }
+            }
+            Err(e) => tracing::warn!("Polling position failed: {e}"),
+        }
+
+        match renderer.status().await {
+            Ok(state) => {
+                if tx.send(RendererEvent::StateUpdate(state)).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => tracing::warn!("Polling transport state failed: {e}"),
+        }
+
+        // Changes with state, e.g. no Seek while TRANSITIONING
+        match renderer.allowed_actions().await {
+            Ok(actions) => {
+                if tx.send(RendererEvent::AllowedActions(actions)).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => tracing::debug!("Polling allowed actions failed: {e}"),
+        }
+
+        // To notice another app taking over the TV
+        if tick % MEDIA_INFO_EVERY == 1 {
+            match renderer.media_info().await {
+                Ok(info) => {
+                    if tx.send(RendererEvent::MediaInfo(info)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => tracing::debug!("Polling media info failed: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use super::{Device, Media, Renderer};
+use crate::dlna::client::SoapConfig;
+use crate::dlna::types::{
+    MediaInfo, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+use crate::roku::client::RokuClient;
+use crate::roku::types::MediaPlayer;
+use crate::roku::{self, PLAY_ON_ROKU};
+
+/// How long `/query/media-player` answers queries. Polls ask for position,
+/// state and allowed actions back to back; one request serves them all.
+const SNAPSHOT_TTL: Duration = Duration::from_millis(250);
+
+/// How long a video may take to load before a player still reporting
+/// nothing counts as stopped. Launching the channel comes first.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Roku device, driven over ECP through the Play on Roku channel.
+///
+/// Launching the channel starts the video, so [`load`] only remembers the
+/// media and [`play`] hands it over. ECP has no seek or volume query;
+/// pausing and resuming press the remote's Play key.
+///
+/// [`load`]: Renderer::load
+/// [`play`]: Renderer::play
+pub struct RokuRenderer {
+    device: Device,
+    /// The device URL; ECP has no separate control endpoint.
+    control_url: String,
+    client: RokuClient,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Media loaded and not handed over yet.
+    pending: Option<Media>,
+    /// The media last handed to Play on Roku.
+    started: Option<Started>,
+    snapshot: Option<(Instant, MediaPlayer)>,
+}
+
+struct Started {
+    media: Media,
+    at: Instant,
+    /// Until the player reports the video, or it is stopped.
+    loading: bool,
+}
+
+impl RokuRenderer {
+    /// Connect to `device` and check that it answers ECP.
+    pub async fn connect(device: Device, config: SoapConfig) -> Result<Self, AppError> {
+        let client = RokuClient::new(
+            roku::base_url(&device.device_url)?,
+            config.connect_timeout,
+            config.request_timeout,
+        );
+        client.device_info().await?;
+        Ok(Self {
+            control_url: device.device_url.to_string(),
+            device,
+            client,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// What the media player is doing, reusing a reply from the last
+    /// [`SNAPSHOT_TTL`].
+    async fn snapshot(&self) -> Result<MediaPlayer, AppError> {
+        let mut state = self.state.lock().await;
+        if let Some((taken, player)) = &state.snapshot {
+            if taken.elapsed() < SNAPSHOT_TTL {
+                return Ok(player.clone());
+            }
+        }
+        let player = self.client.media_player().await?;
+        if player.is_active() {
+            if let Some(started) = &mut state.started {
+                started.loading = false;
+            }
+        }
+        state.snapshot = Some((Instant::now(), player.clone()));
+        Ok(player)
+    }
+
+    /// A fresh look at the media player, for commands that depend on it.
+    async fn current(&self) -> Result<MediaPlayer, AppError> {
+        self.state.lock().await.snapshot = None;
+        self.snapshot().await
+    }
+}
+
+/// What Play on Roku needs to play `media`.
+fn play_params(media: &Media) -> Vec<(&'static str, String)> {
+    let mut params = vec![("t", "v".to_string()), ("u", media.url.clone()), ("videoName", media.title.clone())];
+    let path = media.url.split(['?', '#']).next().unwrap_or_default();
+    if let Some((_, extension)) = path.rsplit('/').next().unwrap_or_default().rsplit_once('.') {
+        params.push(("videoFormat", extension.to_lowercase()));
+    }
+    params
+}
+
+#[async_trait]
+impl Renderer for RokuRenderer {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn control_url(&self) -> &str {
+        &self.control_url
+    }
+
+    /// What ECP does, by AVTransport action names.
+    async fn capabilities(&self) -> Result<RendererCapabilities, AppError> {
+        let actions = ["SetAVTransportURI", "Play", "Pause", "Stop"];
+        Ok(RendererCapabilities {
+            actions: actions.into_iter().map(String::from).collect(),
+            play_modes: Vec::new(),
+            play_speeds: vec!["1".into()],
+        })
+    }
+
+    async fn load(&self, media: &Media) -> Result<(), AppError> {
+        if let Some(subtitles) = &media.subtitle_url {
+            tracing::debug!("Not sending subtitles {subtitles}: Play on Roku takes none");
+        }
+        self.state.lock().await.pending = Some(media.clone());
+        Ok(())
+    }
+
+    /// Hands loaded media to Play on Roku, launching it if another channel
+    /// is in front, or resumes what is paused.
+    async fn play(&self, speed: &str) -> Result<(), AppError> {
+        if speed != "1" {
+            return Err(AppError::Unsupported(format!("play speed {speed}")));
+        }
+        let pending = {
+            let mut state = self.state.lock().await;
+            state.snapshot = None;
+            state.pending.take()
+        };
+        let Some(media) = pending else {
+            // The Play key toggles, so only press it when paused
+            if self.current().await?.state == "pause" {
+                self.client.keypress("Play").await?;
+                self.state.lock().await.snapshot = None;
+            }
+            return Ok(());
+        };
+        let params = play_params(&media);
+        let params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        if self.client.active_app().await?.as_deref() == Some(PLAY_ON_ROKU) {
+            self.client.input(PLAY_ON_ROKU, &params).await?;
+        } else {
+            self.client.launch(PLAY_ON_ROKU, &params).await?;
+        }
+        let mut state = self.state.lock().await;
+        state.snapshot = None;
+        state.started = Some(Started {
+            media,
+            at: Instant::now(),
+            loading: true,
+        });
+        Ok(())
+    }
+
+    async fn pause(&self) -> Result<(), AppError> {
+        if self.current().await?.state == "play" {
+            self.client.keypress("Play").await?;
+            self.state.lock().await.snapshot = None;
+        }
+        Ok(())
+    }
+
+    /// Backs out of the player, which ends the video.
+    async fn stop(&self) -> Result<(), AppError> {
+        {
+            let mut state = self.state.lock().await;
+            state.pending = None;
+            if let Some(started) = &mut state.started {
+                started.loading = false;
+            }
+        }
+        if self.current().await?.is_active() {
+            self.client.keypress("Back").await?;
+        }
+        self.state.lock().await.snapshot = None;
+        Ok(())
+    }
+
+    async fn seek(&self, _target_secs: u64) -> Result<(), AppError> {
+        Err(AppError::Unsupported("seeking over Roku ECP".into()))
+    }
+
+    async fn volume(&self) -> Result<u32, AppError> {
+        Err(AppError::Unsupported("volume over Roku ECP".into()))
+    }
+
+    async fn set_volume(&self, _volume: u32) -> Result<(), AppError> {
+        Err(AppError::Unsupported("volume over Roku ECP".into()))
+    }
+
+    /// A video still loading shows as transitioning for up to
+    /// [`LOAD_TIMEOUT`], while the player may still report nothing.
+    async fn status(&self) -> Result<PlaybackState, AppError> {
+        let player = self.snapshot().await?;
+        if player.is_active() {
+            return Ok(player.playback_state());
+        }
+        let state = self.state.lock().await;
+        Ok(match &state.started {
+            _ if state.pending.is_some() => PlaybackState::Stopped,
+            Some(started) if started.loading && started.at.elapsed() < LOAD_TIMEOUT => {
+                PlaybackState::Transitioning
+            }
+            Some(_) => PlaybackState::Stopped,
+            None => PlaybackState::NoMediaPresent,
+        })
+    }
+
+    async fn position(&self) -> Result<PositionInfo, AppError> {
+        let player = self.snapshot().await?;
+        if !player.is_active() {
+            return Ok(PositionInfo::default());
+        }
+        let state = self.state.lock().await;
+        let track_uri = match &state.started {
+            Some(started) if player.channel_id.as_deref() == Some(PLAY_ON_ROKU) => started.media.url.clone(),
+            _ => String::new(),
+        };
+        Ok(PositionInfo {
+            elapsed_secs: player.position_ms / 1000,
+            duration_secs: player.duration_ms / 1000,
+            track_uri,
+        })
+    }
+
+    async fn allowed_actions(&self) -> Result<TransportActions, AppError> {
+        let player = self.snapshot().await?;
+        let actions: &[&str] = if player.is_active() {
+            &["Play", "Pause", "Stop"]
+        } else if self.state.lock().await.pending.is_some() {
+            &["Play", "Stop"]
+        } else {
+            &[]
+        };
+        Ok(TransportActions(actions.iter().map(|a| a.to_string()).collect()))
+    }
+
+    /// What we started while Play on Roku has the player; another channel
+    /// playing shows as `roku-app:<channel id>`, so it counts as foreign.
+    async fn media_info(&self) -> Result<MediaInfo, AppError> {
+        let player = self.snapshot().await?;
+        if !player.is_active() {
+            return Ok(MediaInfo::default());
+        }
+        let state = self.state.lock().await;
+        let (current_uri, title) = match (&player.channel_id, &state.started) {
+            (Some(id), Some(started)) if id == PLAY_ON_ROKU => {
+                (started.media.url.clone(), Some(started.media.title.clone()))
+            }
+            (Some(id), _) => (format!("roku-app:{id}"), player.channel_name.clone()),
+            (None, _) => return Ok(MediaInfo::default()),
+        };
+        Ok(MediaInfo {
+            nr_tracks: 1,
+            media_duration_secs: player.duration_ms / 1000,
+            current_uri,
+            current_uri_metadata: String::new(),
+            title,
+        })
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use hyper014::client::HttpConnector;
+
+use super::types::{self, DeviceInfo, MediaPlayer};
+use crate::error::AppError;
+
+/// ECP requests to one Roku device.
+pub struct RokuClient {
+    http: hyper014::Client<HttpConnector>,
+    base_url: String,
+    request_timeout: Duration,
+}
+
+impl RokuClient {
+    /// A client for the device at `base_url`, e.g. `http://10.0.0.8:8060`.
+    pub fn new(base_url: String, connect_timeout: Duration, request_timeout: Duration) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(connect_timeout));
+        connector.set_nodelay(true);
+        let http = hyper014::Client::builder()
+            .pool_idle_timeout(Duration::from_secs(30))
+            .build(connector);
+        Self {
+            http,
+            base_url,
+            request_timeout,
+        }
+    }
+
+    pub async fn device_info(&self) -> Result<DeviceInfo, AppError> {
+        let xml = self.request("device-info", "GET", "/query/device-info").await?;
+        DeviceInfo::parse(&xml).ok_or_else(|| AppError::RokuAction("device-info has no serial number".into()))
+    }
+
+    pub async fn media_player(&self) -> Result<MediaPlayer, AppError> {
+        let xml = self.request("media-player", "GET", "/query/media-player").await?;
+        Ok(MediaPlayer::parse(&xml))
+    }
+
+    /// The channel in the foreground; `None` on the home screen.
+    pub async fn active_app(&self) -> Result<Option<String>, AppError> {
+        let xml = self.request("active-app", "GET", "/query/active-app").await?;
+        Ok(types::active_app(&xml))
+    }
+
+    /// Start `channel`, passing it `params`.
+    pub async fn launch(&self, channel: &str, params: &[(&str, &str)]) -> Result<(), AppError> {
+        let path = format!("/launch/{channel}{}", query(params));
+        self.request("launch", "POST", &path).await?;
+        Ok(())
+    }
+
+    /// Pass `params` to `channel`, which is already running.
+    pub async fn input(&self, channel: &str, params: &[(&str, &str)]) -> Result<(), AppError> {
+        let path = format!("/input/{channel}{}", query(params));
+        self.request("input", "POST", &path).await?;
+        Ok(())
+    }
+
+    /// Press a remote key, e.g. `Play` (which toggles pause) or `Back`.
+    pub async fn keypress(&self, key: &str) -> Result<(), AppError> {
+        self.request(key, "POST", &format!("/keypress/{key}")).await?;
+        Ok(())
+    }
+
+    async fn request(&self, what: &str, method: &str, path: &str) -> Result<String, AppError> {
+        let request = http02::Request::builder()
+            .method(method)
+            .uri(format!("{}{path}", self.base_url))
+            .body(hyper014::Body::empty())
+            .map_err(|e| AppError::RokuAction(format!("Failed to build {what} request: {e}")))?;
+        let exchange = async {
+            let response = self
+                .http
+                .request(request)
+                .await
+                .map_err(|e| AppError::NetworkError(format!("Roku {what}: {e}")))?;
+            let status = response.status();
+            let body = hyper014::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| AppError::NetworkError(format!("Roku {what}: {e}")))?;
+            Ok::<_, AppError>((status, body))
+        };
+        let (status, body) = tokio::time::timeout(self.request_timeout, exchange)
+            .await
+            .map_err(|_| AppError::Timeout(format!("Roku {what} after {}s", self.request_timeout.as_secs_f32())))??;
+        match status.as_u16() {
+            200..=299 => Ok(String::from_utf8_lossy(&body).into_owned()),
+            403 => Err(AppError::RokuAction(format!(
+                "{what} refused with HTTP {status}: turn on control by mobile apps in the Roku's settings"
+            ))),
+            _ => Err(AppError::RokuAction(format!("{what} refused with HTTP {status}"))),
+        }
+    }
+}
+
+/// `params` as a query string, with its leading `?`; empty if there are none.
+fn query(params: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = params
+        .iter()
+        .map(|(key, value)| format!("{key}={}", urlencoding::encode(value)))
+        .collect();
+    match pairs.is_empty() {
+        true => String::new(),
+        false => format!("?{}", pairs.join("&")),
+    }
+}
@@ -0,0 +1,44 @@
+//! Roku: the External Control Protocol (ECP), HTTP on port 8060. Media is
+//! handed to the Play on Roku channel through its `launch` and `input`
+//! endpoints; playback is driven with remote key presses and read from
+//! `/query/media-player`.
+
+#[doc(hidden)]
+pub mod client;
+#[doc(hidden)]
+pub mod types;
+
+
+use crate::error::AppError;
+use crate::net::Host;
+use crate::renderer::DeviceUrl;
+
+/// Port ECP listens on.
+pub const DEFAULT_PORT: u16 = 8060;
+
+/// SSDP search target Roku devices answer.
+pub const SEARCH_TARGET: &str = "roku:ecp";
+
+/// The Play on Roku channel, which plays a URL it is given.
+pub const PLAY_ON_ROKU: &str = "15985";
+
+/// URL a Roku device is listed with, e.g. `roku://192.168.1.50:8060`.
+pub fn device_url(host: &Host, port: u16) -> String {
+    format!("roku://{host}:{port}")
+}
+
+/// Where to send ECP requests for a device listed with `device_url`, or
+/// found at an SSDP location such as `http://192.168.1.50:8060/`.
+pub fn base_url(device_url: &DeviceUrl) -> Result<String, AppError> {
+    let host: Host = device_url
+        .host()
+        .ok_or_else(|| AppError::NetworkError(format!("{device_url} has no host")))?
+        .parse()
+        .map_err(AppError::NetworkError)?;
+    Ok(format!("http://{host}:{}", device_url.port().unwrap_or(DEFAULT_PORT)))
+}
+
+/// A device's unique name, as its SSDP `USN` has it.
+pub fn udn(serial_number: &str) -> String {
+    format!("uuid:roku:ecp:{serial_number}")
+}
@@ -0,0 +1,114 @@
+use crate::dlna::types::PlaybackState;
+
+/// The reply to `GET /query/device-info`.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub serial_number: String,
+    /// The name the user gave it, else the model's.
+    pub name: String,
+    pub model_name: String,
+    /// The MAC address of the network it is on.
+    pub mac: Option<String>,
+}
+
+impl DeviceInfo {
+    /// `None` if the reply is not a Roku's device info.
+    pub fn parse(xml: &str) -> Option<Self> {
+        let serial_number = element(xml, "serial-number")?;
+        let model_name = element(xml, "model-name").unwrap_or_default();
+        let name = ["user-device-name", "friendly-device-name", "default-device-name"]
+            .into_iter()
+            .find_map(|tag| element(xml, tag).filter(|name| !name.is_empty()))
+            .unwrap_or_else(|| format!("Roku {model_name}"));
+        let mac = match element(xml, "network-type").as_deref() {
+            Some("ethernet") => element(xml, "ethernet-mac"),
+            _ => element(xml, "wifi-mac"),
+        };
+        Some(Self {
+            serial_number,
+            name,
+            model_name,
+            mac,
+        })
+    }
+}
+
+/// The reply to `GET /query/media-player`: what the media player is doing,
+/// for whichever channel uses it.
+#[derive(Debug, Clone, Default)]
+pub struct MediaPlayer {
+    /// `none`, `close`, `open`, `startup`, `buffer`, `play`, `pause` or `stop`.
+    pub state: String,
+    /// The channel playing, e.g. [`PLAY_ON_ROKU`](super::PLAY_ON_ROKU).
+    pub channel_id: Option<String>,
+    pub channel_name: Option<String>,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+}
+
+impl MediaPlayer {
+    pub fn parse(xml: &str) -> Self {
+        let millis = |tag| {
+            element(xml, tag)
+                .and_then(|value| value.trim_end_matches("ms").trim().parse().ok())
+                .unwrap_or_default()
+        };
+        Self {
+            state: attribute(xml, "player", "state").unwrap_or_default(),
+            channel_id: attribute(xml, "plugin", "id"),
+            channel_name: attribute(xml, "plugin", "name"),
+            position_ms: millis("position"),
+            duration_ms: millis("duration"),
+        }
+    }
+
+    /// Whether something is loaded in the player.
+    pub fn is_active(&self) -> bool {
+        !matches!(self.state.as_str(), "" | "none" | "close")
+    }
+
+    pub fn playback_state(&self) -> PlaybackState {
+        match self.state.as_str() {
+            "play" => PlaybackState::Playing,
+            "pause" => PlaybackState::Paused,
+            "open" | "startup" | "buffer" => PlaybackState::Transitioning,
+            "stop" => PlaybackState::Stopped,
+            "" | "none" | "close" => PlaybackState::NoMediaPresent,
+            other => PlaybackState::Unknown(other.to_string()),
+        }
+    }
+}
+
+/// The channel in the foreground, from `GET /query/active-app`; `None` on
+/// the home screen.
+pub fn active_app(xml: &str) -> Option<String> {
+    attribute(xml, "app", "id")
+}
+
+/// The text of the first `<tag>` element, unescaped.
+fn element(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{tag}>");
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{tag}>"))?;
+    Some(unescape(xml[start..start + end].trim()))
+}
+
+/// The value of `name` on the first `<tag ...>` element.
+fn attribute(xml: &str, tag: &str, name: &str) -> Option<String> {
+    let open = format!("<{tag} ");
+    let start = xml.find(&open)? + open.len() - 1;
+    let attributes = &xml[start..start + xml[start..].find('>')?];
+    // The space keeps `id` from matching the end of another name
+    let marker = format!(" {name}=\"");
+    let value_start = attributes.find(&marker)? + marker.len();
+    let value_end = attributes[value_start..].find('"')?;
+    Some(unescape(&attributes[value_start..value_start + value_end]))
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
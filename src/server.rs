@@ -1,87 +1,226 @@
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
-use axum::extract::{Request, State};
-use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
+use socket2::{Domain, Protocol, Socket, Type};
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::broadcast;
 use tokio_util::io::ReaderStream;
 
-/// Shared state for the HTTP server.
+use crate::error::AppError;
+
+/// A file registered with the media server.
 #[derive(Clone)]
-pub struct ServerState {
-    pub file_path: Arc<PathBuf>,
-    pub file_size: u64,
-    pub mime_type: String,
-    pub file_name: String,
+struct MediaFile {
+    file_path: Arc<PathBuf>,
+    file_size: u64,
+    mime_type: String,
 }
 
-/// Start the HTTP media server. Returns (bound address, serve path, task handle).
-pub async fn start_server(
-    file_path: PathBuf,
-    port: u16,
-) -> anyhow::Result<(SocketAddr, String, tokio::task::JoinHandle<()>)> {
-    let metadata = tokio::fs::metadata(&file_path).await?;
-    let file_size = metadata.len();
-
-    let file_name = file_path
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-
-    let mime_type = mime_guess::from_path(&file_path)
-        .first_or_octet_stream()
-        .to_string();
-
-    let state = ServerState {
-        file_path: Arc::new(file_path),
-        file_size,
-        mime_type,
-        file_name,
-    };
+/// A GET the media server received for a registered file.
+#[derive(Debug, Clone)]
+pub struct MediaRequest {
+    pub serve_path: String,
+    pub remote_addr: SocketAddr,
+    /// The Range header, if the client asked for part of the file.
+    pub range: Option<String>,
+}
 
-    // Use a simple fixed path to avoid issues with complex filenames on DLNA TVs
-    let ext = std::path::Path::new(&state.file_name)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("mp4");
-    let serve_path = format!("/media/stream.{ext}");
+/// Shared state for the HTTP server: registered files keyed by serve path.
+#[derive(Clone)]
+struct ServerState {
+    files: Arc<RwLock<HashMap<String, MediaFile>>>,
+    requests: broadcast::Sender<MediaRequest>,
+}
+
+/// HTTP media server that can serve any number of registered files.
+pub struct MediaServer {
+    addr: SocketAddr,
+    state: ServerState,
+    next_id: AtomicU64,
+    handle: tokio::task::JoinHandle<()>,
+}
 
-    let app = Router::new()
-        .route(&serve_path, get(serve_media))
-        .with_state(state);
+impl MediaServer {
+    /// Start the HTTP media server on all interfaces, IPv4 and IPv6 where
+    /// the system allows both on one socket.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let server = localcast::MediaServer::start(0).await?;
+    /// tokio::net::TcpStream::connect(("::1", server.port())).await?;
+    /// tokio::net::TcpStream::connect(("127.0.0.1", server.port())).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn start(port: u16) -> Result<Self, AppError> {
+        let (requests, _) = broadcast::channel(64);
+        let state = ServerState {
+            files: Arc::default(),
+            requests,
+        };
+
+        let app = Router::new()
+            .route("/media/{id}/{name}", get(serve_media))
+            .with_state(state.clone());
+
+        let listener = listen(port).await?;
+        let bound_addr = listener
+            .local_addr()
+            .map_err(|e| AppError::ServerError(e.to_string()))?;
+
+        let handle = tokio::spawn(async move {
+            let service = app.into_make_service_with_connect_info::<SocketAddr>();
+            if let Err(e) = axum::serve(listener, service).await {
+                tracing::error!("HTTP server error: {e}");
+            }
+        });
+
+        tracing::info!("HTTP server listening on {bound_addr}");
+        Ok(Self {
+            addr: bound_addr,
+            state,
+            next_id: AtomicU64::new(0),
+            handle,
+        })
+    }
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    let bound_addr = listener.local_addr()?;
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Subscribe to notifications of incoming media requests. HEAD requests,
+    /// which only probe the URL, are not reported.
+    pub fn subscribe_requests(&self) -> broadcast::Receiver<MediaRequest> {
+        self.state.requests.subscribe()
+    }
+
+    /// Register a file and return the path it is served under.
+    pub async fn register(&self, file_path: &Path) -> Result<String, AppError> {
+        let metadata = tokio::fs::metadata(file_path)
+            .await
+            .map_err(|_| AppError::FileNotFound(file_path.display().to_string()))?;
+
+        let mime_type = mime_guess::from_path(file_path)
+            .first_or_octet_stream()
+            .to_string();
+
+        // Use a simple fixed name to avoid issues with complex filenames on DLNA TVs
+        let ext = file_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("mp4");
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let serve_path = format!("/media/{id}/stream.{ext}");
+
+        let file = MediaFile {
+            file_path: Arc::new(file_path.to_path_buf()),
+            file_size: metadata.len(),
+            mime_type,
+        };
+        self.state
+            .files
+            .write()
+            .unwrap()
+            .insert(serve_path.clone(), file);
+
+        tracing::info!("Serving {} at {serve_path}", file_path.display());
+        Ok(serve_path)
+    }
+
+    /// Stop serving a previously registered file.
+    pub fn unregister(&self, serve_path: &str) {
+        self.state.files.write().unwrap().remove(serve_path);
+    }
+}
 
-    let handle = tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, app).await {
-            tracing::error!("HTTP server error: {e}");
+/// Listener on all interfaces, IPv4 and IPv6 where the system allows both
+/// on one socket.
+pub(crate) async fn listen(port: u16) -> Result<tokio::net::TcpListener, AppError> {
+    match dual_stack_listener(port) {
+        Ok(listener) => Ok(listener),
+        Err(e) => {
+            tracing::info!("No IPv6 media server ({e}); serving IPv4 only");
+            let addr = SocketAddr::from(([0, 0, 0, 0], port));
+            tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(|e| AppError::ServerError(format!("Cannot bind {addr}: {e}")))
         }
-    });
+    }
+}
 
-    tracing::info!("HTTP server listening on {bound_addr}, path: {serve_path}");
-    Ok((bound_addr, serve_path, handle))
+/// Listener on `[::]:port` that accepts IPv4 connections too.
+fn dual_stack_listener(port: u16) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+impl Drop for MediaServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
 }
 
-/// Handle GET requests for the media file, with Range support.
-async fn serve_media(State(state): State<ServerState>, request: Request) -> Response {
+/// Handle GET requests for a registered media file, with Range support.
+async fn serve_media(
+    State(state): State<ServerState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request,
+) -> Response {
+    // IPv4 clients of the dual-stack socket show up as ::ffff:a.b.c.d
+    let remote_addr = SocketAddr::new(remote_addr.ip().to_canonical(), remote_addr.port());
+    let serve_path = request.uri().path().to_string();
+    let media = match state.files.read().unwrap().get(&serve_path) {
+        Some(m) => m.clone(),
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
     let range_header = request
         .headers()
         .get(header::RANGE)
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string());
 
-    let file_size = state.file_size;
+    tracing::debug!("{remote_addr} requested {serve_path} with {} (range: {range_header:?})", request.method());
+    // A HEAD only probes the URL; the TV hasn't started fetching the media
+    if request.method() == Method::GET {
+        let _ = state.requests.send(MediaRequest {
+            serve_path,
+            remote_addr,
+            range: range_header.clone(),
+        });
+    }
+
+    serve_file(&media.file_path, media.file_size, &media.mime_type, range_header.as_deref()).await
+}
+
+/// Respond with `file_path`, or the part of it `range_header` asks for.
+pub(crate) async fn serve_file(
+    file_path: &Path,
+    file_size: u64,
+    mime_type: &str,
+    range_header: Option<&str>,
+) -> Response {
+    if file_size == 0 {
+        return (StatusCode::OK, [(header::CONTENT_TYPE, mime_type.to_string())], "").into_response();
+    }
 
     // Parse Range header: "bytes=START-END" or "bytes=START-"
-    let (start, end) = match &range_header {
+    let (start, end) = match range_header {
         Some(range) => match parse_range(range, file_size) {
             Some((s, e)) => (s, e),
             None => {
@@ -99,7 +238,7 @@ async fn serve_media(State(state): State<ServerState>, request: Request) -> Resp
     let content_length = end - start + 1;
 
     // Open file and seek to start position
-    let file = match File::open(state.file_path.as_ref()).await {
+    let file = match File::open(file_path).await {
         Ok(f) => f,
         Err(e) => {
             tracing::error!("Failed to open file: {e}");
@@ -129,7 +268,7 @@ async fn serve_media(State(state): State<ServerState>, request: Request) -> Resp
     let mut headers = HeaderMap::new();
     headers.insert(
         header::CONTENT_TYPE,
-        HeaderValue::from_str(&state.mime_type).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        HeaderValue::from_str(mime_type).unwrap_or(HeaderValue::from_static("application/octet-stream")),
     );
     headers.insert(
         header::CONTENT_LENGTH,
@@ -180,3 +319,61 @@ fn parse_range(range: &str, file_size: u64) -> Option<(u64, u64)> {
     let end = end.min(file_size - 1);
     Some((start, end))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Status, Content-Range and body of a GET of `url` with `range`.
+    async fn get(url: &str, range: &str) -> (u16, Option<String>, Vec<u8>) {
+        let request = http02::Request::get(url)
+            .header("Range", range)
+            .body(hyper014::Body::empty())
+            .unwrap();
+        let response = hyper014::Client::new().request(request).await.unwrap();
+        let content_range = response
+            .headers()
+            .get("Content-Range")
+            .map(|v| v.to_str().unwrap().to_string());
+        let status = response.status().as_u16();
+        let body = hyper014::body::to_bytes(response.into_body()).await.unwrap();
+        (status, content_range, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn serves_ranges_over_ipv6_and_ipv4_loopback() {
+        let mut file = tempfile::Builder::new().suffix(".mp4").tempfile().unwrap();
+        file.write_all(b"0123456789").unwrap();
+        let server = MediaServer::start(0).await.unwrap();
+        let serve_path = server.register(file.path()).await.unwrap();
+        let mut requests = server.subscribe_requests();
+
+        let url = format!("{}{serve_path}", crate::net::http_base("::1".parse().unwrap(), server.port()));
+        assert!(url.starts_with("http://[::1]:"));
+        let (status, content_range, body) = get(&url, "bytes=2-5").await;
+        assert_eq!(status, 206);
+        assert_eq!(content_range.as_deref(), Some("bytes 2-5/10"));
+        assert_eq!(body, b"2345");
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request.remote_addr.ip(), "::1".parse::<std::net::IpAddr>().unwrap());
+        assert_eq!(request.range.as_deref(), Some("bytes=2-5"));
+
+        // The same socket takes IPv4, reported without the ::ffff: prefix
+        let url = format!("http://127.0.0.1:{}{serve_path}", server.port());
+        assert_eq!(get(&url, "bytes=-3").await.2, b"789");
+        assert!(requests.recv().await.unwrap().remote_addr.is_ipv4());
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-1023", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=10-", 100), Some((10, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-1000", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=5-4", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+    }
+}
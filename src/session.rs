@@ -0,0 +1,1558 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::cast::{self, CastFailure, CastStep, CastTimeouts};
+use crate::cache::DeviceCache;
+use crate::config::{Config, DeviceConfig, Quirk};
+use crate::discovery::{DiscoveryEvent, DiscoveryService};
+use crate::dlna::client::{SoapClient, SoapConfig};
+use crate::dlna::types::{
+    MediaInfo, PlayMode, PlaybackState, PositionInfo, RendererCapabilities, TransportActions,
+};
+use crate::error::AppError;
+use crate::net::{self, InterfaceFilter};
+use crate::queue::{self, PlayQueue, QueueItem, Subtitle};
+use crate::recovery::{self, RecoveryPolicy};
+use crate::renderer::{self, Device, Renderer, RendererEvent};
+use crate::server::MediaServer;
+use crate::wol::{self, WakeOptions};
+
+/// Queue change detected from a renderer event that the session must act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueTransition {
+    /// The renderer moved on to the preloaded next item by itself.
+    Switched,
+    /// The current item played to the end and the renderer stopped.
+    Finished,
+    /// The renderer stopped mid-item without being asked to (e.g. a Wi-Fi blip).
+    Dropped,
+}
+
+/// Classify a transport state change: an unrequested stop near the end of the
+/// item finishes it; after some progress anywhere else it is a drop to recover from.
+/// Without a known duration (live streams, renderers that don't report one) a
+/// stop during playback can't be told from the natural end, so it finishes the
+/// item rather than replaying it.
+pub fn stop_transition(
+    old: &PlaybackState,
+    new: &PlaybackState,
+    expected: bool,
+    last_position: &PositionInfo,
+) -> Option<QueueTransition> {
+    if expected || !matches!(new, PlaybackState::Stopped | PlaybackState::NoMediaPresent) {
+        return None;
+    }
+    let started = last_position.elapsed_secs > 0;
+    let duration_known = last_position.duration_secs > 0;
+    match old {
+        PlaybackState::Playing if last_position.near_end() => Some(QueueTransition::Finished),
+        PlaybackState::Playing if started && !duration_known => Some(QueueTransition::Finished),
+        PlaybackState::Playing | PlaybackState::Paused
+            if started && duration_known && !last_position.near_end() =>
+        {
+            Some(QueueTransition::Dropped)
+        }
+        _ => None,
+    }
+}
+
+/// Why a session request failed.
+#[derive(Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum SessionError {
+    /// The request doesn't make sense in the current state (bad index, no device, ...).
+    #[error("{0}")]
+    Invalid(String),
+
+    /// The renderer forbids the action right now.
+    #[error("{0}")]
+    NotAllowed(String),
+
+    #[error(transparent)]
+    Cast(#[from] CastFailure),
+
+    /// The user cancelled the operation; `step` is where a cast was at.
+    #[error("Cancelled")]
+    Cancelled { step: Option<CastStep> },
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("{0}")]
+    Renderer(String),
+
+    #[error("Session is no longer running")]
+    Closed,
+}
+
+impl From<AppError> for SessionError {
+    fn from(e: AppError) -> Self {
+        match e {
+            AppError::Timeout(what) => Self::Timeout(what),
+            AppError::Unsupported(_) => Self::NotAllowed(e.to_string()),
+            AppError::FileNotFound(_)
+            | AppError::UnsupportedFormat(_)
+            | AppError::NoDevicesFound
+            | AppError::DeviceNotFound(_)
+            | AppError::Config(_) => {
+                Self::Invalid(e.to_string())
+            }
+            _ => Self::Renderer(e.to_string()),
+        }
+    }
+}
+
+/// Playback state owned by the session; front-ends get copies of it.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SessionState {
+    pub devices: Vec<Device>,
+    pub selected_device: Option<usize>,
+    pub scanning: bool,
+    pub device_name: String,
+    /// Set while our media is loaded on the selected device.
+    pub casting: bool,
+    /// Step of the cast being confirmed.
+    pub cast_step: Option<CastStep>,
+    /// Why the last cast failed, cleared on the next attempt.
+    pub cast_error: Option<String>,
+    /// Config file settings for the selected device.
+    pub device_config: DeviceConfig,
+
+    /// Changed only through [`SessionCommand`]s; read it with
+    /// [`SessionState::queue`].
+    pub(crate) queue: PlayQueue,
+    pub capabilities: RendererCapabilities,
+    /// Result of the last GetCurrentTransportActions call.
+    pub transport_actions: TransportActions,
+    /// Result of the last GetMediaInfo call: what the renderer has loaded.
+    pub media_info: MediaInfo,
+    /// Set when the renderer is playing media that localcast didn't send.
+    pub taken_over: bool,
+    /// Play mode the renderer itself runs in; the queue emulates the rest.
+    pub renderer_play_mode: PlayMode,
+    /// Current `TransportPlaySpeed`, e.g. "1" or "2".
+    pub speed: String,
+
+    pub playback_state: PlaybackState,
+    pub position: PositionInfo,
+    /// Last position reported with a non-zero elapsed time; renderers often
+    /// reset to zero when they stop, so this is where recovery resumes.
+    pub last_position: PositionInfo,
+    /// Set when the user stopped playback, so the stop isn't treated as end of item.
+    pub user_stopped: bool,
+    /// Attempt number while resuming after a drop.
+    pub recovering: Option<u32>,
+    /// Successful recoveries since the cast started.
+    pub recoveries: u32,
+    pub max_recovery_attempts: u32,
+}
+
+impl SessionState {
+    fn new(recovery_policy: &RecoveryPolicy) -> Self {
+        Self {
+            devices: Vec::new(),
+            selected_device: None,
+            scanning: false,
+            device_name: String::new(),
+            casting: false,
+            cast_step: None,
+            cast_error: None,
+            device_config: DeviceConfig::default(),
+            queue: PlayQueue::default(),
+            capabilities: RendererCapabilities::default(),
+            transport_actions: TransportActions::default(),
+            media_info: MediaInfo::default(),
+            taken_over: false,
+            renderer_play_mode: PlayMode::Normal,
+            speed: "1".into(),
+            playback_state: PlaybackState::Stopped,
+            position: PositionInfo::default(),
+            last_position: PositionInfo::default(),
+            user_stopped: false,
+            recovering: None,
+            recoveries: 0,
+            max_recovery_attempts: recovery_policy.max_attempts,
+        }
+    }
+
+    /// The files to cast, in order, and where playback is in them.
+    pub fn queue(&self) -> &PlayQueue {
+        &self.queue
+    }
+
+    pub fn current_file_name(&self) -> String {
+        self.queue
+            .current()
+            .map(|i| i.file_name.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn apply_renderer_event(&mut self, event: RendererEvent) -> Option<QueueTransition> {
+        match event {
+            RendererEvent::PositionUpdate(pos) => {
+                let switched = self.queue.on_track_uri(&pos.track_uri);
+                if switched {
+                    self.last_position = PositionInfo::default();
+                }
+                if pos.elapsed_secs > 0 {
+                    self.last_position = pos.clone();
+                }
+                self.position = pos;
+                switched.then_some(QueueTransition::Switched)
+            }
+            RendererEvent::StateUpdate(state) => {
+                let transition = stop_transition(
+                    &self.playback_state,
+                    &state,
+                    self.user_stopped || self.taken_over,
+                    &self.last_position,
+                );
+                self.playback_state = state;
+                transition
+            }
+            RendererEvent::AllowedActions(actions) => {
+                self.transport_actions = actions;
+                None
+            }
+            RendererEvent::MediaInfo(info) => {
+                let own_paths: Vec<&str> = self
+                    .queue
+                    .items()
+                    .iter()
+                    .map(|i| i.serve_path.as_str())
+                    .collect();
+                let taken_over = info.is_foreign(&own_paths);
+                if taken_over && !self.taken_over {
+                    tracing::warn!("Another app took over the renderer: {}", info.display_name());
+                }
+                self.taken_over = taken_over;
+                self.media_info = info;
+                None
+            }
+        }
+    }
+}
+
+/// Requests a front-end sends to the session.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SessionCommand {
+    Discover,
+    /// Discover, listening for this long instead of the configured timeout.
+    DiscoverFor(Duration),
+    /// Add a renderer SSDP cannot find, by IP address or description URL.
+    AddDevice(String),
+    /// Resolve the device's control URL and capabilities.
+    SelectDevice(usize),
+    /// Cast the queue's current item, selecting `device` first if given.
+    Cast { device: Option<DeviceRef> },
+    /// Wake `device` (or the selected one) from standby with Wake-on-LAN,
+    /// wait until it answers, then cast to it.
+    WakeAndCast { device: Option<DeviceRef> },
+    /// Replace the queue with these files.
+    SetFiles(Vec<PathBuf>),
+    Enqueue(PathBuf),
+    RemoveItem(usize),
+    MoveItem { from: usize, to: usize },
+    /// Remove every item except the one being cast.
+    ClearQueue,
+    Jump(usize),
+    Next,
+    Previous,
+    Play,
+    Pause,
+    TogglePlayPause,
+    Stop,
+    Seek(u64),
+    SeekBy(i64),
+    SetPlayMode(PlayMode),
+    CyclePlayMode,
+    SetSpeed(String),
+    ChangeSpeed { faster: bool },
+    /// Stop playback and release the device.
+    Disconnect,
+    /// Stop playback and end the session.
+    Quit,
+}
+
+/// A device in [`SessionState::devices`] to cast to. Positions shift as
+/// devices come and go, so a front-end that read the list a while ago
+/// should name the device by UDN; the session finds it when it gets to
+/// the command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeviceRef {
+    Index(usize),
+    Udn(String),
+}
+
+impl From<usize> for DeviceRef {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+/// Things front-ends may want to react to beyond the state itself.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    CastStarted,
+    Disconnected,
+    /// A renderer appeared on the network.
+    DeviceAdded(Device),
+    /// A renderer was renamed or moved to a new address.
+    DeviceUpdated(Device),
+    /// A renderer left the network or stopped announcing itself.
+    DeviceRemoved(Device),
+    Notice(String),
+    Error(String),
+    /// The renderer dropped the item and it couldn't be resumed; playback
+    /// has stopped for good.
+    RecoveryFailed(String),
+    Closed,
+}
+
+type Reply = oneshot::Sender<Result<(), SessionError>>;
+
+struct Request {
+    command: SessionCommand,
+    /// Where to send the outcome; without one, failures are reported as events.
+    reply: Option<Reply>,
+}
+
+/// Handle to a running cast session: the one controller both front-ends
+/// drive. Cheap to clone; every clone talks to the same session.
+#[derive(Clone)]
+pub struct CastSession {
+    requests: mpsc::UnboundedSender<Request>,
+    cancel: Arc<Mutex<CancellationToken>>,
+    state: watch::Receiver<SessionState>,
+    events: broadcast::Sender<SessionEvent>,
+    discovery: DiscoveryService,
+}
+
+impl CastSession {
+    /// Send a command without waiting; failures arrive as events.
+    pub fn send(&self, command: SessionCommand) {
+        let request = Request {
+            command,
+            reply: None,
+        };
+        if self.requests.send(request).is_err() {
+            tracing::warn!("Session is no longer running");
+        }
+    }
+
+    /// Send a command and wait for it to finish. Returns the state after it.
+    pub async fn request(&self, command: SessionCommand) -> Result<SessionState, SessionError> {
+        let (tx, rx) = oneshot::channel();
+        let request = Request {
+            command,
+            reply: Some(tx),
+        };
+        self.requests.send(request).map_err(|_| SessionError::Closed)?;
+        rx.await.map_err(|_| SessionError::Closed)??;
+        Ok(self.state())
+    }
+
+    /// Cancel the slow operation in progress (confirming a cast, recovering).
+    pub fn cancel(&self) {
+        self.cancel
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .cancel();
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state.borrow().clone()
+    }
+
+    /// Receiver that is notified whenever the state changes.
+    pub fn watch(&self) -> watch::Receiver<SessionState> {
+        self.state.clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// The discovery service feeding the device list, for searches whose
+    /// results are wanted as they arrive.
+    pub fn discovery(&self) -> &DiscoveryService {
+        &self.discovery
+    }
+}
+
+impl CastSession {
+    /// Start configuring a new session.
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::default()
+    }
+}
+
+/// Configuration for a [`CastSession`].
+///
+/// ```no_run
+/// # async fn run() -> Result<(), localcast::AppError> {
+/// use std::time::Duration;
+///
+/// let session = localcast::CastSession::builder()
+///     .media_port(8000)
+///     .request_timeout(Duration::from_secs(10))
+///     .recovery_attempts(0)
+///     .start()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SessionBuilder {
+    media_port: u16,
+    discovery_timeout: Duration,
+    search_interval: Duration,
+    poll_interval: Duration,
+    recovery_policy: RecoveryPolicy,
+    cast_timeouts: CastTimeouts,
+    soap_config: SoapConfig,
+    config: Config,
+    devices: Vec<String>,
+    interface: Option<InterfaceFilter>,
+    device_cache: Option<PathBuf>,
+    wake: WakeOptions,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self {
+            media_port: 0,
+            discovery_timeout: Duration::from_secs(5),
+            search_interval: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(1),
+            recovery_policy: RecoveryPolicy::default(),
+            cast_timeouts: CastTimeouts::default(),
+            soap_config: SoapConfig::default(),
+            config: Config::default(),
+            devices: Vec::new(),
+            interface: None,
+            device_cache: None,
+            wake: WakeOptions::default(),
+        }
+    }
+}
+
+impl SessionBuilder {
+    /// Port for the HTTP media server; 0 (the default) picks a free one.
+    pub fn media_port(mut self, port: u16) -> Self {
+        self.media_port = port;
+        self
+    }
+
+    /// How long a discovery scan listens for SSDP responses.
+    pub fn discovery_timeout(mut self, timeout: Duration) -> Self {
+        self.discovery_timeout = timeout;
+        self
+    }
+
+    /// How often to search for renderers in the background. Renderers that
+    /// announce themselves with SSDP NOTIFY are picked up between searches.
+    pub fn search_interval(mut self, interval: Duration) -> Self {
+        self.search_interval = interval;
+        self
+    }
+
+    /// A renderer to list without waiting for SSDP: an IP address, whose
+    /// common description locations are probed, or a description URL. May be
+    /// given several times.
+    pub fn device(mut self, address: impl Into<String>) -> Self {
+        self.devices.push(address.into());
+        self
+    }
+
+    /// Search for renderers only on the interfaces matching `filter`, and
+    /// advertise media URLs from one of their addresses.
+    pub fn interface(mut self, filter: InterfaceFilter) -> Self {
+        self.interface = Some(filter);
+        self
+    }
+
+    /// Remember renderers in the cache file at `path`: those listed there
+    /// show up at once, marked stale until they answer, and are cast to
+    /// without resolving their control URL again.
+    pub fn device_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.device_cache = Some(path.into());
+        self
+    }
+
+    /// UDP port Wake-on-LAN packets are sent to.
+    pub fn wake_port(mut self, port: u16) -> Self {
+        self.wake.port = port;
+        self
+    }
+
+    /// How long a renderer woken with Wake-on-LAN may take to answer.
+    pub fn wake_timeout(mut self, timeout: Duration) -> Self {
+        self.wake.timeout = timeout;
+        self
+    }
+
+    /// How often to poll the renderer for position and state while casting.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Per-device settings (volume, subtitles, quirks) from a config file.
+    /// Only `config.devices` is used; global values are left to the other
+    /// builder methods so callers can layer their own overrides on top.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Times to try resuming when the renderer drops playback mid-item; 0 disables recovery.
+    pub fn recovery_attempts(mut self, attempts: u32) -> Self {
+        self.recovery_policy.max_attempts = attempts;
+        self
+    }
+
+    /// Delay before the first resume attempt; doubled after each failed one.
+    pub fn recovery_backoff(mut self, backoff: Duration) -> Self {
+        self.recovery_policy.initial_backoff = backoff;
+        self
+    }
+
+    /// Time a renderer may take to fetch the media after Play before the
+    /// cast fails.
+    pub fn fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.cast_timeouts.request = timeout;
+        self
+    }
+
+    /// Time a renderer may take to start playing once it has fetched the
+    /// media before the cast fails.
+    pub fn start_timeout(mut self, timeout: Duration) -> Self {
+        self.cast_timeouts.playing = timeout;
+        self
+    }
+
+    /// Time allowed to connect to a renderer.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.soap_config.connect_timeout = timeout;
+        self
+    }
+
+    /// Time allowed for each request to a renderer.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.soap_config.request_timeout = timeout;
+        self
+    }
+
+    /// Extra attempts for status queries that fail; commands are never resent.
+    pub fn request_retries(mut self, retries: u32) -> Self {
+        self.soap_config.retries = retries;
+        self
+    }
+
+    /// Start the media server and the session.
+    pub async fn start(self) -> Result<CastSession, AppError> {
+        let media_server = Arc::new(MediaServer::start(self.media_port).await?);
+        let discovery = DiscoveryService::start(self.discovery_timeout, self.search_interval, self.interface.as_ref()).await?;
+        if let Some(path) = &self.device_cache {
+            let cached = DeviceCache::load(path).stale_devices();
+            if !cached.is_empty() {
+                discovery.add_cached(cached).await?;
+            }
+        }
+        let devices = self.devices.clone();
+        let session = spawn(self, media_server, discovery);
+        for address in devices {
+            session.send(SessionCommand::AddDevice(address));
+        }
+        Ok(session)
+    }
+}
+
+/// Spawn the session actor and return a handle to it.
+fn spawn(builder: SessionBuilder, media_server: Arc<MediaServer>, discovery: DiscoveryService) -> CastSession {
+    let SessionBuilder {
+        poll_interval,
+        recovery_policy,
+        cast_timeouts,
+        soap_config,
+        config,
+        interface,
+        device_cache,
+        wake,
+        ..
+    } = builder;
+    let (request_tx, request_rx) = mpsc::unbounded_channel();
+    let (discovered_tx, discovered_rx) = mpsc::unbounded_channel();
+    let mut state = SessionState::new(&recovery_policy);
+    state.devices = discovery.devices();
+    let (state_tx, state_rx) = watch::channel(state.clone());
+    let (event_tx, _) = broadcast::channel(64);
+    let cancel = Arc::new(Mutex::new(CancellationToken::new()));
+
+    let session = Session {
+        state,
+        renderer: None,
+        client: SoapClient::new(soap_config),
+        media_base: String::new(),
+        media_server,
+        discovery: discovery.clone(),
+        poll_interval,
+        recovery_policy,
+        cast_timeouts,
+        config,
+        interface,
+        device_cache,
+        wake,
+        renderer_events: None,
+        discovered_tx,
+        state_tx,
+        events: event_tx.clone(),
+        cancel: cancel.clone(),
+    };
+    tokio::spawn(session.run(request_rx, discovered_rx));
+
+    CastSession {
+        requests: request_tx,
+        cancel,
+        state: state_rx,
+        events: event_tx,
+        discovery,
+    }
+}
+
+type Discovered = (Result<Vec<Device>, AppError>, Option<Reply>);
+
+/// Device change from the discovery service; `None` if events were missed.
+type DeviceChange = Option<DiscoveryEvent>;
+
+/// Input the actor loop waits on.
+enum Input {
+    Request(Option<Request>),
+    Discovered(Discovered),
+    Device(DeviceChange),
+    Renderer(RendererEvent),
+}
+
+/// The session actor: owns the device connection, the queue and all renderer I/O.
+struct Session {
+    state: SessionState,
+    /// Connection to the selected device.
+    renderer: Option<Arc<dyn Renderer>>,
+    /// Sends requests to renderers, with this session's timeouts.
+    client: SoapClient,
+    /// Base URL of the media server as reachable from the device.
+    media_base: String,
+    media_server: Arc<MediaServer>,
+    discovery: DiscoveryService,
+    poll_interval: Duration,
+    recovery_policy: RecoveryPolicy,
+    cast_timeouts: CastTimeouts,
+    config: Config,
+    /// Interfaces to advertise media URLs from, if restricted.
+    interface: Option<InterfaceFilter>,
+    device_cache: Option<PathBuf>,
+    wake: WakeOptions,
+    /// Playback reports from the renderer while casting.
+    renderer_events: Option<mpsc::Receiver<RendererEvent>>,
+    discovered_tx: mpsc::UnboundedSender<Discovered>,
+    state_tx: watch::Sender<SessionState>,
+    events: broadcast::Sender<SessionEvent>,
+    cancel: Arc<Mutex<CancellationToken>>,
+}
+
+impl Session {
+    async fn run(
+        mut self,
+        mut requests: mpsc::UnboundedReceiver<Request>,
+        mut discovered: mpsc::UnboundedReceiver<Discovered>,
+    ) {
+        let mut device_events = self.discovery.subscribe();
+        loop {
+            let input = tokio::select! {
+                request = requests.recv() => Input::Request(request),
+                Some(result) = discovered.recv() => Input::Discovered(result),
+                // We hold the service, so the channel only fails by lagging
+                event = device_events.recv() => Input::Device(event.ok()),
+                Some(event) = recv_renderer(&mut self.renderer_events) => Input::Renderer(event),
+            };
+
+            let (result, reply) = match input {
+                Input::Request(None) => {
+                    self.disconnect().await;
+                    break;
+                }
+                Input::Request(Some(Request {
+                    command: SessionCommand::Quit,
+                    reply,
+                })) => {
+                    self.disconnect().await;
+                    self.emit_state();
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Ok(()));
+                    }
+                    break;
+                }
+                Input::Request(Some(Request { command, mut reply })) => {
+                    let result = self.handle(command, &mut reply).await;
+                    (result, reply)
+                }
+                Input::Discovered((result, reply)) => (self.on_discovered(result), reply),
+                Input::Device(change) => {
+                    self.on_device_change(change);
+                    (Ok(()), None)
+                }
+                Input::Renderer(event) => (self.on_renderer_event(event).await, None),
+            };
+
+            self.emit_state();
+            match (result, reply) {
+                (result, Some(reply)) => {
+                    let _ = reply.send(result);
+                }
+                (Err(e), None) => self.report(e),
+                (Ok(()), None) => {}
+            }
+        }
+        self.emit(SessionEvent::Closed);
+    }
+
+    fn emit(&self, event: SessionEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn emit_state(&self) {
+        self.state_tx.send_replace(self.state.clone());
+    }
+
+    /// Change the device cache file, if there is one. Failing to write it
+    /// only costs time on the next launch.
+    fn update_cache(&self, change: impl FnOnce(&mut DeviceCache)) {
+        if let Some(path) = &self.device_cache {
+            if let Err(e) = DeviceCache::update(path, change) {
+                tracing::warn!("{e}");
+            }
+        }
+    }
+
+    /// Report a failure nobody is waiting for.
+    fn report(&self, e: SessionError) {
+        match e {
+            SessionError::Invalid(message) | SessionError::NotAllowed(message) => {
+                self.emit(SessionEvent::Notice(message));
+            }
+            // Shown through `cast_error` already
+            SessionError::Cast(_) | SessionError::Cancelled { .. } => {}
+            e => {
+                tracing::error!("{e}");
+                self.emit(SessionEvent::Error(e.to_string()));
+            }
+        }
+    }
+
+    /// Start a cancellable operation; `CastSession::cancel` fires the token.
+    fn begin_cancellable(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        *self.cancel.lock().unwrap_or_else(|e| e.into_inner()) = token.clone();
+        token
+    }
+
+    async fn handle(&mut self, command: SessionCommand, reply: &mut Option<Reply>) -> Result<(), SessionError> {
+        match command {
+            SessionCommand::Discover => {
+                self.discover(None, reply.take());
+                Ok(())
+            }
+            SessionCommand::DiscoverFor(timeout) => {
+                self.discover(Some(timeout), reply.take());
+                Ok(())
+            }
+            SessionCommand::AddDevice(address) => self.add_device(&address).await,
+            SessionCommand::SelectDevice(index) => self.select_device(index).await,
+            SessionCommand::Cast { device } => {
+                if let Some(device) = device {
+                    self.select_device(self.device_index(&device)?).await?;
+                }
+                self.cast().await
+            }
+            SessionCommand::WakeAndCast { device } => {
+                let index = match device {
+                    Some(device) => self.device_index(&device)?,
+                    None => self
+                        .state
+                        .selected_device
+                        .ok_or_else(|| SessionError::Invalid("No device selected".into()))?,
+                };
+                let index = self.wake_device(index).await?;
+                self.select_device(index).await?;
+                self.cast().await
+            }
+            SessionCommand::SetFiles(paths) => self.set_files(&paths).await,
+            SessionCommand::Enqueue(path) => self.enqueue(&path).await,
+            SessionCommand::RemoveItem(index) => self.remove_item(index).await,
+            SessionCommand::MoveItem { from, to } => {
+                if !self.state.queue.move_item(from, to) {
+                    return Err(SessionError::Invalid("Invalid queue index".into()));
+                }
+                self.preload_next().await;
+                Ok(())
+            }
+            SessionCommand::ClearQueue => {
+                self.clear_queue();
+                Ok(())
+            }
+            SessionCommand::Jump(index) => {
+                if self.state.queue.jump(index).is_none() {
+                    return Err(SessionError::Invalid("Invalid queue index".into()));
+                }
+                self.play_current_item().await
+            }
+            SessionCommand::Next => {
+                if self.state.queue.advance().is_none() {
+                    return Err(SessionError::Invalid("Already at the end of the queue".into()));
+                }
+                self.play_current_item().await
+            }
+            SessionCommand::Previous => {
+                if self.state.queue.previous().is_none() {
+                    return Err(SessionError::Invalid("Already at the start of the queue".into()));
+                }
+                self.play_current_item().await
+            }
+            SessionCommand::Play => {
+                let speed = self.state.speed.clone();
+                self.play_at_speed(&speed).await
+            }
+            SessionCommand::Pause => self.pause().await,
+            SessionCommand::TogglePlayPause => match self.state.playback_state {
+                PlaybackState::Playing => self.pause().await,
+                PlaybackState::Paused | PlaybackState::Stopped => {
+                    let speed = self.state.speed.clone();
+                    self.play_at_speed(&speed).await
+                }
+                _ => Ok(()),
+            },
+            SessionCommand::Stop => self.stop().await,
+            SessionCommand::Seek(target_secs) => self.seek(target_secs).await,
+            SessionCommand::SeekBy(delta_secs) => {
+                let position = &self.state.position;
+                let target = (position.elapsed_secs as i64 + delta_secs).max(0) as u64;
+                let target = if position.duration_secs > 0 {
+                    target.min(position.duration_secs)
+                } else {
+                    target
+                };
+                self.seek(target).await
+            }
+            SessionCommand::SetPlayMode(mode) => {
+                self.state.queue.set_play_mode(mode);
+                self.sync_play_mode().await;
+                self.preload_next().await;
+                Ok(())
+            }
+            SessionCommand::CyclePlayMode => {
+                let mode = self.state.queue.play_mode().cycle();
+                self.state.queue.set_play_mode(mode);
+                self.sync_play_mode().await;
+                self.preload_next().await;
+                Ok(())
+            }
+            SessionCommand::SetSpeed(speed) => {
+                if !self
+                    .state
+                    .capabilities
+                    .supports_speed(&speed, &self.state.transport_actions)
+                {
+                    return Err(SessionError::Invalid(format!("Speed {speed} not supported by renderer")));
+                }
+                self.play_at_speed(&speed).await
+            }
+            SessionCommand::ChangeSpeed { faster } => {
+                let Some(speed) = self
+                    .state
+                    .capabilities
+                    .step_speed(&self.state.speed, faster, &self.state.transport_actions)
+                else {
+                    let which = if faster { "faster" } else { "slower" };
+                    return Err(SessionError::NotAllowed(format!(
+                        "No {which} play speed supported by renderer"
+                    )));
+                };
+                self.play_at_speed(speed).await
+            }
+            SessionCommand::Disconnect => {
+                self.disconnect().await;
+                self.emit(SessionEvent::Disconnected);
+                Ok(())
+            }
+            // Handled by the run loop
+            SessionCommand::Quit => Ok(()),
+        }
+    }
+
+    // --- Devices ---
+
+    /// Search in the background so playback controls stay responsive. The
+    /// device list also changes on its own as renderers come and go.
+    fn discover(&mut self, timeout: Option<Duration>, reply: Option<Reply>) {
+        self.state.scanning = true;
+        let discovered = self.discovered_tx.clone();
+        let discovery = self.discovery.clone();
+        tokio::spawn(async move {
+            let result = match timeout {
+                Some(timeout) => discovery.search_for(timeout).await,
+                None => discovery.search().await,
+            };
+            let _ = discovered.send((result, reply));
+        });
+    }
+
+    /// Build the device from its description and have discovery track it.
+    async fn add_device(&mut self, address: &str) -> Result<(), SessionError> {
+        let device = self.discovery.device_at(address).await?;
+        self.discovery.add(device).await?;
+        self.set_devices(self.discovery.devices());
+        Ok(())
+    }
+
+    fn on_discovered(&mut self, result: Result<Vec<Device>, AppError>) -> Result<(), SessionError> {
+        self.state.scanning = false;
+        let devices = result.map_err(|e| SessionError::Renderer(format!("Discovery failed: {e}")))?;
+        self.set_devices(devices);
+        Ok(())
+    }
+
+    fn on_device_change(&mut self, change: DeviceChange) {
+        self.set_devices(self.discovery.devices());
+        if let Some(DiscoveryEvent::Added(device) | DiscoveryEvent::Updated(device)) = &change {
+            if !device.stale {
+                self.update_cache(|cache| cache.remember(device));
+            }
+        }
+        match change {
+            Some(DiscoveryEvent::Added(device)) => self.emit(SessionEvent::DeviceAdded(device)),
+            Some(DiscoveryEvent::Updated(device)) => self.emit(SessionEvent::DeviceUpdated(device)),
+            Some(DiscoveryEvent::Removed(device)) => self.emit(SessionEvent::DeviceRemoved(device)),
+            _ => {}
+        }
+    }
+
+    /// Replace the device list, keeping the selection if the selected device
+    /// is still around.
+    fn set_devices(&mut self, devices: Vec<Device>) {
+        let selected_udn = self.renderer.as_ref().map(|r| r.device().udn.clone());
+        self.state.selected_device =
+            selected_udn.and_then(|udn| devices.iter().position(|d| d.udn == udn));
+        if self.state.selected_device.is_none() && !self.state.casting {
+            self.renderer = None;
+        }
+        self.state.devices = devices;
+    }
+
+    /// Where `device` is in the device list right now.
+    fn device_index(&self, device: &DeviceRef) -> Result<usize, SessionError> {
+        match device {
+            DeviceRef::Index(index) => Ok(*index),
+            DeviceRef::Udn(udn) => self
+                .state
+                .devices
+                .iter()
+                .position(|d| d.udn == *udn)
+                .ok_or_else(|| SessionError::Invalid(format!("{udn} is not listed"))),
+        }
+    }
+
+    /// Connect to a discovered device and read its capabilities.
+    async fn select_device(&mut self, index: usize) -> Result<(), SessionError> {
+        if self.state.devices.is_empty() {
+            return Err(AppError::NoDevicesFound.into());
+        }
+        let device = self
+            .state
+            .devices
+            .get(index)
+            .cloned()
+            .ok_or_else(|| SessionError::Invalid("Invalid device index".into()))?;
+        self.disconnect().await;
+
+        // A device not seen yet this run can't have moved its control URL
+        // without changing its description URL, which the cache checks
+        let cached_control_url = match &self.device_cache {
+            Some(path) if device.stale => DeviceCache::load(path)
+                .get(&device.udn)
+                .filter(|cached| cached.device_url == device.device_url.to_string())
+                .and_then(|cached| cached.control_url.clone()),
+            _ => None,
+        };
+        let renderer = self.connect(&device, cached_control_url.as_deref()).await?;
+
+        // Read what the renderer supports (play modes, speeds, gapless)
+        let capabilities = renderer
+            .capabilities()
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to read renderer capabilities: {e}");
+                Default::default()
+            });
+        let device_config = self.config.device(&device).cloned().unwrap_or_default();
+        self.state.queue.set_gapless_supported(
+            capabilities.supports_action("SetNextAVTransportURI") && !device_config.has_quirk(Quirk::NoGapless),
+        );
+        self.state
+            .queue
+            .set_subtitle_language(device_config.subtitle_language.clone());
+        self.state.capabilities = capabilities;
+        self.state.device_config = device_config;
+        self.state.selected_device = Some(index);
+        self.state.device_name = device.friendly_name.clone();
+        self.renderer = Some(renderer);
+        Ok(())
+    }
+
+    /// Wake the device at `index` and wait until it answers. Returns its
+    /// index in the refreshed device list.
+    async fn wake_device(&mut self, index: usize) -> Result<usize, SessionError> {
+        let device = self
+            .state
+            .devices
+            .get(index)
+            .cloned()
+            .ok_or_else(|| SessionError::Invalid("Invalid device index".into()))?;
+        self.state.cast_error = None;
+        self.state.cast_step = Some(CastStep::Waking);
+        self.emit_state();
+
+        let token = self.begin_cancellable();
+        let result = tokio::select! {
+            result = wol::wake(&device, &self.wake) => result,
+            _ = token.cancelled() => {
+                self.state.cast_step = None;
+                self.state.cast_error = Some("Cast cancelled".into());
+                return Err(SessionError::Cancelled { step: Some(CastStep::Waking) });
+            }
+        };
+        self.state.cast_step = None;
+        let awake = match result {
+            Ok(awake) => awake,
+            // Nothing to wake it with is a usage problem, not a failed cast
+            Err(e @ AppError::WakeOnLan(_)) if device.mac.is_none() => {
+                return Err(SessionError::Invalid(e.to_string()));
+            }
+            Err(e) => {
+                let failure = CastFailure {
+                    step: CastStep::Waking,
+                    message: e.to_string(),
+                };
+                tracing::warn!("{failure}");
+                self.state.cast_error = Some(failure.to_string());
+                return Err(failure.into());
+            }
+        };
+
+        // Discovery may not hear from it for a while; list it as up now
+        let udn = awake.udn.clone();
+        self.discovery.add(awake).await?;
+        self.set_devices(self.discovery.devices());
+        self.state
+            .devices
+            .iter()
+            .position(|d| d.udn == udn)
+            .ok_or_else(|| SessionError::Invalid(format!("{} is no longer listed", device.friendly_name)))
+    }
+
+    /// Connect to `device`, at `control_url` if the cache has it. Otherwise
+    /// the control URL is looked up and cached along with the formats the
+    /// renderer plays.
+    async fn connect(&self, device: &Device, control_url: Option<&str>) -> Result<Arc<dyn Renderer>, SessionError> {
+        let renderer = renderer::connect(device, control_url, &self.client)
+            .await
+            .map_err(|e| match SessionError::from(e) {
+                SessionError::Timeout(what) => {
+                    SessionError::Timeout(format!("{} did not respond ({what})", device.friendly_name))
+                }
+                e => SessionError::Renderer(format!("Cannot reach {}: {e}", device.friendly_name)),
+            })?;
+        if control_url.is_none() && self.device_cache.is_some() {
+            let protocol_info = renderer.protocol_info().await.unwrap_or_else(|e| {
+                tracing::debug!("No protocol info from {}: {e}", device.friendly_name);
+                Vec::new()
+            });
+            self.update_cache(|cache| cache.resolved(device, renderer.control_url(), protocol_info));
+        }
+        Ok(renderer)
+    }
+
+    /// The renderer of the selected device.
+    fn selected(&self) -> Result<Arc<dyn Renderer>, SessionError> {
+        self.renderer
+            .clone()
+            .ok_or_else(|| SessionError::Invalid("No device selected".into()))
+    }
+
+    /// Reject an action the renderer currently forbids (e.g. Seek while TRANSITIONING).
+    fn check_allowed(&self, action: &str) -> Result<(), SessionError> {
+        if self.state.transport_actions.allows(action) {
+            Ok(())
+        } else {
+            Err(SessionError::NotAllowed(format!(
+                "{action} is not allowed by the renderer right now"
+            )))
+        }
+    }
+
+    // --- Casting ---
+
+    /// Cast the queue's current item to the selected device, confirming each
+    /// step until the TV reports PLAYING.
+    async fn cast(&mut self) -> Result<(), SessionError> {
+        let renderer = self.selected()?;
+        let device = renderer.device().clone();
+        let item = self
+            .state
+            .queue
+            .current()
+            .cloned()
+            .ok_or_else(|| SessionError::Invalid("No file selected".into()))?;
+        self.stop_poller();
+        self.state.casting = false;
+        self.state.cast_error = None;
+
+        let result = self.confirm_cast(renderer.as_ref(), &item).await;
+        self.state.cast_step = None;
+        if let Err(e) = result {
+            match &e {
+                SessionError::Cancelled { .. } => {
+                    tracing::info!("Cast to {} cancelled", device.friendly_name);
+                    let _ = renderer.stop().await;
+                    self.state.cast_error = Some("Cast cancelled".into());
+                }
+                e => {
+                    tracing::warn!("Cast to {} failed: {e}", device.friendly_name);
+                    self.state.cast_error = Some(e.to_string());
+                }
+            }
+            return Err(e);
+        }
+
+        self.state.casting = true;
+        self.update_cache(|cache| cache.last_used = Some(device.udn.clone()));
+        self.state.queue.reset_preload();
+        self.state.playback_state = PlaybackState::Playing;
+        self.state.user_stopped = false;
+        self.state.speed = "1".into();
+        self.state.position = Default::default();
+        self.state.last_position = Default::default();
+        self.state.recoveries = 0;
+        self.sync_play_mode().await;
+        self.state.transport_actions = renderer.allowed_actions().await.unwrap_or_default();
+        self.preload_next().await;
+        if let Some(volume) = self.state.device_config.volume {
+            self.apply_volume(renderer.as_ref(), volume).await;
+        }
+
+        self.renderer_events = Some(renderer.subscribe(self.poll_interval));
+        self.emit(SessionEvent::CastStarted);
+        Ok(())
+    }
+
+    /// Set the volume configured for the device; a failure only warrants a notice.
+    async fn apply_volume(&self, renderer: &dyn Renderer, volume: u32) {
+        if let Err(e) = renderer.set_volume(volume).await {
+            tracing::warn!("Setting the configured volume failed: {e}");
+            self.emit(SessionEvent::Notice(format!("Could not set volume to {volume}: {e}")));
+        }
+    }
+
+    async fn confirm_cast(&mut self, renderer: &dyn Renderer, item: &QueueItem) -> Result<(), SessionError> {
+        // Determine the correct local IP for this device
+        let media_base = media_base_for_device(renderer.device(), self.media_server.port(), self.interface.as_ref())?;
+
+        let token = self.begin_cancellable();
+        let state = &mut self.state;
+        let state_tx = &self.state_tx;
+        let confirm = cast::confirm_cast(
+            renderer,
+            item,
+            &media_base,
+            &self.media_server,
+            self.cast_timeouts,
+            |step| {
+                state.cast_step = Some(step);
+                state_tx.send_replace(state.clone());
+            },
+        );
+        tokio::select! {
+            result = confirm => result?,
+            _ = token.cancelled() => {
+                return Err(SessionError::Cancelled { step: self.state.cast_step });
+            }
+        }
+        self.media_base = media_base;
+        Ok(())
+    }
+
+    /// Stop listening to the renderer; dropping the receiver ends its polling.
+    fn stop_poller(&mut self) {
+        self.renderer_events = None;
+    }
+
+    /// Stop the renderer if we are casting and stop polling it.
+    async fn disconnect(&mut self) {
+        self.stop_poller();
+        if self.state.casting {
+            if let Ok(renderer) = self.selected() {
+                let _ = renderer.stop().await;
+            }
+        }
+        self.state.casting = false;
+        self.media_base.clear();
+        self.state.playback_state = PlaybackState::Stopped;
+        self.state.position = Default::default();
+        self.state.recovering = None;
+    }
+
+    // --- Transport ---
+
+    async fn play_at_speed(&mut self, speed: &str) -> Result<(), SessionError> {
+        self.check_allowed("Play")?;
+        self.selected()?.play(speed).await?;
+        self.state.speed = speed.to_string();
+        self.state.playback_state = PlaybackState::Playing;
+        self.state.user_stopped = false;
+        Ok(())
+    }
+
+    async fn pause(&mut self) -> Result<(), SessionError> {
+        self.check_allowed("Pause")?;
+        self.selected()?.pause().await?;
+        self.state.playback_state = PlaybackState::Paused;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), SessionError> {
+        self.check_allowed("Stop")?;
+        self.selected()?.stop().await?;
+        self.state.playback_state = PlaybackState::Stopped;
+        self.state.user_stopped = true;
+        Ok(())
+    }
+
+    async fn seek(&mut self, target_secs: u64) -> Result<(), SessionError> {
+        self.check_allowed("Seek")?;
+        self.selected()?.seek(target_secs).await?;
+        Ok(())
+    }
+
+    /// Apply the queue's play mode to the renderer of a running cast.
+    async fn sync_play_mode(&mut self) {
+        if !self.state.casting {
+            return;
+        }
+        let Ok(renderer) = self.selected() else {
+            return;
+        };
+        if self.state.device_config.has_quirk(Quirk::NoPlayMode) {
+            self.state.renderer_play_mode = PlayMode::Normal;
+            return;
+        }
+        self.state.renderer_play_mode = queue::apply_play_mode(
+            renderer.as_ref(),
+            self.state.queue.native_play_mode(),
+            &self.state.capabilities,
+        )
+        .await;
+    }
+
+    // --- Queue ---
+
+    /// Validate a file and register it with the media server.
+    async fn queue_item_for(&self, path: &std::path::Path) -> Result<QueueItem, SessionError> {
+        let path = queue::validate_media_file(path)?;
+        let file_size = std::fs::metadata(&path)
+            .map_err(|e| SessionError::Invalid(format!("Cannot read file: {e}")))?
+            .len();
+        let serve_path = self
+            .media_server
+            .register(&path)
+            .await
+            .map_err(|e| SessionError::Renderer(format!("Cannot serve file: {e}")))?;
+        let mut item = QueueItem::new(&path, file_size, serve_path);
+
+        for (language, subtitle_path) in queue::find_subtitles(&path) {
+            match self.media_server.register(&subtitle_path).await {
+                Ok(serve_path) => item.subtitles.push(Subtitle { language, serve_path }),
+                Err(e) => tracing::warn!("Cannot serve subtitles {}: {e}", subtitle_path.display()),
+            }
+        }
+        Ok(item)
+    }
+
+    /// Stop serving the given queue items.
+    fn release_items(&self, items: &[QueueItem]) {
+        for item in items {
+            self.media_server.unregister(&item.serve_path);
+            for subtitle in &item.subtitles {
+                self.media_server.unregister(&subtitle.serve_path);
+            }
+        }
+    }
+
+    async fn set_files(&mut self, paths: &[PathBuf]) -> Result<(), SessionError> {
+        // Validate everything before touching the queue
+        let mut items = Vec::with_capacity(paths.len());
+        for path in paths {
+            match self.queue_item_for(path).await {
+                Ok(item) => items.push(item),
+                Err(e) => {
+                    self.release_items(&items);
+                    return Err(e);
+                }
+            }
+        }
+        let old = self.state.queue.clear();
+        self.release_items(&old);
+        for item in items {
+            self.state.queue.push(item);
+        }
+        Ok(())
+    }
+
+    async fn enqueue(&mut self, path: &std::path::Path) -> Result<(), SessionError> {
+        let item = self.queue_item_for(path).await?;
+        let was_single = self.state.queue.len() == 1;
+        self.state.queue.push(item);
+
+        // A single-item queue may have handed repeat to the renderer; take it back
+        if was_single {
+            self.sync_play_mode().await;
+        }
+        self.preload_next().await;
+        Ok(())
+    }
+
+    async fn remove_item(&mut self, index: usize) -> Result<(), SessionError> {
+        if self.state.casting && self.state.queue.current_index() == Some(index) {
+            return Err(SessionError::NotAllowed("Cannot remove the item being cast".into()));
+        }
+        let item = self
+            .state
+            .queue
+            .remove(index)
+            .ok_or_else(|| SessionError::Invalid("Invalid queue index".into()))?;
+        self.release_items(&[item]);
+        self.preload_next().await;
+        Ok(())
+    }
+
+    fn clear_queue(&mut self) {
+        let keep = if self.state.casting { self.state.queue.current().cloned() } else { None };
+        let old = self.state.queue.clear();
+        match keep {
+            Some(item) => {
+                let others: Vec<QueueItem> = old
+                    .into_iter()
+                    .filter(|i| i.serve_path != item.serve_path)
+                    .collect();
+                self.release_items(&others);
+                self.state.queue.push(item);
+            }
+            None => self.release_items(&old),
+        }
+    }
+
+    /// Switch a running cast to the queue's current item and preload the one
+    /// after it. If nothing is being cast, only the cursor moves.
+    async fn play_current_item(&mut self) -> Result<(), SessionError> {
+        if !self.state.casting {
+            return Ok(());
+        }
+        let renderer = self.selected()?;
+        let Some(item) = self.state.queue.current() else {
+            return Ok(());
+        };
+        queue::switch_to_item(renderer.as_ref(), item, &self.media_base)
+            .await
+            .map_err(|e| SessionError::Renderer(format!("Failed to play {}: {e}", item.file_name)))?;
+        self.state.queue.reset_preload();
+        self.state.position = Default::default();
+        self.state.last_position = Default::default();
+        self.state.playback_state = PlaybackState::Playing;
+        self.state.user_stopped = false;
+        self.state.speed = "1".into();
+        self.preload_next().await;
+        Ok(())
+    }
+
+    /// Hand the next queue item to the renderer if it supports gapless playback.
+    async fn preload_next(&mut self) {
+        if !self.state.casting {
+            return;
+        }
+        let (Ok(renderer), Some(item)) = (self.selected(), self.state.queue.next_to_preload()) else {
+            return;
+        };
+        let outcome = queue::preload_item(renderer.as_ref(), &item, &self.media_base).await;
+        self.state.queue.mark_preloaded(&item.serve_path, outcome);
+    }
+
+    // --- Renderer events ---
+
+    async fn on_renderer_event(&mut self, event: RendererEvent) -> Result<(), SessionError> {
+        match self.state.apply_renderer_event(event) {
+            Some(transition) => self.handle_queue_transition(transition).await,
+            None => Ok(()),
+        }
+    }
+
+    /// React to the renderer finishing, switching or dropping queue items.
+    async fn handle_queue_transition(&mut self, transition: QueueTransition) -> Result<(), SessionError> {
+        match transition {
+            QueueTransition::Switched => {
+                tracing::info!("Renderer switched to next item: {}", self.state.current_file_name());
+                self.preload_next().await;
+            }
+            QueueTransition::Finished => {
+                if self.state.queue.auto_advance().is_some() {
+                    tracing::info!("Item finished, playing next: {}", self.state.current_file_name());
+                    self.play_current_item().await?;
+                }
+            }
+            QueueTransition::Dropped => self.recover_playback().await,
+        }
+        Ok(())
+    }
+
+    /// Resume the current item where the renderer dropped it, per the recovery policy.
+    async fn recover_playback(&mut self) {
+        let resume_secs = self.state.last_position.elapsed_secs;
+        let file_name = self.state.current_file_name();
+        if !self.recovery_policy.enabled() {
+            tracing::warn!("Renderer dropped {file_name} at {resume_secs}s; recovery disabled");
+            return;
+        }
+        let (Some(renderer), Some(item)) = (self.renderer.clone(), self.state.queue.current().cloned()) else {
+            return;
+        };
+        tracing::warn!("Renderer dropped {file_name} at {resume_secs}s, recovering");
+
+        let token = self.begin_cancellable();
+        let policy = self.recovery_policy;
+        let state = &mut self.state;
+        let state_tx = &self.state_tx;
+        let resume = recovery::resume_playback(
+            renderer.as_ref(),
+            &item,
+            &self.media_base,
+            resume_secs,
+            &policy,
+            |attempt| {
+                state.recovering = Some(attempt);
+                state_tx.send_replace(state.clone());
+            },
+        );
+        let result = tokio::select! {
+            result = resume => result.map_err(SessionError::from),
+            _ = token.cancelled() => Err(SessionError::Cancelled { step: None }),
+        };
+        self.state.recovering = None;
+
+        match result {
+            Ok(attempt) => {
+                tracing::info!("Recovered {file_name} at {resume_secs}s on attempt {attempt}");
+                self.emit(SessionEvent::Notice(format!("Recovered playback at {resume_secs}s")));
+                self.state.recoveries += 1;
+                self.state.queue.reset_preload();
+                self.state.speed = "1".into();
+                // Renderer events queued during recovery still report the drop;
+                // they must not look like another one.
+                self.state.playback_state = PlaybackState::Transitioning;
+                self.preload_next().await;
+            }
+            Err(e) => {
+                tracing::error!("Giving up on recovering {file_name}: {e}");
+                self.emit(SessionEvent::RecoveryFailed(format!("Could not recover playback: {e}")));
+                self.state.playback_state = PlaybackState::Stopped;
+            }
+        }
+    }
+}
+
+/// Receive from the renderer if casting; never resolves otherwise.
+async fn recv_renderer(rx: &mut Option<mpsc::Receiver<RendererEvent>>) -> Option<RendererEvent> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Build the media server base URL using the local IP that can reach the
+/// device, taken from the selected interfaces if any.
+fn media_base_for_device(
+    device: &Device,
+    server_port: u16,
+    interface: Option<&InterfaceFilter>,
+) -> Result<String, AppError> {
+    let device_host = device
+        .device_url
+        .host()
+        .ok_or_else(|| AppError::NetworkError("Device URL has no host".into()))?;
+    let local_ip = net::advertised_ip(device_host, interface, device.interface.as_deref())?;
+    let url = net::http_base(local_ip, server_port);
+    tracing::info!("Media base URL for {}: {}", device.friendly_name, url);
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(elapsed_secs: u64, duration_secs: u64) -> PositionInfo {
+        PositionInfo {
+            elapsed_secs,
+            duration_secs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stop_near_the_end_finishes() {
+        let transition = stop_transition(&PlaybackState::Playing, &PlaybackState::Stopped, false, &position(598, 600));
+        assert_eq!(transition, Some(QueueTransition::Finished));
+    }
+
+    #[test]
+    fn stop_midway_is_a_drop() {
+        let transition = stop_transition(&PlaybackState::Playing, &PlaybackState::Stopped, false, &position(120, 600));
+        assert_eq!(transition, Some(QueueTransition::Dropped));
+    }
+
+    #[test]
+    fn stop_with_unknown_duration_finishes() {
+        let transition = stop_transition(&PlaybackState::Playing, &PlaybackState::Stopped, false, &position(120, 0));
+        assert_eq!(transition, Some(QueueTransition::Finished));
+        let transition =
+            stop_transition(&PlaybackState::Playing, &PlaybackState::NoMediaPresent, false, &position(120, 0));
+        assert_eq!(transition, Some(QueueTransition::Finished));
+    }
+
+    #[test]
+    fn paused_stop_with_unknown_duration_is_not_recovered() {
+        let transition = stop_transition(&PlaybackState::Paused, &PlaybackState::Stopped, false, &position(120, 0));
+        assert_eq!(transition, None);
+    }
+
+    #[test]
+    fn requested_stop_is_ignored() {
+        let transition = stop_transition(&PlaybackState::Playing, &PlaybackState::Stopped, true, &position(120, 600));
+        assert_eq!(transition, None);
+    }
+}
@@ -8,13 +8,24 @@ pub enum AppAction {
     MoveDown,
     Select,
     Rescan,
+    /// Open the prompt for adding a TV by IP address or description URL.
+    AddDevice,
+    /// Wake the selected TV with Wake-on-LAN, then cast to it.
+    WakeAndCast,
     TogglePlayPause,
     Stop,
-    SeekForward30,
-    SeekBackward30,
-    SeekForward5Min,
-    SeekBackward5Min,
+    SeekForward,
+    SeekBackward,
+    SeekForwardLong,
+    SeekBackwardLong,
+    NextItem,
+    PreviousItem,
+    CyclePlayMode,
+    SpeedUp,
+    SlowDown,
     BackToDevices,
+    /// Cancel the slow operation in progress (confirming a cast, recovering).
+    Cancel,
     None,
 }
 
@@ -26,10 +37,34 @@ pub fn map_browser_key(key: KeyEvent) -> AppAction {
         KeyCode::Down | KeyCode::Char('j') => AppAction::MoveDown,
         KeyCode::Enter => AppAction::Select,
         KeyCode::Char('r') => AppAction::Rescan,
+        KeyCode::Char('a') => AppAction::AddDevice,
+        KeyCode::Char('w') => AppAction::WakeAndCast,
+        KeyCode::Esc => AppAction::Cancel,
         _ => AppAction::None,
     }
 }
 
+/// Editing keys while a text prompt is open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptKey {
+    Char(char),
+    Backspace,
+    Submit,
+    Cancel,
+    None,
+}
+
+/// Map a key event while a text prompt is open.
+pub fn map_prompt_key(key: KeyEvent) -> PromptKey {
+    match key.code {
+        KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => PromptKey::Char(c),
+        KeyCode::Backspace => PromptKey::Backspace,
+        KeyCode::Enter => PromptKey::Submit,
+        KeyCode::Esc => PromptKey::Cancel,
+        _ => PromptKey::None,
+    }
+}
+
 /// Map a key event in playback screen to an action.
 pub fn map_playback_key(key: KeyEvent) -> AppAction {
     let shift = key.modifiers.contains(KeyModifiers::SHIFT);
@@ -37,11 +72,17 @@ pub fn map_playback_key(key: KeyEvent) -> AppAction {
         KeyCode::Char('q') => AppAction::Quit,
         KeyCode::Char(' ') => AppAction::TogglePlayPause,
         KeyCode::Char('s') => AppAction::Stop,
-        KeyCode::Left if shift => AppAction::SeekBackward5Min,
-        KeyCode::Right if shift => AppAction::SeekForward5Min,
-        KeyCode::Left => AppAction::SeekBackward30,
-        KeyCode::Right => AppAction::SeekForward30,
+        KeyCode::Left if shift => AppAction::SeekBackwardLong,
+        KeyCode::Right if shift => AppAction::SeekForwardLong,
+        KeyCode::Left => AppAction::SeekBackward,
+        KeyCode::Right => AppAction::SeekForward,
+        KeyCode::Char('n') => AppAction::NextItem,
+        KeyCode::Char('p') => AppAction::PreviousItem,
+        KeyCode::Char('m') => AppAction::CyclePlayMode,
+        KeyCode::Char('>') | KeyCode::Char('.') => AppAction::SpeedUp,
+        KeyCode::Char('<') | KeyCode::Char(',') => AppAction::SlowDown,
         KeyCode::Char('b') => AppAction::BackToDevices,
+        KeyCode::Esc => AppAction::Cancel,
         _ => AppAction::None,
     }
 }
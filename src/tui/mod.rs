@@ -12,14 +12,15 @@ use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 
 use crate::app::{App, AppScreen};
+use localcast::AppError;
 use crate::tui::event::{map_browser_key, map_playback_key, AppAction};
-use crate::tui::ui::{render_device_browser, render_playback};
+use crate::tui::ui::{render_device_browser, render_playback, render_toasts};
 
 pub type Tui = Terminal<CrosstermBackend<io::Stdout>>;
 
 /// Initialize the terminal for TUI rendering.
 pub fn init_terminal() -> anyhow::Result<Tui> {
-    enable_raw_mode()?;
+    enable_raw_mode().map_err(|e| AppError::TuiError(format!("Cannot enable raw mode: {e}")))?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(stdout);
@@ -50,19 +51,12 @@ pub fn read_key_event(timeout: std::time::Duration) -> anyhow::Result<Option<cro
 
 /// Render the current app state to the terminal.
 pub fn render(terminal: &mut Tui, app: &App) -> anyhow::Result<()> {
-    terminal.draw(|frame| match &app.screen {
-        AppScreen::DeviceBrowser => {
-            render_device_browser(frame, &app.devices, app.selected_device, app.scanning);
-        }
-        AppScreen::Playback => {
-            render_playback(
-                frame,
-                &app.file_name,
-                &app.current_device_name(),
-                &app.playback_state,
-                &app.position,
-            );
+    terminal.draw(|frame| {
+        match &app.screen {
+            AppScreen::DeviceBrowser => render_device_browser(frame, app),
+            AppScreen::Playback => render_playback(frame, app),
         }
+        render_toasts(frame, app);
     })?;
     Ok(())
 }
@@ -1,23 +1,27 @@
-use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph};
+use ratatui::widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph};
 use ratatui::Frame;
 
-use crate::dlna::types::{DlnaDevice, PlaybackState, PositionInfo};
+use crate::app::App;
+use localcast::dlna::types::{format_duration, speed_label, PlayMode, PlaybackState};
+use localcast::PlayQueue;
 
 /// Render the device browser screen.
-pub fn render_device_browser(
-    frame: &mut Frame,
-    devices: &[DlnaDevice],
-    selected: usize,
-    scanning: bool,
-) {
+pub fn render_device_browser(frame: &mut Frame, app: &App) {
     let area = frame.area();
+    let devices = &app.session.devices;
+    let selected = app.selected_device;
+    let scanning = app.session.scanning;
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .constraints([
+            Constraint::Min(3),    // Device list
+            Constraint::Length(3), // Cast status
+            Constraint::Length(3), // Help
+        ])
         .split(area);
 
     // Device list
@@ -32,12 +36,24 @@ pub fn render_device_browser(
             } else {
                 Style::default().fg(Color::White)
             };
-            ListItem::new(Line::from(Span::styled(&d.friendly_name, style)))
+            let mut spans = vec![Span::styled(d.friendly_name.as_str(), style)];
+            if d.stale {
+                spans.push(Span::styled("  (not seen yet)", Style::default().fg(Color::DarkGray)));
+            }
+            if let Some(interface) = &d.interface {
+                spans.push(Span::styled(format!("  via {interface}"), Style::default().fg(Color::DarkGray)));
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect();
 
+    // Devices are listed as they answer; the title shows the search is still going
+    let scanning_title = match devices.len() {
+        0 => format!(" {} Scanning for devices... ", app.spinner()),
+        n => format!(" {} Scanning... {n} found, select one any time ", app.spinner()),
+    };
     let title = if scanning {
-        " Scanning for DLNA devices... "
+        scanning_title.as_str()
     } else if devices.is_empty() {
         " No devices found (r to rescan) "
     } else {
@@ -57,6 +73,38 @@ pub fn render_device_browser(
     }
     frame.render_stateful_widget(list, chunks[0], &mut state);
 
+    // Cast status: the step being confirmed, or why the last cast failed.
+    // The add-device prompt takes its place while open.
+    let status_line = if let Some(input) = &app.device_prompt {
+        Line::from(vec![
+            Span::styled(" IP or description URL: ", Style::default().fg(Color::Gray)),
+            Span::styled(format!("{input}▏"), Style::default().fg(Color::White)),
+            Span::styled("  Enter to add, Esc to cancel", Style::default().fg(Color::DarkGray)),
+        ])
+    } else if let Some(step) = app.session.cast_step {
+        Line::from(Span::styled(
+            format!(" {} {}... (Esc to cancel)", app.spinner(), step.label()),
+            Style::default().fg(Color::Yellow),
+        ))
+    } else if let Some(error) = &app.session.cast_error {
+        Line::from(Span::styled(
+            format!(" {error}"),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ))
+    } else {
+        Line::from(Span::styled(
+            " Select a device to start casting",
+            Style::default().fg(Color::Gray),
+        ))
+    };
+    let status = Paragraph::new(status_line).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(if app.device_prompt.is_some() { " Add TV " } else { " Cast " })
+            .border_style(Style::default().fg(Color::DarkGray)),
+    );
+    frame.render_widget(status, chunks[1]);
+
     // Help bar
     let help = Paragraph::new(Line::from(vec![
         Span::styled(" ↑/k", Style::default().fg(Color::Green)),
@@ -65,8 +113,14 @@ pub fn render_device_browser(
         Span::raw(" Down  "),
         Span::styled("Enter", Style::default().fg(Color::Green)),
         Span::raw(" Select  "),
+        Span::styled("Esc", Style::default().fg(Color::Green)),
+        Span::raw(" Cancel  "),
         Span::styled("r", Style::default().fg(Color::Green)),
         Span::raw(" Rescan  "),
+        Span::styled("a", Style::default().fg(Color::Green)),
+        Span::raw(" Add TV  "),
+        Span::styled("w", Style::default().fg(Color::Green)),
+        Span::raw(" Wake & cast  "),
         Span::styled("q", Style::default().fg(Color::Green)),
         Span::raw(" Quit"),
     ]))
@@ -75,47 +129,56 @@ pub fn render_device_browser(
             .borders(Borders::ALL)
             .border_style(Style::default().fg(Color::DarkGray)),
     );
-    frame.render_widget(help, chunks[1]);
+    frame.render_widget(help, chunks[2]);
 }
 
 /// Render the playback control screen.
-pub fn render_playback(
-    frame: &mut Frame,
-    file_name: &str,
-    device_name: &str,
-    state: &PlaybackState,
-    position: &PositionInfo,
-) {
+pub fn render_playback(frame: &mut Frame, app: &App) {
     let area = frame.area();
+    let session = &app.session;
+    let file_name = app.current_file_name();
+    let device_name = app.current_device_name();
+    let state = &session.playback_state;
+    let position = &session.position;
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(3), // Info
+            Constraint::Length(3), // Renderer's view
             Constraint::Length(3), // Progress bar
             Constraint::Length(3), // Time display
-            Constraint::Min(0),   // Spacer
+            Constraint::Min(0),    // Queue
             Constraint::Length(3), // Help
         ])
         .split(area);
 
     // Info line
     let state_color = match state {
+        _ if session.recovering.is_some() => Color::Yellow,
         PlaybackState::Playing => Color::Green,
         PlaybackState::Paused => Color::Yellow,
         PlaybackState::Stopped => Color::Red,
         _ => Color::Gray,
     };
+    let state_label = match session.recovering {
+        Some(attempt) => format!(
+            "{} Recovering ({attempt}/{}, Esc to cancel)",
+            app.spinner(),
+            session.max_recovery_attempts
+        ),
+        None => state.label().to_string(),
+    };
 
     let info = Paragraph::new(Line::from(vec![
         Span::styled(" File: ", Style::default().fg(Color::Gray)),
-        Span::styled(file_name, Style::default().fg(Color::White)),
+        Span::styled(file_name.as_str(), Style::default().fg(Color::White)),
         Span::raw("  │  "),
         Span::styled("Device: ", Style::default().fg(Color::Gray)),
-        Span::styled(device_name, Style::default().fg(Color::White)),
+        Span::styled(device_name.as_str(), Style::default().fg(Color::White)),
         Span::raw("  │  "),
         Span::styled(
-            state.label(),
+            state_label,
             Style::default()
                 .fg(state_color)
                 .add_modifier(Modifier::BOLD),
@@ -129,6 +192,38 @@ pub fn render_playback(
     );
     frame.render_widget(info, chunks[0]);
 
+    // What the renderer reports as loaded (GetMediaInfo)
+    let media = &session.media_info;
+    let renderer_line = if session.taken_over {
+        Line::from(Span::styled(
+            format!(" Another app took over the TV: {}", media.display_name()),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ))
+    } else if media.current_uri.is_empty() {
+        Line::from(Span::styled(" Nothing loaded", Style::default().fg(Color::Gray)))
+    } else {
+        Line::from(vec![
+            Span::styled(" Loaded: ", Style::default().fg(Color::Gray)),
+            Span::styled(media.display_name(), Style::default().fg(Color::White)),
+            Span::raw("  │  "),
+            Span::styled("Tracks: ", Style::default().fg(Color::Gray)),
+            Span::styled(media.nr_tracks.to_string(), Style::default().fg(Color::White)),
+            Span::raw("  │  "),
+            Span::styled("Duration: ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format_duration(media.media_duration_secs),
+                Style::default().fg(Color::White),
+            ),
+        ])
+    };
+    let renderer = Paragraph::new(renderer_line).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" On the TV ")
+            .border_style(Style::default().fg(Color::DarkGray)),
+    );
+    frame.render_widget(renderer, chunks[1]);
+
     // Progress bar
     let ratio = position.progress_ratio();
     let gauge = Gauge::default()
@@ -139,7 +234,7 @@ pub fn render_playback(
         )
         .gauge_style(Style::default().fg(Color::Cyan).bg(Color::DarkGray))
         .ratio(ratio.clamp(0.0, 1.0));
-    frame.render_widget(gauge, chunks[1]);
+    frame.render_widget(gauge, chunks[2]);
 
     // Time display
     let time_text = format!(
@@ -147,22 +242,59 @@ pub fn render_playback(
         position.elapsed_display(),
         position.duration_display()
     );
-    let time = Paragraph::new(Line::from(Span::styled(
-        time_text,
-        Style::default().fg(Color::White),
-    )));
-    frame.render_widget(time, chunks[2]);
+    let play_mode = session.queue().play_mode();
+    let mode_text = if play_mode != PlayMode::Normal && session.renderer_play_mode != play_mode {
+        format!("{} (emulated)", play_mode.label())
+    } else {
+        play_mode.label().to_string()
+    };
+    let mut time_spans = vec![
+        Span::styled(time_text, Style::default().fg(Color::White)),
+        Span::raw("  │  "),
+        Span::styled("Mode: ", Style::default().fg(Color::Gray)),
+        Span::styled(mode_text, Style::default().fg(Color::White)),
+        Span::raw("  │  "),
+        Span::styled("Speed: ", Style::default().fg(Color::Gray)),
+        Span::styled(speed_label(&session.speed), Style::default().fg(Color::White)),
+    ];
+    if session.recoveries > 0 {
+        time_spans.push(Span::raw("  │  "));
+        time_spans.push(Span::styled("Recovered: ", Style::default().fg(Color::Gray)));
+        time_spans.push(Span::styled(
+            format!("{}×", session.recoveries),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    let time = Paragraph::new(Line::from(time_spans));
+    frame.render_widget(time, chunks[3]);
 
-    // Help bar
+    // Queue
+    render_queue(frame, session.queue(), chunks[4]);
+
+    // Help bar; keys for actions the renderer currently forbids are dimmed
+    let actions = &session.transport_actions;
+    let can_toggle = match state {
+        PlaybackState::Playing => actions.can_pause(),
+        _ => actions.can_play(),
+    };
+    let key_style = |enabled: bool| {
+        Style::default().fg(if enabled { Color::Green } else { Color::DarkGray })
+    };
     let help = Paragraph::new(Line::from(vec![
-        Span::styled(" Space", Style::default().fg(Color::Green)),
+        Span::styled(" Space", key_style(can_toggle)),
         Span::raw(" Play/Pause  "),
-        Span::styled("s", Style::default().fg(Color::Green)),
+        Span::styled("s", key_style(actions.can_stop())),
         Span::raw(" Stop  "),
-        Span::styled("←/→", Style::default().fg(Color::Green)),
-        Span::raw(" ±30s  "),
-        Span::styled("Shift+←/→", Style::default().fg(Color::Green)),
-        Span::raw(" ±5min  "),
+        Span::styled("←/→", key_style(actions.can_seek())),
+        Span::raw(format!(" ±{}  ", step_label(app.seek_step()))),
+        Span::styled("Shift+←/→", key_style(actions.can_seek())),
+        Span::raw(format!(" ±{}  ", step_label(app.seek_step_long))),
+        Span::styled("n/p", Style::default().fg(Color::Green)),
+        Span::raw(" Next/Prev  "),
+        Span::styled("m", Style::default().fg(Color::Green)),
+        Span::raw(" Mode  "),
+        Span::styled("</>", key_style(actions.can_play())),
+        Span::raw(" Speed  "),
         Span::styled("b", Style::default().fg(Color::Green)),
         Span::raw(" Back  "),
         Span::styled("q", Style::default().fg(Color::Green)),
@@ -173,5 +305,82 @@ pub fn render_playback(
             .borders(Borders::ALL)
             .border_style(Style::default().fg(Color::DarkGray)),
     );
-    frame.render_widget(help, chunks[4]);
+    frame.render_widget(help, chunks[5]);
+}
+
+/// A seek step for the help bar: "30s", "5min".
+fn step_label(secs: u64) -> String {
+    if secs.is_multiple_of(60) {
+        format!("{}min", secs / 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// Render the play queue, highlighting the item currently loaded on the TV.
+fn render_queue(frame: &mut Frame, queue: &PlayQueue, area: Rect) {
+    let current = queue.current_index();
+    let items: Vec<ListItem> = queue
+        .items()
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let (marker, style) = if Some(i) == current {
+                (
+                    "▶ ",
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else {
+                ("  ", Style::default().fg(Color::White))
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!(" {marker}{:>2}. ", i + 1), style),
+                Span::styled(item.file_name.as_str(), style),
+            ]))
+        })
+        .collect();
+
+    let title = format!(
+        " Queue ({}/{}) ",
+        current.map(|i| i + 1).unwrap_or(0),
+        queue.len()
+    );
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .border_style(Style::default().fg(Color::DarkGray)),
+    );
+
+    let mut state = ListState::default();
+    state.select(current);
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+/// Render toasts stacked in the bottom-right corner, above the help bar.
+pub fn render_toasts(frame: &mut Frame, app: &App) {
+    let area = frame.area();
+    let width = area.width.min(60);
+    let mut bottom = area.height.saturating_sub(3);
+    for toast in app.toasts.iter().rev() {
+        if bottom < 3 {
+            break;
+        }
+        let rect = Rect::new(area.width - width, bottom - 3, width, 3);
+        bottom -= 3;
+        let color = if toast.is_error { Color::Red } else { Color::Cyan };
+        let paragraph = Paragraph::new(Line::from(Span::styled(
+            format!(" {}", toast.message),
+            Style::default().fg(Color::White),
+        )))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(color)),
+        );
+        frame.render_widget(Clear, rect);
+        frame.render_widget(paragraph, rect);
+    }
 }
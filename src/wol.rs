@@ -0,0 +1,298 @@
+//! Wake-on-LAN for renderers that drop off the network in standby: their
+//! MAC address, read from the system's neighbor table while they are up,
+//! and the magic packet that wakes them.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+use crate::discovery;
+use crate::error::AppError;
+use crate::net::{self, Host};
+use crate::renderer::{Device, Protocol};
+
+/// The customary Wake-on-LAN port (discard).
+pub const DEFAULT_PORT: u16 = 9;
+
+/// How often to ask for the description while waiting for a renderer.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to repeat the magic packet; NICs in standby may miss one.
+const RESEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for the neighbor table tool.
+const NEIGHBOR_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A hardware (MAC) address.
+///
+/// ```
+/// use localcast::wol::MacAddress;
+///
+/// let mac: MacAddress = "A8-23-FE-0:1b:9c".parse()?;
+/// assert_eq!(mac.to_string(), "a8:23:fe:00:1b:9c");
+///
+/// let packet = mac.magic_packet();
+/// assert_eq!(packet.len(), 102);
+/// assert_eq!(&packet[..6], &[0xff; 6]);
+/// assert_eq!(&packet[96..], &mac.octets());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MacAddress([u8; 6]);
+
+impl MacAddress {
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Six `0xFF` bytes, then the address sixteen times.
+    pub fn magic_packet(&self) -> [u8; 102] {
+        let mut packet = [0xff; 102];
+        for chunk in packet[6..].chunks_mut(6) {
+            chunk.copy_from_slice(&self.0);
+        }
+        packet
+    }
+
+    /// Whether this is a unicast address of an actual interface, not the
+    /// all-zero placeholder of an incomplete neighbor entry.
+    fn is_unicast(&self) -> bool {
+        self.0 != [0; 6] && self.0[0] & 1 == 0
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = String;
+
+    /// Accepts six hex octets separated by `:` or `-`; leading zeros may be
+    /// left out, as BSD `arp` does.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' is not a MAC address");
+        let mut octets = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for octet in &mut octets {
+            let part = parts.next().filter(|p| (1..=2).contains(&p.len())).ok_or_else(invalid)?;
+            *octet = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl TryFrom<String> for MacAddress {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<MacAddress> for String {
+    fn from(mac: MacAddress) -> Self {
+        mac.to_string()
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// How to wake a renderer.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct WakeOptions {
+    /// UDP port the magic packet is sent to.
+    pub port: u16,
+    /// How long the renderer may take to answer once woken.
+    pub timeout: Duration,
+}
+
+impl Default for WakeOptions {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The MAC address the system has for `host` in its ARP or neighbor table.
+/// The entry is fresh right after talking to the host, e.g. fetching its
+/// description. `None` if there is none or it can't be read.
+pub async fn neighbor_mac(host: &Host) -> Option<MacAddress> {
+    #[cfg(target_os = "linux")]
+    if let IpAddr::V4(ip) = host.ip {
+        let table = tokio::fs::read_to_string("/proc/net/arp").await.ok()?;
+        return proc_arp_entry(&table, ip);
+    }
+    let output = tokio::time::timeout(NEIGHBOR_TIMEOUT, neighbor_command(host).kill_on_drop(true).output())
+        .await
+        .ok()?
+        .ok()?;
+    first_mac(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The command listing the neighbor entry for `host`.
+fn neighbor_command(host: &Host) -> tokio::process::Command {
+    let ip = host.ip.to_string();
+    if cfg!(target_os = "linux") {
+        let mut command = tokio::process::Command::new("ip");
+        command.args(["neigh", "show", &ip]);
+        if let Some(zone) = &host.zone {
+            command.args(["dev", zone]);
+        }
+        command
+    } else if cfg!(windows) {
+        let mut command = tokio::process::Command::new("arp");
+        command.args(["-a", &ip]);
+        command
+    } else {
+        // BSD and macOS keep IPv6 neighbors apart
+        let tool = if host.ip.is_ipv6() { "ndp" } else { "arp" };
+        let mut command = tokio::process::Command::new(tool);
+        command.args(["-n", &ip]);
+        command
+    }
+}
+
+/// The complete entry for `ip` in the text of `/proc/net/arp`:
+/// `IP address  HW type  Flags  HW address  Mask  Device`.
+#[cfg(target_os = "linux")]
+fn proc_arp_entry(table: &str, ip: Ipv4Addr) -> Option<MacAddress> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [addr, _, _, hw, ..] if addr.parse() == Ok(ip) => hw.parse().ok().filter(MacAddress::is_unicast),
+            _ => None,
+        }
+    })
+}
+
+/// The first word of a neighbor tool's output that is a usable MAC address.
+fn first_mac(output: &str) -> Option<MacAddress> {
+    output
+        .split_whitespace()
+        .filter_map(|word| word.parse().ok())
+        .find(MacAddress::is_unicast)
+}
+
+/// Send the magic packet for `mac` towards `device`: broadcast on the
+/// interface it was found on, and to its last known address in case the
+/// network still knows where that is.
+pub async fn send_magic_packet(mac: MacAddress, device: &Device, port: u16) -> Result<(), AppError> {
+    let packet = mac.magic_packet();
+    let host = device.device_url.host().and_then(|h| h.parse::<Host>().ok());
+    let interface = device.interface.as_deref();
+
+    let mut sends: Vec<(SocketAddr, SocketAddr)> = Vec::new();
+    let source_v4 = interface
+        .and_then(|name| {
+            net::interfaces()
+                .ok()?
+                .into_iter()
+                .find(|i| i.name == name && i.addr.is_ipv4())
+                .map(|i| i.addr)
+        })
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    sends.push(((source_v4, 0).into(), (Ipv4Addr::BROADCAST, port).into()));
+    match &host {
+        Some(host @ Host { ip: IpAddr::V6(_), zone }) => {
+            let unspecified = SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0));
+            if let Ok(addr) = host.socket_addr(port) {
+                sends.push((unspecified, addr));
+            }
+            // All nodes on the link, which includes the sleeping one
+            let scope_id = zone.as_deref().or(interface).and_then(net::interface_index);
+            if let Some(scope_id) = scope_id {
+                let all_nodes = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+                sends.push((unspecified, SocketAddrV6::new(all_nodes, port, 0, scope_id).into()));
+            }
+        }
+        Some(Host { ip, .. }) => sends.push(((source_v4, 0).into(), (*ip, port).into())),
+        None => {}
+    }
+
+    let mut last_error = None;
+    let mut sent = false;
+    for (source, dest) in sends {
+        let result = async {
+            let socket = UdpSocket::bind(source).await?;
+            socket.set_broadcast(true)?;
+            socket.send_to(&packet, dest).await
+        }
+        .await;
+        match result {
+            Ok(_) => sent = true,
+            Err(e) => {
+                tracing::debug!("Sending Wake-on-LAN packet to {dest}: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    match (sent, last_error) {
+        (false, Some(e)) => Err(AppError::NetworkError(format!("Cannot send Wake-on-LAN packet: {e}"))),
+        _ => Ok(()),
+    }
+}
+
+/// Wake `device` and wait until it answers at its address, repeating the
+/// magic packet now and then. Returns the device as it describes itself now.
+pub async fn wake(device: &Device, options: &WakeOptions) -> Result<Device, AppError> {
+    let name = &device.friendly_name;
+    let mac = device.mac.ok_or_else(|| {
+        AppError::WakeOnLan(format!("no MAC address known for {name}; it has to be seen on the network once"))
+    })?;
+    let location = device.device_url.to_string();
+    let deadline = Instant::now() + options.timeout;
+    let mut resend = Instant::now();
+    while Instant::now() < deadline {
+        if Instant::now() >= resend {
+            tracing::info!("Sending Wake-on-LAN packet to {name} ({mac})");
+            send_magic_packet(mac, device, options.port).await?;
+            resend = Instant::now() + RESEND_INTERVAL;
+        }
+        let attempt = Instant::now();
+        match tokio::time::timeout_at(deadline, discovery::device_at(&location)).await {
+            // A Cast device doesn't say who it is; answering at its address will do
+            Ok(Ok(awake)) if awake.protocol == Protocol::GoogleCast => {
+                tracing::info!("{name} is awake");
+                return Ok(Device {
+                    stale: false,
+                    mac: awake.mac.or(Some(mac)),
+                    ..device.clone()
+                });
+            }
+            Ok(Ok(awake)) if awake.udn == device.udn => {
+                tracing::info!("{name} is awake");
+                return Ok(Device {
+                    mac: awake.mac.or(Some(mac)),
+                    ..awake
+                });
+            }
+            Ok(Ok(other)) => {
+                return Err(AppError::DeviceNotFound(format!(
+                    "{location} now describes {} instead of {name}",
+                    other.friendly_name
+                )));
+            }
+            Ok(Err(e)) => tracing::debug!("{name} is not up yet: {e}"),
+            Err(_) => break,
+        }
+        // A refused connection fails at once; don't hammer a booting TV
+        tokio::time::sleep_until((attempt + POLL_INTERVAL).min(deadline)).await;
+    }
+    Err(AppError::Timeout(format!(
+        "{name} did not wake up within {}s",
+        options.timeout.as_secs()
+    )))
+}
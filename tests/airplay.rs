@@ -0,0 +1,20 @@
+//! The AirPlay backend against the Apple TV stand-in.
+
+mod common;
+
+#[allow(dead_code)]
+#[path = "../examples/fake_appletv.rs"]
+mod fake_appletv;
+
+#[tokio::test]
+async fn controls_apple_tv_stand_in() {
+    let served = common::serve_media("airplay").await;
+    let (listener, port) = common::listen().await;
+    tokio::spawn(async move {
+        let options = fake_appletv::Options::default();
+        let _ = fake_appletv::run(listener, &options).await;
+    });
+
+    let renderer = common::connect(&format!("airplay://127.0.0.1:{port}")).await;
+    common::drive(renderer.as_ref(), &served.media).await;
+}
@@ -0,0 +1,109 @@
+//! What the stand-in tests share: a media server with a file to play, and
+//! renderers connected to stand-ins started in-process.
+
+// Compiled into each test, which uses only some of it
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use localcast::dlna::client::SoapClient;
+use localcast::renderer::{self, Media, Renderer};
+use localcast::{MediaServer, PlaybackState};
+use tokio::net::TcpListener;
+
+/// How long a stand-in may take to reach a state it was asked for.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A file served by a media server of its own, removed when dropped.
+pub struct Served {
+    pub media: Media,
+    _server: MediaServer,
+    file: PathBuf,
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.file);
+    }
+}
+
+/// Serve a small file named after `test`, so tests running in parallel
+/// don't share one.
+pub async fn serve_media(test: &str) -> Served {
+    let file = std::env::temp_dir().join(format!("localcast-{test}-{}.mp4", std::process::id()));
+    std::fs::write(&file, vec![0u8; 64 * 1024]).expect("write media file");
+    let server = MediaServer::start(0).await.expect("start media server");
+    let path = server.register(&file).await.expect("register media file");
+    let url = format!("http://127.0.0.1:{}{path}", server.port());
+    Served {
+        media: Media::new(url, "Test video", "video/mp4", 64 * 1024),
+        _server: server,
+        file,
+    }
+}
+
+/// A listener on a free loopback port for a stand-in, and its port.
+pub async fn listen() -> (TcpListener, u16) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind stand-in");
+    let port = listener.local_addr().expect("stand-in address").port();
+    (listener, port)
+}
+
+/// Find the device at `address` and connect to it.
+pub async fn connect(address: &str) -> Arc<dyn Renderer> {
+    let device = localcast::discovery::device_at(address).await.expect("find stand-in");
+    renderer::connect(&device, None, &SoapClient::default())
+        .await
+        .expect("connect to stand-in")
+}
+
+/// Wait until `renderer` reports `state`.
+pub async fn wait_for(renderer: &dyn Renderer, state: PlaybackState) {
+    let waiting = async {
+        loop {
+            if renderer.status().await.ok().as_ref() == Some(&state) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    if tokio::time::timeout(SETTLE_TIMEOUT, waiting).await.is_err() {
+        panic!("renderer never reported {state:?}, last {:?}", renderer.status().await);
+    }
+}
+
+/// Load, play, pause, seek and resume `renderer`, checking what it reports
+/// after each step.
+pub async fn drive(renderer: &dyn Renderer, media: &Media) {
+    play_and_pause(renderer, media).await;
+    seek_and_resume(renderer).await;
+}
+
+/// Load and play `media` on `renderer`, then pause, checking what it
+/// reports after each step.
+pub async fn play_and_pause(renderer: &dyn Renderer, media: &Media) {
+    renderer.load(media).await.expect("load");
+    renderer.play("1").await.expect("play");
+    wait_for(renderer, PlaybackState::Playing).await;
+
+    renderer.pause().await.expect("pause");
+    wait_for(renderer, PlaybackState::Paused).await;
+}
+
+/// Seek the paused `renderer` and check the position it reports, then
+/// resume.
+async fn seek_and_resume(renderer: &dyn Renderer) {
+    renderer.seek(120).await.expect("seek");
+    let position = renderer.position().await.expect("position");
+    assert!(
+        (120..125).contains(&position.elapsed_secs),
+        "position after seeking to 120 s: {} s",
+        position.elapsed_secs
+    );
+    assert!(position.duration_secs > 0, "no duration reported");
+
+    renderer.play("1").await.expect("resume");
+    wait_for(renderer, PlaybackState::Playing).await;
+}
@@ -0,0 +1,20 @@
+//! The Google Cast backend against the Chromecast stand-in.
+
+mod common;
+
+#[allow(dead_code)]
+#[path = "../examples/fake_chromecast.rs"]
+mod fake_chromecast;
+
+#[tokio::test]
+async fn controls_chromecast_stand_in() {
+    let served = common::serve_media("googlecast").await;
+    let (listener, port) = common::listen().await;
+    tokio::spawn(async move {
+        let options = fake_chromecast::Options::default();
+        let _ = fake_chromecast::run(listener, &options).await;
+    });
+
+    let renderer = common::connect(&format!("cast://127.0.0.1:{port}")).await;
+    common::drive(renderer.as_ref(), &served.media).await;
+}
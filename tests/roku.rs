@@ -0,0 +1,28 @@
+//! The Roku backend against the Roku stand-in.
+
+mod common;
+
+use localcast::{AppError, PlaybackState};
+
+#[allow(dead_code)]
+#[path = "../examples/fake_roku.rs"]
+mod fake_roku;
+
+#[tokio::test]
+async fn controls_roku_stand_in() {
+    let served = common::serve_media("roku").await;
+    let (listener, port) = common::listen().await;
+    tokio::spawn(async move {
+        let options = fake_roku::Options::default();
+        let _ = fake_roku::run(listener, &options).await;
+    });
+
+    let renderer = common::connect(&format!("roku://127.0.0.1:{port}")).await;
+    common::play_and_pause(renderer.as_ref(), &served.media).await;
+
+    // ECP only skips by fixed steps
+    let seek = renderer.seek(120).await;
+    assert!(matches!(seek, Err(AppError::Unsupported(_))), "seek: {seek:?}");
+    renderer.play("1").await.expect("resume");
+    common::wait_for(renderer.as_ref(), PlaybackState::Playing).await;
+}
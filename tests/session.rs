@@ -0,0 +1,183 @@
+//! Confirmed casts through a whole session, against stand-ins told to hang
+//! at one step: the failure or cancellation names the step it stopped at.
+
+mod common;
+
+use std::time::Duration;
+
+use localcast::{CastSession, CastStep, DeviceRef, SessionCommand, SessionError};
+use tempfile::TempDir;
+
+#[allow(dead_code)]
+#[path = "../examples/fake_chromecast.rs"]
+mod fake_chromecast;
+#[allow(dead_code)]
+#[path = "../examples/fake_roku.rs"]
+mod fake_roku;
+
+use fake_roku::Stall;
+
+/// Long enough for a stand-in on loopback, short enough to keep tests quick.
+const STEP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A session with a file queued and the stand-in at `address` listed.
+struct Setup {
+    session: CastSession,
+    _dir: TempDir,
+}
+
+async fn setup(address: String) -> Setup {
+    let dir = tempfile::tempdir().expect("create media folder");
+    let file = dir.path().join("clip.mp4");
+    std::fs::write(&file, vec![0u8; 64 * 1024]).expect("write media file");
+    let session = CastSession::builder()
+        .media_port(0)
+        .discovery_timeout(Duration::from_millis(100))
+        .search_interval(Duration::from_secs(3600))
+        .device(address)
+        .request_timeout(STEP_TIMEOUT)
+        .request_retries(0)
+        .fetch_timeout(STEP_TIMEOUT)
+        .start_timeout(STEP_TIMEOUT)
+        .wake_timeout(STEP_TIMEOUT)
+        .poll_interval(Duration::from_millis(100))
+        .start()
+        .await
+        .expect("start session");
+    session
+        .request(SessionCommand::SetFiles(vec![file]))
+        .await
+        .expect("queue the file");
+    let mut state = session.watch();
+    tokio::time::timeout(Duration::from_secs(5), state.wait_for(|s| !s.devices.is_empty()))
+        .await
+        .expect("stand-in never listed")
+        .expect("session closed");
+    Setup { session, _dir: dir }
+}
+
+async fn roku(stall: Option<Stall>) -> Setup {
+    let (listener, port) = common::listen().await;
+    tokio::spawn(async move {
+        let options = fake_roku::Options {
+            stall,
+            ..Default::default()
+        };
+        let _ = fake_roku::run(listener, &options).await;
+    });
+    setup(format!("roku://127.0.0.1:{port}")).await
+}
+
+fn cast() -> SessionCommand {
+    SessionCommand::Cast {
+        device: Some(DeviceRef::Index(0)),
+    }
+}
+
+/// Cast, expecting it to fail at `step`; also checks what the state shows.
+async fn assert_fails_at(setup: &Setup, command: SessionCommand, step: CastStep) {
+    match setup.session.request(command).await {
+        Err(SessionError::Cast(failure)) => assert_eq!(failure.step, step, "{failure}"),
+        other => panic!("expected a failure at {}, got {other:?}", step.id()),
+    }
+    let state = setup.session.state();
+    assert!(!state.casting);
+    assert_eq!(state.cast_step, None);
+    let error = state.cast_error.expect("no cast error shown");
+    assert!(error.starts_with(step.label()), "{error}");
+}
+
+/// Cast, cancelling once the session reports `step`.
+async fn assert_cancels_at(setup: &Setup, command: SessionCommand, step: CastStep) {
+    let session = setup.session.clone();
+    let casting = tokio::spawn(async move { session.request(command).await });
+    let mut state = setup.session.watch();
+    tokio::time::timeout(Duration::from_secs(5), state.wait_for(|s| s.cast_step == Some(step)))
+        .await
+        .unwrap_or_else(|_| panic!("never reached {}", step.id()))
+        .expect("session closed");
+    setup.session.cancel();
+    match casting.await.expect("cast task") {
+        Err(SessionError::Cancelled { step: cancelled }) => assert_eq!(cancelled, Some(step)),
+        other => panic!("expected a cancellation at {}, got {other:?}", step.id()),
+    }
+    let state = setup.session.state();
+    assert_eq!(state.cast_step, None);
+    assert_eq!(state.cast_error.as_deref(), Some("Cast cancelled"));
+}
+
+#[tokio::test]
+async fn casts_to_roku_stand_in() {
+    let setup = roku(None).await;
+    let state = setup.session.request(cast()).await.expect("cast");
+    assert!(state.casting);
+    assert_eq!(state.cast_step, None);
+    assert_eq!(state.cast_error, None);
+}
+
+#[tokio::test]
+async fn load_timeout_fails_setting_uri() {
+    let (listener, port) = common::listen().await;
+    tokio::spawn(async move {
+        let options = fake_chromecast::Options {
+            ignore_load: true,
+            ..Default::default()
+        };
+        let _ = fake_chromecast::run(listener, &options).await;
+    });
+    let setup = setup(format!("cast://127.0.0.1:{port}")).await;
+    assert_fails_at(&setup, cast(), CastStep::SettingUri).await;
+}
+
+#[tokio::test]
+async fn play_timeout_fails_starting() {
+    let setup = roku(Some(Stall::Launch)).await;
+    assert_fails_at(&setup, cast(), CastStep::Starting).await;
+}
+
+#[tokio::test]
+async fn missing_media_request_fails_waiting_for_request() {
+    let setup = roku(Some(Stall::Fetch)).await;
+    assert_fails_at(&setup, cast(), CastStep::WaitingForRequest).await;
+}
+
+#[tokio::test]
+async fn never_playing_fails_transitioning() {
+    let setup = roku(Some(Stall::Startup)).await;
+    assert_fails_at(&setup, cast(), CastStep::Transitioning).await;
+}
+
+#[tokio::test]
+async fn unanswered_wake_fails_waking() {
+    let (listener, port) = common::listen().await;
+    let stand_in = tokio::spawn(async move {
+        let _ = fake_roku::run(listener, &fake_roku::Options::default()).await;
+    });
+    let setup = setup(format!("roku://127.0.0.1:{port}")).await;
+    assert!(setup.session.state().devices[0].mac.is_some(), "no MAC to wake with");
+    // Switched off: nothing answers at its address any more
+    stand_in.abort();
+    let _ = stand_in.await;
+    let command = SessionCommand::WakeAndCast {
+        device: Some(DeviceRef::Index(0)),
+    };
+    assert_fails_at(&setup, command, CastStep::Waking).await;
+}
+
+#[tokio::test]
+async fn cancels_while_starting() {
+    let setup = roku(Some(Stall::Launch)).await;
+    assert_cancels_at(&setup, cast(), CastStep::Starting).await;
+}
+
+#[tokio::test]
+async fn cancels_while_waiting_for_request() {
+    let setup = roku(Some(Stall::Fetch)).await;
+    assert_cancels_at(&setup, cast(), CastStep::WaitingForRequest).await;
+}
+
+#[tokio::test]
+async fn cancels_while_transitioning() {
+    let setup = roku(Some(Stall::Startup)).await;
+    assert_cancels_at(&setup, cast(), CastStep::Transitioning).await;
+}